@@ -113,12 +113,16 @@ fn handle_internal_linking(rt: &Runtime) {
         };
         debug!("Link to = {:?}", to.iter().map(|f| f.deref()).collect::<Vec<&Entry>>());
 
+        // Links are written to both sides, so we write all entries in one transaction
+        let mut transaction = rt.store().transaction();
+
         match cmd.subcommand_name() {
             Some("add") => {
                 for mut to_entry in to {
                     if let Err(e) = to_entry.add_internal_link(&mut from) {
                         trace_error_exit(&e, 1);
                     }
+                    transaction.update(to_entry);
                 }
             },
 
@@ -127,11 +131,17 @@ fn handle_internal_linking(rt: &Runtime) {
                     if let Err(e) = to_entry.remove_internal_link(&mut from) {
                         trace_error_exit(&e, 1);
                     }
+                    transaction.update(to_entry);
                 }
             },
 
             _ => unreachable!(),
         };
+
+        transaction.update(from);
+        if let Err(e) = transaction.commit() {
+            trace_error_exit(&e, 1);
+        }
     }
 }
 
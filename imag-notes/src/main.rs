@@ -91,38 +91,27 @@ fn edit_entry(rt: &Runtime, name: String) -> bool {
 }
 
 fn list(rt: &Runtime) {
-    use std::cmp::Ordering;
-
-    let iter = Note::all_notes(rt.store());
+    let iter = Note::all_note_headers(rt.store());
     if iter.is_err() {
         trace_error_exit(&iter.unwrap_err(), 1);
     }
 
-    let mut iter = iter.unwrap()
-        .filter_map(|note| {
-            match note {
+    let mut names = iter.unwrap()
+        .filter_map(|header| {
+            match header {
                 Err(e) => {
                     trace_error(&e);
                     None
                 },
-                Ok(e) => Some(e)
+                Ok(h) => Some(h.name)
             }
         })
-        .collect::<Vec<Note>>();
-
-    iter.sort_by(|note_a, note_b| {
-        if let (Ok(a), Ok(b)) = (note_a.get_name(), note_b.get_name()) {
-            return a.cmp(&b)
-        } else {
-            return Ordering::Greater;
-        }
-    });
-
-    for note in iter {
-        note.get_name()
-            .map(|name| println!("{}", name))
-            .map_err(|e| trace_error(&e))
-            .ok();
+        .collect::<Vec<String>>();
+
+    names.sort();
+
+    for name in names {
+        println!("{}", name);
     }
 }
 
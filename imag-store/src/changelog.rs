@@ -0,0 +1,42 @@
+use std::str::FromStr;
+
+use libimagrt::runtime::Runtime;
+use libimagstore::storeid::StoreId;
+use libimagerror::trace::trace_error_exit;
+
+pub fn changelog(rt: &Runtime) {
+    let scmd = rt.cli().subcommand_matches("log").unwrap(); // safe by main()
+
+    let since = match scmd.value_of("since").map(u64::from_str) {
+        Some(Ok(since)) => since,
+        Some(Err(_)) => {
+            error!("--since expects a number of seconds since the UNIX epoch");
+            ::std::process::exit(1);
+        },
+        None => 0,
+    };
+    let module = scmd.value_of("module");
+
+    let in_module = |id: &StoreId| {
+        id.strip_prefix(rt.store().path())
+            .ok()
+            .and_then(|rel| rel.components().next())
+            .and_then(|c| c.as_os_str().to_str())
+            .map(|c| Some(c) == module)
+            .unwrap_or(false)
+    };
+
+    let events = rt.store()
+        .changes_since(since)
+        .unwrap_or_else(|e| trace_error_exit(&e, 1));
+
+    for event in events {
+        let matches = module.is_none() ||
+            in_module(&event.id) ||
+            event.new_id.as_ref().map_or(false, &in_module);
+        if !matches {
+            continue;
+        }
+        println!("{}", event);
+    }
+}
@@ -7,10 +7,12 @@ use std::ops::DerefMut;
 use std::io::Write;
 use std::io::stderr;
 use std::process::exit;
+use std::str::FromStr;
 
 use clap::ArgMatches;
 
 use libimagrt::runtime::Runtime;
+use libimagstore::format::Format;
 use libimagstore::store::Entry;
 use libimagstore::store::EntryHeader;
 use libimagstore::storeid::build_entry_path;
@@ -50,6 +52,8 @@ pub fn create(rt: &Runtime) {
                                                                 &path,
                                                                 String::new(),
                                                                 EntryHeader::new()))
+            } else if scmd.is_present("from-raw") {
+                create_from_source(rt, scmd, &path)
             } else {
                 create_with_content_and_header(rt, &path, String::new(), EntryHeader::new())
             }
@@ -93,7 +97,11 @@ fn create_from_source(rt: &Runtime, matches: &ArgMatches, path: &PathBuf) -> Res
     let content = content.unwrap();
     debug!("Content with len = {}", content.len());
 
-    Entry::from_str(path.clone(), &content[..])
+    let format = matches.value_of("format")
+        .map(|f| Format::from_str(f).unwrap()) // safe by clap
+        .unwrap_or(Format::Toml);
+
+    Entry::from_str_as(path.clone(), &content[..], format)
         .and_then(|new_e| {
             let r = rt.store()
                 .create(path.clone())
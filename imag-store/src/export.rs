@@ -0,0 +1,28 @@
+use std::fs::File;
+use std::io::stdout;
+
+use libimagrt::runtime::Runtime;
+use libimagerror::trace::trace_error_exit;
+
+pub fn export(rt: &Runtime) {
+    let scmd = rt.cli().subcommand_matches("export").unwrap(); // safe by main()
+
+    let modules : Vec<&str> = scmd.values_of("module").map(|v| v.collect()).unwrap_or(vec![]);
+    let output = scmd.value_of("output").unwrap(); // safe by clap
+
+    let res = if output == "-" {
+        let out = stdout();
+        let res = rt.store().export(&modules, out.lock());
+        res
+    } else {
+        let file = File::create(output).unwrap_or_else(|e| trace_error_exit(&e, 1));
+        rt.store().export(&modules, file)
+    };
+
+    match res {
+        Ok(manifest) => info!("Exported {} entries and {} attachments",
+                              manifest.entries.len(),
+                              manifest.attachments.len()),
+        Err(e) => trace_error_exit(&e, 1),
+    }
+}
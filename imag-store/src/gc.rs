@@ -0,0 +1,36 @@
+use libimagrt::runtime::Runtime;
+use libimagentrylink::gc::gc as gc_links;
+use libimagerror::trace::trace_error_exit;
+
+pub fn gc(rt: &Runtime) {
+    let fix = rt.cli()
+        .subcommand_matches("gc")
+        .map(|scmd| scmd.is_present("fix"))
+        .unwrap_or(false);
+    let store = rt.store();
+
+    // Removing links and external link entries can leave directories empty, so links come first
+    let links = gc_links(store, !fix).unwrap_or_else(|e| trace_error_exit(&e, 1));
+    for g in links.iter() {
+        println!("{}", g);
+    }
+
+    let garbage = store.gc(!fix).unwrap_or_else(|e| trace_error_exit(&e, 1));
+    for g in garbage.iter() {
+        println!("{}", g);
+    }
+
+    let attachments = store.gc_attachments(!fix).unwrap_or_else(|e| trace_error_exit(&e, 1));
+    for hash in attachments.iter() {
+        println!("unused data     {}", hash);
+    }
+
+    let count = links.len() + garbage.len() + attachments.len();
+    if count == 0 {
+        info!("Store is clean");
+    } else if fix {
+        info!("Cleaned up {} items", count);
+    } else {
+        info!("Found {} items, run with --fix to clean them up", count);
+    }
+}
@@ -2,7 +2,7 @@ use libimagstore::storeid::build_entry_path;
 use libimagrt::runtime::Runtime;
 use libimagerror::trace::{trace_error, trace_error_exit};
 
-use retrieve::print_entry;
+use retrieve::{do_filter, filter_entries, print_entry};
 
 pub fn get(rt: &Runtime) {
     rt.cli()
@@ -10,6 +10,10 @@ pub fn get(rt: &Runtime) {
         .map(|scmd| {
             scmd.value_of("id")
                 .map(|id| {
+                    if do_filter(scmd) {
+                        return filter_entries(rt, scmd, id);
+                    }
+
                     let path = build_entry_path(rt.store(), id);
                     if path.is_err() {
                         trace_error_exit(&path.unwrap_err(), 1);
@@ -18,7 +22,7 @@ pub fn get(rt: &Runtime) {
                     debug!("path = {:?}", path);
 
                     match rt.store().get(path) {
-                        Ok(Some(entry)) => print_entry(rt, scmd, entry),
+                        Ok(Some(entry)) => print_entry(scmd, entry),
                         Ok(None)        => info!("No entry found"),
                         Err(e)          => trace_error(&e),
                     }
@@ -0,0 +1,29 @@
+use std::fs::File;
+use std::io::stdin;
+use std::str::FromStr;
+
+use libimagrt::runtime::Runtime;
+use libimagstore::archive::ConflictStrategy;
+use libimagerror::trace::trace_error_exit;
+
+pub fn import(rt: &Runtime) {
+    let scmd = rt.cli().subcommand_matches("import").unwrap(); // safe by main()
+
+    let input    = scmd.value_of("archive").unwrap(); // safe by clap
+    let strategy = scmd.value_of("on-conflict")
+        .map(|s| ConflictStrategy::from_str(s).unwrap()) // safe by clap
+        .unwrap_or(ConflictStrategy::Skip);
+
+    let res = if input == "-" {
+        let inp = stdin();
+        let res = rt.store().import(inp.lock(), strategy);
+        res
+    } else {
+        let file = File::open(input).unwrap_or_else(|e| trace_error_exit(&e, 1));
+        rt.store().import(file, strategy)
+    };
+
+    for outcome in res.unwrap_or_else(|e| trace_error_exit(&e, 1)) {
+        println!("{}", outcome);
+    }
+}
@@ -16,33 +16,54 @@
 extern crate clap;
 #[macro_use] extern crate log;
 extern crate semver;
+extern crate serde_json;
 extern crate toml;
 #[macro_use] extern crate version;
 
+extern crate libimagentrylink;
 extern crate libimagrt;
 extern crate libimagstore;
-extern crate libimagutil;
 #[macro_use] extern crate libimagerror;
 
 use libimagrt::setup::generate_runtime_setup;
 
+mod changelog;
 mod create;
 mod delete;
 mod error;
+mod export;
+mod gc;
 mod get;
+mod import;
+mod migrate;
+mod mv;
+mod reindex;
 mod retrieve;
+mod search;
+mod snapshot;
 mod ui;
 mod update;
 mod verify;
+mod watch;
 mod util;
 
+use changelog::changelog;
 use create::create;
 use delete::delete;
+use export::export;
+use gc::gc;
 use get::get;
+use import::import;
+use migrate::migrate;
+use mv::mv;
+use reindex::reindex;
 use retrieve::retrieve;
+use search::search;
+use snapshot::snapshot;
 use ui::build_ui;
 use update::update;
 use verify::verify;
+use watch::watch;
 
 fn main() {
     let rt = generate_runtime_setup("imag-store",
@@ -62,10 +83,20 @@ fn main() {
                 match name {
                     "create"   => create(&rt),
                     "delete"   => delete(&rt),
+                    "export"   => export(&rt),
+                    "gc"       => gc(&rt),
                     "get"      => get(&rt),
+                    "import"   => import(&rt),
+                    "log"      => changelog(&rt),
+                    "migrate"  => migrate(&rt),
+                    "move"     => mv(&rt),
+                    "reindex"  => reindex(&rt),
+                    "search"   => search(&rt),
+                    "snapshot" => snapshot(&rt),
                     "retrieve" => retrieve(&rt),
                     "update"   => update(&rt),
                     "verify"   => verify(&rt),
+                    "watch"    => watch(&rt),
                     _ => {
                         debug!("Unknown command");
                         // More error handling
@@ -0,0 +1,48 @@
+use std::process::exit;
+
+use libimagrt::runtime::Runtime;
+use libimagstore::migration::MigrationRegistry;
+use libimagerror::trace::{trace_error, trace_error_exit};
+
+/// Build the registry of all known migrations
+///
+/// Modules which change the layout of their header add their migrations here.
+fn registry() -> MigrationRegistry {
+    MigrationRegistry::new()
+}
+
+pub fn migrate(rt: &Runtime) {
+    let dry_run = rt.cli()
+        .subcommand_matches("migrate")
+        .map(|scmd| scmd.is_present("dry-run"))
+        .unwrap_or(false);
+
+    let results = rt.store()
+        .migrate(&registry(), dry_run)
+        .unwrap_or_else(|e| trace_error_exit(&e, 1));
+
+    if results.is_empty() {
+        info!("Nothing to migrate");
+        return;
+    }
+
+    let mut failed = false;
+    for (id, result) in results {
+        match result {
+            Ok(report) => if dry_run {
+                println!("{:?}: would migrate {}", id, report);
+            } else {
+                println!("{:?}: migrated {}", id, report);
+            },
+            Err(e) => {
+                println!("{:?}: failed", id);
+                trace_error(&e);
+                failed = true;
+            },
+        }
+    }
+
+    if failed {
+        exit(1);
+    }
+}
@@ -0,0 +1,27 @@
+use libimagstore::storeid::{StoreId, build_entry_path};
+use libimagrt::runtime::Runtime;
+use libimagentrylink::internal::move_entry;
+use libimagerror::trace::trace_error_exit;
+
+pub fn mv(rt: &Runtime) {
+    let scmd = rt.cli().subcommand_matches("move").unwrap(); // safe, we checked in main()
+    let store = rt.store();
+
+    let from = build_entry_path(store, scmd.value_of("from").unwrap()) // enforced by clap
+        .map(StoreId::from)
+        .unwrap_or_else(|e| trace_error_exit(&e, 1));
+    let to = build_entry_path(store, scmd.value_of("to").unwrap()) // enforced by clap
+        .map(StoreId::from)
+        .unwrap_or_else(|e| trace_error_exit(&e, 1));
+    debug!("Moving {:?} -> {:?}", from, to);
+
+    let report = move_entry(store, from, to, scmd.is_present("rewrite-markdown"))
+        .unwrap_or_else(|e| trace_error_exit(&e, 1));
+
+    for id in report.rewritten_links.iter() {
+        info!("Rewrote link in {:?}", id);
+    }
+    for &(ref id, n) in report.rewritten_content.iter() {
+        info!("Rewrote {} markdown links in {:?}", n, id);
+    }
+}
@@ -0,0 +1,14 @@
+use libimagrt::runtime::Runtime;
+use libimagerror::trace::trace_error_exit;
+
+pub fn reindex(rt: &Runtime) {
+    match rt.store().rebuild_index() {
+        Ok(_)  => info!("Index rebuilt"),
+        Err(e) => trace_error_exit(&e, 1),
+    }
+
+    match rt.store().rebuild_search_index() {
+        Ok(_)  => info!("Search index rebuilt"),
+        Err(e) => trace_error_exit(&e, 1),
+    }
+}
@@ -1,17 +1,28 @@
+use std::process::exit;
+use std::str::FromStr;
+
 use clap::ArgMatches;
 use toml::Value;
 
+use libimagstore::format::Format;
 use libimagstore::store::FileLockEntry;
 use libimagstore::storeid::build_entry_path;
 use libimagrt::runtime::Runtime;
 use libimagerror::trace::{trace_error, trace_error_exit};
 
+use util::parse_header_filter;
+
 pub fn retrieve(rt: &Runtime) {
     rt.cli()
         .subcommand_matches("retrieve")
         .map(|scmd| {
             scmd.value_of("id")
                 .map(|id| {
+                    if do_filter(scmd) {
+                        filter_entries(rt, scmd, id);
+                        return Ok(());
+                    }
+
                     let path = build_entry_path(rt.store(), id);
                     if path.is_err() {
                         trace_error_exit(&path.unwrap_err(), 1);
@@ -22,7 +33,7 @@ pub fn retrieve(rt: &Runtime) {
                     rt.store()
                         // "id" must be present, enforced via clap spec
                         .retrieve(path)
-                        .map(|e| print_entry(rt, scmd, e))
+                        .map(|e| print_entry(scmd, e))
                         .map_err(|e| {
                             debug!("No entry.");
                             debug!("{}:", e);
@@ -32,24 +43,24 @@ pub fn retrieve(rt: &Runtime) {
         });
 }
 
-pub fn print_entry(rt: &Runtime, scmd: &ArgMatches, e: FileLockEntry) {
+pub fn print_entry(scmd: &ArgMatches, e: FileLockEntry) {
     if do_print_raw(scmd) {
-        debug!("Printing raw content...");
-        println!("{}", e.to_str());
-    } else if do_filter(scmd) {
-        debug!("Filtering...");
-        warn!("Filtering via header specs is currently now supported.");
-        warn!("Will fail now!");
-        unimplemented!()
+        let format = get_format(scmd);
+        debug!("Printing raw content as {}...", format);
+        match e.to_str_as(format) {
+            Ok(s)  => println!("{}", s),
+            Err(e) => trace_error_exit(&e, 1),
+        }
     } else {
         debug!("Printing structured...");
         if do_print_header(scmd) {
             debug!("Printing header...");
-            if do_print_header_as_json(rt.cli()) {
+            if do_print_header_as_json(scmd) {
                 debug!("Printing header as json...");
-                warn!("Printing as JSON currently not supported.");
-                warn!("Will fail now!");
-                unimplemented!()
+                match ::serde_json::to_string_pretty(&e.get_header().to_json()) {
+                    Ok(s)  => println!("{}", s),
+                    Err(e) => trace_error_exit(&e, 1),
+                }
             } else {
                 debug!("Printing header as TOML...");
                 // We have to Value::Table() for Display
@@ -65,6 +76,48 @@ pub fn print_entry(rt: &Runtime, scmd: &ArgMatches, e: FileLockEntry) {
     }
 }
 
+/// Print the entries below `module` which match the `filter-header` subcommand of `scmd`
+///
+/// Header paths which are indexed by the store are looked up in the index.
+pub fn filter_entries(rt: &Runtime, scmd: &ArgMatches, module: &str) {
+    let fcmd = scmd.subcommand_matches("filter-header").unwrap(); // safe, checked by caller
+
+    if fcmd.is_present("header-field-grep") {
+        warn!("Filtering with --grep is not supported, use --where");
+        exit(1);
+    }
+
+    let (path, value) = match fcmd.value_of("header-field-where").map(parse_header_filter) {
+        Some(Some(filter)) => filter,
+        Some(None) => {
+            warn!("Not a header filter, expected 'header.field=value'");
+            exit(1);
+        },
+        None => {
+            warn!("Nothing to filter with, pass --where");
+            exit(1);
+        },
+    };
+    debug!("Filtering for '{}' = {:?}", path, value);
+
+    let module = module.trim_matches('/');
+    let module = if module.is_empty() { None } else { Some(module) };
+
+    let ids = match rt.store().find_by_header(module, &path, &value) {
+        Ok(ids) => ids,
+        Err(e) => trace_error_exit(&e, 1),
+    };
+
+    for id in ids {
+        println!("{}", id.to_str().unwrap_or("<non-UTF-8 id>"));
+        match rt.store().get(id) {
+            Ok(Some(e)) => print_entry(scmd, e),
+            Ok(None)    => debug!("Entry vanished"),
+            Err(e)      => trace_error(&e),
+        }
+    }
+}
+
 fn do_print_header(m: &ArgMatches) -> bool {
     m.is_present("header")
 }
@@ -81,7 +134,13 @@ fn do_print_raw(m: &ArgMatches) -> bool {
     m.is_present("raw")
 }
 
-fn do_filter(m: &ArgMatches) -> bool {
+fn get_format(m: &ArgMatches) -> Format {
+    m.value_of("format")
+        .map(|f| Format::from_str(f).unwrap()) // safe by clap
+        .unwrap_or(Format::Toml)
+}
+
+pub fn do_filter(m: &ArgMatches) -> bool {
     m.subcommand_matches("filter-header").is_some()
 }
 
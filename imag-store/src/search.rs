@@ -0,0 +1,22 @@
+use libimagrt::runtime::Runtime;
+use libimagerror::trace::trace_error_exit;
+
+pub fn search(rt: &Runtime) {
+    let query = rt.cli()
+        .subcommand_matches("search")
+        .and_then(|scmd| scmd.values_of("query"))
+        .map(|values| values.collect::<Vec<&str>>().join(" "))
+        .unwrap(); // safe by clap
+
+    let hits = rt.store()
+        .search(&query)
+        .unwrap_or_else(|e| trace_error_exit(&e, 1));
+
+    if hits.is_empty() {
+        info!("Nothing found");
+    }
+
+    for hit in hits {
+        println!("{} ({}): {}", hit.id.display(), hit.score, hit.snippet);
+    }
+}
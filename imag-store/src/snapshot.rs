@@ -0,0 +1,53 @@
+use libimagrt::runtime::Runtime;
+use libimagerror::trace::trace_error_exit;
+
+pub fn snapshot(rt: &Runtime) {
+    let scmd = rt.cli().subcommand_matches("snapshot").unwrap(); // safe by main()
+
+    match scmd.subcommand() {
+        ("create", Some(scmd)) => {
+            let name   = scmd.value_of("name").unwrap(); // safe by clap
+            let module = scmd.value_of("module");
+
+            let snapshot = rt.store()
+                .snapshot(name, module)
+                .unwrap_or_else(|e| trace_error_exit(&e, 1));
+            info!("Took snapshot {}", snapshot);
+        },
+
+        ("list", _) => {
+            let snapshots = rt.store()
+                .snapshots()
+                .unwrap_or_else(|e| trace_error_exit(&e, 1));
+            for snapshot in snapshots {
+                println!("{}", snapshot);
+            }
+        },
+
+        ("diff", Some(scmd)) => {
+            let name = scmd.value_of("name").unwrap(); // safe by clap
+            let changes = rt.store()
+                .diff_snapshot(name)
+                .unwrap_or_else(|e| trace_error_exit(&e, 1));
+            for change in changes {
+                println!("{}", change);
+            }
+        },
+
+        ("restore", Some(scmd)) => {
+            let name = scmd.value_of("name").unwrap(); // safe by clap
+            let path = scmd.value_of("path");
+            let changes = rt.store()
+                .restore_snapshot(name, path)
+                .unwrap_or_else(|e| trace_error_exit(&e, 1));
+            for change in changes {
+                println!("{}", change);
+            }
+        },
+
+        _ => {
+            error!("Use one of 'create', 'list', 'diff' or 'restore'");
+            ::std::process::exit(1);
+        },
+    }
+}
@@ -23,6 +23,13 @@ pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
                         .takes_value(true)
                         .help("Create a new entry by reading this file ('-' for stdin)")
                         .value_name("FILE"))
+                   .arg(Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["toml", "json", "yaml"])
+                        .help("The format of the entry read with --from-raw, default: toml")
+                        .value_name("FORMAT"))
 
                    .group(ArgGroup::with_name("create-destination-group")
                           .args(&["path", "id"])
@@ -83,9 +90,16 @@ pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
                         .long("raw")
                         .short("r")
                         .help("Print Entries as they are in the store"))
+                   .arg(Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["toml", "json", "yaml"])
+                        .help("Print the entry in this format with --raw, default: toml")
+                        .value_name("FORMAT"))
 
                    .subcommand(SubCommand::with_name("filter-header")
-                               .about("Retrieve Entries by filtering")
+                               .about("Retrieve the entries below the --id path by filtering their headers")
                                .version("0.1")
                                .arg(Arg::with_name("header-field-where")
                                     .long("where")
@@ -127,9 +141,16 @@ pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
                         .long("raw")
                         .short("r")
                         .help("Print Entries as they are in the store"))
+                   .arg(Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["toml", "json", "yaml"])
+                        .help("Print the entry in this format with --raw, default: toml")
+                        .value_name("FORMAT"))
 
                    .subcommand(SubCommand::with_name("filter-header")
-                               .about("Retrieve Entries by filtering")
+                               .about("Retrieve the entries below the --id path by filtering their headers")
                                .version("0.1")
                                .arg(Arg::with_name("header-field-where")
                                     .long("where")
@@ -167,7 +188,7 @@ pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
                         .short("h")
                         .takes_value(true)
                         .multiple(true)
-                        .help("Set a header field. Specify as 'header.field.value=value', multiple allowed"))
+                        .help("Set header fields. Specify as 'header.field.value=value', where the path can match many fields, e.g. 'tags[?value==\"work\"]=office' or 'todos.*.done=true'. Multiple allowed"))
                    )
 
        .subcommand(SubCommand::with_name("delete")
@@ -186,4 +207,189 @@ pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
                    .about("Verify the store")
                    .version("0.1")
                    )
+
+       .subcommand(SubCommand::with_name("gc")
+                   .about("Find dangling links, unused external links, attachments and objects, empty directories and unparsable files")
+                   .version("0.1")
+                   .arg(Arg::with_name("fix")
+                        .long("fix")
+                        .takes_value(false)
+                        .required(false)
+                        .help("Clean up what was found. Unparsable files are moved to .lost+found in the store"))
+                   )
+
+       .subcommand(SubCommand::with_name("migrate")
+                   .about("Apply pending header migrations to all entries")
+                   .version("0.1")
+                   .arg(Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .short("n")
+                        .takes_value(false)
+                        .required(false)
+                        .help("Only print what would be migrated"))
+                   )
+
+       .subcommand(SubCommand::with_name("move")
+                   .about("Move an entry and rewrite the links to it in the entries it links to")
+                   .version("0.1")
+                   .arg(Arg::with_name("from")
+                        .long("from")
+                        .short("f")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Move this entry")
+                        .value_name("PATH"))
+                   .arg(Arg::with_name("to")
+                        .long("to")
+                        .short("t")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Move the entry here")
+                        .value_name("PATH"))
+                   .arg(Arg::with_name("rewrite-markdown")
+                        .long("rewrite-markdown")
+                        .short("m")
+                        .takes_value(false)
+                        .required(false)
+                        .help("Rewrite markdown links to the entry in the content of all entries, too"))
+                   )
+
+       .subcommand(SubCommand::with_name("reindex")
+                   .about("Rebuild the index over the configured header fields and the search index")
+                   .version("0.1")
+                   )
+
+       .subcommand(SubCommand::with_name("search")
+                   .about("Search the content of all entries")
+                   .version("0.1")
+                   .arg(Arg::with_name("query")
+                        .index(1)
+                        .takes_value(true)
+                        .required(true)
+                        .multiple(true)
+                        .help("Words to search for. 'word*' matches words starting with 'word', \"some words\" matches a phrase")
+                        .value_name("QUERY"))
+                   )
+
+       .subcommand(SubCommand::with_name("log")
+                   .about("Show the changes to the store")
+                   .version("0.1")
+                   .arg(Arg::with_name("since")
+                        .long("since")
+                        .short("s")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Only show changes since this time, in seconds since the UNIX epoch")
+                        .value_name("SECONDS"))
+                   .arg(Arg::with_name("module")
+                        .long("module")
+                        .short("m")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Only show changes to entries of this module")
+                        .value_name("MODULE"))
+                   )
+
+       .subcommand(SubCommand::with_name("watch")
+                   .about("Print changes to a module made outside of imag, as JSON lines")
+                   .version("0.1")
+                   .arg(Arg::with_name("module")
+                        .index(1)
+                        .takes_value(true)
+                        .required(true)
+                        .help("The module to watch, eg. 'diary'")
+                        .value_name("MODULE"))
+                   )
+
+       .subcommand(SubCommand::with_name("snapshot")
+                   .about("Take, list, compare and restore snapshots of the store")
+                   .version("0.1")
+                   .subcommand(SubCommand::with_name("create")
+                               .about("Take a snapshot")
+                               .version("0.1")
+                               .arg(Arg::with_name("name")
+                                    .index(1)
+                                    .takes_value(true)
+                                    .required(true)
+                                    .help("The name of the snapshot")
+                                    .value_name("NAME"))
+                               .arg(Arg::with_name("module")
+                                    .long("module")
+                                    .short("m")
+                                    .takes_value(true)
+                                    .required(false)
+                                    .help("Only take a snapshot of the entries of this module")
+                                    .value_name("MODULE"))
+                               )
+                   .subcommand(SubCommand::with_name("list")
+                               .about("List the snapshots, oldest first")
+                               .version("0.1")
+                               )
+                   .subcommand(SubCommand::with_name("diff")
+                               .about("Show the entries which were added (+), removed (-) or changed (~) since a snapshot")
+                               .version("0.1")
+                               .arg(Arg::with_name("name")
+                                    .index(1)
+                                    .takes_value(true)
+                                    .required(true)
+                                    .help("The name of the snapshot")
+                                    .value_name("NAME"))
+                               )
+                   .subcommand(SubCommand::with_name("restore")
+                               .about("Restore entries to their state in a snapshot")
+                               .version("0.1")
+                               .arg(Arg::with_name("name")
+                                    .index(1)
+                                    .takes_value(true)
+                                    .required(true)
+                                    .help("The name of the snapshot")
+                                    .value_name("NAME"))
+                               .arg(Arg::with_name("path")
+                                    .long("path")
+                                    .short("p")
+                                    .takes_value(true)
+                                    .required(false)
+                                    .help("Only restore this entry or module, eg. 'diary' or 'notes/todo'")
+                                    .value_name("PATH"))
+                               )
+                   )
+
+       .subcommand(SubCommand::with_name("export")
+                   .about("Write entries and their attachments to an archive which can be imported into another store")
+                   .version("0.1")
+                   .arg(Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Write the archive (tar) to this file ('-' for stdout)")
+                        .value_name("FILE"))
+                   .arg(Arg::with_name("module")
+                        .long("module")
+                        .short("m")
+                        .takes_value(true)
+                        .multiple(true)
+                        .required(false)
+                        .help("Only export the entries of this module, multiple allowed. Exports all entries if not given")
+                        .value_name("MODULE"))
+                   )
+
+       .subcommand(SubCommand::with_name("import")
+                   .about("Merge an archive written by 'export' into the store")
+                   .version("0.1")
+                   .arg(Arg::with_name("archive")
+                        .index(1)
+                        .takes_value(true)
+                        .required(true)
+                        .help("The archive to import ('-' for stdin)")
+                        .value_name("FILE"))
+                   .arg(Arg::with_name("on-conflict")
+                        .long("on-conflict")
+                        .short("c")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["skip", "overwrite", "keep-both"])
+                        .help("What to do with entries which exist with another content: keep them (skip, default), replace them (overwrite) or import the entry with another id (keep-both)")
+                        .value_name("STRATEGY"))
+                   )
 }
@@ -1,65 +1,140 @@
 use std::borrow::Cow;
 use std::collections::btree_map::{BTreeMap, Entry};
-use std::str::Split;
+use std::vec::IntoIter;
 
 use clap::ArgMatches;
-use toml::Value;
+use toml::{Table, Value};
 
+use libimagstore::headerpath::HeaderPath;
 use libimagstore::store::EntryHeader;
-use libimagutil::key_value_split::IntoKeyValue;
+use libimagerror::trace::trace_error;
 
-pub fn build_toml_header(matches: &ArgMatches, header: EntryHeader) -> EntryHeader {
+/// Set the header fields given with `--header` in `header`
+///
+/// The path of a field can match more than one value (see `libimagstore::headerpath`), all of
+/// them are replaced. If it does not match anything, the field is created, if the path consists
+/// of keys only.
+pub fn build_toml_header(matches: &ArgMatches, mut header: EntryHeader) -> EntryHeader {
     debug!("Building header from cli spec");
     if let Some(headerspecs) = matches.values_of("header") {
-        let mut main = header.into();
-        let kvs = headerspecs.into_iter()
-                            .filter_map(|hs| {
-                                debug!("- Processing: '{}'", hs);
-                                let kv = String::from(hs).into_kv();
-                                debug!("-        got: '{:?}'", kv);
-                                kv
-                            });
-        for tpl in kvs {
-            let (key, value) = tpl.into();
-            debug!("Splitting: {:?}", key);
-            let mut split = key.split('.');
-            let current = split.next();
-            if current.is_some() {
-                insert_key_into(String::from(current.unwrap()), &mut split, Cow::Owned(value), &mut main);
+        for hs in headerspecs {
+            debug!("- Processing: '{}'", hs);
+            let (key, value) = match split_header_spec(hs) {
+                Some(kv) => kv,
+                None => {
+                    warn!("Not a header field assignment: '{}'", hs);
+                    continue;
+                },
+            };
+            debug!("-        got: '{}' = '{}'", key, value);
+
+            let path = match HeaderPath::parse(&key) {
+                Ok(path) => path,
+                Err(e) => {
+                    trace_error(&e);
+                    continue;
+                },
+            };
+            let value = parse_value(Cow::Owned(value));
+
+            match header.set_all(&path, value.clone()) {
+                Ok(0) => (),
+                Ok(n) => {
+                    debug!("Replaced {} values at '{}'", n, key);
+                    continue;
+                },
+                Err(e) => {
+                    trace_error(&e);
+                    continue;
+                },
+            }
+
+            match path.keys() {
+                Some(keys) => {
+                    let mut main : Table = header.into();
+                    let mut keys = keys.into_iter();
+                    if let Some(current) = keys.next() {
+                        insert_key_into(current, &mut keys, value, &mut main);
+                    }
+                    header = EntryHeader::from(main);
+                },
+                None => warn!("'{}' does not match any header field", key),
             }
         }
+    }
 
-        debug!("Header = {:?}", main);
-        EntryHeader::from(main)
-    } else {
-        debug!("Header = {:?}", header);
-        header
+    debug!("Header = {:?}", header);
+    header
+}
+
+/// Parse a header filter `header.field=value`, see `imag-store retrieve filter-header`
+///
+/// The value is parsed like in `--header`, a value in double quotes is always a String.
+pub fn parse_header_filter(spec: &str) -> Option<(String, Value)> {
+    split_header_spec(spec).map(|(key, value)| {
+        let rest   = &spec[key.len()..];
+        let quoted = rest.len() >= 3 && rest.starts_with("=\"") && rest.ends_with('"');
+        let value = if quoted {
+            Value::String(value)
+        } else {
+            parse_value(Cow::Owned(value))
+        };
+        (key, value)
+    })
+}
+
+/// Split `spec` at the first `=` which is not part of the header path
+///
+/// A value in double quotes is unquoted.
+fn split_header_spec(spec: &str) -> Option<(String, String)> {
+    let mut depth  = 0;
+    let mut quoted = false;
+    let mut escape = false;
+    for (i, c) in spec.char_indices() {
+        match c {
+            _ if escape         => escape = false,
+            '\\' if quoted      => escape = true,
+            '"'                 => quoted = !quoted,
+            '[' if !quoted      => depth += 1,
+            ']' if !quoted      => depth -= 1,
+            '=' if !quoted && depth == 0 => {
+                let value = &spec[(i + 1)..];
+                let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                    &value[1..(value.len() - 1)]
+                } else {
+                    value
+                };
+                return Some((String::from(&spec[..i]), String::from(value)));
+            },
+            _ => (),
+        }
     }
+    None
 }
 
-fn insert_key_into<'a>(current: String,
-                   rest_path: &mut Split<char>,
-                   value: Cow<'a, str>,
+fn insert_key_into(current: String,
+                   rest_path: &mut IntoIter<String>,
+                   value: Value,
                    map: &mut BTreeMap<String, Value>) {
     let next = rest_path.next();
 
     if next.is_none() {
         debug!("Inserting into {:?} = {:?}", current, value);
-        map.insert(current, parse_value(value));
+        map.insert(current, value);
     } else {
         debug!("Inserting into {:?} ... = {:?}", current, value);
         match map.entry(current) {
             Entry::Occupied(ref mut e) => {
                 match *e.get_mut() {
                     Value::Table(ref mut t) => {
-                        insert_key_into(String::from(next.unwrap()), rest_path, value, t);
+                        insert_key_into(next.unwrap(), rest_path, value, t);
                     },
                     _ => unreachable!(),
                 }
             },
             Entry::Vacant(v) => { v.insert(Value::Table( {
                 let mut submap = BTreeMap::new();
-                insert_key_into(String::from(next.unwrap()), rest_path, value, &mut submap);
+                insert_key_into(next.unwrap(), rest_path, value, &mut submap);
                 debug!("Inserting submap = {:?}", submap);
                 submap }));
             }
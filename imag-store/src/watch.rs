@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value as Json;
+
+use libimagrt::runtime::Runtime;
+use libimagstore::store::WatchEvent;
+use libimagstore::storeid::StoreId;
+use libimagerror::trace::trace_error_exit;
+
+pub fn watch(rt: &Runtime) {
+    let module = rt.cli()
+        .subcommand_matches("watch")
+        .and_then(|scmd| scmd.value_of("module"))
+        .unwrap(); // safe by clap
+
+    let watch = rt.store()
+        .watch(module)
+        .unwrap_or_else(|e| trace_error_exit(&e, 1));
+
+    for event in watch {
+        println!("{}", to_json(&event));
+    }
+}
+
+/// Format `event` as a JSON object on one line
+fn to_json(event: &WatchEvent) -> String {
+    let (name, id, new_id) = match *event {
+        WatchEvent::Created(ref id)           => ("create", id, None),
+        WatchEvent::Modified(ref id)          => ("modify", id, None),
+        WatchEvent::Deleted(ref id)           => ("delete", id, None),
+        WatchEvent::Renamed(ref from, ref to) => ("rename", from, Some(to)),
+    };
+
+    let mut object = BTreeMap::new();
+    object.insert(String::from("event"), Json::String(String::from(name)));
+    object.insert(String::from("id"), json_id(id));
+    if let Some(new_id) = new_id {
+        object.insert(String::from("new_id"), json_id(new_id));
+    }
+
+    ::serde_json::to_string(&Json::Object(object))
+        .unwrap_or_else(|e| trace_error_exit(&e, 1))
+}
+
+fn json_id(id: &StoreId) -> Json {
+    Json::String(format!("{}", id))
+}
@@ -13,6 +13,7 @@ use std::process::exit;
 
 use libimagrt::runtime::Runtime;
 use libimagrt::setup::generate_runtime_setup;
+use libimagentrytag::lookup::entries_with_tag;
 use libimagentrytag::tagable::Tagable;
 use libimagentrytag::tag::Tag;
 use libimagstore::storeid::build_entry_path;
@@ -41,7 +42,8 @@ fn main() {
             |name| {
                 debug!("Call: {}", name);
                 match name {
-                    "list" => list(id, &rt),
+                    "entries" => entries(id, &rt),
+                    "list"    => list(id, &rt),
                     _ => {
                         warn!("Unknown command");
                         // More error handling
@@ -100,7 +102,7 @@ fn list(id: &str, rt: &Runtime) {
     };
     debug!("path = {:?}", path);
 
-    let entry = match rt.store().get(path.clone()) {
+    let entry = match rt.store().get_read_only(path.clone()) {
         Ok(Some(e)) => e,
         Ok(None) => {
             info!("No entry found.");
@@ -152,3 +154,17 @@ fn list(id: &str, rt: &Runtime) {
     }
 }
 
+fn entries(id: &str, rt: &Runtime) {
+    let scmd = rt.cli().subcommand_matches("entries").unwrap(); // safe, we checked in main()
+    let tag  = scmd.value_of("tag").unwrap(); // enforced by clap
+
+    let module = id.trim_matches('/');
+    let module = if module.is_empty() { None } else { Some(module) };
+
+    match entries_with_tag(rt.store(), module, tag) {
+        Ok(ids) => for id in ids {
+            println!("{}", id.to_str().unwrap_or("<non-UTF-8 id>"));
+        },
+        Err(e) => trace_error_exit(&e, 1),
+    }
+}
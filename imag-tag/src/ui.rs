@@ -53,4 +53,16 @@ pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
                           .required(true))
                    )
 
+       .subcommand(SubCommand::with_name("entries")
+                   .about("List the entries below the --id path which have a tag")
+                   .version("0.1")
+                   .arg(Arg::with_name("tag")
+                        .long("tag")
+                        .short("t")
+                        .takes_value(true)
+                        .required(true)
+                        .help("List entries with this tag")
+                        .value_name("TAG"))
+                   )
+
 }
@@ -14,7 +14,7 @@ pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
             .long("versions")
             .takes_value(false)
             .required(false)
-            .help("Only print available versions for this file"))
+            .help("Only print available revisions for this entry"))
 
         .arg(Arg::with_name("view-header")
             .long("header")
@@ -1,12 +1,8 @@
-use std::ops::DerefMut;
-
-use toml::Value;
-
-use std::collections::BTreeMap;
-
 use libimagstore::store::Store;
 use libimagstore::storeid::StoreIdIterator;
-use libimagstore::store::FileLockEntry;
+use libimagstore::store::{EntryHeader, FileLockEntry};
+use libimagstore::stream::HeaderIter;
+use libimagstore::schema::{HeaderSchema, FieldType};
 use libimagstore::storeid::StoreId;
 use libimagstore::storeid::IntoStoreId;
 
@@ -17,6 +13,13 @@ use error::CounterErrorKind as CEK;
 
 pub type CounterName = String;
 
+/// The `counter` section of the header of a counter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CounterHeader {
+    pub name: CounterName,
+    pub value: i64,
+}
+
 pub struct Counter<'a> {
     fle: FileLockEntry<'a>,
 }
@@ -24,101 +27,69 @@ pub struct Counter<'a> {
 impl<'a> Counter<'a> {
 
     pub fn new(store: &Store, name: CounterName, init: i64) -> Result<Counter> {
-        use std::ops::DerefMut;
-
         debug!("Creating new counter: '{}' with value: {}", name, init);
-        let fle = {
-            let lockentry = store.create(ModuleEntryPath::new(name.clone()).into_storeid());
-            if lockentry.is_err() {
-                return Err(CE::new(CEK::StoreWriteError, Some(Box::new(lockentry.err().unwrap()))));
-            }
-            let mut lockentry = lockentry.unwrap();
-
-            {
-                let mut entry  = lockentry.deref_mut();
-                let mut header = entry.get_header_mut();
-                let setres = header.set("counter", Value::Table(BTreeMap::new()));
-                if setres.is_err() {
-                    return Err(CE::new(CEK::StoreWriteError, Some(Box::new(setres.unwrap_err()))));
-                }
-
-                let setres = header.set("counter.name", Value::String(name));
-                if setres.is_err() {
-                    return Err(CE::new(CEK::StoreWriteError, Some(Box::new(setres.unwrap_err()))));
-                }
-
-                let setres = header.set("counter.value", Value::Integer(init));
-                if setres.is_err() {
-                    return Err(CE::new(CEK::StoreWriteError, Some(Box::new(setres.unwrap_err()))));
-                }
-            }
-
-            lockentry
-        };
-
-        Ok(Counter { fle: fle })
+        try!(store.register_schema(Counter::schema())
+             .map_err(|e| CE::new(CEK::StoreWriteError, Some(Box::new(e)))));
+
+        let fle = try!(store.create(ModuleEntryPath::new(name.clone()).into_storeid())
+                       .map_err(|e| CE::new(CEK::StoreWriteError, Some(Box::new(e)))));
+
+        let mut counter = Counter { fle: fle };
+        try!(counter.write_header(&CounterHeader { name: name, value: init }));
+        Ok(counter)
+    }
+
+    /// The schema of the `counter` header namespace
+    pub fn schema() -> HeaderSchema {
+        HeaderSchema::new("counter")
+            .require("name", FieldType::String)
+            .require("value", FieldType::Integer)
+    }
+
+    /// Read the `counter` section of the header
+    pub fn header(&self) -> Result<CounterHeader> {
+        Counter::parse_header(self.fle.get_header())
+    }
+
+    fn parse_header(header: &EntryHeader) -> Result<CounterHeader> {
+        header.read_section("counter")
+            .map_err(|e| CE::new(CEK::HeaderTypeError, Some(Box::new(e))))
+            .and_then(|h| h.ok_or(CE::new(CEK::HeaderFieldMissingError, None)))
+    }
+
+    fn write_header(&mut self, header: &CounterHeader) -> Result<()> {
+        self.fle.get_header_mut().write_section("counter", header)
+            .map_err(|e| CE::new(CEK::StoreWriteError, Some(Box::new(e))))
     }
 
     pub fn inc(&mut self) -> Result<()> {
-        let mut header = self.fle.deref_mut().get_header_mut();
-        match header.read("counter.value") {
-            Ok(Some(Value::Integer(i))) => {
-                header.set("counter.value", Value::Integer(i + 1))
-                    .map_err(|e| CE::new(CEK::StoreWriteError, Some(Box::new(e))))
-                    .map(|_| ())
-            },
-            Err(e) => Err(CE::new(CEK::StoreReadError, Some(Box::new(e)))),
-            _ => Err(CE::new(CEK::StoreReadError, None)),
-        }
+        let mut header = try!(self.header());
+        header.value += 1;
+        self.write_header(&header)
     }
 
     pub fn dec(&mut self) -> Result<()> {
-        let mut header = self.fle.deref_mut().get_header_mut();
-        match header.read("counter.value") {
-            Ok(Some(Value::Integer(i))) => {
-                header.set("counter.value", Value::Integer(i - 1))
-                    .map_err(|e| CE::new(CEK::StoreWriteError, Some(Box::new(e))))
-                    .map(|_| ())
-            },
-            Err(e) => Err(CE::new(CEK::StoreReadError, Some(Box::new(e)))),
-            _ => Err(CE::new(CEK::StoreReadError, None)),
-        }
+        let mut header = try!(self.header());
+        header.value -= 1;
+        self.write_header(&header)
     }
 
     pub fn reset(&mut self) -> Result<()> {
-        let mut header = self.fle.deref_mut().get_header_mut();
-        header.set("counter.value", Value::Integer(0))
-            .map_err(|e| CE::new(CEK::StoreWriteError, Some(Box::new(e))))
-            .map(|_| ())
+        self.set(0)
     }
 
     pub fn set(&mut self, v: i64) -> Result<()> {
-        let mut header = self.fle.deref_mut().get_header_mut();
-        header.set("counter.value", Value::Integer(v))
-            .map_err(|e| CE::new(CEK::StoreWriteError, Some(Box::new(e))))
-            .map(|_| ())
+        let mut header = try!(self.header());
+        header.value = v;
+        self.write_header(&header)
     }
 
     pub fn name(&self) -> Result<CounterName> {
-        self.fle.get_header().read("counter.name")
-            .map_err(|e| CE::new(CEK::StoreWriteError, Some(Box::new(e))))
-            .and_then(|v| {
-                match v {
-                    Some(Value::String(s)) => Ok(s),
-                    _ => Err(CE::new(CEK::HeaderTypeError, None)),
-                }
-            })
+        self.header().map(|h| h.name)
     }
 
     pub fn value(&self) -> Result<i64> {
-        self.fle.get_header().read("counter.value")
-            .map_err(|e| CE::new(CEK::StoreWriteError, Some(Box::new(e))))
-            .and_then(|v| {
-                match v {
-                    Some(Value::Integer(i)) => Ok(i),
-                    _ => Err(CE::new(CEK::HeaderTypeError, None)),
-                }
-            })
+        self.header().map(|h| h.value)
     }
 
     pub fn load(name: CounterName, store: &Store) -> Result<Counter> {
@@ -141,6 +112,13 @@ impl<'a> Counter<'a> {
             .map_err(|e| CE::new(CEK::StoreReadError, Some(Box::new(e))))
     }
 
+    /// Iterate over the headers of all counters, sorted by name, without loading the counters
+    pub fn all_counter_headers(store: &Store) -> Result<CounterHeaderIterator> {
+        store.select("counter")
+            .map(|selection| CounterHeaderIterator(selection.headers()))
+            .map_err(|e| CE::new(CEK::StoreReadError, Some(Box::new(e))))
+    }
+
 }
 
 trait FromStoreId {
@@ -186,3 +164,16 @@ impl<'a> Iterator for CounterIterator<'a> {
 
 }
 
+pub struct CounterHeaderIterator<'a>(HeaderIter<'a>);
+
+impl<'a> Iterator for CounterHeaderIterator<'a> {
+    type Item = Result<CounterHeader>;
+
+    fn next(&mut self) -> Option<Result<CounterHeader>> {
+        self.0.next().map(|r| {
+            r.map_err(|e| CE::new(CEK::StoreReadError, Some(Box::new(e))))
+                .and_then(|(_, header)| Counter::parse_header(&header))
+        })
+    }
+
+}
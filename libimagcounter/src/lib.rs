@@ -12,9 +12,9 @@
     while_true,
 )]
 
-extern crate toml;
 #[macro_use] extern crate log;
 #[macro_use] extern crate semver;
+#[macro_use] extern crate serde_derive;
 
 #[macro_use] extern crate libimagstore;
 #[macro_use] extern crate libimagerror;
@@ -1,7 +1,9 @@
 use regex::Regex;
 use regex::Error as RError;
 
-use libimagstore::store::Entry;
+use libimagstore::search::is_search_index_enabled;
+use libimagstore::store::{Entry, Store};
+use libimagstore::storeid::StoreId;
 
 use filter::Filter;
 
@@ -27,6 +29,7 @@ impl<'a> IntoRegex for Regex {
 
 pub struct ContentGrep {
     regex: Regex,
+    candidates: Option<Vec<StoreId>>,
 }
 
 impl ContentGrep {
@@ -38,16 +41,107 @@ impl ContentGrep {
             .map(|reg| {
                 ContentGrep {
                     regex: reg,
+                    candidates: None,
                 }
             })
     }
 
+    /// Only match the entries which the full-text index of `store` finds for `query`
+    ///
+    /// An entry has to match `query` (see `libimagstore::search` for the syntax) and the regex,
+    /// which is only applied to the entries found in the index. This finds the same entries as the
+    /// regex alone only if every match of the regex contains the words of `query`, so `query`
+    /// should be literal words taken from the regex. Entries which are missing from the index are
+    /// not found until it is rebuilt. If the index is disabled or cannot be queried, all entries
+    /// are grepped.
+    pub fn using_index(mut self, store: &Store, query: &str) -> ContentGrep {
+        if !is_search_index_enabled(&store.config().cloned()) {
+            debug!("Search index is disabled, grepping all entries");
+            return self;
+        }
+
+        match store.search(query) {
+            Ok(hits) => self.candidates = Some(hits.into_iter().map(|hit| hit.id).collect()),
+            Err(e) => {
+                debug!("Cannot use search index, grepping all entries");
+                debug!("{:?}", e);
+            },
+        }
+        self
+    }
+
 }
 
 impl Filter for ContentGrep {
 
     fn filter(&self, e: &Entry) -> bool {
-        self.regex.captures(&e.get_content()[..]).is_some()
+        let is_candidate = self.candidates
+            .as_ref()
+            .map(|c| c.contains(e.get_location()))
+            .unwrap_or(true);
+
+        is_candidate && self.regex.captures(&e.get_content()[..]).is_some()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use toml::{Parser, Value};
+
+    use libimagstore::backend::InMemoryBackend;
+    use libimagstore::store::Store;
+
+    use filter::Filter;
+    use super::ContentGrep;
+
+    /// A store without hooks on a new `InMemoryBackend`, with `extra` added to its configuration
+    fn store(extra: &str) -> Store {
+        let positions = ["store-unload", "pre-create", "post-create", "pre-retrieve",
+                         "post-retrieve", "pre-update", "post-update", "pre-delete",
+                         "post-delete", "pre-move", "post-move"];
+        let mut config = String::from(extra);
+        for position in positions.iter() {
+            config.push_str(&format!("\n{}-hook-aspects = []", position));
+        }
+        config.push_str("\n[hooks]\n[aspects]\n");
+
+        let config = Parser::new(&config).parse().map(Value::Table);
+        Store::new(PathBuf::from("/store"), config, Box::new(InMemoryBackend::new())).unwrap()
+    }
+
+    fn grep(store: &Store, filter: &ContentGrep) -> Vec<String> {
+        let mut found = vec![];
+        for (name, content) in vec![("a", "apples and pears"), ("b", "apples"), ("c", "pears")] {
+            let mut entry = store.retrieve(PathBuf::from(format!("notes/{}", name))).unwrap();
+            *entry.get_content_mut() = String::from(content);
+        }
+        for name in vec!["a", "b", "c"] {
+            let entry = store.retrieve_copy(PathBuf::from(format!("notes/{}", name))).unwrap();
+            if filter.filter(&entry) {
+                found.push(String::from(name));
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn test_grep_using_index() {
+        let store  = store("search-index = true");
+        let filter = ContentGrep::new("pea?rs").unwrap();
+        assert_eq!(grep(&store, &filter), vec!["a", "c"]);
+
+        let filter = filter.using_index(&store, "apples");
+        assert_eq!(grep(&store, &filter), vec!["a"]);
+    }
+
+    #[test]
+    fn test_grep_without_index() {
+        let store  = store("");
+        let filter = ContentGrep::new("pea?rs").unwrap().using_index(&store, "apples");
+        assert_eq!(grep(&store, &filter), vec!["a", "c"]);
     }
 
 }
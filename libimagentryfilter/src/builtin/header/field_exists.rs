@@ -20,7 +20,7 @@ impl FieldExists {
 impl Filter for FieldExists {
 
     fn filter(&self, e: &Entry) -> bool {
-        e.get_header().read(&self.header_field_path[..]).is_ok()
+        !e.get_header().read_all(&self.header_field_path).is_empty()
     }
 
 }
@@ -23,19 +23,19 @@ impl Filter for FieldIsEmpty {
 
     fn filter(&self, e: &Entry) -> bool {
         e.get_header()
-            .read(&self.header_field_path[..])
-            .map(|v| {
+            .read_all(&self.header_field_path)
+            .into_iter()
+            .all(|v| {
                 match v {
-                    Some(Value::Array(a))   => a.is_empty(),
-                    Some(Value::String(s))  => s.is_empty(),
-                    Some(Value::Table(t))   => t.is_empty(),
-                    Some(Value::Boolean(_)) |
-                    Some(Value::Float(_))   |
-                    Some(Value::Integer(_)) => false,
-                    _                       => true,
+                    Value::Array(a)   => a.is_empty(),
+                    Value::String(s)  => s.is_empty(),
+                    Value::Table(t)   => t.is_empty(),
+                    Value::Boolean(_) |
+                    Value::Float(_)   |
+                    Value::Integer(_) => false,
+                    _                 => true,
                 }
             })
-            .unwrap_or(false)
     }
 
 }
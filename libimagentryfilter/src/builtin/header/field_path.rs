@@ -1 +1,6 @@
-pub type FieldPath = String;
+//! Paths of header fields, see `libimagstore::headerpath` for their syntax
+//!
+//! A path can match more than one value. The filters match if any of these values fulfills them,
+//! except `FieldIsEmpty`, which matches if all of them are empty.
+
+pub use libimagstore::headerpath::HeaderPath as FieldPath;
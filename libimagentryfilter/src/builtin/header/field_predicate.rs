@@ -30,14 +30,9 @@ impl<P: Predicate> Filter for FieldPredicate<P> {
 
     fn filter(&self, e: &Entry) -> bool {
         e.get_header()
-            .read(&self.header_field_path[..])
-            .map(|val| {
-                match val {
-                    None => false,
-                    Some(v) => (*self.predicate).evaluate(v),
-                }
-            })
-            .unwrap_or(false)
+            .read_all(&self.header_field_path)
+            .into_iter()
+            .any(|v| (*self.predicate).evaluate(v))
     }
 
 }
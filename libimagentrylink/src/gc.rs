@@ -0,0 +1,182 @@
+//! Garbage left behind by deleted entries
+//!
+//! Deleting an entry does not remove the links other entries have to it, and the entries which
+//! hold external links (see `external`) stay in the store when no entry links to them anymore.
+//! `gc()` finds (and removes) both.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::fmt::Error as FmtError;
+use std::result::Result as RResult;
+
+use libimagstore::store::Store;
+use libimagstore::storeid::StoreId;
+
+use error::LinkErrorKind as LEK;
+use error::MapErrInto;
+use internal::{InternalLinker, rewrite_links};
+use result::Result;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LinkGarbage {
+    /// The first entry links to the second one, which does not exist
+    DanglingLink(StoreId, StoreId),
+
+    /// An entry holding an external link, which no entry links to
+    UnreferencedExternalLink(StoreId),
+}
+
+impl Display for LinkGarbage {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        match *self {
+            LinkGarbage::DanglingLink(ref from, ref to) => {
+                write!(fmt, "dangling link   {} -> {}", from, to)
+            },
+            LinkGarbage::UnreferencedExternalLink(ref id) => {
+                write!(fmt, "unused external {}", id)
+            },
+        }
+    }
+
+}
+
+/// Whether `id` (storified) is an entry holding an external link
+fn is_external_link_entry(store: &Store, id: &StoreId) -> bool {
+    let mut dir = store.path().clone();
+    dir.push("links");
+    dir.push("external");
+    id.starts_with(dir)
+}
+
+/// Find the dangling links and unreferenced external link entries in `store`
+///
+/// Unless `dry_run` is set, the dangling links are removed from the headers of their entries and
+/// the external link entries are deleted. Returns what was found. Entries which cannot be parsed
+/// are skipped, see `Store::gc()` for them.
+pub fn gc(store: &Store, dry_run: bool) -> Result<Vec<LinkGarbage>> {
+    let ids : BTreeSet<StoreId> = try!(store.ids().map_err_into(LEK::StoreReadError)).collect();
+
+    let mut garbage    = vec![];
+    let mut referenced = BTreeSet::new();
+    for id in ids.iter() {
+        let links = match store.retrieve_copy(id.clone()) {
+            Ok(entry) => match entry.get_internal_links() {
+                Ok(links) => links,
+                Err(e) => {
+                    warn!("Cannot read the links of {}, skipping it", id);
+                    debug!("{:?}", e);
+                    continue;
+                },
+            },
+            Err(e) => {
+                debug!("Cannot read {}, skipping it: {:?}", id, e);
+                continue;
+            },
+        };
+
+        for link in links {
+            let target = link.clone().storified(store);
+            if !ids.contains(&target) {
+                garbage.push(LinkGarbage::DanglingLink(id.clone(), link));
+            } else if target != *id {
+                referenced.insert(target);
+            }
+        }
+    }
+
+    for id in ids.iter() {
+        if is_external_link_entry(store, id) && !referenced.contains(id) {
+            garbage.push(LinkGarbage::UnreferencedExternalLink(id.clone()));
+        }
+    }
+
+    if !dry_run {
+        try!(remove(store, &garbage));
+    }
+    Ok(garbage)
+}
+
+fn remove(store: &Store, garbage: &[LinkGarbage]) -> Result<()> {
+    let mut deleted  = BTreeSet::new();
+    let mut dangling = BTreeMap::new();
+    for g in garbage {
+        match *g {
+            LinkGarbage::UnreferencedExternalLink(ref id) => {
+                try!(store.delete(id.clone()).map_err_into(LEK::StoreWriteError));
+                deleted.insert(id.clone());
+            },
+            LinkGarbage::DanglingLink(ref from, ref to) => {
+                dangling.entry(from.clone()).or_insert_with(Vec::new).push(to.clone());
+            },
+        }
+    }
+
+    for (from, targets) in dangling.into_iter() {
+        if deleted.contains(&from) {
+            continue;
+        }
+
+        let mut fle = try!(store.retrieve(from).map_err_into(LEK::StoreReadError));
+        let links = try!(fle.get_internal_links())
+            .into_iter()
+            .filter(|l| !targets.contains(l))
+            .collect();
+        try!(rewrite_links(fle.get_header_mut(), links));
+        try!(store.update(fle).map_err_into(LEK::StoreWriteError));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use libimagstore::backend::InMemoryBackend;
+    use libimagstore::store::Store;
+    use libimagstore::storeid::StoreId;
+
+    use internal::InternalLinker;
+
+    use super::{LinkGarbage, gc};
+
+    fn id(s: &str) -> StoreId {
+        StoreId::from(PathBuf::from(s))
+    }
+
+    #[test]
+    fn test_gc() {
+        let store = Store::new(PathBuf::from("/store"), None, Box::new(InMemoryBackend::new()))
+            .unwrap();
+        {
+            let mut a = store.retrieve(PathBuf::from("notes/a")).unwrap();
+            let mut b = store.retrieve(PathBuf::from("notes/b")).unwrap();
+            let mut c = store.retrieve(PathBuf::from("notes/c")).unwrap();
+            a.add_internal_link(&mut b).unwrap();
+            a.add_internal_link(&mut c).unwrap();
+
+            let mut x    = store.retrieve(PathBuf::from("notes/x")).unwrap();
+            let mut used = store.retrieve(PathBuf::from("links/external/used")).unwrap();
+            x.add_internal_link(&mut used).unwrap();
+        }
+        store.retrieve(PathBuf::from("links/external/orphan")).unwrap();
+        store.delete(PathBuf::from("notes/c")).unwrap();
+
+        let garbage = vec![
+            LinkGarbage::DanglingLink(id("/store/notes/a"), id("/store/notes/c")),
+            LinkGarbage::UnreferencedExternalLink(id("/store/links/external/orphan")),
+        ];
+        assert_eq!(gc(&store, true).unwrap(), garbage);
+        assert!(store.get(PathBuf::from("links/external/orphan")).unwrap().is_some());
+
+        assert_eq!(gc(&store, false).unwrap(), garbage);
+        let a = store.retrieve_copy(PathBuf::from("notes/a")).unwrap();
+        assert_eq!(a.get_internal_links().unwrap(), vec![id("/store/notes/b")]);
+        assert!(store.get(PathBuf::from("links/external/orphan")).unwrap().is_none());
+        assert!(store.get(PathBuf::from("links/external/used")).unwrap().is_some());
+
+        assert!(gc(&store, true).unwrap().is_empty());
+    }
+
+}
@@ -3,10 +3,12 @@ use std::cmp::Ordering;
 use libimagstore::storeid::StoreId;
 use libimagstore::store::Entry;
 use libimagstore::store::EntryHeader;
+use libimagstore::store::Store;
 use libimagstore::store::Result as StoreResult;
 use libimagerror::into::IntoError;
 
 use error::LinkErrorKind as LEK;
+use error::MapErrInto;
 use result::Result;
 
 use toml::Value;
@@ -90,6 +92,132 @@ impl InternalLinker for Entry {
 
 }
 
+/// What `move_entry()` changed
+#[derive(Debug, Clone, Default)]
+pub struct MoveReport {
+    /// Entries whose link to the moved entry was rewritten
+    pub rewritten_links: Vec<StoreId>,
+
+    /// Entries in which markdown links to the moved entry were rewritten, with the number of
+    /// rewritten links
+    pub rewritten_content: Vec<(StoreId, usize)>,
+}
+
+/// Move the entry `old` to `new` and rewrite the links pointing to it
+///
+/// Each entry linked from `old` gets its link back rewritten to `new`. With `rewrite_markdown`,
+/// markdown links to `old` (as absolute path or relative to the store) are rewritten in the
+/// content of all entries as well, which reads every entry of the store.
+///
+/// `Store::move_by_id()` and `Store::save_as()` do not touch links, entries moved with them are
+/// left with dangling links pointing to them.
+///
+/// The move and the rewritten entries are committed in one transaction (see
+/// `libimagstore::store::Transaction`), so either all of them happen or none.
+pub fn move_entry(store: &Store, old: StoreId, new: StoreId, rewrite_markdown: bool)
+    -> Result<MoveReport>
+{
+    let old = old.storified(store);
+    let new = new.storified(store);
+
+    let links = try!(store
+                     .retrieve_copy(old.clone())
+                     .map_err_into(LEK::StoreReadError)
+                     .and_then(|entry| entry.get_internal_links()));
+
+    let mut report = MoveReport::default();
+    let mut tx     = store.transaction();
+
+    for link in links {
+        let link = link.storified(store);
+        if link == old {
+            continue;
+        }
+
+        let mut fle = match try!(store.get(link.clone()).map_err_into(LEK::StoreReadError)) {
+            Some(fle) => fle,
+            None => {
+                debug!("Not rewriting dangling link to {:?}", link);
+                continue;
+            },
+        };
+
+        let links = try!(fle.get_internal_links())
+            .into_iter()
+            .map(|l| if l.clone().storified(store) == old { new.clone() } else { l })
+            .collect();
+        try!(rewrite_links(fle.get_header_mut(), links));
+        report.rewritten_links.push(link.clone());
+
+        if rewrite_markdown {
+            let (content, n) = rewrite_content_links(store, fle.get_content(), &old, &new);
+            if n > 0 {
+                *fle.get_content_mut() = content;
+                report.rewritten_content.push((link.clone(), n));
+            }
+        }
+
+        tx.update(fle);
+    }
+
+    if rewrite_markdown {
+        // Entries may link to `old` in their content without linking it in their header
+        let mut ids = try!(store.ids().map_err_into(LEK::StoreReadError))
+            .map(|id| id.storified(store))
+            .filter(|id| *id != old && !report.rewritten_links.contains(id))
+            .collect::<Vec<StoreId>>();
+        ids.sort();
+
+        for id in ids {
+            let mut fle = match try!(store.get(id.clone()).map_err_into(LEK::StoreReadError)) {
+                Some(fle) => fle,
+                None => continue,
+            };
+
+            let (content, n) = rewrite_content_links(store, fle.get_content(), &old, &new);
+            if n > 0 {
+                *fle.get_content_mut() = content;
+                report.rewritten_content.push((id, n));
+                tx.update(fle);
+            }
+        }
+    }
+
+    tx.move_by_id(old, new);
+    tx.commit().map_err_into(LEK::StoreWriteError).map(|_| report)
+}
+
+/// Rewrite markdown links to `old` in `content`, both in absolute and store-relative form
+fn rewrite_content_links(store: &Store, content: &str, old: &StoreId, new: &StoreId)
+    -> (String, usize)
+{
+    use libimagentrymarkdown::link::rewrite_links;
+
+    let forms = |id: &StoreId| {
+        let absolute = id.to_str().map(String::from);
+        let relative = id.strip_prefix(store.path())
+            .ok()
+            .and_then(|p| p.to_str())
+            .map(String::from);
+        (absolute, relative)
+    };
+
+    let mut content = String::from(content);
+    let mut count   = 0;
+    match (forms(old), forms(new)) {
+        ((Some(old_abs), Some(old_rel)), (Some(new_abs), Some(new_rel))) => {
+            for &(ref from, ref to) in [(old_abs, new_abs), (old_rel, new_rel)].iter() {
+                let (c, n) = rewrite_links(&content, from, to);
+                content = c;
+                count  += n;
+            }
+        },
+        _ => warn!("Cannot rewrite content links for {:?}, path is not valid UTF-8", old),
+    }
+
+    (content, count)
+}
+
 fn links_into_values(links: Vec<StoreId>) -> Vec<Option<Value>> {
     links
         .into_iter()
@@ -105,7 +233,8 @@ fn links_into_values(links: Vec<StoreId>) -> Vec<Option<Value>> {
         })
 }
 
-fn rewrite_links(header: &mut EntryHeader, links: Vec<StoreId>) -> Result<()> {
+/// Replace the links in `header` with `links`
+pub fn rewrite_links(header: &mut EntryHeader, links: Vec<StoreId>) -> Result<()> {
     let links = links_into_values(links);
 
     if links.iter().any(|o| o.is_none()) {
@@ -172,3 +301,56 @@ fn process_rw_result(links: StoreResult<Option<Value>>) -> Result<Vec<Link>> {
     Ok(links)
 }
 
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use libimagstore::backend::InMemoryBackend;
+    use libimagstore::store::Store;
+    use libimagstore::storeid::StoreId;
+
+    use super::{InternalLinker, move_entry};
+
+    fn id(s: &str) -> StoreId {
+        StoreId::from(PathBuf::from(s))
+    }
+
+    #[test]
+    fn test_move_entry_rewrites_backlinks() {
+        let store = Store::new(PathBuf::from("/store"), None, Box::new(InMemoryBackend::new()))
+            .unwrap();
+        {
+            let mut a = store.retrieve(PathBuf::from("notes/a")).unwrap();
+            let mut b = store.retrieve(PathBuf::from("notes/b")).unwrap();
+            *b.get_content_mut() = String::from("See [a](notes/a)");
+            a.add_internal_link(&mut b).unwrap();
+        }
+        store.retrieve(PathBuf::from("notes/c")).unwrap();
+        {
+            let mut d = store.retrieve(PathBuf::from("notes/d")).unwrap();
+            *d.get_content_mut() = String::from("Also see [a](/store/notes/a)");
+        }
+
+        // The target exists, so nothing is moved and nothing is rewritten
+        assert!(move_entry(&store, id("notes/a"), id("notes/c"), true).is_err());
+        let b = store.retrieve_copy(PathBuf::from("notes/b")).unwrap();
+        assert_eq!(b.get_internal_links().unwrap(), vec![id("/store/notes/a")]);
+        assert_eq!(b.get_content(), "See [a](notes/a)");
+
+        let report = move_entry(&store, id("notes/a"), id("notes/moved"), true).unwrap();
+        assert_eq!(report.rewritten_links, vec![id("/store/notes/b")]);
+        assert_eq!(report.rewritten_content, vec![(id("/store/notes/b"), 1),
+                                                  (id("/store/notes/d"), 1)]);
+
+        assert!(store.get(PathBuf::from("notes/a")).unwrap().is_none());
+        let moved = store.retrieve_copy(PathBuf::from("notes/moved")).unwrap();
+        assert_eq!(moved.get_internal_links().unwrap(), vec![id("/store/notes/b")]);
+
+        let b = store.retrieve_copy(PathBuf::from("notes/b")).unwrap();
+        assert_eq!(b.get_internal_links().unwrap(), vec![id("/store/notes/moved")]);
+        assert_eq!(b.get_content(), "See [a](notes/moved)");
+        let d = store.retrieve_copy(PathBuf::from("notes/d")).unwrap();
+        assert_eq!(d.get_content(), "Also see [a](/store/notes/moved)");
+    }
+
+}
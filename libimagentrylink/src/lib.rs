@@ -22,11 +22,13 @@ extern crate crypto;
 #[macro_use] extern crate libimagstore;
 #[macro_use] extern crate libimagerror;
 #[macro_use] extern crate libimagutil;
+extern crate libimagentrymarkdown;
 
 module_entry_path_mod!("links", "0.2.0");
 
 pub mod error;
 pub mod external;
+pub mod gc;
 pub mod internal;
 pub mod result;
 
@@ -80,9 +80,23 @@ pub fn extract_links(buf: &str) -> Vec<Link> {
     le.links()
 }
 
+/// Let all links in `buf` which point to `from` point to `to` instead
+///
+/// Returns the new text and the number of links which were rewritten.
+pub fn rewrite_links(buf: &str, from: &str, to: &str) -> (String, usize) {
+    if !extract_links(buf).iter().any(|l| l.link == from) {
+        return (String::from(buf), 0);
+    }
+
+    let pattern     = format!("]({})", from);
+    let replacement = format!("]({})", to);
+    let n = buf.matches(&pattern[..]).count();
+    (buf.replace(&pattern[..], &replacement[..]), n)
+}
+
 #[cfg(test)]
 mod test {
-    use super::{Link, extract_links};
+    use super::{Link, extract_links, rewrite_links};
 
     #[test]
     fn test_one_link() {
@@ -139,5 +153,18 @@ Some more [foo](http://example.com/foo).
         assert_eq!(exp1, links.pop().unwrap());
     }
 
+    #[test]
+    fn test_rewrite_links() {
+        let testtext = "Some [entry](notes/a), [another](notes/ab) and [again](notes/a).";
+
+        let (text, n) = rewrite_links(testtext, "notes/a", "notes/b");
+        assert_eq!(2, n);
+        assert_eq!("Some [entry](notes/b), [another](notes/ab) and [again](notes/b).", text);
+
+        let (text, n) = rewrite_links(testtext, "notes/c", "notes/b");
+        assert_eq!(0, n);
+        assert_eq!(testtext, text);
+    }
+
 }
 
@@ -3,6 +3,7 @@ generate_error_module!(
         TagTypeError     => "Entry Header Tag Type wrong",
         HeaderReadError  => "Error while reading entry header",
         HeaderWriteError => "Error while writing entry header",
+        StoreReadError   => "Error while reading from the store",
         NotATag          => "String is not a tag"
     );
 );
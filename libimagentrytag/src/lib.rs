@@ -24,6 +24,7 @@ extern crate libimagstore;
 
 pub mod error;
 pub mod exec;
+pub mod lookup;
 pub mod result;
 pub mod tag;
 pub mod tagable;
@@ -0,0 +1,21 @@
+use toml::Value;
+
+use libimagstore::store::Store;
+use libimagstore::storeid::StoreIdIterator;
+use libimagerror::into::IntoError;
+
+use error::TagErrorKind;
+use result::Result;
+use tag::TagSlice;
+
+/// Get the entries (below the module `module`, if given) which are tagged with `t`
+///
+/// If the store indexes `imag.tags`, the index is used, otherwise the headers of all entries are
+/// read, see `Store::find_by_header()`.
+pub fn entries_with_tag(store: &Store, module: Option<&str>, t: TagSlice)
+    -> Result<StoreIdIterator>
+{
+    store.find_by_header(module, "imag.tags", &Value::String(String::from(t)))
+        .map_err(Box::new)
+        .map_err(|e| TagErrorKind::StoreReadError.into_error_with_cause(e))
+}
@@ -23,32 +23,14 @@ impl<'a> VersionsViewer<'a> {
 impl<'a> Viewer for VersionsViewer<'a> {
 
     fn view_entry(&self, entr: &Entry) -> Result<()> {
-        use glob::glob;
-
-        entr.get_location()
-            .clone()
-            .storified(self.store)
-            .to_str()
-            .and_then(|s| s.split("~").next())
-            .map(|component| {
-                glob(&format!("{}~*", component)[..])
-                    .map_err(|_| VEK::PatternError.into_error())
-                    .and_then(|paths| {
-                        for entry in paths {
-                            let p = match entry {
-                                Err(_) => return Err(VEK::GlobError.into_error()),
-                                Ok(p) => p,
-                            };
-                            let p = p.file_name()
-                                .and_then(|s| s.to_str())
-                                .unwrap(); // TODO
-                            println!("{}", p);
-                        };
-                        Ok(())
-                    })
+        self.store
+            .revisions(entr.get_location().clone())
+            .map_err(|e| VEK::StoreError.into_error_with_cause(Box::new(e)))
+            .map(|revisions| {
+                for rev in revisions {
+                    println!("{}", rev);
+                }
             })
-            .unwrap_or(Err(VEK::PatternBuildingError.into_error()))
     }
 
 }
-
@@ -15,7 +15,6 @@
 )]
 
 #[macro_use] extern crate log;
-extern crate glob;
 extern crate toml;
 
 extern crate libimagstore;
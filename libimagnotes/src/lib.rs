@@ -16,7 +16,7 @@
 
 #[macro_use] extern crate log;
 extern crate semver;
-extern crate toml;
+#[macro_use] extern crate serde_derive;
 
 extern crate libimagrt;
 #[macro_use] extern crate libimagstore;
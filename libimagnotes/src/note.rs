@@ -1,15 +1,14 @@
-use std::collections::BTreeMap;
 use std::ops::Deref;
 
-use toml::Value;
-
 use libimagrt::runtime::Runtime;
 use libimagrt::edit::{Edit, EditResult};
 use libimagstore::storeid::IntoStoreId;
 use libimagstore::storeid::StoreId;
 use libimagstore::storeid::StoreIdIterator;
-use libimagstore::store::FileLockEntry;
+use libimagstore::store::{EntryHeader, FileLockEntry};
+use libimagstore::stream::HeaderIter;
 use libimagstore::store::Store;
+use libimagstore::schema::{HeaderSchema, FieldType};
 use libimagentrytag::tag::{Tag, TagSlice};
 use libimagentrytag::tagable::Tagable;
 use libimagentrytag::result::Result as TagResult;
@@ -19,6 +18,12 @@ use result::Result;
 use error::NoteError as NE;
 use error::NoteErrorKind as NEK;
 
+/// The `note` section of the header of a note
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoteHeader {
+    pub name: String,
+}
+
 #[derive(Debug)]
 pub struct Note<'a> {
     entry: FileLockEntry<'a>,
@@ -27,55 +32,46 @@ pub struct Note<'a> {
 impl<'a> Note<'a> {
 
     pub fn new(store: &Store, name: String, text: String) -> Result<Note> {
-        use std::ops::DerefMut;
-
         debug!("Creating new Note: '{}'", name);
-        let fle = {
-            let lockentry = store.create(ModuleEntryPath::new(name.clone()).into_storeid());
-            if lockentry.is_err() {
-                return Err(NE::new(NEK::StoreWriteError, Some(Box::new(lockentry.unwrap_err()))));
-            }
-            let mut lockentry = lockentry.unwrap();
-
-            {
-                let mut entry  = lockentry.deref_mut();
-
-                {
-                    let mut header = entry.get_header_mut();
-                    let setres = header.set("note", Value::Table(BTreeMap::new()));
-                    if setres.is_err() {
-                        let kind = NEK::StoreWriteError;
-                        return Err(NE::new(kind, Some(Box::new(setres.unwrap_err()))));
-                    }
-
-                    let setres = header.set("note.name", Value::String(name));
-                    if setres.is_err() {
-                        let kind = NEK::StoreWriteError;
-                        return Err(NE::new(kind, Some(Box::new(setres.unwrap_err()))));
-                    }
-                }
-
-                *entry.get_content_mut() = text;
-            }
-
-            lockentry
-        };
+        try!(store.register_schema(Note::schema())
+             .map_err(|e| NE::new(NEK::StoreWriteError, Some(Box::new(e)))));
+
+        let mut fle = try!(store.create(ModuleEntryPath::new(name.clone()).into_storeid())
+             .map_err(|e| NE::new(NEK::StoreWriteError, Some(Box::new(e)))));
+
+        try!(fle.get_header_mut()
+             .write_section("note", &NoteHeader { name: name })
+             .map_err(|e| NE::new(NEK::StoreWriteError, Some(Box::new(e)))));
+        *fle.get_content_mut() = text;
 
         Ok(Note { entry: fle })
     }
 
+    /// The schema of the `note` header namespace
+    pub fn schema() -> HeaderSchema {
+        HeaderSchema::new("note").require("name", FieldType::String)
+    }
+
     pub fn set_name(&mut self, n: String) -> Result<()> {
-        let mut header = self.entry.get_header_mut();
-        header.set("note.name", Value::String(n))
+        self.entry
+            .get_header_mut()
+            .write_section("note", &NoteHeader { name: n })
             .map_err(|e| NE::new(NEK::StoreWriteError, Some(Box::new(e))))
-            .map(|_| ())
     }
 
     pub fn get_name(&self) -> Result<String> {
-        let header = self.entry.get_header();
-        match header.read("note.name") {
-            Ok(Some(Value::String(s))) => Ok(String::from(s)),
-            Ok(_)                => {
+        self.header().map(|h| h.name)
+    }
+
+    /// Read the `note` section of the header
+    pub fn header(&self) -> Result<NoteHeader> {
+        Note::parse_header(self.entry.get_header())
+    }
+
+    fn parse_header(header: &EntryHeader) -> Result<NoteHeader> {
+        match header.read_section("note") {
+            Ok(Some(h)) => Ok(h),
+            Ok(None)    => {
                 let e = NE::new(NEK::HeaderTypeError, None);
                 Err(NE::new(NEK::StoreReadError, Some(Box::new(e))))
             },
@@ -118,6 +114,13 @@ impl<'a> Note<'a> {
             .map_err(|e| NE::new(NEK::StoreReadError, Some(Box::new(e))))
     }
 
+    /// Iterate over the headers of all notes, without reading the notes themselves
+    pub fn all_note_headers(store: &Store) -> Result<NoteHeaderIterator> {
+        store.select("notes")
+            .map(|selection| NoteHeaderIterator(selection.headers()))
+            .map_err(|e| NE::new(NEK::StoreReadError, Some(Box::new(e))))
+    }
+
 }
 
 impl<'a> Edit for Note<'a> {
@@ -210,3 +213,16 @@ impl<'a> Iterator for NoteIterator<'a> {
 
 }
 
+pub struct NoteHeaderIterator<'a>(HeaderIter<'a>);
+
+impl<'a> Iterator for NoteHeaderIterator<'a> {
+    type Item = Result<NoteHeader>;
+
+    fn next(&mut self) -> Option<Result<NoteHeader>> {
+        self.0.next().map(|r| {
+            r.map_err(|e| NE::new(NEK::StoreReadError, Some(Box::new(e))))
+                .and_then(|(_, header)| Note::parse_header(&header))
+        })
+    }
+
+}
@@ -2,6 +2,7 @@
 extern crate crypto;
 extern crate itertools;
 extern crate semver;
+#[macro_use] extern crate serde_derive;
 extern crate toml;
 extern crate version;
 extern crate walkdir;
@@ -2,8 +2,6 @@ use std::default::Default;
 use std::io::stdout;
 use std::io::Write;
 
-use toml::Value;
-
 use libimagentrylist::lister::Lister;
 use libimagentrylist::result::Result;
 use libimagerror::trace::trace_error;
@@ -13,7 +11,7 @@ use libimagerror::into::IntoError;
 use libimagentrylist::error::ListError as LE;
 use libimagentrylist::error::ListErrorKind as LEK;
 
-use reference::Ref;
+use reference::{Ref, RefHeader};
 use error::MapErrInto;
 use error::RefErrorKind as REK;
 
@@ -65,15 +63,15 @@ impl Default for RefLister {
 }
 
 fn list_fn(e: &Entry) -> String {
-    let stored_hash = match e.get_header().read("ref.content_hash") {
-        Ok(Some(Value::String(s))) => s.clone(),
-        _                          => String::from("<Error: Could not read stored hash>"),
-    };
-
-    let filepath = match e.get_header().read("ref.path") {
-        Ok(Some(Value::String(ref s))) => s.clone(),
-        _ => String::from("<Error: Could not read file path>"),
-    };
+    let header : Option<RefHeader> = e.get_header().read_section("ref").ok().and_then(|h| h);
+
+    let stored_hash = header.as_ref()
+        .and_then(|h| h.content_hash.clone())
+        .unwrap_or_else(|| String::from("<Error: Could not read stored hash>"));
+
+    let filepath = header
+        .map(|h| h.path)
+        .unwrap_or_else(|| String::from("<Error: Could not read file path>"));
 
     format!("Ref({} -> {})", stored_hash, filepath)
 }
@@ -4,7 +4,6 @@
 use std::path::PathBuf;
 use std::ops::Deref;
 use std::ops::DerefMut;
-use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::fmt::{Display, Error as FmtError, Formatter};
@@ -15,6 +14,7 @@ use libimagstore::store::FileLockEntry;
 use libimagstore::storeid::StoreId;
 use libimagstore::storeid::IntoStoreId;
 use libimagstore::store::Store;
+use libimagstore::schema::{HeaderSchema, FieldType};
 use libimagerror::into::IntoError;
 
 use toml::Value;
@@ -26,6 +26,24 @@ use flags::RefFlags;
 use result::Result;
 use module_path::ModuleEntryPath;
 
+/// The `ref` section of the header of a ref
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefHeader {
+    /// The canonicalized path of the referenced file
+    pub path: String,
+
+    /// The hash of the contents of the referenced file, if content hashing was enabled
+    pub content_hash: Option<String>,
+
+    /// The permissions of the referenced file, if permission tracking was enabled
+    pub permissions: Option<RefPermissions>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefPermissions {
+    pub ro: bool,
+}
+
 #[derive(Debug)]
 pub struct Ref<'a>(FileLockEntry<'a>);
 
@@ -56,6 +74,20 @@ impl<'a> Ref<'a> {
             .map_err(|e| REK::StoreReadError.into_error_with_cause(e))
     }
 
+    /// Get all Refs whose target has the content hash `hash`
+    ///
+    /// If the store indexes `ref.content_hash` (see `libimagstore::index`), the index is used,
+    /// otherwise the headers of all refs are read, see `Store::find_by_header()`.
+    pub fn get_by_content_hash(store: &'a Store, hash: String) -> Result<Vec<Ref<'a>>> {
+        store
+            .find_by_header(Some("ref"), "ref.content_hash", &Value::String(hash))
+            .map_err(Box::new)
+            .map_err(|e| REK::StoreReadError.into_error_with_cause(e))
+            .and_then(|ids| {
+                ids.map(|id| Ref::get(store, id)).collect()
+            })
+    }
+
     /// Delete a ref by hash
     ///
     /// If the returned Result contains an error, the ref might not be deleted.
@@ -67,16 +99,40 @@ impl<'a> Ref<'a> {
     }
 
     fn read_reference(fle: &FileLockEntry<'a>) -> Result<PathBuf> {
-        match fle.get_header().read("ref.path") {
-            Ok(Some(Value::String(s))) => Ok(PathBuf::from(s)),
-            Ok(Some(_)) => Err(REK::HeaderTypeError.into_error()),
+        Ref::read_header(fle).map(|h| PathBuf::from(h.path))
+    }
+
+    fn read_header(fle: &FileLockEntry<'a>) -> Result<RefHeader> {
+        match fle.get_header().read_section("ref") {
+            Ok(Some(h)) => Ok(h),
             Ok(None)    => Err(REK::HeaderFieldMissingError.into_error()),
-            Err(e)      => Err(REK::StoreReadError.into_error_with_cause(Box::new(e))),
+            Err(e)      => Err(REK::HeaderTypeError.into_error_with_cause(Box::new(e))),
         }
     }
 
+    fn write_header(&mut self, header: &RefHeader) -> Result<()> {
+        self.0
+            .get_header_mut()
+            .write_section("ref", header)
+            .map_err(Box::new)
+            .map_err(|e| REK::HeaderFieldWriteError.into_error_with_cause(e))
+    }
+
+    /// The schema of the `ref` header namespace
+    pub fn schema() -> HeaderSchema {
+        HeaderSchema::new("ref")
+            .require("path", FieldType::String)
+            .optional("content_hash", FieldType::String)
+            .optional("permissions", FieldType::Table)
+            .optional("permissions.ro", FieldType::Boolean)
+    }
+
     /// Create a Ref object which refers to `pb`
     pub fn create(store: &'a Store, pb: PathBuf, flags: RefFlags) -> Result<Ref<'a>> {
+        try!(store.register_schema(Ref::schema())
+             .map_err(Box::new)
+             .map_err(|e| REK::StoreWriteError.into_error_with_cause(e)));
+
         if !pb.exists() {
             return Err(REK::RefTargetDoesNotExist.into_error());
         }
@@ -84,7 +140,7 @@ impl<'a> Ref<'a> {
             return Err(REK::RefTargetCannotBeHashed.into_error());
         }
 
-        let (mut fle, content_hash, permissions, canonical_path) = { // scope to be able to fold
+        let (fle, content_hash, permissions, canonical_path) = { // scope to be able to fold
             try!(File::open(pb.clone())
                 .map_err(Box::new)
                 .map_err(|e| REK::RefTargetFileCannotBeOpened.into_error_with_cause(e))
@@ -165,40 +221,15 @@ impl<'a> Ref<'a> {
             )
         };
 
-        for tpl in [
-                Some(("ref",             Value::Table(BTreeMap::new()))),
-                Some(("ref.permissions", Value::Table(BTreeMap::new()))),
-
-                Some(("ref.path", Value::String(canonical_path))),
-
-                content_hash.map(|h| ("ref.content_hash", Value::String(h))),
-                permissions.map(|p| ("ref.permissions.ro", Value::Boolean(p.readonly()))),
-            ].into_iter()
-        {
-            match tpl {
-                &Some((ref s, ref v)) => {
-                    match fle.get_header_mut().insert(s, v.clone()) {
-                        Ok(false) => {
-                            let e = REK::HeaderFieldAlreadyExistsError.into_error();
-                            let e = Box::new(e);
-                            let e = REK::HeaderFieldWriteError.into_error_with_cause(e);
-                            return Err(e);
-                        },
-                        Err(e) => {
-                            let e = Box::new(e);
-                            let e = REK::HeaderFieldWriteError.into_error_with_cause(e);
-                            return Err(e);
-                        },
-                        _ => (),
-                    }
-                }
-                &None => {
-                    debug!("Not going to insert.");
-                }
-            }
-        }
+        let header = RefHeader {
+            path: canonical_path,
+            content_hash: content_hash,
+            permissions: permissions.map(|p| RefPermissions { ro: p.readonly() }),
+        };
 
-        Ok(Ref(fle))
+        let mut r = Ref(fle);
+        try!(r.write_header(&header));
+        Ok(r)
     }
 
     /// Creates a Hash from a PathBuf by making the PathBuf absolute and then running a hash
@@ -231,19 +262,10 @@ impl<'a> Ref<'a> {
 
     /// Get the hash of the link target which is stored in the ref object
     pub fn get_stored_hash(&self) -> Result<String> {
-        match self.0.get_header().read("ref.content_hash") {
-            // content hash stored...
-            Ok(Some(Value::String(s))) => Ok(s),
-
-            // content hash header field has wrong type
-            Ok(Some(_)) => Err(REK::HeaderTypeError.into_error()),
-
-            // content hash not stored
-            Ok(None) => Err(REK::HeaderFieldMissingError.into_error()),
-
-            // Error
-            Err(e) => Err(REK::StoreReadError.into_error_with_cause(Box::new(e))),
-        }
+        self.header()
+            .and_then(|h| {
+                h.content_hash.ok_or_else(|| REK::HeaderFieldMissingError.into_error())
+            })
     }
 
     /// Get the hash of the link target by reading the link target and hashing the contents
@@ -310,17 +332,13 @@ impl<'a> Ref<'a> {
     /// Check whether the file permissions of the referenced file are equal to the stored
     /// permissions
     pub fn fs_link_valid_permissions(&self) -> Result<bool> {
-        self.0
-            .get_header()
-            .read("ref.permissions.ro")
+        self.header()
             .map_err(Box::new)
             .map_err(|e| REK::HeaderFieldReadError.into_error_with_cause(e))
-            .and_then(|ro| {
-                match ro {
-                    Some(Value::Boolean(b)) => Ok(b),
-                    Some(_)                 => Err(REK::HeaderTypeError.into_error()),
-                    None                    => Err(REK::HeaderFieldMissingError.into_error()),
-                }
+            .and_then(|h| {
+                h.permissions
+                    .map(|p| p.ro)
+                    .ok_or_else(|| REK::HeaderFieldMissingError.into_error())
             })
             .and_then(|ro| self.get_current_permissions().map(|perm| ro == perm.readonly()))
             .map_err(Box::new)
@@ -340,31 +358,22 @@ impl<'a> Ref<'a> {
         let current_hash = try!(self.get_current_hash());
         let current_perm = try!(self.get_current_permissions());
 
-        try!(self.0
-            .get_header_mut()
-            .set("ref.permissions.ro", Value::Boolean(current_perm.readonly()))
+        let mut header = try!(self.header());
+        header.content_hash = Some(current_hash);
+        header.permissions  = Some(RefPermissions { ro: current_perm.readonly() });
+        self.write_header(&header)
             .map_err(Box::new)
             .map_err(|e| REK::StoreWriteError.into_error_with_cause(e))
-        );
-
-        try!(self.0
-            .get_header_mut()
-            .set("ref.content_hash", Value::String(current_hash))
-            .map_err(Box::new)
-            .map_err(|e| REK::StoreWriteError.into_error_with_cause(e))
-        );
+    }
 
-        Ok(())
+    /// Get the `ref` section of the header
+    pub fn header(&self) -> Result<RefHeader> {
+        Ref::read_header(&self.0)
     }
 
     /// Get the path of the file which is reffered to by this Ref
     pub fn fs_file(&self) -> Result<PathBuf> {
-        match self.0.get_header().read("ref.path") {
-            Ok(Some(Value::String(ref s))) => Ok(PathBuf::from(s)),
-            Ok(Some(_)) => Err(REK::HeaderTypeError.into_error()),
-            Ok(None)    => Err(REK::HeaderFieldMissingError.into_error()),
-            Err(e)      => Err(REK::StoreReadError.into_error_with_cause(Box::new(e))),
-        }
+        self.header().map(|h| PathBuf::from(h.path))
     }
 
     /// Check whether there is a reference to the file at `pb`
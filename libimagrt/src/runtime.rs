@@ -38,11 +38,18 @@ impl<'a> Runtime<'a> {
      */
     pub fn new(cli_spec: App<'a, 'a>) -> Result<Runtime<'a>, RuntimeError> {
         use std::env;
+        use std::sync::{Arc, Mutex};
 
+        use libimagstore::backend::{StoreBackend, FileSystemBackend};
+        use libimagstore::encryption::{EncryptionBackend, is_encryption_enabled};
+        use libimagstore::object::{ObjectBackend, is_object_layer_enabled};
         use libimagstore::hook::position::HookPosition as HP;
         use libimagstore::hook::Hook;
+        use libimagstore::search::is_search_index_enabled;
         use libimagstore::error::StoreErrorKind;
         use libimagstorestdhook::debug::DebugHook;
+        use libimagstorestdhook::git::GitHook;
+        use libimagstorestdhook::search::SearchIndexHook;
         use libimagerror::trace::trace_error;
         use libimagerror::trace::trace_error_dbg;
         use libimagerror::into::IntoError;
@@ -53,6 +60,7 @@ impl<'a> Runtime<'a> {
 
         let is_debugging = matches.is_present("debugging");
         let is_verbose   = matches.is_present("verbosity");
+        let is_read_only = matches.is_present("read-only");
 
         Runtime::init_logger(is_debugging, is_verbose);
 
@@ -101,7 +109,41 @@ impl<'a> Runtime<'a> {
             write!(stderr(), "Store-config: {:?}\n", store_config).ok();
         }
 
-        Store::new(storepath, store_config).map(|mut store| {
+        let backend : Box<StoreBackend> = if is_object_layer_enabled(&store_config) {
+            Box::new(ObjectBackend::new(storepath.clone(), Box::new(FileSystemBackend::new())))
+        } else {
+            Box::new(FileSystemBackend::new())
+        };
+
+        // Entries are encrypted before they are split into objects
+        let backend : Box<StoreBackend> = if is_encryption_enabled(&store_config) {
+            match EncryptionBackend::from_config(storepath.clone(), backend, &store_config) {
+                Ok(backend) => Box::new(backend),
+                Err(e) => {
+                    return Err(RuntimeErrorKind::Instantiate.into_error_with_cause(Box::new(e)));
+                },
+            }
+        } else {
+            backend
+        };
+
+        let store = if is_read_only {
+            Store::new_read_only(storepath, store_config, backend)
+        } else {
+            Store::new(storepath, store_config, backend)
+        };
+
+        store.map(|mut store| {
+            // Record the program name (eg. "imag-diary") in the change log of the store
+            let program = env::args()
+                .next()
+                .and_then(|a| {
+                    PathBuf::from(a).file_name().and_then(|n| n.to_str()).map(String::from)
+                });
+            if let Some(program) = program {
+                store.set_origin(program);
+            }
+
             // If we are debugging, generate hooks for all positions
             if is_debugging {
                 let hooks : Vec<(Box<Hook>, &str, HP)> = vec![
@@ -113,6 +155,8 @@ impl<'a> Runtime<'a> {
                     (Box::new(DebugHook::new(HP::PostUpdate))         , "debug", HP::PostUpdate),
                     (Box::new(DebugHook::new(HP::PreDelete))          , "debug", HP::PreDelete),
                     (Box::new(DebugHook::new(HP::PostDelete))         , "debug", HP::PostDelete),
+                    (Box::new(DebugHook::new(HP::PreMove))            , "debug", HP::PreMove),
+                    (Box::new(DebugHook::new(HP::PostMove))           , "debug", HP::PostMove),
                 ];
 
                 // If hook registration fails, trace the error and warn, but continue.
@@ -128,6 +172,38 @@ impl<'a> Runtime<'a> {
                 }
             }
 
+            // Nothing is changed in a read-only store, so there is nothing to index or commit
+            if !is_read_only {
+                // The full-text index is maintained by hooks in the "search" aspect, if it is
+                // enabled and the aspect is configured
+                if is_search_index_enabled(&store.config().cloned()) {
+                    let index = store.search_index();
+                    for position in vec![HP::PostCreate, HP::PostUpdate, HP::PostDelete,
+                                         HP::PostMove] {
+                        let hook = Box::new(SearchIndexHook::new(position.clone(),
+                                                                 index.clone()));
+                        if let Err(e) = store.register_hook(position, "search", hook) {
+                            debug!("Not maintaining the search index");
+                            trace_error_dbg(&e);
+                        }
+                    }
+                }
+
+                // Changes are committed to git by hooks in the "version-control" aspect, if the
+                // aspect is configured
+                let pending  = Arc::new(Mutex::new(vec![]));
+                let location = store.path().clone();
+                for position in vec![HP::PostCreate, HP::PostUpdate, HP::PostDelete,
+                                     HP::PostMove, HP::StoreUnload] {
+                    let hook = Box::new(GitHook::new(position.clone(), location.clone(),
+                                                     pending.clone()));
+                    if let Err(e) = store.register_hook(position, "version-control", hook) {
+                        debug!("Not committing changes to git");
+                        trace_error_dbg(&e);
+                    }
+                }
+            }
+
             Runtime {
                 cli_matches: matches,
                 configuration: cfg,
@@ -147,6 +223,7 @@ impl<'a> Runtime<'a> {
      *   * -c <file> | --config <file> for alternative configuration file
      *   * -r <path> | --rtp <path> for alternative runtimepath
      *   * --store <path> for alternative store path
+     *   * --read-only for opening the store read-only
      * Each has the appropriate help text included.
      *
      * The `appname` shall be "imag-<command>".
@@ -191,6 +268,12 @@ impl<'a> Runtime<'a> {
                 .required(false)
                 .takes_value(true))
 
+            .arg(Arg::with_name("read-only")
+                .long("read-only")
+                .help("Open the store read-only, operations which would write to it fail")
+                .required(false)
+                .takes_value(false))
+
             .arg(Arg::with_name("editor")
                 .long("editor")
                 .help("Set editor")
@@ -0,0 +1,463 @@
+//! Portable archives of store entries
+//!
+//! `Store::export()` writes the entries of some (or all) modules into a tar archive, which
+//! `Store::import()` merges into another store. The archive contains
+//!
+//! * `manifest.toml`, which lists the exported entries by their path inside the store, with the
+//!   `imag.version` of their header and the SHA-256 hash of their content
+//! * `entries/<path inside the store>`, the entries as they are in the store
+//! * `attachments/<hash>`, the attachments of the exported entries (see `attachment`)
+//!
+//! ```toml
+//! format = 1
+//! attachments = ["9f86d08..."]
+//!
+//! [[entries]]
+//! id = "notes/todo"
+//! version = "0.2.0"
+//! hash = "2c26b46..."
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fmt::Error as FmtError;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::result::Result as RResult;
+use std::str::FromStr;
+
+use tar::{Archive, Builder, Header};
+use toml::{Parser, Value};
+
+use attachment::{attachments_of, blob_path, content_hash};
+use backend::StoreBackend;
+use error::StoreErrorKind as SEK;
+use error::MapErrInto;
+use store::{Entry, Result, Store};
+use storeid::{StoreId, free_id};
+
+use libimagerror::into::IntoError;
+
+pub static MANIFEST_FILE      : &'static str = "manifest.toml";
+pub static ENTRIES_PREFIX     : &'static str = "entries";
+pub static ATTACHMENTS_PREFIX : &'static str = "attachments";
+
+/// The version of the archive layout
+static FORMAT : i64 = 1;
+
+/// An entry listed in the manifest of an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// The path of the entry inside the store
+    pub id: PathBuf,
+
+    /// The `imag.version` of the header of the entry
+    pub version: String,
+
+    /// The SHA-256 hash of the entry, in hex
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Manifest {
+    pub entries: Vec<ArchiveEntry>,
+
+    /// The hashes of the attachments in the archive
+    pub attachments: Vec<String>,
+}
+
+impl Manifest {
+
+    fn to_text(&self) -> String {
+        let entries = self.entries
+            .iter()
+            .map(|e| {
+                let mut t = BTreeMap::new();
+                t.insert(String::from("id"), Value::String(format!("{}", e.id.display())));
+                t.insert(String::from("version"), Value::String(e.version.clone()));
+                t.insert(String::from("hash"), Value::String(e.hash.clone()));
+                Value::Table(t)
+            })
+            .collect();
+        let attachments = self.attachments.iter().cloned().map(Value::String).collect();
+
+        let mut t = BTreeMap::new();
+        t.insert(String::from("format"), Value::Integer(FORMAT));
+        t.insert(String::from("entries"), Value::Array(entries));
+        t.insert(String::from("attachments"), Value::Array(attachments));
+        ::toml::encode_str(&Value::Table(t))
+    }
+
+    fn from_text(text: &str) -> Option<Manifest> {
+        let t = match Parser::new(text).parse() {
+            Some(t) => t,
+            None => return None,
+        };
+
+        match t.get("format") {
+            Some(&Value::Integer(f)) if f == FORMAT => {},
+            _ => return None,
+        }
+
+        let string = |v: &Value, key: &str| match *v {
+            Value::Table(ref t) => match t.get(key) {
+                Some(&Value::String(ref s)) => Some(s.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let entries = match t.get("entries") {
+            Some(&Value::Array(ref a)) => a.iter()
+                .map(|v| match (string(v, "id"), string(v, "version"), string(v, "hash")) {
+                    (Some(id), Some(version), Some(hash)) => Some(ArchiveEntry {
+                        id: PathBuf::from(id),
+                        version: version,
+                        hash: hash,
+                    }),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+
+        let attachments = match t.get("attachments") {
+            Some(&Value::Array(ref a)) => a.iter()
+                .map(|v| match *v {
+                    Value::String(ref s) => Some(s.clone()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+
+        match (entries, attachments) {
+            (Some(entries), Some(attachments)) => Some(Manifest {
+                entries: entries,
+                attachments: attachments,
+            }),
+            _ => None,
+        }
+    }
+
+}
+
+/// What to do when an imported entry exists in the store with a different content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictStrategy {
+    /// Keep the entry in the store
+    Skip,
+
+    /// Replace the entry in the store with the imported one
+    Overwrite,
+
+    /// Keep the entry in the store and import the entry with another id
+    KeepBoth,
+}
+
+impl FromStr for ConflictStrategy {
+    type Err = ();
+
+    fn from_str(s: &str) -> RResult<ConflictStrategy, ()> {
+        match s {
+            "skip"      => Ok(ConflictStrategy::Skip),
+            "overwrite" => Ok(ConflictStrategy::Overwrite),
+            "keep-both" => Ok(ConflictStrategy::KeepBoth),
+            _           => Err(()),
+        }
+    }
+
+}
+
+/// What happened to an entry of an archive on import
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    /// The entry did not exist in the store
+    Imported(StoreId),
+
+    /// The entry exists in the store with the same content
+    Unchanged(StoreId),
+
+    /// The entry exists in the store with another content and was left alone
+    Skipped(StoreId),
+
+    /// The entry exists in the store with another content and was replaced
+    Overwritten(StoreId),
+
+    /// The entry exists in the store with another content, so it was imported with the new id
+    Renamed(StoreId, StoreId),
+}
+
+impl Display for ImportOutcome {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        match *self {
+            ImportOutcome::Imported(ref id)      => write!(fmt, "imported    {}", id),
+            ImportOutcome::Unchanged(ref id)     => write!(fmt, "unchanged   {}", id),
+            ImportOutcome::Skipped(ref id)       => write!(fmt, "skipped     {}", id),
+            ImportOutcome::Overwritten(ref id)   => write!(fmt, "overwritten {}", id),
+            ImportOutcome::Renamed(ref id, ref new_id) => {
+                write!(fmt, "renamed     {} -> {}", id, new_id)
+            },
+        }
+    }
+
+}
+
+/// Check whether `id` is the path of an entry inside the store, and not of an internal file or
+/// of a file outside of the store
+pub fn is_entry_path(id: &Path) -> bool {
+    let starts_internal = id.components()
+        .next()
+        .and_then(|c| c.as_os_str().to_str())
+        .map(|c| c.starts_with('.'))
+        .unwrap_or(true);
+
+    !starts_internal && id.components().all(|c| match c {
+        Component::Normal(_) => true,
+        _ => false,
+    })
+}
+
+pub fn entry_file(id: &Path) -> String {
+    format!("{}/{}", ENTRIES_PREFIX, id.display())
+}
+
+pub fn attachment_file(hash: &str) -> String {
+    format!("{}/{}", ATTACHMENTS_PREFIX, hash)
+}
+
+/// Write an archive with `manifest` and `files` (by their path inside the archive) to `out`
+pub fn write<W: Write>(out: W, manifest: &Manifest, files: &BTreeMap<String, Vec<u8>>)
+    -> Result<()>
+{
+    let mut builder = Builder::new(out);
+    let manifest    = manifest.to_text();
+    let all = Some((String::from(MANIFEST_FILE), manifest.as_bytes()))
+        .into_iter()
+        .chain(files.iter().map(|(path, data)| (path.clone(), &data[..])));
+
+    for (path, data) in all {
+        let mut header = Header::new_gnu();
+        try!(header.set_path(&path).map_err_into(SEK::ArchiveError));
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        try!(builder.append(&header, data).map_err_into(SEK::ArchiveError));
+    }
+
+    builder.finish().map_err_into(SEK::ArchiveError)
+}
+
+/// Read an archive from `input`
+///
+/// Returns the manifest and all other files by their path inside the archive. Fails with
+/// `ArchiveCorrupted` if there is no manifest or it cannot be parsed.
+pub fn read<R: Read>(input: R) -> Result<(Manifest, BTreeMap<String, Vec<u8>>)> {
+    let mut archive  = Archive::new(input);
+    let mut files    = BTreeMap::new();
+    let mut manifest = None;
+
+    for file in try!(archive.entries().map_err_into(SEK::ArchiveError)) {
+        let mut file = try!(file.map_err_into(SEK::ArchiveError));
+        let path = try!(file.path()
+                        .map(|p| format!("{}", p.display()))
+                        .map_err_into(SEK::ArchiveError));
+
+        let mut data = vec![];
+        try!(file.read_to_end(&mut data).map_err_into(SEK::ArchiveError));
+
+        if path == MANIFEST_FILE {
+            let text = try!(String::from_utf8(data).map_err_into(SEK::ArchiveCorrupted));
+            manifest = Some(try!(Manifest::from_text(&text)
+                                 .ok_or(SEK::ArchiveCorrupted.into_error())));
+        } else {
+            files.insert(path, data);
+        }
+    }
+
+    manifest
+        .ok_or(SEK::ArchiveCorrupted.into_error())
+        .map(|manifest| (manifest, files))
+}
+
+/// An archive which was read and checked against its manifest, see `check()`
+pub struct Import {
+    /// The entries, with the hash of their content in the archive
+    entries: Vec<(StoreId, String, Entry)>,
+
+    /// The attachments, by the path they are kept at in the store
+    blobs: Vec<(PathBuf, Vec<u8>)>,
+}
+
+/// Write `entries` (by their path inside the store at `location`) and their attachments to `out`
+///
+/// `read` reads the raw content of an entry. Returns the manifest of the archive.
+pub fn export<W, F>(backend: &StoreBackend, location: &Path, entries: BTreeMap<PathBuf, StoreId>,
+                    read: F, out: W) -> Result<Manifest>
+    where W: Write,
+          F: Fn(&StoreId) -> Result<Vec<u8>>
+{
+    let mut manifest = Manifest::default();
+    let mut files    = BTreeMap::new();
+    for (rel, id) in entries {
+        let data  = try!(read(&id));
+        let entry = try!(String::from_utf8(data.clone())
+                         .map_err_into(SEK::EncodingError)
+                         .and_then(|text| Entry::from_str(id.clone(), &text[..])));
+
+        let version = match try!(entry.get_header().read("imag.version")) {
+            Some(Value::String(v)) => v,
+            _ => return Err(SEK::HeaderTypeFailure.into_error()),
+        };
+
+        for attachment in try!(attachments_of(entry.get_header())) {
+            let path = attachment_file(&attachment.hash);
+            if files.contains_key(&path) {
+                continue;
+            }
+            let blob = try!(backend
+                            .read(&blob_path(location, &attachment.hash))
+                            .map_err_into(SEK::AttachmentNotFound));
+            files.insert(path, blob);
+            manifest.attachments.push(attachment.hash);
+        }
+
+        manifest.entries.push(ArchiveEntry {
+            id: rel.clone(),
+            version: version,
+            hash: content_hash(&data),
+        });
+        files.insert(entry_file(&rel), data);
+    }
+
+    write(out, &manifest, &files).map(|_| manifest)
+}
+
+/// Read the archive from `input` for importing it into the store at `location`
+///
+/// Fails with `ArchiveCorrupted` if a file of the archive is missing or does not match its hash
+/// in the manifest, so nothing is imported from a corrupted archive.
+pub fn check<R: Read>(location: &Path, input: R) -> Result<Import> {
+    let (manifest, mut files) = try!(read(input));
+
+    let mut entries = vec![];
+    for archived in manifest.entries.iter() {
+        let data = try!(files.remove(&entry_file(&archived.id))
+                        .ok_or(SEK::ArchiveCorrupted.into_error()));
+        if content_hash(&data) != archived.hash || !is_entry_path(&archived.id) {
+            return Err(SEK::ArchiveCorrupted.into_error());
+        }
+
+        let mut path = location.to_path_buf();
+        path.push(&archived.id);
+        let id    = StoreId::from(path);
+        let entry = try!(String::from_utf8(data)
+                         .map_err_into(SEK::EncodingError)
+                         .and_then(|text| Entry::from_str(id.clone(), &text[..])));
+        entries.push((id, archived.hash.clone(), entry));
+    }
+
+    let mut blobs = vec![];
+    for hash in manifest.attachments.iter() {
+        let data = try!(files.remove(&attachment_file(hash))
+                        .ok_or(SEK::ArchiveCorrupted.into_error()));
+        if content_hash(&data) != *hash {
+            return Err(SEK::ArchiveCorrupted.into_error());
+        }
+        blobs.push((blob_path(location, hash), data));
+    }
+
+    Ok(Import { entries: entries, blobs: blobs })
+}
+
+/// Merge `import` into `store`
+///
+/// Entries which exist in the store with another content are handled as told by `strategy`. The
+/// entries are written through `store`, so the hooks run for them. `read` reads the raw content
+/// of an entry.
+pub fn merge<F>(store: &Store, backend: &StoreBackend, import: Import, strategy: ConflictStrategy,
+                read: F) -> Result<Vec<ImportOutcome>>
+    where F: Fn(&StoreId) -> Result<Vec<u8>>
+{
+    for (path, data) in import.blobs {
+        if !backend.is_file(&path) {
+            try!(backend.write(&path, &data).map_err_into(SEK::AttachmentError));
+        }
+    }
+
+    let mut outcomes = vec![];
+    for (id, hash, entry) in import.entries {
+        if !backend.is_file(&id) {
+            try!(create_from(store, &id, &entry));
+            outcomes.push(ImportOutcome::Imported(id));
+            continue;
+        }
+
+        if content_hash(&try!(read(&id))) == hash {
+            outcomes.push(ImportOutcome::Unchanged(id));
+            continue;
+        }
+
+        let outcome = match strategy {
+            ConflictStrategy::Skip => ImportOutcome::Skipped(id),
+            ConflictStrategy::Overwrite => {
+                let mut fle = try!(store.retrieve(id.clone()));
+                *fle.get_header_mut()  = entry.get_header().clone();
+                *fle.get_content_mut() = entry.get_content().clone();
+                try!(store.update(fle));
+                ImportOutcome::Overwritten(id)
+            },
+            ConflictStrategy::KeepBoth => {
+                let new_id = free_id(backend, &id);
+                try!(create_from(store, &new_id, &entry));
+                ImportOutcome::Renamed(id, new_id)
+            },
+        };
+        outcomes.push(outcome);
+    }
+
+    Ok(outcomes)
+}
+
+/// Create the entry `id` in `store` with the header and content of `entry`
+fn create_from(store: &Store, id: &StoreId, entry: &Entry) -> Result<()> {
+    let mut fle = try!(store.create(id.clone()));
+    *fle.get_header_mut()  = entry.get_header().clone();
+    *fle.get_content_mut() = entry.get_content().clone();
+    store.update(fle)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use super::{ArchiveEntry, Manifest, read, write, is_entry_path};
+
+    #[test]
+    fn test_write_read() {
+        let manifest = Manifest {
+            entries: vec![ArchiveEntry {
+                id: PathBuf::from("notes/todo"),
+                version: String::from("0.2.0"),
+                hash: String::from("abc"),
+            }],
+            attachments: vec![String::from("def")],
+        };
+
+        let mut files = BTreeMap::new();
+        files.insert(String::from("entries/notes/todo"), b"---\n---\ntodo".to_vec());
+        files.insert(String::from("attachments/def"), vec![0, 1, 2]);
+
+        let mut archive = vec![];
+        write(&mut archive, &manifest, &files).unwrap();
+        assert_eq!(read(&archive[..]).unwrap(), (manifest, files));
+
+        assert!(is_entry_path(&PathBuf::from("notes/todo")));
+        assert!(!is_entry_path(&PathBuf::from(".objects/ab/cdef")));
+        assert!(!is_entry_path(&PathBuf::from("notes/../../etc/passwd")));
+        assert!(!is_entry_path(&PathBuf::from("/etc/passwd")));
+    }
+
+}
@@ -0,0 +1,293 @@
+//! Binary attachments of entries
+//!
+//! The content of an entry is text, so binary data (PDFs, images, audio, ...) is kept as an
+//! attachment instead. Attachments are stored in `<store>/.attachments`, named by the SHA-256 hash
+//! of their content, so an attachment which is added to several entries is stored only once. The
+//! header of an entry lists its attachments in `imag.attachments`:
+//!
+//! ```toml
+//! [[imag.attachments]]
+//! name = "scan.pdf"
+//! mime = "application/pdf"
+//! size = 48213
+//! hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//! ```
+//!
+//! Removing an attachment from an entry does not remove the stored data, as other entries may
+//! still reference it. `Store::gc_attachments()` removes the data no entry references anymore.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use toml::Value;
+
+use backend::StoreBackend;
+use error::StoreErrorKind as SEK;
+use error::MapErrInto;
+use store::{EntryHeader, Result};
+
+use libimagerror::into::IntoError;
+
+/// The name of the directory (inside the store) where attachments are kept
+pub static ATTACHMENTS_DIR : &'static str = ".attachments";
+
+/// The header path where the attachments of an entry are listed
+pub static ATTACHMENTS_HEADER_PATH : &'static str = "imag.attachments";
+
+/// The record of an attachment in the header of an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// The name of the attachment, unique per entry
+    pub name: String,
+
+    /// The mime type, as passed when adding the attachment
+    pub mime: String,
+
+    /// The size in bytes
+    pub size: u64,
+
+    /// The SHA-256 hash of the content, in hex
+    pub hash: String,
+}
+
+impl Attachment {
+
+    /// Create the record of an attachment with the content `data`
+    pub fn new(name: String, mime: String, data: &[u8]) -> Attachment {
+        Attachment {
+            name: name,
+            mime: mime,
+            size: data.len() as u64,
+            hash: content_hash(data),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut t = BTreeMap::new();
+        t.insert(String::from("name"), Value::String(self.name.clone()));
+        t.insert(String::from("mime"), Value::String(self.mime.clone()));
+        t.insert(String::from("size"), Value::Integer(self.size as i64));
+        t.insert(String::from("hash"), Value::String(self.hash.clone()));
+        Value::Table(t)
+    }
+
+    pub fn from_value(v: &Value) -> Option<Attachment> {
+        let t = match *v {
+            Value::Table(ref t) => t,
+            _ => return None,
+        };
+
+        let string = |key: &str| match t.get(key) {
+            Some(&Value::String(ref s)) => Some(s.clone()),
+            _ => None,
+        };
+        let size = match t.get("size") {
+            Some(&Value::Integer(i)) if i >= 0 => Some(i as u64),
+            _ => None,
+        };
+
+        match (string("name"), string("mime"), size, string("hash")) {
+            (Some(name), Some(mime), Some(size), Some(hash)) => Some(Attachment {
+                name: name,
+                mime: mime,
+                size: size,
+                hash: hash,
+            }),
+            _ => None,
+        }
+    }
+
+}
+
+/// Get the SHA-256 hash of `data`, in hex
+pub fn content_hash(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result_str()
+}
+
+/// Get the path where the content with `hash` is kept in the store at `location`
+///
+/// The first two characters of the hash are used as a subdirectory, so no directory grows too
+/// large.
+pub fn blob_path(location: &Path, hash: &str) -> PathBuf {
+    let mut path = location.to_path_buf();
+    path.push(ATTACHMENTS_DIR);
+    if hash.len() > 2 {
+        path.push(&hash[..2]);
+        path.push(&hash[2..]);
+    } else {
+        path.push(hash);
+    }
+    path
+}
+
+/// Get the hash of the content kept at `path`, the reverse of `blob_path()`
+pub fn hash_of_blob_path(location: &Path, path: &Path) -> Option<String> {
+    let mut dir = location.to_path_buf();
+    dir.push(ATTACHMENTS_DIR);
+
+    path.strip_prefix(&dir)
+        .ok()
+        .and_then(|rel| rel.to_str())
+        .map(|rel| rel.replace("/", "").replace("\\", ""))
+}
+
+/// Get the attachments listed in `header`
+pub fn attachments_of(header: &EntryHeader) -> Result<Vec<Attachment>> {
+    match try!(header.read(ATTACHMENTS_HEADER_PATH)) {
+        None => Ok(vec![]),
+        Some(Value::Array(a)) => a.iter()
+            .map(|v| Attachment::from_value(v).ok_or(SEK::AttachmentError.into_error()))
+            .collect(),
+        Some(_) => Err(SEK::AttachmentError.into_error()),
+    }
+}
+
+/// List `attachments` in `header`, replacing the list which was there before
+pub fn set_attachments(header: &mut EntryHeader, attachments: &[Attachment]) -> Result<()> {
+    if attachments.is_empty() {
+        return header.delete(ATTACHMENTS_HEADER_PATH).map(|_| ());
+    }
+
+    let list = attachments.iter().map(Attachment::to_value).collect();
+    header.set(ATTACHMENTS_HEADER_PATH, Value::Array(list)).map(|_| ())
+}
+
+/// Keep `data` in the store at `location` and list it in `header` as `name`, replacing an
+/// attachment of the same name
+pub fn add(backend: &StoreBackend, location: &Path, header: &mut EntryHeader, name: &str,
+           mime: &str, data: &[u8]) -> Result<Attachment>
+{
+    let attachment = Attachment::new(String::from(name), String::from(mime), data);
+    let path       = blob_path(location, &attachment.hash);
+
+    if backend.is_file(&path) {
+        debug!("Attachment data is in the store already: {}", attachment.hash);
+    } else {
+        try!(backend.write(&path, data).map_err_into(SEK::AttachmentError));
+    }
+
+    let mut attachments = try!(attachments_of(header));
+    attachments.retain(|a| a.name != name);
+    attachments.push(attachment.clone());
+    set_attachments(header, &attachments).map(|_| attachment)
+}
+
+/// Read the data of the attachment `name` listed in `header` from the store at `location`
+///
+/// Fails with `AttachmentCorrupted` if the data does not match the hash in the header.
+pub fn read(backend: &StoreBackend, location: &Path, header: &EntryHeader, name: &str)
+    -> Result<Vec<u8>>
+{
+    let attachment = try!(try!(attachments_of(header))
+                          .into_iter()
+                          .find(|a| a.name == name)
+                          .ok_or(SEK::AttachmentNotFound.into_error()));
+
+    let data = try!(backend
+                    .read(&blob_path(location, &attachment.hash))
+                    .map_err_into(SEK::AttachmentError));
+    if content_hash(&data) == attachment.hash {
+        Ok(data)
+    } else {
+        Err(SEK::AttachmentCorrupted.into_error())
+    }
+}
+
+/// Remove the attachment `name` from `header`, returns whether there was such an attachment
+pub fn remove(header: &mut EntryHeader, name: &str) -> Result<bool> {
+    let mut attachments = try!(attachments_of(header));
+    let before = attachments.len();
+    attachments.retain(|a| a.name != name);
+    if attachments.len() == before {
+        return Ok(false);
+    }
+
+    set_attachments(header, &attachments).map(|_| true)
+}
+
+/// Remove the attachment data in the store at `location` whose hash is not in `referenced`
+///
+/// Returns the hashes of the removed data, nothing is removed with `dry_run`.
+pub fn remove_unreferenced(backend: &StoreBackend, location: &Path, referenced: &BTreeSet<String>,
+                           dry_run: bool) -> Result<Vec<String>>
+{
+    let mut dir = location.to_path_buf();
+    dir.push(ATTACHMENTS_DIR);
+    let blobs = try!(backend.list(&dir).map_err_into(SEK::AttachmentError));
+
+    let mut removed = vec![];
+    for blob in blobs {
+        let hash = match hash_of_blob_path(location, &blob) {
+            Some(hash) => hash,
+            None => continue,
+        };
+        if referenced.contains(&hash) {
+            continue;
+        }
+
+        if !dry_run {
+            try!(backend.remove(&blob).map_err_into(SEK::AttachmentError));
+        }
+        removed.push(hash);
+    }
+
+    Ok(removed)
+}
+
+/// Check that the data of all attachments listed in `header` is in the store at `location` and
+/// matches its hash
+pub fn problems(backend: &StoreBackend, location: &Path, header: &EntryHeader) -> Vec<String> {
+    let attachments = match attachments_of(header) {
+        Ok(a) => a,
+        Err(_) => return vec![String::from("Malformed 'imag.attachments'")],
+    };
+
+    attachments.into_iter()
+        .filter_map(|a| {
+            match backend.read(&blob_path(location, &a.hash)) {
+                Err(_) => Some(format!("Attachment '{}' is missing", a.name)),
+                Ok(ref data) if content_hash(data) != a.hash => {
+                    Some(format!("Attachment '{}' is corrupted", a.name))
+                },
+                Ok(_) => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use store::EntryHeader;
+
+    use super::{Attachment, attachments_of, set_attachments, blob_path, hash_of_blob_path};
+
+    #[test]
+    fn test_attachments_in_header() {
+        let mut header = EntryHeader::new();
+        assert!(attachments_of(&header).unwrap().is_empty());
+
+        let a = Attachment::new(String::from("a.bin"), String::from("application/octet-stream"),
+                                &[0, 1, 2, 3]);
+        let b = Attachment::new(String::from("b.txt"), String::from("text/plain"), b"b");
+        set_attachments(&mut header, &[a.clone(), b.clone()]).unwrap();
+        assert_eq!(attachments_of(&header).unwrap(), vec![a, b]);
+
+        set_attachments(&mut header, &[]).unwrap();
+        assert!(attachments_of(&header).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_blob_path() {
+        let location = Path::new("/store");
+        let path     = blob_path(location, "abcdef");
+        assert_eq!(path, PathBuf::from("/store/.attachments/ab/cdef"));
+        assert_eq!(hash_of_blob_path(location, &path), Some(String::from("abcdef")));
+    }
+
+}
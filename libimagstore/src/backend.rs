@@ -0,0 +1,1039 @@
+//! Storage backends for the store
+//!
+//! The store does not talk to the filesystem directly but through an implementation of
+//! `StoreBackend`. The default is the `FileSystemBackend`, which maps each entry to a file. The
+//! `InMemoryBackend` keeps everything in memory and is meant for testing.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::fmt::Error as FmtError;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::io::Result as IoResult;
+use std::path::{Path, PathBuf};
+use std::result::Result as RResult;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver};
+
+use fs2::FileExt;
+use notify::{RecommendedWatcher, Watcher, op};
+use notify::Event as NotifyEvent;
+use walkdir::WalkDir;
+
+use attachment::content_hash;
+
+/// The suffix of files which are written next to the file they replace and renamed over it
+/// afterwards, by `BackendFile::write_entry()` and transactions
+pub static STAGED_SUFFIX : &'static str = ".imag-tx";
+
+/// Check whether `path` is a staged file (see `STAGED_SUFFIX`) rather than an entry
+pub fn is_staged(path: &Path) -> bool {
+    path.to_str().map(|s| s.ends_with(STAGED_SUFFIX)).unwrap_or(false)
+}
+
+/// A file handle returned by a `StoreBackend`
+pub trait BackendFile : Read + Write + Seek + Debug + Send {
+    fn set_len(&self, size: u64) -> IoResult<()>;
+    fn sync_all(&self) -> IoResult<()>;
+
+    /// Replace the whole content of the file with `content`
+    ///
+    /// Others see either the old or the new content, never a part of it, even if writing fails
+    /// halfway. Afterwards, the position is at the end of the file.
+    fn write_entry(&mut self, content: &[u8]) -> IoResult<()>;
+}
+
+/// An exclusive lock on a file of a `StoreBackend`, released when dropped
+pub trait BackendLock : Debug + Send {}
+
+/// A change to a file below a path watched with `StoreBackend::watch()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendEvent {
+    Created(PathBuf),
+    Modified(PathBuf),
+    Removed(PathBuf),
+    Renamed(PathBuf, PathBuf),
+}
+
+/// The stream of changes below a watched path, watching stops when it is dropped
+pub trait BackendWatcher : Debug + Send {
+
+    /// Wait for the next change, returns `None` if no more changes can be reported
+    fn recv(&mut self) -> Option<BackendEvent>;
+
+    /// Get the next change without waiting, if there is one
+    fn try_recv(&mut self) -> Option<BackendEvent>;
+
+}
+
+/// An object found while walking a backend
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BackendObject {
+    Directory(PathBuf),
+    File(PathBuf),
+}
+
+/// The interface the store uses to access its storage
+pub trait StoreBackend : Debug + Send {
+
+    /// Open an existing file for reading and writing
+    fn open(&self, path: &Path) -> IoResult<Box<BackendFile>>;
+
+    /// Open a file for reading and writing, creating it (and its parent directories) if it does
+    /// not exist yet. The content of an existing file is left untouched.
+    fn create(&self, path: &Path) -> IoResult<Box<BackendFile>>;
+
+    fn remove(&self, path: &Path) -> IoResult<()>;
+    fn rename(&self, from: &Path, to: &Path) -> IoResult<()>;
+    fn create_dir_all(&self, path: &Path) -> IoResult<()>;
+
+    fn exists(&self, path: &Path) -> bool;
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Recursively list all files and directories below `path`, including `path` itself
+    fn walk(&self, path: &Path) -> IoResult<Vec<BackendObject>>;
+
+    /// Try to take an exclusive lock on the file at `path`, creating the file if necessary
+    ///
+    /// Returns `Ok(None)` without blocking if someone else holds the lock. Two locks on the same
+    /// path exclude each other even if they are taken by the same process. Backends which create
+    /// a file for the lock remove it again when the lock is released.
+    fn try_lock(&self, path: &Path) -> IoResult<Option<Box<BackendLock>>>;
+
+    /// Watch the files below `path` for changes, no matter who makes them
+    ///
+    /// Backends which cannot be changed from outside of the store do not support this.
+    fn watch(&self, path: &Path) -> IoResult<Box<BackendWatcher>> {
+        Err(IoError::new(IoErrorKind::Other, format!("Cannot watch {:?} in this backend", path)))
+    }
+
+    /// Remove the empty directory at `path`
+    ///
+    /// Backends in which directories are implicit have nothing to do.
+    fn remove_dir(&self, _: &Path) -> IoResult<()> {
+        Ok(())
+    }
+
+    /// Read the complete file at `path`
+    fn read(&self, path: &Path) -> IoResult<Vec<u8>> {
+        let mut v = vec![];
+        try!(self.open(path).and_then(|mut f| f.read_to_end(&mut v)));
+        Ok(v)
+    }
+
+    /// Replace the content of the file at `path`, creating it if necessary
+    fn write(&self, path: &Path, content: &[u8]) -> IoResult<()> {
+        self.create(path).and_then(|mut f| f.write_entry(content))
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> IoResult<()> {
+        self.read(from).and_then(|content| self.write(to, &content))
+    }
+
+    /// Get an identifier for the content of the file at `path`
+    ///
+    /// Two files have the same identifier if and only if they have the same content.
+    fn content_id(&self, path: &Path) -> IoResult<String> {
+        self.read(path).map(|content| content_hash(&content))
+    }
+
+    /// Get the hashes of the objects (see `object`) the file at `path` is made of
+    ///
+    /// Backends which do not keep files as objects have none.
+    fn referenced_objects(&self, _: &Path) -> IoResult<Vec<String>> {
+        Ok(vec![])
+    }
+
+    /// Recursively list all files below `path`
+    fn list(&self, path: &Path) -> IoResult<Vec<PathBuf>> {
+        self.walk(path).map(|objs| {
+            objs.into_iter()
+                .filter_map(|o| match o {
+                    BackendObject::File(p) => Some(p),
+                    BackendObject::Directory(_) => None,
+                })
+                .collect()
+        })
+    }
+
+}
+
+/// A file of a `FileSystemBackend`
+#[derive(Debug)]
+struct FileSystemFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl FileSystemFile {
+
+    fn open(path: &Path, create: bool) -> IoResult<FileSystemFile> {
+        OpenOptions::new()
+            .write(true)
+            .read(true)
+            .create(create)
+            .open(path)
+            .map(|file| FileSystemFile { file: file, path: path.to_path_buf() })
+    }
+
+}
+
+impl Read for FileSystemFile {
+
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.file.read(buf)
+    }
+
+}
+
+impl Write for FileSystemFile {
+
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.file.flush()
+    }
+
+}
+
+impl Seek for FileSystemFile {
+
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        self.file.seek(pos)
+    }
+
+}
+
+impl BackendFile for FileSystemFile {
+
+    fn set_len(&self, size: u64) -> IoResult<()> {
+        self.file.set_len(size)
+    }
+
+    fn sync_all(&self) -> IoResult<()> {
+        self.file.sync_all()
+    }
+
+    /// Writes the content to a staged file which is renamed over the file, and opens the new
+    /// file afterwards
+    fn write_entry(&mut self, content: &[u8]) -> IoResult<()> {
+        let mut staged = self.path.clone().into_os_string();
+        staged.push(STAGED_SUFFIX);
+        let staged = PathBuf::from(staged);
+
+        let written = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&staged)
+            .and_then(|mut f| {
+                try!(f.write_all(content));
+                f.sync_all()
+            })
+            .and_then(|_| ::std::fs::rename(&staged, &self.path));
+
+        if let Err(e) = written {
+            if let Err(e) = ::std::fs::remove_file(&staged) {
+                debug!("Removing staged file {:?} failed: {:?}", staged, e);
+            }
+            return Err(e);
+        }
+
+        *self = try!(FileSystemFile::open(&self.path, false));
+        self.file.seek(SeekFrom::End(0)).map(|_| ())
+    }
+
+}
+
+/// A lock taken with `flock()` (`LockFileEx()` on Windows) on a lock file
+///
+/// The lock file is removed before the lock is released. Someone who opened the file before and
+/// locks it afterwards holds a lock on a file which is not at the path anymore, so
+/// `FileSystemBackend::try_lock()` checks that the locked file is the one at the path.
+#[derive(Debug)]
+struct FileLock(File, PathBuf);
+
+impl BackendLock for FileLock {}
+
+impl Drop for FileLock {
+
+    fn drop(&mut self) {
+        if let Err(e) = ::std::fs::remove_file(&self.1) {
+            debug!("Removing lock file {:?} failed: {:?}", self.1, e);
+        }
+        if let Err(e) = self.0.unlock() {
+            debug!("Unlocking failed: {:?}", e);
+        }
+    }
+
+}
+
+/// Check whether `file` is (still) the file at `path`
+#[cfg(unix)]
+fn is_file_at(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), ::std::fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Check whether `file` is (still) the file at `path`
+///
+/// Open files cannot be removed on other platforms, so it always is.
+#[cfg(not(unix))]
+fn is_file_at(_: &File, _: &Path) -> bool {
+    true
+}
+
+/// The default backend, storing each entry as a file on the filesystem
+#[derive(Debug, Clone)]
+pub struct FileSystemBackend;
+
+impl FileSystemBackend {
+
+    pub fn new() -> FileSystemBackend {
+        FileSystemBackend
+    }
+
+}
+
+impl StoreBackend for FileSystemBackend {
+
+    fn open(&self, path: &Path) -> IoResult<Box<BackendFile>> {
+        FileSystemFile::open(path, false).map(|f| Box::new(f) as Box<BackendFile>)
+    }
+
+    fn create(&self, path: &Path) -> IoResult<Box<BackendFile>> {
+        if let Some(parent) = path.parent() {
+            debug!("Implicitely creating directory: {:?}", parent);
+            try!(self.create_dir_all(parent));
+        }
+        FileSystemFile::open(path, true).map(|f| Box::new(f) as Box<BackendFile>)
+    }
+
+    fn remove(&self, path: &Path) -> IoResult<()> {
+        ::std::fs::remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> IoResult<()> {
+        ::std::fs::rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> IoResult<()> {
+        ::std::fs::create_dir_all(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> IoResult<()> {
+        ::std::fs::remove_dir(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn walk(&self, path: &Path) -> IoResult<Vec<BackendObject>> {
+        let mut v = vec![];
+        for res in WalkDir::new(path).into_iter() {
+            match res {
+                Ok(dent) => if dent.file_type().is_dir() {
+                    v.push(BackendObject::Directory(dent.path().to_path_buf()));
+                } else if dent.file_type().is_file() {
+                    v.push(BackendObject::File(dent.path().to_path_buf()));
+                },
+                Err(e) => {
+                    warn!("Error in Walker");
+                    debug!("{:?}", e);
+                },
+            }
+        }
+        Ok(v)
+    }
+
+    fn try_lock(&self, path: &Path) -> IoResult<Option<Box<BackendLock>>> {
+        if let Some(parent) = path.parent() {
+            try!(self.create_dir_all(parent));
+        }
+        loop {
+            let file = try!(OpenOptions::new().write(true).create(true).open(path));
+
+            match file.try_lock_exclusive() {
+                Ok(()) => if is_file_at(&file, path) {
+                    return Ok(Some(Box::new(FileLock(file, path.to_path_buf()))));
+                } else {
+                    // The lock was released (and its file removed) while we were taking it
+                    debug!("Lock file {:?} was replaced, locking again", path);
+                    try!(file.unlock());
+                },
+                Err(ref e) if e.raw_os_error() == ::fs2::lock_contended_error().raw_os_error() => {
+                    return Ok(None);
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn watch(&self, path: &Path) -> IoResult<Box<BackendWatcher>> {
+        let (tx, rx) = channel();
+        let mut watcher : RecommendedWatcher = try!(Watcher::new(tx).map_err(notify_error));
+        try!(watcher.watch(path).map_err(notify_error));
+
+        Ok(Box::new(FileSystemWatcher {
+            _watcher: watcher,
+            events: rx,
+            queue: VecDeque::new(),
+            renamed_from: None,
+        }))
+    }
+
+}
+
+fn notify_error(e: ::notify::Error) -> IoError {
+    IoError::new(IoErrorKind::Other, format!("{:?}", e))
+}
+
+/// A `BackendWatcher` using the notification mechanism of the OS (inotify on Linux)
+///
+/// The OS reports the two sides of a rename as separate events, so this pairs a rename of a path
+/// which does not exist anymore with the rename of a path which exists right after it. Unpaired
+/// sides are reported as a removal resp. creation.
+struct FileSystemWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<NotifyEvent>,
+    queue: VecDeque<BackendEvent>,
+    renamed_from: Option<PathBuf>,
+}
+
+impl FileSystemWatcher {
+
+    fn handle(&mut self, event: NotifyEvent) {
+        let path = match event.path {
+            Some(p) => p,
+            None => return,
+        };
+        let op = match event.op {
+            Ok(op) => op,
+            Err(e) => {
+                debug!("Error from watcher: {:?}", e);
+                return;
+            },
+        };
+
+        if op.contains(op::RENAME) {
+            if path.exists() {
+                match self.renamed_from.take() {
+                    Some(from) => self.queue.push_back(BackendEvent::Renamed(from, path)),
+                    None       => self.queue.push_back(BackendEvent::Created(path)),
+                }
+            } else {
+                self.flush_rename();
+                self.renamed_from = Some(path);
+            }
+            return;
+        }
+
+        self.flush_rename();
+        if op.contains(op::REMOVE) {
+            self.queue.push_back(BackendEvent::Removed(path));
+        } else if op.contains(op::CREATE) {
+            self.queue.push_back(BackendEvent::Created(path));
+        } else if op.contains(op::WRITE) {
+            self.queue.push_back(BackendEvent::Modified(path));
+        }
+    }
+
+    /// A pending rename without a counterpart moved the file out of the watched path
+    fn flush_rename(&mut self) {
+        if let Some(from) = self.renamed_from.take() {
+            self.queue.push_back(BackendEvent::Removed(from));
+        }
+    }
+
+}
+
+impl Debug for FileSystemWatcher {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        write!(fmt, "FileSystemWatcher {{ queue: {:?}, renamed_from: {:?} }}",
+               self.queue, self.renamed_from)
+    }
+
+}
+
+impl BackendWatcher for FileSystemWatcher {
+
+    fn recv(&mut self) -> Option<BackendEvent> {
+        while self.queue.is_empty() {
+            match self.events.recv() {
+                Ok(event) => self.handle(event),
+                Err(_) => {
+                    self.flush_rename();
+                    break;
+                },
+            }
+
+            // the counterpart of a rename is reported right away, if there is one
+            if self.queue.is_empty() && self.renamed_from.is_some() {
+                match self.events.try_recv() {
+                    Ok(event) => self.handle(event),
+                    Err(_) => self.flush_rename(),
+                }
+            }
+        }
+        self.queue.pop_front()
+    }
+
+    fn try_recv(&mut self) -> Option<BackendEvent> {
+        while let Ok(event) = self.events.try_recv() {
+            self.handle(event);
+        }
+        self.flush_rename();
+        self.queue.pop_front()
+    }
+
+}
+
+type InMemoryFiles = Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>;
+
+/// A backend which keeps all files in memory
+///
+/// Clones of an `InMemoryBackend` share their files, so a test can keep a clone to inspect what
+/// the store wrote. Directories are implicit: a directory exists if a file exists below it.
+///
+/// Clones also share their locks, so two stores on clones of one backend behave like two
+/// processes on the same store directory. Locks do not create files.
+#[derive(Debug, Clone)]
+pub struct InMemoryBackend {
+    files: InMemoryFiles,
+    locks: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl InMemoryBackend {
+
+    pub fn new() -> InMemoryBackend {
+        InMemoryBackend {
+            files: Arc::new(Mutex::new(HashMap::new())),
+            locks: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    fn lock_error() -> IoError {
+        IoError::new(IoErrorKind::Other, "In-memory backend lock poisoned")
+    }
+
+    fn not_found(path: &Path) -> IoError {
+        IoError::new(IoErrorKind::NotFound, format!("No such file: {:?}", path))
+    }
+
+}
+
+impl StoreBackend for InMemoryBackend {
+
+    fn open(&self, path: &Path) -> IoResult<Box<BackendFile>> {
+        let files = try!(self.files.lock().map_err(|_| InMemoryBackend::lock_error()));
+        if !files.contains_key(path) {
+            return Err(InMemoryBackend::not_found(path));
+        }
+
+        Ok(Box::new(InMemoryFile {
+            files: self.files.clone(),
+            path: path.to_path_buf(),
+            pos: 0,
+        }))
+    }
+
+    fn create(&self, path: &Path) -> IoResult<Box<BackendFile>> {
+        {
+            let mut files = try!(self.files.lock().map_err(|_| InMemoryBackend::lock_error()));
+            files.entry(path.to_path_buf()).or_insert_with(Vec::new);
+        }
+        self.open(path)
+    }
+
+    fn remove(&self, path: &Path) -> IoResult<()> {
+        let mut files = try!(self.files.lock().map_err(|_| InMemoryBackend::lock_error()));
+        files.remove(path).map(|_| ()).ok_or_else(|| InMemoryBackend::not_found(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> IoResult<()> {
+        let mut files = try!(self.files.lock().map_err(|_| InMemoryBackend::lock_error()));
+        let content = try!(files.remove(from).ok_or_else(|| InMemoryBackend::not_found(from)));
+        files.insert(to.to_path_buf(), content);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, _: &Path) -> IoResult<()> {
+        Ok(()) // Directories are implicit
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files
+            .lock()
+            .map(|files| files.keys().any(|p| p.starts_with(path)))
+            .unwrap_or(false)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files
+            .lock()
+            .map(|files| files.contains_key(path))
+            .unwrap_or(false)
+    }
+
+    fn walk(&self, path: &Path) -> IoResult<Vec<BackendObject>> {
+        let files = try!(self.files.lock().map_err(|_| InMemoryBackend::lock_error()));
+        let mut objects = BTreeSet::new();
+
+        for file in files.keys().filter(|p| p.starts_with(path) && *p != path) {
+            objects.insert(BackendObject::File(file.clone()));
+
+            let mut dir = file.parent();
+            while let Some(d) = dir {
+                if !d.starts_with(path) {
+                    break;
+                }
+                objects.insert(BackendObject::Directory(d.to_path_buf()));
+                dir = d.parent();
+            }
+        }
+
+        Ok(objects.into_iter().collect())
+    }
+
+    fn try_lock(&self, path: &Path) -> IoResult<Option<Box<BackendLock>>> {
+        let mut locks = try!(self.locks.lock().map_err(|_| InMemoryBackend::lock_error()));
+        if !locks.insert(path.to_path_buf()) {
+            return Ok(None);
+        }
+
+        Ok(Some(Box::new(InMemoryLock {
+            locks: self.locks.clone(),
+            path: path.to_path_buf(),
+        })))
+    }
+
+}
+
+/// A lock in an `InMemoryBackend`
+#[derive(Debug)]
+struct InMemoryLock {
+    locks: Arc<Mutex<HashSet<PathBuf>>>,
+    path: PathBuf,
+}
+
+impl BackendLock for InMemoryLock {}
+
+impl Drop for InMemoryLock {
+
+    fn drop(&mut self) {
+        match self.locks.lock() {
+            Ok(mut locks) => { locks.remove(&self.path); },
+            Err(_) => warn!("In-memory backend lock poisoned, cannot release {:?}", self.path),
+        }
+    }
+
+}
+
+/// A handle to a file in an `InMemoryBackend`
+#[derive(Debug)]
+struct InMemoryFile {
+    files: InMemoryFiles,
+    path: PathBuf,
+    pos: u64,
+}
+
+impl InMemoryFile {
+
+    fn with_content<T, F>(&self, f: F) -> IoResult<T>
+        where F: FnOnce(&mut Vec<u8>) -> T
+    {
+        let mut files = try!(self.files.lock().map_err(|_| InMemoryBackend::lock_error()));
+        files.get_mut(&self.path)
+            .map(f)
+            .ok_or_else(|| InMemoryBackend::not_found(&self.path))
+    }
+
+}
+
+impl Read for InMemoryFile {
+
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let pos = self.pos as usize;
+        let n = try!(self.with_content(|content| {
+            if pos >= content.len() {
+                return 0;
+            }
+            let n = ::std::cmp::min(buf.len(), content.len() - pos);
+            buf[..n].copy_from_slice(&content[pos..(pos + n)]);
+            n
+        }));
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+}
+
+impl Write for InMemoryFile {
+
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let pos = self.pos as usize;
+        try!(self.with_content(|content| {
+            if content.len() < pos + buf.len() {
+                content.resize(pos + buf.len(), 0);
+            }
+            content[pos..(pos + buf.len())].copy_from_slice(buf);
+        }));
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+
+}
+
+impl Seek for InMemoryFile {
+
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        let len = try!(self.with_content(|content| content.len() as i64));
+        let new_pos = match pos {
+            SeekFrom::Start(n)   => n as i64,
+            SeekFrom::End(n)     => len + n,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+        };
+
+        if new_pos < 0 {
+            return Err(IoError::new(IoErrorKind::InvalidInput, "Seek to negative position"));
+        }
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+
+}
+
+impl BackendFile for InMemoryFile {
+
+    fn set_len(&self, size: u64) -> IoResult<()> {
+        self.with_content(|content| content.resize(size as usize, 0))
+    }
+
+    fn sync_all(&self) -> IoResult<()> {
+        Ok(())
+    }
+
+    fn write_entry(&mut self, new: &[u8]) -> IoResult<()> {
+        try!(self.with_content(|content| *content = new.to_vec()));
+        self.pos = new.len() as u64;
+        Ok(())
+    }
+
+}
+
+/// The way a backend which wraps another backend reads and writes whole entries
+///
+/// Backends which transform entries on their way to the storage (see `object` and `encryption`)
+/// implement this and hand out `CodecFile`s.
+pub trait EntryCodec : Clone + Debug + Send + 'static {
+
+    /// Read the entry at `path`, as the store sees it
+    fn read_entry(&self, path: &Path) -> IoResult<Vec<u8>>;
+
+    /// Replace the entry at `path` with `content`
+    fn write_entry(&self, path: &Path, content: &[u8]) -> IoResult<()>;
+
+}
+
+/// A handle to an entry of a backend implementing `EntryCodec`
+///
+/// The content is kept in memory. Changes are written as a whole entry when the file is flushed
+/// or dropped, `write_entry()` writes right away. Seeking to the start reloads the content (after
+/// writing pending changes), as the store does that before each access to an entry.
+#[derive(Debug)]
+pub struct CodecFile<C: EntryCodec> {
+    codec: C,
+    path: PathBuf,
+    content: RefCell<Vec<u8>>,
+    pos: u64,
+
+    /// Whether there are changes which were not written yet
+    dirty: Cell<bool>,
+}
+
+impl<C: EntryCodec> CodecFile<C> {
+
+    /// Open the entry at `path`, which must exist
+    pub fn open(codec: C, path: &Path) -> IoResult<CodecFile<C>> {
+        codec.read_entry(path).map(|content| {
+            CodecFile {
+                codec: codec,
+                path: path.to_path_buf(),
+                content: RefCell::new(content),
+                pos: 0,
+                dirty: Cell::new(false),
+            }
+        })
+    }
+
+    /// Write the entry if it was changed
+    fn persist(&self) -> IoResult<()> {
+        if self.dirty.get() {
+            try!(self.codec.write_entry(&self.path, &self.content.borrow()));
+            self.dirty.set(false);
+        }
+        Ok(())
+    }
+
+}
+
+impl<C: EntryCodec> Read for CodecFile<C> {
+
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let content = self.content.borrow();
+        let pos     = ::std::cmp::min(self.pos as usize, content.len());
+        let n       = ::std::cmp::min(buf.len(), content.len() - pos);
+        buf[..n].copy_from_slice(&content[pos..(pos + n)]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+}
+
+impl<C: EntryCodec> Write for CodecFile<C> {
+
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        {
+            let pos = self.pos as usize;
+            let mut content = self.content.borrow_mut();
+            if content.len() < pos + buf.len() {
+                content.resize(pos + buf.len(), 0);
+            }
+            content[pos..(pos + buf.len())].copy_from_slice(buf);
+        }
+        self.pos += buf.len() as u64;
+        self.dirty.set(true);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.persist()
+    }
+
+}
+
+impl<C: EntryCodec> Seek for CodecFile<C> {
+
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        let len = self.content.borrow().len() as i64;
+        let new_pos = match pos {
+            SeekFrom::Start(0) => {
+                try!(self.persist());
+                *self.content.borrow_mut() = try!(self.codec.read_entry(&self.path));
+                0
+            },
+            SeekFrom::Start(n)   => n as i64,
+            SeekFrom::End(n)     => len + n,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+        };
+
+        if new_pos < 0 {
+            return Err(IoError::new(IoErrorKind::InvalidInput, "Seek to negative position"));
+        }
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+
+}
+
+impl<C: EntryCodec> BackendFile for CodecFile<C> {
+
+    fn set_len(&self, size: u64) -> IoResult<()> {
+        self.content.borrow_mut().resize(size as usize, 0);
+        self.dirty.set(true);
+        Ok(())
+    }
+
+    fn sync_all(&self) -> IoResult<()> {
+        self.persist()
+    }
+
+    fn write_entry(&mut self, content: &[u8]) -> IoResult<()> {
+        *self.content.borrow_mut() = content.to_vec();
+        self.pos = content.len() as u64;
+        self.dirty.set(true);
+        self.persist()
+    }
+
+}
+
+impl<C: EntryCodec> Drop for CodecFile<C> {
+
+    fn drop(&mut self) {
+        if let Err(e) = self.persist() {
+            warn!("Could not write {:?}, changes are lost", self.path);
+            debug!("{:?}", e);
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+
+    use super::{StoreBackend, FileSystemBackend, InMemoryBackend, BackendObject};
+
+    #[test]
+    fn in_memory_write_read() {
+        let backend = InMemoryBackend::new();
+        let path = PathBuf::from("/store/module/entry");
+
+        write!(backend.create(&path).unwrap(), "Hello World").unwrap();
+
+        let mut s = String::new();
+        backend.open(&path).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "Hello World");
+
+        backend.write(&path, b"Hello").unwrap();
+        assert_eq!(backend.read(&path).unwrap(), b"Hello".to_vec());
+    }
+
+    #[test]
+    fn in_memory_rename_remove() {
+        let backend = InMemoryBackend::new();
+        let from = PathBuf::from("/store/module/from");
+        let to   = PathBuf::from("/store/module/to");
+
+        backend.write(&from, b"content").unwrap();
+        backend.rename(&from, &to).unwrap();
+        assert!(!backend.exists(&from));
+        assert!(backend.is_file(&to));
+
+        backend.remove(&to).unwrap();
+        assert!(!backend.exists(&to));
+        assert!(backend.open(&to).is_err());
+    }
+
+    #[test]
+    fn in_memory_walk() {
+        let backend = InMemoryBackend::new();
+        backend.write(&PathBuf::from("/store/module/a"), b"").unwrap();
+        backend.write(&PathBuf::from("/store/module/sub/b"), b"").unwrap();
+        backend.write(&PathBuf::from("/store/other/c"), b"").unwrap();
+
+        let objs = backend.walk(&PathBuf::from("/store/module")).unwrap();
+        assert_eq!(objs, vec![
+            BackendObject::Directory(PathBuf::from("/store/module")),
+            BackendObject::Directory(PathBuf::from("/store/module/sub")),
+            BackendObject::File(PathBuf::from("/store/module/a")),
+            BackendObject::File(PathBuf::from("/store/module/sub/b")),
+        ]);
+
+        let files = backend.list(&PathBuf::from("/store/module")).unwrap();
+        assert_eq!(files.len(), 2);
+    }
+
+    #[test]
+    fn in_memory_locks_are_shared_between_clones() {
+        let backend = InMemoryBackend::new();
+        let other   = backend.clone();
+        let path    = PathBuf::from("/store/.locks/module/entry");
+
+        let lock = backend.try_lock(&path).unwrap();
+        assert!(lock.is_some());
+        assert!(other.try_lock(&path).unwrap().is_none());
+
+        drop(lock);
+        assert!(other.try_lock(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn file_system_lock_files_are_removed() {
+        use tempdir::TempDir;
+
+        let dir     = TempDir::new("imag-locks").unwrap();
+        let backend = FileSystemBackend::new();
+        let path    = dir.path().join(".locks").join("module").join("entry");
+
+        let lock = backend.try_lock(&path).unwrap();
+        assert!(lock.is_some());
+        assert!(path.is_file());
+        assert!(backend.try_lock(&path).unwrap().is_none());
+
+        drop(lock);
+        assert!(!path.exists());
+        assert!(backend.try_lock(&path).unwrap().is_some());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn file_system_write_entry_replaces_file() {
+        use std::io::{Seek, SeekFrom};
+        use tempdir::TempDir;
+
+        let dir     = TempDir::new("imag-write-entry").unwrap();
+        let backend = FileSystemBackend::new();
+        let path    = dir.path().join("module").join("entry");
+
+        let mut file = backend.create(&path).unwrap();
+        file.write_entry(b"Hello World").unwrap();
+        file.write_entry(b"Hello").unwrap();
+        assert_eq!(backend.read(&path).unwrap(), b"Hello".to_vec());
+        assert!(!dir.path().join("module").join("entry.imag-tx").exists());
+
+        // The handle refers to the new file
+        let mut s = String::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "Hello");
+    }
+
+    #[test]
+    fn codec_file_writes_on_flush() {
+        use std::path::Path;
+        use std::io::Result as IoResult;
+        use super::{EntryCodec, CodecFile};
+
+        #[derive(Debug, Clone)]
+        struct Counting(InMemoryBackend, ::std::sync::Arc<::std::sync::Mutex<usize>>);
+
+        impl EntryCodec for Counting {
+            fn read_entry(&self, path: &Path) -> IoResult<Vec<u8>> {
+                self.0.read(path)
+            }
+
+            fn write_entry(&self, path: &Path, content: &[u8]) -> IoResult<()> {
+                *self.1.lock().unwrap() += 1;
+                self.0.write(path, content)
+            }
+        }
+
+        let codec = Counting(InMemoryBackend::new(), Default::default());
+        let path  = PathBuf::from("/store/module/entry");
+        codec.0.write(&path, b"").unwrap();
+
+        {
+            let mut file = CodecFile::open(codec.clone(), &path).unwrap();
+            write!(file, "Hello {}", "World").unwrap();
+            assert_eq!(*codec.1.lock().unwrap(), 0);
+            file.flush().unwrap();
+            assert_eq!(*codec.1.lock().unwrap(), 1);
+
+            write!(file, "!").unwrap();
+        }
+        assert_eq!(*codec.1.lock().unwrap(), 2);
+        assert_eq!(codec.0.read(&path).unwrap(), b"Hello World!".to_vec());
+    }
+
+}
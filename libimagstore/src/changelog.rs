@@ -0,0 +1,414 @@
+//! Change log of the store
+//!
+//! The store appends an event to `<store>/.changelog` whenever it writes, moves or deletes an
+//! entry, so tools can find out what changed since they last looked without rescanning the store
+//! (see `Store::changes_since()` and `imag-store log`). Each line of the file is one event:
+//!
+//! ```text
+//! <timestamp>\t<operation>\t<origin>\t<id>[\t<new id>]
+//! ```
+//!
+//! where the timestamp is in seconds since the UNIX epoch and the origin is the name of the
+//! program which changed the store (`-` if it is unknown). Only moves have a new id.
+//!
+//! The log can be turned off with `changelog = false` in the `[store]` section of the
+//! configuration.
+//!
+//! Once the log grows beyond `changelog-max-size` bytes, it is rotated to `.changelog.1`,
+//! replacing the previously rotated log, so at most the last two logs are kept. Programs which
+//! poll the log can remember a `Position` and only read what was appended since (see
+//! `read_from()`).
+
+use std::fmt::{Display, Formatter};
+use std::fmt::Error as FmtError;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::result::Result as RResult;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use backend::StoreBackend;
+use error::StoreErrorKind as SEK;
+use error::MapErrInto;
+use store::Result;
+use storeid::StoreId;
+
+/// The name of the file (inside the store) where the change log is kept
+pub static CHANGELOG_FILE : &'static str = ".changelog";
+
+/// The name of the file the change log is rotated to
+pub static ROTATED_CHANGELOG_FILE : &'static str = ".changelog.1";
+
+/// What happened to an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+    Move,
+}
+
+impl Operation {
+
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Operation::Create => "create",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+            Operation::Move   => "move",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Operation> {
+        match s {
+            "create" => Some(Operation::Create),
+            "update" => Some(Operation::Update),
+            "delete" => Some(Operation::Delete),
+            "move"   => Some(Operation::Move),
+            _        => None,
+        }
+    }
+
+}
+
+impl Display for Operation {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        write!(fmt, "{}", self.as_str())
+    }
+
+}
+
+/// One change to the store
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    /// Seconds since the UNIX epoch
+    pub timestamp: u64,
+
+    pub operation: Operation,
+
+    /// The entry which was changed, the old id for moves
+    pub id: StoreId,
+
+    /// The new id of a moved entry
+    pub new_id: Option<StoreId>,
+
+    /// The program which changed the store, if known
+    pub origin: Option<String>,
+}
+
+impl ChangeEvent {
+
+    /// Create an event which happened now
+    pub fn new(operation: Operation, id: StoreId, new_id: Option<StoreId>, origin: Option<String>)
+        -> ChangeEvent
+    {
+        ChangeEvent {
+            timestamp: now(),
+            operation: operation,
+            id: id,
+            new_id: new_id,
+            origin: origin,
+        }
+    }
+
+    fn to_line(&self) -> String {
+        let origin = self.origin.as_ref().map(|o| &o[..]).unwrap_or("-");
+        let mut line = format!("{}\t{}\t{}\t{}", self.timestamp, self.operation, origin, self.id);
+        if let Some(ref new_id) = self.new_id {
+            line.push_str(&format!("\t{}", new_id));
+        }
+        line.push('\n');
+        line
+    }
+
+    fn from_line(line: &str) -> Option<ChangeEvent> {
+        let parts : Vec<&str> = line.split('\t').collect();
+        if parts.len() < 4 || parts.len() > 5 {
+            return None;
+        }
+
+        let timestamp = match u64::from_str(parts[0]) {
+            Ok(t) => t,
+            Err(_) => return None,
+        };
+
+        Operation::parse(parts[1]).map(|operation| ChangeEvent {
+            timestamp: timestamp,
+            operation: operation,
+            id: StoreId::from(PathBuf::from(parts[3])),
+            new_id: parts.get(4).map(|p| StoreId::from(PathBuf::from(*p))),
+            origin: if parts[2] == "-" { None } else { Some(String::from(parts[2])) },
+        })
+    }
+
+}
+
+impl Display for ChangeEvent {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        try!(write!(fmt, "{} {: <6} {}", self.timestamp, self.operation.as_str(), self.id));
+        if let Some(ref new_id) = self.new_id {
+            try!(write!(fmt, " -> {}", new_id));
+        }
+        if let Some(ref origin) = self.origin {
+            try!(write!(fmt, " ({})", origin));
+        }
+        Ok(())
+    }
+
+}
+
+/// The current time in seconds since the UNIX epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Append `event` to the change log of the store at `location`
+///
+/// If the log is larger than `max_size` bytes afterwards, it is rotated. The caller has to make
+/// sure no other process appends at the same time.
+pub fn append(backend: &StoreBackend, location: &Path, event: &ChangeEvent, max_size: u64)
+    -> Result<()>
+{
+    let file = changelog_file(location);
+    let size = try!(backend.create(&file)
+        .and_then(|mut f| {
+            try!(f.seek(SeekFrom::End(0)));
+            try!(f.write_all(event.to_line().as_bytes()));
+            f.seek(SeekFrom::Current(0))
+        })
+        .map_err_into(SEK::ChangelogError));
+
+    if size > max_size {
+        debug!("Rotating change log after {} bytes", size);
+        try!(backend.rename(&file, &rotated_changelog_file(location))
+             .map_err_into(SEK::ChangelogError));
+    }
+    Ok(())
+}
+
+/// Read all events at or after `since` (in seconds since the UNIX epoch) from the change log of
+/// the store at `location`, oldest first
+///
+/// The rotated log is only read if the current one does not go back far enough. Malformed lines
+/// are skipped.
+pub fn read(backend: &StoreBackend, location: &Path, since: u64) -> Result<Vec<ChangeEvent>> {
+    let (current, _) = try!(read_file(backend, &changelog_file(location), 0));
+    let complete = current.first().map(|e| e.timestamp < since).unwrap_or(false);
+
+    let mut events = if complete {
+        vec![]
+    } else {
+        let (rotated, _) = try!(read_file(backend, &rotated_changelog_file(location), 0));
+        rotated
+    };
+    events.extend(current);
+    events.retain(|e| e.timestamp >= since);
+    Ok(events)
+}
+
+/// A position in the change log, as returned by `read_from()`
+///
+/// `Position::default()` is the start of the log, including the rotated one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Position {
+    offset: u64,
+
+    /// The first line of the log the offset is in, to notice that it was rotated
+    first_line: Option<String>,
+}
+
+/// Read the events which were appended to the change log of the store at `location` since `pos`
+///
+/// Returns the events, oldest first, and the position to continue from. Only the part of the log
+/// after `pos` is read. If the log was rotated in the meantime, the rest of the rotated log is
+/// read as well. Events which were rotated away twice since are lost.
+pub fn read_from(backend: &StoreBackend, location: &Path, pos: &Position)
+    -> Result<(Vec<ChangeEvent>, Position)>
+{
+    let file         = changelog_file(location);
+    let rotated_file = rotated_changelog_file(location);
+    let first_line         = try!(read_first_line(backend, &file));
+    let rotated_first_line = try!(read_first_line(backend, &rotated_file));
+
+    let mut events = vec![];
+    let mut rotated_end = 0;
+    let offset = if pos.first_line.is_some() && pos.first_line == first_line {
+        pos.offset
+    } else {
+        let from = if pos.first_line.is_some() && pos.first_line == rotated_first_line {
+            pos.offset
+        } else {
+            if pos.first_line.is_some() {
+                warn!("Change log was rotated more than once since it was last read");
+            }
+            0
+        };
+        let (rest, end) = try!(read_file(backend, &rotated_file, from));
+        events = rest;
+        rotated_end = end;
+        0
+    };
+
+    let (current, end) = try!(read_file(backend, &file, offset));
+    events.extend(current);
+
+    let pos = if first_line.is_some() {
+        Position { offset: end, first_line: first_line }
+    } else {
+        // nothing was appended since the last rotation
+        Position { offset: rotated_end, first_line: rotated_first_line }
+    };
+    Ok((events, pos))
+}
+
+/// Read the events from `file`, starting at byte `offset`
+///
+/// Returns the events and the offset after the last complete line.
+fn read_file(backend: &StoreBackend, file: &Path, offset: u64) -> Result<(Vec<ChangeEvent>, u64)> {
+    if !backend.is_file(file) {
+        return Ok((vec![], 0));
+    }
+
+    let mut bytes = vec![];
+    try!(backend.open(file)
+        .and_then(|mut f| {
+            try!(f.seek(SeekFrom::Start(offset)));
+            f.read_to_end(&mut bytes)
+        })
+        .map_err_into(SEK::ChangelogError));
+
+    // a line which is still being written is read the next time
+    let complete = bytes.iter().rposition(|b| *b == b'\n').map(|i| i + 1).unwrap_or(0);
+    bytes.truncate(complete);
+    let text = try!(String::from_utf8(bytes).map_err_into(SEK::ChangelogError));
+
+    let events = text.lines()
+        .filter_map(|line| {
+            let event = ChangeEvent::from_line(line);
+            if event.is_none() {
+                warn!("Ignoring malformed line in change log: '{}'", line);
+            }
+            event
+        })
+        .collect();
+
+    Ok((events, offset + complete as u64))
+}
+
+fn read_first_line(backend: &StoreBackend, file: &Path) -> Result<Option<String>> {
+    if !backend.is_file(file) {
+        return Ok(None);
+    }
+
+    let mut bytes = vec![];
+    try!(backend.open(file)
+        .and_then(|f| {
+            for byte in f.bytes() {
+                let byte = try!(byte);
+                if byte == b'\n' {
+                    break;
+                }
+                bytes.push(byte);
+            }
+            Ok(())
+        })
+        .map_err_into(SEK::ChangelogError));
+
+    Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+pub fn changelog_file(location: &Path) -> PathBuf {
+    let mut file = location.to_path_buf();
+    file.push(CHANGELOG_FILE);
+    file
+}
+
+pub fn rotated_changelog_file(location: &Path) -> PathBuf {
+    let mut file = location.to_path_buf();
+    file.push(ROTATED_CHANGELOG_FILE);
+    file
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use backend::InMemoryBackend;
+    use storeid::StoreId;
+
+    use super::{ChangeEvent, Operation, Position, append, read, read_from};
+
+    #[test]
+    fn test_append_read() {
+        let backend  = InMemoryBackend::new();
+        let location = PathBuf::from("/store");
+        let id       = |s: &str| StoreId::from(PathBuf::from(s));
+
+        let created = ChangeEvent::new(Operation::Create, id("/store/a"), None,
+                                       Some(String::from("imag-notes")));
+        let moved   = ChangeEvent::new(Operation::Move, id("/store/a"), Some(id("/store/b")), None);
+
+        append(&backend, &location, &created, 4096).unwrap();
+        append(&backend, &location, &moved, 4096).unwrap();
+
+        assert_eq!(read(&backend, &location, 0).unwrap(), vec![created.clone(), moved.clone()]);
+        assert!(read(&backend, &location, created.timestamp + 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rotate() {
+        let backend  = InMemoryBackend::new();
+        let location = PathBuf::from("/store");
+        let event    = |s: &str| {
+            ChangeEvent::new(Operation::Update, StoreId::from(PathBuf::from(s)), None, None)
+        };
+
+        let (a, b, c) = (event("/store/a"), event("/store/b"), event("/store/c"));
+
+        // every line is longer than the maximum size, so each append rotates the log
+        append(&backend, &location, &a, 10).unwrap();
+        append(&backend, &location, &b, 10).unwrap();
+        append(&backend, &location, &c, 10).unwrap();
+
+        assert!(!backend.is_file(&PathBuf::from("/store/.changelog")));
+        assert_eq!(read(&backend, &location, 0).unwrap(), vec![c]);
+    }
+
+    #[test]
+    fn test_read_from() {
+        let backend  = InMemoryBackend::new();
+        let location = PathBuf::from("/store");
+        let event    = |s: &str| {
+            ChangeEvent::new(Operation::Update, StoreId::from(PathBuf::from(s)), None, None)
+        };
+        let (a, b, c, d) = (event("/store/a"), event("/store/b"), event("/store/c"),
+                            event("/store/d"));
+
+        // all lines have the same length, every second one rotates the log
+        let max_size = (a.to_line().len() * 2 - 1) as u64;
+
+        append(&backend, &location, &a, max_size).unwrap();
+        let (events, pos) = read_from(&backend, &location, &Position::default()).unwrap();
+        assert_eq!(events, vec![a]);
+
+        let (events, pos) = read_from(&backend, &location, &pos).unwrap();
+        assert!(events.is_empty());
+
+        append(&backend, &location, &b, max_size).unwrap();
+        append(&backend, &location, &c, max_size).unwrap();
+        assert!(backend.is_file(&PathBuf::from("/store/.changelog.1")));
+
+        let (events, pos) = read_from(&backend, &location, &pos).unwrap();
+        assert_eq!(events, vec![b, c]);
+
+        append(&backend, &location, &d, max_size).unwrap();
+        let (events, _) = read_from(&backend, &location, &pos).unwrap();
+        assert_eq!(events, vec![d]);
+    }
+
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use toml::Value;
 
 /// Check whether the configuration is valid for the store
@@ -24,7 +26,7 @@ use toml::Value;
 /// aspect = "encryption"
 /// key = "0x123456789"
 ///
-/// [store.hooks.git]
+/// [store.hooks.stdhook_git]
 /// aspect = "version-control"
 /// ```
 ///
@@ -192,6 +194,157 @@ pub fn get_post_move_aspect_names(value: &Option<Value>) -> Vec<String> {
     get_aspect_names_for_aspect_position("post-move-hook-aspects", value)
 }
 
+/// Get the number of revisions the store keeps per entry
+///
+/// This is read from the `revisions-keep` key in the `[store]` section:
+///
+/// ```toml
+/// [store]
+/// revisions-keep = 10
+/// ```
+///
+/// `0` disables revisions. The default is to keep 10 revisions.
+pub fn get_revisions_keep(value: &Option<Value>) -> usize {
+    let default = 10;
+    match *value {
+        Some(Value::Table(ref t)) => match t.get("revisions-keep") {
+            Some(&Value::Integer(i)) if i >= 0 => i as usize,
+            Some(_) => {
+                warn!("'revisions-keep' should be a non-negative Integer, ignoring it");
+                default
+            },
+            None => default,
+        },
+        _ => default,
+    }
+}
+
+/// Get the header paths which are indexed by the store
+///
+/// This is read from the `index` key in the `[store]` section:
+///
+/// ```toml
+/// [store]
+/// index = [ "imag.tags", "ref.content_hash", "note.name" ]
+/// ```
+///
+/// Returns an empty list if nothing should be indexed (which is the default).
+pub fn get_index_paths(value: &Option<Value>) -> Vec<String> {
+    match *value {
+        Some(Value::Table(ref t)) => match t.get("index") {
+            Some(&Value::Array(ref a)) => a.iter()
+                .filter_map(|v| match *v {
+                    Value::String(ref s) => Some(s.clone()),
+                    _ => {
+                        warn!("'index' should only contain Strings, ignoring {:?}", v);
+                        None
+                    },
+                })
+                .collect(),
+            Some(_) => {
+                warn!("'index' should be an Array of Strings, ignoring it");
+                vec![]
+            },
+            None => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// Check whether the store keeps a change log (see `changelog`)
+///
+/// This is read from the `changelog` key in the `[store]` section and defaults to `true`:
+///
+/// ```toml
+/// [store]
+/// changelog = false
+/// ```
+pub fn is_changelog_enabled(value: &Option<Value>) -> bool {
+    match *value {
+        Some(Value::Table(ref t)) => match t.get("changelog") {
+            Some(&Value::Boolean(b)) => b,
+            Some(_) => {
+                warn!("'changelog' should be a Boolean, ignoring it");
+                true
+            },
+            None => true,
+        },
+        _ => true,
+    }
+}
+
+/// Get the size (in bytes) after which the change log is rotated (see `changelog`)
+///
+/// This is read from the `changelog-max-size` key in the `[store]` section and defaults to 1 MiB:
+///
+/// ```toml
+/// [store]
+/// changelog-max-size = 1048576
+/// ```
+pub fn get_changelog_max_size(value: &Option<Value>) -> u64 {
+    let default = 1024 * 1024;
+    match *value {
+        Some(Value::Table(ref t)) => match t.get("changelog-max-size") {
+            Some(&Value::Integer(i)) if i > 0 => i as u64,
+            Some(_) => {
+                warn!("'changelog-max-size' should be a positive Integer, ignoring it");
+                default
+            },
+            None => default,
+        },
+        _ => default,
+    }
+}
+
+/// Check whether the store keeps a full-text index of the content of its entries
+///
+/// This is read from the `search-index` key in the `[store]` section and defaults to `false`:
+///
+/// ```toml
+/// [store]
+/// search-index = true
+/// ```
+pub fn is_search_index_enabled(value: &Option<Value>) -> bool {
+    match *value {
+        Some(Value::Table(ref t)) => match t.get("search-index") {
+            Some(&Value::Boolean(b)) => b,
+            Some(_) => {
+                warn!("'search-index' should be a Boolean, ignoring it");
+                false
+            },
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Get how long the store waits for a lock which is held by another process
+///
+/// This is read from the `lock-timeout` key (in seconds) in the `[store]` section:
+///
+/// ```toml
+/// [store]
+/// lock-timeout = 10
+/// ```
+///
+/// `0` makes the store fail right away if an entry is locked, a negative value makes it wait
+/// forever (returning `None`). The default is to wait 10 seconds.
+pub fn get_lock_timeout(value: &Option<Value>) -> Option<Duration> {
+    let default = Some(Duration::from_secs(10));
+    match *value {
+        Some(Value::Table(ref t)) => match t.get("lock-timeout") {
+            Some(&Value::Integer(i)) if i < 0 => None,
+            Some(&Value::Integer(i)) => Some(Duration::from_secs(i as u64)),
+            Some(_) => {
+                warn!("'lock-timeout' should be an Integer, ignoring it");
+                default
+            },
+            None => default,
+        },
+        _ => default,
+    }
+}
+
 #[derive(Debug)]
 pub struct AspectConfig {
     parallel: bool,
@@ -0,0 +1,900 @@
+//! Encryption of entries at rest
+//!
+//! With a `[store.encryption]` section in the configuration, the runtime wraps the backend of the
+//! store in an `EncryptionBackend`. It encrypts the entries of the configured modules (and single
+//! entries) when they are written and decrypts them when they are read, so the store and the
+//! modules on top of it see plain entries and need no changes:
+//!
+//! ```toml
+//! [store.encryption]
+//! # Encrypt all entries of these modules ...
+//! modules = ["diary", "notes"]
+//!
+//! # ... and these entries (paths inside the store, without version)
+//! entries = ["contact/bank"]
+//!
+//! # Also encrypt the header, except for the `imag` section
+//! encrypt-header = false
+//!
+//! # A file with at least 32 random bytes, eg. from `head -c 32 /dev/urandom`. Without it, the
+//! # key is derived from the passphrase in $IMAG_PASSPHRASE.
+//! key-file = "/home/user/.imag/store.key"
+//!
+//! # Encrypt the entries which should be encrypted but are not when the store is opened
+//! migrate-plaintext = false
+//! ```
+//!
+//! An encrypted entry is still an entry with a header, so it can be listed and linked without the
+//! key. The header gets an `imag.encryption` section and the content is replaced by the ciphertext
+//! (AES-256 in CTR mode, authenticated with HMAC-SHA256), in hex:
+//!
+//! ```text
+//! ---
+//! [imag]
+//! version = "0.2.0"
+//! links = []
+//! [imag.encryption]
+//! cipher = "aes-256-ctr"
+//! header = false
+//! nonce = "9f86d08..."
+//! mac = "2c26b46..."
+//! ---
+//! 4a1f0c2d...
+//! ```
+//!
+//! With `header = true`, the ciphertext is the whole entry and the cleartext header only has the
+//! `imag` section. The nonce is random, so equal entries do not have equal ciphertexts. The MAC
+//! covers the header in the clear and the path of the file inside the store as well, so neither
+//! can be changed and entries cannot be swapped. Moving an encrypted entry therefore encrypts it
+//! again and needs the key. The revisions of encrypted entries and the entries in snapshots are
+//! encrypted as well.
+//!
+//! Without the key, reading an encrypted entry fails with `PermissionDenied`, a modified one fails
+//! with `InvalidData`. Reading an entry in the clear which should be encrypted fails with
+//! `InvalidData` as well, as anybody who can write to the store could replace an encrypted entry
+//! with one in the clear otherwise. Entries written before they were configured to be encrypted
+//! have to be migrated with `migrate-plaintext = true`, which encrypts them when the store is
+//! opened (while no other imag process uses the store).
+//!
+//! The store keeps encrypted entries out of the header and full-text indexes. Attachments and
+//! exported archives are *not* encrypted, the store warns when they are written for encrypted
+//! entries.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{Debug, Formatter};
+use std::fmt::Error as FmtError;
+use std::fs::File;
+use std::io::Read;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::io::Result as IoResult;
+use std::path::{Path, PathBuf};
+use std::result::Result as RResult;
+use std::sync::{Arc, Mutex};
+
+use crypto::aes::{ctr, KeySize};
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::pbkdf2::pbkdf2;
+use crypto::sha2::Sha256;
+use crypto::symmetriccipher::SynchronousStreamCipher;
+use crypto::util::fixed_time_eq;
+use rand::{OsRng, Rng};
+use toml::{Parser, Value};
+
+use backend::{StoreBackend, BackendFile, BackendObject, BackendLock, BackendWatcher};
+use backend::{EntryCodec, CodecFile};
+use error::StoreErrorKind as SEK;
+use error::MapErrInto;
+use object::{OBJECTS_DIR, split_entry};
+use revision::REVISIONS_DIR;
+use store::Result;
+
+use libimagerror::into::IntoError;
+
+/// The name of the directory (inside the store) with the salt for the passphrase
+pub static ENCRYPTION_DIR : &'static str = ".encryption";
+
+/// The environment variable the passphrase is read from, if there is no key file
+pub static PASSPHRASE_VAR : &'static str = "IMAG_PASSPHRASE";
+
+static CIPHER      : &'static str = "aes-256-ctr";
+static MARKER      : &'static str = "[imag.encryption]\n";
+static ITERATIONS  : u32 = 100_000;
+static MIN_KEY_LEN : usize = 32;
+
+/// Check whether the configuration has a `[store.encryption]` section
+pub fn is_encryption_enabled(config: &Option<Value>) -> bool {
+    match *config {
+        Some(Value::Table(ref t)) => t.contains_key("encryption"),
+        _ => false,
+    }
+}
+
+/// What to encrypt, see the module documentation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncryptionSettings {
+    pub modules: Vec<String>,
+    pub entries: Vec<PathBuf>,
+    pub encrypt_header: bool,
+    pub key_file: Option<PathBuf>,
+    pub migrate_plaintext: bool,
+}
+
+impl EncryptionSettings {
+
+    /// Read the settings from the `[store]` section of the configuration
+    pub fn from_config(config: &Option<Value>) -> Result<EncryptionSettings> {
+        let table = match *config {
+            Some(Value::Table(ref t)) => match t.get("encryption") {
+                Some(&Value::Table(ref t)) => t,
+                Some(_) => return Err(SEK::EncryptionConfigError.into_error()),
+                None    => return Ok(EncryptionSettings::default()),
+            },
+            _ => return Ok(EncryptionSettings::default()),
+        };
+
+        let mut settings = EncryptionSettings {
+            modules: try!(strings(table, "modules")),
+            entries: try!(strings(table, "entries")).into_iter().map(PathBuf::from).collect(),
+            encrypt_header: false,
+            key_file: None,
+            migrate_plaintext: false,
+        };
+
+        for (key, value) in table.iter() {
+            match (&key[..], value) {
+                ("modules", _) | ("entries", _) => {},
+                ("encrypt-header", &Value::Boolean(b)) => settings.encrypt_header = b,
+                ("key-file", &Value::String(ref s)) => {
+                    settings.key_file = Some(PathBuf::from(s))
+                },
+                ("migrate-plaintext", &Value::Boolean(b)) => settings.migrate_plaintext = b,
+                _ => return Err(SEK::EncryptionConfigError.into_error()),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Whether the entry at `path` (inside the store) is to be encrypted
+    pub fn covers(&self, path: &Path) -> bool {
+        let unversioned = without_version(path);
+        self.modules.iter().any(|m| path.starts_with(m)) ||
+            self.entries.iter().any(|e| *e == unversioned)
+    }
+
+}
+
+fn strings(table: &BTreeMap<String, Value>, key: &str) -> Result<Vec<String>> {
+    match table.get(key) {
+        None => Ok(vec![]),
+        Some(&Value::Array(ref a)) => a.iter()
+            .map(|v| match *v {
+                Value::String(ref s) => Ok(s.clone()),
+                _ => Err(SEK::EncryptionConfigError.into_error()),
+            })
+            .collect(),
+        Some(_) => Err(SEK::EncryptionConfigError.into_error()),
+    }
+}
+
+/// Strip the `~<version>` from the last component of `path`
+fn without_version(path: &Path) -> PathBuf {
+    match path.file_name().and_then(|n| n.to_str()).and_then(|n| n.rfind('~').map(|i| &n[..i])) {
+        Some(name) => path.with_file_name(name),
+        None => path.to_path_buf(),
+    }
+}
+
+/// The keys for encrypting and authenticating entries
+#[derive(Clone)]
+pub struct Keys {
+    encryption: Vec<u8>,
+    mac: Vec<u8>,
+}
+
+impl Keys {
+
+    /// Derive the keys from the content of a key file, which must have at least 32 bytes
+    pub fn from_key(key: &[u8]) -> Result<Keys> {
+        if key.len() < MIN_KEY_LEN {
+            return Err(SEK::EncryptionConfigError.into_error());
+        }
+
+        Ok(Keys {
+            encryption: hmac(key, &[&b"imag-encryption-key"[..]]),
+            mac: hmac(key, &[&b"imag-mac-key"[..]]),
+        })
+    }
+
+    /// Derive the keys from a passphrase with PBKDF2
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Keys {
+        let mut out = [0u8; 64];
+        let mut mac = Hmac::new(Sha256::new(), passphrase.as_bytes());
+        pbkdf2(&mut mac, salt, ITERATIONS, &mut out);
+
+        Keys {
+            encryption: out[..32].to_vec(),
+            mac: out[32..].to_vec(),
+        }
+    }
+
+}
+
+impl Debug for Keys {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        write!(fmt, "Keys {{ .. }}")
+    }
+
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let mut mac = Hmac::new(Sha256::new(), key);
+    for part in parts {
+        mac.input(part);
+    }
+    mac.result().code().to_vec()
+}
+
+fn apply_keystream(key: &[u8], nonce: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0; data.len()];
+    ctr(KeySize::KeySize256, key, nonce).process(data, &mut out);
+    out
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    let digits : Vec<u8> = s.bytes().filter(|b| !(*b as char).is_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+
+    digits.chunks(2)
+        .map(|pair| {
+            ::std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect()
+}
+
+/// The header of an entry, between the `---` lines
+fn header_text(header_part: &[u8]) -> Option<&str> {
+    if header_part.len() < 8 {
+        return None;
+    }
+    ::std::str::from_utf8(&header_part[4..(header_part.len() - 4)]).ok()
+}
+
+/// Where the `imag.encryption` section starts in the header `text`, if it has one
+fn marker_position(text: &str) -> Option<usize> {
+    text.rfind(MARKER).and_then(|pos| {
+        if pos == 0 || text[..pos].ends_with('\n') { Some(pos) } else { None }
+    })
+}
+
+/// The parts of an encrypted entry
+struct Encrypted<'a> {
+    /// The header in the clear, without the `imag.encryption` section
+    header: &'a str,
+
+    /// Whether the ciphertext is the whole entry or only the content
+    whole: bool,
+    nonce: Vec<u8>,
+    mac: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+impl<'a> Encrypted<'a> {
+
+    /// Parse `data`, `Ok(None)` if it is no encrypted entry
+    fn parse(data: &'a [u8]) -> IoResult<Option<Encrypted<'a>>> {
+        let parts = split_entry(data);
+        if parts.len() != 2 {
+            return Ok(None);
+        }
+
+        let text = match header_text(parts[0]) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let pos = match marker_position(text) {
+            Some(pos) => pos,
+            None => return Ok(None),
+        };
+
+        let section = Parser::new(&text[pos..])
+            .parse()
+            .and_then(|mut t| t.remove("imag"))
+            .and_then(|imag| match imag {
+                Value::Table(mut t) => t.remove("encryption"),
+                _ => None,
+            });
+        let section = match section {
+            Some(Value::Table(t)) => t,
+            _ => return Err(invalid_data("Invalid encryption section")),
+        };
+
+        match section.get("cipher") {
+            Some(&Value::String(ref c)) if c == CIPHER => {},
+            _ => return Err(invalid_data("Unknown cipher")),
+        }
+
+        let hex = |key: &str| match section.get(key) {
+            Some(&Value::String(ref s)) => from_hex(s),
+            _ => None,
+        };
+
+        match (section.get("header"), hex("nonce"), hex("mac"), from_utf8_hex(parts[1])) {
+            (Some(&Value::Boolean(whole)), Some(nonce), Some(mac), Some(ciphertext)) => {
+                Ok(Some(Encrypted {
+                    header: &text[..pos],
+                    whole: whole,
+                    nonce: nonce,
+                    mac: mac,
+                    ciphertext: ciphertext,
+                }))
+            },
+            _ => Err(invalid_data("Invalid encryption section")),
+        }
+    }
+
+    /// Authenticate `ciphertext`, with the path `rel` of the file inside the store and the
+    /// `header` in the clear as associated data
+    fn mac(keys: &Keys, whole: bool, rel: &Path, header: &str, nonce: &[u8], ciphertext: &[u8])
+        -> Vec<u8>
+    {
+        let flag : &[u8] = if whole { b"1" } else { b"0" };
+        let rel          = format!("{}", rel.display());
+        let lengths      = format!("{}:{}:", rel.len(), header.len());
+        hmac(&keys.mac, &[flag, lengths.as_bytes(), rel.as_bytes(), header.as_bytes(), nonce,
+                          ciphertext])
+    }
+
+    /// Decrypt the file at `rel` (inside the store)
+    fn decrypt(&self, keys: &Keys, rel: &Path) -> IoResult<Vec<u8>> {
+        let mac = Encrypted::mac(keys, self.whole, rel, self.header, &self.nonce, &self.ciphertext);
+        if !fixed_time_eq(&mac, &self.mac) {
+            return Err(invalid_data("Entry was modified or the key is wrong"));
+        }
+
+        let plaintext = apply_keystream(&keys.encryption, &self.nonce, &self.ciphertext);
+        if self.whole {
+            Ok(plaintext)
+        } else {
+            let mut data = format!("---\n{}---\n", self.header).into_bytes();
+            data.extend(plaintext);
+            Ok(data)
+        }
+    }
+
+}
+
+fn from_utf8_hex(data: &[u8]) -> Option<Vec<u8>> {
+    ::std::str::from_utf8(data).ok().and_then(from_hex)
+}
+
+fn invalid_data(msg: &str) -> IoError {
+    IoError::new(IoErrorKind::InvalidData, msg)
+}
+
+fn no_key() -> IoError {
+    IoError::new(IoErrorKind::PermissionDenied, "No key to encrypt or decrypt entries")
+}
+
+/// Encrypt the entry `data` for the file at `rel` (inside the store), keeping the header (or only
+/// its `imag` section) in the clear
+fn encrypt(keys: &Keys, encrypt_header: bool, rel: &Path, data: &[u8]) -> IoResult<Vec<u8>> {
+    let parts  = split_entry(data);
+    let header = if parts.len() == 2 { header_text(parts[0]) } else { None };
+
+    let (mut header, plaintext, whole) = match header {
+        Some(text) if !encrypt_header => (String::from(text), parts[1], false),
+        Some(text) => {
+            let imag = Parser::new(text).parse().and_then(|mut t| t.remove("imag"));
+            let header = match imag {
+                Some(imag) => {
+                    let mut t = BTreeMap::new();
+                    t.insert(String::from("imag"), imag);
+                    ::toml::encode_str(&Value::Table(t))
+                },
+                None => String::new(),
+            };
+            (header, data, true)
+        },
+        None => (String::new(), data, true),
+    };
+
+    if !header.is_empty() && !header.ends_with('\n') {
+        header.push('\n');
+    }
+
+    let mut nonce = vec![0u8; 16];
+    try!(OsRng::new()).fill_bytes(&mut nonce);
+
+    let ciphertext = apply_keystream(&keys.encryption, &nonce, plaintext);
+    let mac        = Encrypted::mac(keys, whole, rel, &header, &nonce, &ciphertext);
+    let mut text = format!("---\n{}{}cipher = \"{}\"\nheader = {}\nnonce = \"{}\"\nmac = \"{}\"\n",
+                           header, MARKER, CIPHER, whole, to_hex(&nonce), to_hex(&mac));
+    text.push_str("---\n");
+    for line in to_hex(&ciphertext).as_bytes().chunks(64) {
+        text.push_str(&String::from_utf8_lossy(line));
+        text.push('\n');
+    }
+    Ok(text.into_bytes())
+}
+
+/// What a file inside the store is, as far as encryption is concerned
+enum Kind {
+    /// An entry or a revision of the entry, with the path of the entry
+    Entry(PathBuf),
+    Object,
+    Other,
+}
+
+/// A backend which encrypts the entries of a store in another backend, see the module
+/// documentation
+#[derive(Clone)]
+pub struct EncryptionBackend {
+    location: PathBuf,
+    inner: Arc<Mutex<Box<StoreBackend>>>,
+    settings: EncryptionSettings,
+    keys: Option<Keys>,
+}
+
+impl EncryptionBackend {
+
+    /// Wrap `inner` for the store at `location`
+    ///
+    /// Without `keys`, plain entries can still be read and written, but encrypted ones cannot.
+    pub fn new(location: PathBuf,
+               inner: Box<StoreBackend>,
+               settings: EncryptionSettings,
+               keys: Option<Keys>)
+        -> EncryptionBackend
+    {
+        EncryptionBackend {
+            location: location,
+            inner: Arc::new(Mutex::new(inner)),
+            settings: settings,
+            keys: keys,
+        }
+    }
+
+    /// Wrap `inner` for the store at `location` as configured in the `[store]` section of the
+    /// configuration
+    ///
+    /// The keys are read from the key file, or derived from the passphrase in `$IMAG_PASSPHRASE`
+    /// with the salt of the store, which is created if necessary.
+    pub fn from_config(location: PathBuf, inner: Box<StoreBackend>, config: &Option<Value>)
+        -> Result<EncryptionBackend>
+    {
+        let settings = try!(EncryptionSettings::from_config(config));
+
+        let keys = match settings.key_file {
+            Some(ref path) => {
+                let key = try!(File::open(path)
+                    .and_then(|mut f| {
+                        let mut key = vec![];
+                        f.read_to_end(&mut key).map(|_| key)
+                    })
+                    .map_err_into(SEK::EncryptionConfigError));
+                Some(try!(Keys::from_key(&key)))
+            },
+            None => match env::var(PASSPHRASE_VAR) {
+                Ok(passphrase) => {
+                    let salt = try!(salt(&**inner, &location).map_err_into(SEK::EncryptionError));
+                    Some(Keys::from_passphrase(&passphrase, &salt))
+                },
+                Err(_) => {
+                    warn!("No key file configured and ${} not set", PASSPHRASE_VAR);
+                    warn!("Encrypted entries cannot be read or written");
+                    None
+                },
+            },
+        };
+
+        let backend = EncryptionBackend::new(location, inner, settings, keys);
+        if backend.settings.migrate_plaintext {
+            let encrypted = try!(backend.encrypt_plaintext().map_err_into(SEK::EncryptionError));
+            info!("Encrypted {} files which were in the clear", encrypted.len());
+        }
+        Ok(backend)
+    }
+
+    /// Encrypt the files which should be encrypted but are in the clear, returns their paths
+    ///
+    /// No other process may use the store meanwhile, as the files are not locked.
+    pub fn encrypt_plaintext(&self) -> IoResult<Vec<PathBuf>> {
+        let keys = try!(self.keys.as_ref().ok_or_else(no_key));
+        if !self.exists(&self.location) {
+            return Ok(vec![]);
+        }
+
+        let mut encrypted = vec![];
+        for obj in try!(self.walk(&self.location)) {
+            let path = match obj {
+                BackendObject::File(ref p) if self.must_be_encrypted(p) => p.clone(),
+                _ => continue,
+            };
+
+            let data = try!(self.with_inner(|inner| inner.read(&path)));
+            if data.is_empty() || try!(Encrypted::parse(&data)).is_some() {
+                continue;
+            }
+
+            debug!("Encrypting {:?}", path);
+            let data = try!(encrypt(keys, self.settings.encrypt_header, self.relative(&path),
+                                    &data));
+            try!(self.with_inner(|inner| inner.write(&path, &data)));
+            encrypted.push(path);
+        }
+        Ok(encrypted)
+    }
+
+    fn with_inner<T, F>(&self, f: F) -> IoResult<T>
+        where F: FnOnce(&StoreBackend) -> IoResult<T>
+    {
+        self.inner
+            .lock()
+            .map_err(|_| IoError::new(IoErrorKind::Other, "Encryption backend lock poisoned"))
+            .and_then(|inner| f(&**inner))
+    }
+
+    fn kind(&self, path: &Path) -> Kind {
+        let rel = match path.strip_prefix(&self.location) {
+            Ok(rel) => rel,
+            Err(_) => return Kind::Other,
+        };
+
+        let first = rel.components()
+            .next()
+            .and_then(|c| c.as_os_str().to_str())
+            .unwrap_or("");
+
+        if first == REVISIONS_DIR {
+            // .revisions/<path of the entry>/<number>
+            match rel.strip_prefix(REVISIONS_DIR).ok().and_then(|p| p.parent()) {
+                Some(entry) => Kind::Entry(entry.to_path_buf()),
+                None => Kind::Other,
+            }
+        } else if first == OBJECTS_DIR {
+            Kind::Object
+        } else if first.starts_with('.') || first.is_empty() {
+            Kind::Other
+        } else {
+            Kind::Entry(rel.to_path_buf())
+        }
+    }
+
+    /// Get `path` relative to the store, which the MAC of an encrypted file covers
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.location).unwrap_or(path)
+    }
+
+    /// Decrypt `data`, read from `path`, `Ok(None)` if it is not encrypted
+    ///
+    /// Fails if `path` should be encrypted but is not, unless it is empty (it is being created).
+    fn decrypt(&self, path: &Path, data: &[u8]) -> IoResult<Option<Vec<u8>>> {
+        match try!(Encrypted::parse(data)) {
+            None if data.is_empty() || !self.must_be_encrypted(path) => Ok(None),
+            None => Err(invalid_data("Entry should be encrypted, see 'migrate-plaintext'")),
+            Some(encrypted) => {
+                let keys = try!(self.keys.as_ref().ok_or_else(no_key));
+                encrypted.decrypt(keys, self.relative(path)).map(Some)
+            },
+        }
+    }
+
+    /// Get the encrypted files at or below `path`, decrypted
+    fn decrypted_at(&self, path: &Path) -> IoResult<Vec<(PathBuf, Vec<u8>)>> {
+        let files = if self.is_file(path) {
+            vec![path.to_path_buf()]
+        } else {
+            try!(self.walk(path))
+                .into_iter()
+                .filter_map(|obj| match obj {
+                    BackendObject::File(p) => Some(p),
+                    BackendObject::Directory(_) => None,
+                })
+                .collect()
+        };
+
+        let mut decrypted = vec![];
+        for file in files.into_iter().filter(|f| self.is_transformed(f)) {
+            let data = try!(self.with_inner(|inner| inner.read(&file)));
+            if let Some(plain) = try!(self.decrypt(&file, &data)) {
+                decrypted.push((file, plain));
+            }
+        }
+        Ok(decrypted)
+    }
+
+    /// Whether the files at `path` are en- and decrypted
+    fn is_transformed(&self, path: &Path) -> bool {
+        match self.kind(path) {
+            Kind::Other => false,
+            _ => true,
+        }
+    }
+
+    /// Whether the file at `path` is an entry (or a revision of one) which has to be encrypted
+    fn must_be_encrypted(&self, path: &Path) -> bool {
+        match self.kind(path) {
+            Kind::Entry(ref entry) => self.settings.covers(entry),
+            _ => false,
+        }
+    }
+
+    /// Whether `content` is to be encrypted when written to `path`
+    ///
+    /// Objects are encrypted whenever they are entries, as they cannot be traced back to the
+    /// entry they were taken of.
+    fn is_encrypted(&self, path: &Path, content: &[u8]) -> bool {
+        match self.kind(path) {
+            Kind::Entry(ref entry) => self.settings.covers(entry),
+            Kind::Object => self.keys.is_some() && content.starts_with(b"---\n"),
+            Kind::Other => false,
+        }
+    }
+
+}
+
+/// Get the salt for the passphrase of the store at `location`, create it if there is none
+fn salt(backend: &StoreBackend, location: &Path) -> IoResult<Vec<u8>> {
+    let mut path = location.to_path_buf();
+    path.push(ENCRYPTION_DIR);
+    path.push("salt");
+
+    if backend.is_file(&path) {
+        return backend.read(&path).and_then(|data| {
+            from_utf8_hex(&data).ok_or_else(|| invalid_data("Invalid salt"))
+        });
+    }
+
+    let mut salt = vec![0u8; 16];
+    try!(OsRng::new()).fill_bytes(&mut salt);
+    try!(backend.create_dir_all(path.parent().unwrap_or(location)));
+    backend.write(&path, to_hex(&salt).as_bytes()).map(|_| salt)
+}
+
+impl EntryCodec for EncryptionBackend {
+
+    fn read_entry(&self, path: &Path) -> IoResult<Vec<u8>> {
+        let data = try!(self.with_inner(|inner| inner.read(path)));
+        self.decrypt(path, &data).map(|plain| plain.unwrap_or(data))
+    }
+
+    fn write_entry(&self, path: &Path, content: &[u8]) -> IoResult<()> {
+        if !self.is_encrypted(path, content) {
+            return self.with_inner(|inner| inner.write(path, content));
+        }
+
+        let keys = try!(self.keys.as_ref().ok_or_else(no_key));
+        let data = try!(encrypt(keys, self.settings.encrypt_header, self.relative(path), content));
+        self.with_inner(|inner| inner.write(path, &data))
+    }
+
+}
+
+impl Debug for EncryptionBackend {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        write!(fmt, "EncryptionBackend {{ location: {:?}, settings: {:?} }}",
+               self.location, self.settings)
+    }
+
+}
+
+impl StoreBackend for EncryptionBackend {
+
+    fn open(&self, path: &Path) -> IoResult<Box<BackendFile>> {
+        if !self.is_transformed(path) {
+            return self.with_inner(|inner| inner.open(path));
+        }
+
+        CodecFile::open(self.clone(), path).map(|f| Box::new(f) as Box<BackendFile>)
+    }
+
+    fn create(&self, path: &Path) -> IoResult<Box<BackendFile>> {
+        if !self.is_transformed(path) {
+            return self.with_inner(|inner| inner.create(path));
+        }
+
+        if !self.is_file(path) {
+            try!(self.with_inner(|inner| inner.write(path, b"")));
+        }
+        self.open(path)
+    }
+
+    fn remove(&self, path: &Path) -> IoResult<()> {
+        self.with_inner(|inner| inner.remove(path))
+    }
+
+    /// Encrypted files are bound to their path, so they are encrypted again at (or below) `to`, or
+    /// written in the clear if `to` is not covered
+    fn rename(&self, from: &Path, to: &Path) -> IoResult<()> {
+        let decrypted = try!(self.decrypted_at(from));
+        try!(self.with_inner(|inner| inner.rename(from, to)));
+
+        for (file, plain) in decrypted {
+            let mut moved = to.to_path_buf();
+            if let Ok(rel) = file.strip_prefix(from) {
+                if rel.components().next().is_some() {
+                    moved.push(rel);
+                }
+            }
+            try!(self.write_entry(&moved, &plain));
+        }
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> IoResult<()> {
+        self.with_inner(|inner| inner.create_dir_all(path))
+    }
+
+    fn remove_dir(&self, path: &Path) -> IoResult<()> {
+        self.with_inner(|inner| inner.remove_dir(path))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.with_inner(|inner| Ok(inner.exists(path))).unwrap_or(false)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.with_inner(|inner| Ok(inner.is_file(path))).unwrap_or(false)
+    }
+
+    fn walk(&self, path: &Path) -> IoResult<Vec<BackendObject>> {
+        self.with_inner(|inner| inner.walk(path))
+    }
+
+    fn try_lock(&self, path: &Path) -> IoResult<Option<Box<BackendLock>>> {
+        self.with_inner(|inner| inner.try_lock(path))
+    }
+
+    fn watch(&self, path: &Path) -> IoResult<Box<BackendWatcher>> {
+        self.with_inner(|inner| inner.watch(path))
+    }
+
+    fn read(&self, path: &Path) -> IoResult<Vec<u8>> {
+        if self.is_transformed(path) {
+            self.read_entry(path)
+        } else {
+            self.with_inner(|inner| inner.read(path))
+        }
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> IoResult<()> {
+        if self.is_transformed(path) {
+            self.write_entry(path, content)
+        } else {
+            self.with_inner(|inner| inner.write(path, content))
+        }
+    }
+
+    fn referenced_objects(&self, path: &Path) -> IoResult<Vec<String>> {
+        self.with_inner(|inner| inner.referenced_objects(path))
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+
+    use backend::{StoreBackend, InMemoryBackend};
+
+    use super::{EncryptionBackend, EncryptionSettings, Keys};
+
+    static ENTRY : &'static [u8] =
+        b"---\n[imag]\nversion = \"0.2.0\"\n\n[diary]\nmood = \"good\"\n---\nDear diary";
+
+    fn backend(inner: &InMemoryBackend, encrypt_header: bool, key: bool) -> EncryptionBackend {
+        let settings = EncryptionSettings {
+            modules: vec![String::from("diary")],
+            encrypt_header: encrypt_header,
+            ..EncryptionSettings::default()
+        };
+        let keys = if key { Some(Keys::from_key(&[42; 32]).unwrap()) } else { None };
+        EncryptionBackend::new(PathBuf::from("/store"), Box::new(inner.clone()), settings, keys)
+    }
+
+    #[test]
+    fn test_entries_are_encrypted() {
+        let inner = InMemoryBackend::new();
+        let a     = PathBuf::from("/store/diary/a~0.2.0");
+        let b     = PathBuf::from("/store/notes/b~0.2.0");
+
+        backend(&inner, false, true).write(&a, ENTRY).unwrap();
+        backend(&inner, false, true).write(&b, ENTRY).unwrap();
+
+        let on_disk = String::from_utf8(inner.read(&a).unwrap()).unwrap();
+        assert!(!on_disk.contains("Dear diary"));
+        assert!(on_disk.contains("mood = \"good\""));
+        assert_eq!(inner.read(&b).unwrap(), ENTRY.to_vec());
+        assert_eq!(backend(&inner, false, true).read(&a).unwrap(), ENTRY.to_vec());
+
+        backend(&inner, true, true).write(&a, ENTRY).unwrap();
+        let on_disk = String::from_utf8(inner.read(&a).unwrap()).unwrap();
+        assert!(!on_disk.contains("mood"));
+        assert!(on_disk.contains("version = \"0.2.0\""));
+        assert_eq!(backend(&inner, false, true).read(&a).unwrap(), ENTRY.to_vec());
+
+        let err = backend(&inner, false, false).read(&a).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(backend(&inner, false, false).read(&b).unwrap(), ENTRY.to_vec());
+
+        let pos = on_disk.rfind("\n---\n").unwrap() + 5;
+        let mut tampered = on_disk.into_bytes();
+        tampered[pos] = if tampered[pos] == b'a' { b'b' } else { b'a' };
+        inner.write(&a, &tampered).unwrap();
+        let err = backend(&inner, false, true).read(&a).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_header_and_path_are_authenticated() {
+        let inner = InMemoryBackend::new();
+        let a     = PathBuf::from("/store/diary/a~0.2.0");
+        let b     = PathBuf::from("/store/diary/b~0.2.0");
+        let c     = PathBuf::from("/store/diary/c~0.2.0");
+
+        backend(&inner, false, true).write(&a, ENTRY).unwrap();
+        let on_disk = String::from_utf8(inner.read(&a).unwrap()).unwrap();
+
+        inner.write(&b, on_disk.replace("good", "bad").as_bytes()).unwrap();
+        let err = backend(&inner, false, true).read(&b).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        inner.write(&b, on_disk.as_bytes()).unwrap();
+        let err = backend(&inner, false, true).read(&b).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        backend(&inner, false, true).rename(&a, &c).unwrap();
+        assert_eq!(backend(&inner, false, true).read(&c).unwrap(), ENTRY.to_vec());
+        assert!(!String::from_utf8(inner.read(&c).unwrap()).unwrap().contains("Dear diary"));
+    }
+
+    #[test]
+    fn test_nonce_is_random() {
+        let inner = InMemoryBackend::new();
+        let a     = PathBuf::from("/store/diary/a~0.2.0");
+
+        backend(&inner, false, true).write(&a, ENTRY).unwrap();
+        let first = inner.read(&a).unwrap();
+        backend(&inner, false, true).write(&a, ENTRY).unwrap();
+        assert!(first != inner.read(&a).unwrap());
+        assert_eq!(backend(&inner, false, true).read(&a).unwrap(), ENTRY.to_vec());
+    }
+
+    #[test]
+    fn test_plaintext_entries_are_rejected() {
+        let inner = InMemoryBackend::new();
+        let a     = PathBuf::from("/store/diary/a~0.2.0");
+        let b     = PathBuf::from("/store/notes/b~0.2.0");
+
+        inner.write(&a, ENTRY).unwrap();
+        inner.write(&b, ENTRY).unwrap();
+        let err = backend(&inner, false, true).read(&a).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // A new entry is empty until it is written
+        inner.write(&a, b"").unwrap();
+        assert!(backend(&inner, false, true).read(&a).unwrap().is_empty());
+
+        inner.write(&a, ENTRY).unwrap();
+        let encrypted = backend(&inner, false, true).encrypt_plaintext().unwrap();
+        assert_eq!(encrypted, vec![a.clone()]);
+        assert!(!String::from_utf8(inner.read(&a).unwrap()).unwrap().contains("Dear diary"));
+        assert_eq!(backend(&inner, false, true).read(&a).unwrap(), ENTRY.to_vec());
+        assert_eq!(inner.read(&b).unwrap(), ENTRY.to_vec());
+    }
+
+}
@@ -15,6 +15,7 @@ generate_custom_error_types!(StoreError, StoreErrorKind, CustomErrorData,
     FileNotCreated          => "File corresponding to ID could not be created",
     StorePathExists         => "Store path exists",
     StorePathCreate         => "Store path create",
+    StoreReadOnly           => "The store is opened read-only",
     LockError               => "Error locking datastructure",
     LockPoisoned            => "The internal Store Lock has been poisoned",
     EntryAlreadyBorrowed    => "Entry is already borrowed",
@@ -23,7 +24,9 @@ generate_custom_error_types!(StoreError, StoreErrorKind, CustomErrorData,
     HeaderPathSyntaxError   => "Syntax error in accessor string",
     HeaderPathTypeFailure   => "Header has wrong type for path",
     HeaderKeyNotFound       => "Header Key not found",
+    HeaderPathNotSingular   => "Header path does not denote a single field",
     HeaderTypeFailure       => "Header type is wrong",
+    HeaderSectionTypeError  => "Header section does not match the expected type",
     HookRegisterError       => "Hook register error",
     AspectNameNotFoundError => "Aspect name not found",
     HookExecutionError      => "Hook execution error",
@@ -35,17 +38,79 @@ generate_custom_error_types!(StoreError, StoreErrorKind, CustomErrorData,
     StorePathError          => "Store Path error",
     EntryRenameError        => "Entry rename error",
     StoreIdHandlingError    => "StoreId handling error",
+    TransactionError        => "Transaction error",
+    JournalReplayError      => "Replaying the transaction journal failed",
+    RevisionNotFound        => "Revision not found",
+    RevisionError           => "Error while handling revisions",
+    SchemaViolation         => "Header violates a registered schema",
+    SchemaError             => "Error while handling header schemas",
+    MigrationError          => "Error while migrating entry",
+    IndexError              => "Error while handling the header index",
+    NotIndexed              => "Header path is not indexed",
+    SearchIndexError        => "Error while handling the search index",
+    EntryLockedByOtherProcess => "Entry is locked by another process",
+    StoreLockedByOtherProcess => "Store is locked by another process",
+    ChangelogError          => "Error while handling the change log",
+    WatchError              => "Error while watching the store",
+    AttachmentError         => "Error while handling attachments",
+    AttachmentNotFound      => "Attachment not found",
+    AttachmentCorrupted     => "Attachment does not match its hash",
+    ObjectError             => "Error while handling objects",
+    SnapshotError           => "Error while handling snapshots",
+    SnapshotNotFound        => "Snapshot not found",
+    SnapshotExists          => "Snapshot exists already",
+    SnapshotScopeError      => "Path is not covered by the snapshot",
+    ArchiveError            => "Error while handling an archive",
+    ArchiveCorrupted        => "Archive is corrupted",
+    JsonError               => "Error while converting from or to JSON",
+    YamlError               => "Error while converting from or to YAML",
+    EncryptionError         => "Error while encrypting or decrypting an entry",
+    EncryptionConfigError   => "Encryption is not configured correctly",
+    WorkerError             => "A worker thread failed",
 
     CreateCallError            => "Error when calling create()",
     RetrieveCallError          => "Error when calling retrieve()",
     GetCallError               => "Error when calling get()",
-    GetAllVersionsCallError    => "Error when calling get_all_versions()",
     RetrieveForModuleCallError => "Error when calling retrieve_for_module()",
     UpdateCallError            => "Error when calling update()",
     RetrieveCopyCallError      => "Error when calling retrieve_copy()",
     DeleteCallError            => "Error when calling delete()",
     MoveCallError              => "Error when calling move()",
-    MoveByIdCallError          => "Error when calling move_by_id()"
+    SaveToCallError            => "Error when calling save_to()",
+    MoveByIdCallError          => "Error when calling move_by_id()",
+    CommitCallError            => "Error when calling commit()",
+    RevisionsCallError         => "Error when calling revisions()",
+    RetrieveRevisionCallError  => "Error when calling retrieve_revision()",
+    DiffRevisionsCallError     => "Error when calling diff_revisions()",
+    RestoreRevisionCallError   => "Error when calling restore_revision()",
+    RegisterSchemaCallError    => "Error when calling register_schema()",
+    MigrateCallError           => "Error when calling migrate()",
+    QueryIndexCallError        => "Error when calling query_index()",
+    FindByHeaderCallError      => "Error when calling find_by_header()",
+    RebuildIndexCallError      => "Error when calling rebuild_index()",
+    SearchCallError            => "Error when calling search()",
+    RebuildSearchIndexCallError => "Error when calling rebuild_search_index()",
+    ChangesSinceCallError      => "Error when calling changes_since()",
+    ChangesFromCallError       => "Error when calling changes_from()",
+    WatchCallError             => "Error when calling watch()",
+    AddAttachmentCallError     => "Error when calling add_attachment()",
+    ReadAttachmentCallError    => "Error when calling read_attachment()",
+    RemoveAttachmentCallError  => "Error when calling remove_attachment()",
+    GcAttachmentsCallError     => "Error when calling gc_attachments()",
+    SameContentCallError       => "Error when calling same_content()",
+    SnapshotCallError          => "Error when calling snapshot()",
+    SnapshotsCallError         => "Error when calling snapshots()",
+    DiffSnapshotCallError      => "Error when calling diff_snapshot()",
+    RestoreSnapshotCallError   => "Error when calling restore_snapshot()",
+    ExportCallError            => "Error when calling export()",
+    ImportCallError            => "Error when calling import()",
+    IdsCallError               => "Error when calling ids()",
+    GcCallError                => "Error when calling gc()",
+    SelectCallError            => "Error when calling select()",
+    ReadHeaderCallError        => "Error when calling read_header()",
+    ReadRawCallError           => "Error when calling read_raw()",
+    GetReadOnlyCallError       => "Error when calling get_read_only()",
+    ForceUpdateCallError       => "Error when calling force_update()"
 );
 
 generate_result_helper!(StoreError, StoreErrorKind);
@@ -0,0 +1,369 @@
+//! Entries in other formats than the TOML frontmatter
+//!
+//! Entries are kept in the store as TOML header between `---` lines followed by the content (see
+//! `Entry::to_str()`). For programs which do not speak TOML, entries can be converted to and from
+//! JSON and YAML, where an entry is an object with the header (as an object) and the content (as a
+//! string):
+//!
+//! ```json
+//! {
+//!   "header": { "imag": { "version": "0.2.0", "links": [] } },
+//!   "content": "Some text"
+//! }
+//! ```
+//!
+//! JSON and YAML have no datetimes, so a TOML datetime becomes an object with the single key
+//! `$datetime`, eg. `{ "$datetime": "2016-08-01T12:00:00Z" }`, and such an object becomes a
+//! datetime again when converting back. Strings stay strings, whatever they look like. Keys which
+//! could be mistaken for `$datetime` are escaped with another `$`, so a key `$datetime` is written
+//! as `$$datetime`, `$$datetime` as `$$$datetime` and so on. There is nothing like `null` in TOML,
+//! so it cannot be converted.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fmt::Error as FmtError;
+use std::result::Result as RResult;
+use std::str::FromStr;
+
+use regex::Regex;
+use serde_json::Value as Json;
+use toml::Value;
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash as YamlHash;
+
+use error::StoreErrorKind as SEK;
+use store::Result;
+
+use libimagerror::into::IntoError;
+
+/// The formats an entry can be read from and written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The format of the store, TOML header between `---` lines followed by the content
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> RResult<Format, ()> {
+        match s {
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            "yaml" => Ok(Format::Yaml),
+            _      => Err(()),
+        }
+    }
+
+}
+
+impl Display for Format {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        let s = match *self {
+            Format::Toml => "toml",
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+        };
+        write!(fmt, "{}", s)
+    }
+
+}
+
+/// The key of the object a datetime is written as in JSON and YAML
+pub static DATETIME_TAG : &'static str = "$datetime";
+
+fn is_datetime(s: &str) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(\.\d+)?Z$")
+            .unwrap();
+    }
+    RE.is_match(s)
+}
+
+/// Whether the key `k` is `DATETIME_TAG` with any number of `$` in front of it
+fn is_tag_like(k: &str) -> bool {
+    let name = DATETIME_TAG.trim_left_matches('$');
+    k.len() > name.len() && k.ends_with(name) &&
+        k[..(k.len() - name.len())].chars().all(|c| c == '$')
+}
+
+/// Escape the key `k` of a table, so it is not mistaken for `DATETIME_TAG`
+fn escape_key(k: &str) -> String {
+    if is_tag_like(k) { format!("${}", k) } else { String::from(k) }
+}
+
+/// Undo `escape_key()`
+fn unescape_key(k: &str) -> String {
+    if is_tag_like(k) && k != DATETIME_TAG { String::from(&k[1..]) } else { String::from(k) }
+}
+
+/// Get the datetime `s`, which was tagged with `DATETIME_TAG`
+fn tagged_to_toml(s: &str) -> Option<Value> {
+    if is_datetime(s) {
+        Some(Value::Datetime(String::from(s)))
+    } else {
+        None
+    }
+}
+
+/// Write `f` so YAML reads it as float again, ie. with a decimal point
+fn float_to_yaml(f: f64) -> String {
+    if f.is_nan() {
+        String::from(".nan")
+    } else if f.is_infinite() {
+        String::from(if f > 0.0 { ".inf" } else { "-.inf" })
+    } else {
+        let s = format!("{}", f);
+        if s.contains('.') || s.contains('e') { s } else { s + ".0" }
+    }
+}
+
+fn float_from_yaml(s: &str) -> Result<f64> {
+    use std::f64::{INFINITY, NEG_INFINITY, NAN};
+
+    match s {
+        ".nan" | ".NaN" | ".NAN"          => Ok(NAN),
+        ".inf" | ".Inf" | ".INF" | "+.inf" => Ok(INFINITY),
+        "-.inf" | "-.Inf" | "-.INF"       => Ok(NEG_INFINITY),
+        _ => f64::from_str(s).map_err(|_| SEK::YamlError.into_error()),
+    }
+}
+
+pub fn toml_to_json(v: &Value) -> Json {
+    match *v {
+        Value::String(ref s)   => Json::String(s.clone()),
+        Value::Integer(i)      => Json::I64(i),
+        Value::Float(f)        => Json::F64(f),
+        Value::Boolean(b)      => Json::Bool(b),
+        Value::Datetime(ref s) => {
+            let mut o = BTreeMap::new();
+            o.insert(String::from(DATETIME_TAG), Json::String(s.clone()));
+            Json::Object(o)
+        },
+        Value::Array(ref a)    => Json::Array(a.iter().map(toml_to_json).collect()),
+        Value::Table(ref t)    => {
+            Json::Object(t.iter().map(|(k, v)| (escape_key(k), toml_to_json(v))).collect())
+        },
+    }
+}
+
+/// Convert `v` to TOML, fails with `JsonError` for `null`, integers which are too large,
+/// datetimes which are malformed and objects with `DATETIME_TAG` and other keys
+pub fn json_to_toml(v: &Json) -> Result<Value> {
+    match *v {
+        Json::Null           => Err(SEK::JsonError.into_error()),
+        Json::Bool(b)        => Ok(Value::Boolean(b)),
+        Json::I64(i)         => Ok(Value::Integer(i)),
+        Json::U64(u) if u <= i64::max_value() as u64 => Ok(Value::Integer(u as i64)),
+        Json::U64(_)         => Err(SEK::JsonError.into_error()),
+        Json::F64(f)         => Ok(Value::Float(f)),
+        Json::String(ref s)  => Ok(Value::String(s.clone())),
+        Json::Array(ref a)   => a.iter().map(json_to_toml).collect::<Result<_>>().map(Value::Array),
+        Json::Object(ref o) if o.len() == 1 && o.contains_key(DATETIME_TAG) => {
+            let datetime = match o.get(DATETIME_TAG) {
+                Some(&Json::String(ref s)) => tagged_to_toml(s),
+                _ => None,
+            };
+            datetime.ok_or(SEK::JsonError.into_error())
+        },
+        Json::Object(ref o) if o.contains_key(DATETIME_TAG) => Err(SEK::JsonError.into_error()),
+        Json::Object(ref o)  => {
+            let mut t = BTreeMap::new();
+            for (k, v) in o.iter() {
+                t.insert(unescape_key(k), try!(json_to_toml(v)));
+            }
+            Ok(Value::Table(t))
+        },
+    }
+}
+
+/// Remove the `null` values from the objects in `v`, as TOML leaves out what is not there
+pub fn without_nulls(v: Json) -> Json {
+    match v {
+        Json::Object(o) => {
+            Json::Object(o.into_iter()
+                         .filter(|&(_, ref v)| *v != Json::Null)
+                         .map(|(k, v)| (k, without_nulls(v)))
+                         .collect())
+        },
+        Json::Array(a) => Json::Array(a.into_iter().map(without_nulls).collect()),
+        v => v,
+    }
+}
+
+/// Replace the tagged datetimes in `v` with their strings and unescape the keys (see
+/// `escaped_keys()`)
+pub fn untagged(v: Json) -> Json {
+    match v {
+        Json::Object(mut o) => {
+            if o.len() == 1 && o.contains_key(DATETIME_TAG) {
+                return o.remove(DATETIME_TAG).unwrap();
+            }
+            Json::Object(o.into_iter().map(|(k, v)| (unescape_key(&k), untagged(v))).collect())
+        },
+        Json::Array(a) => Json::Array(a.into_iter().map(untagged).collect()),
+        v => v,
+    }
+}
+
+/// Escape the keys of the objects in `v` which could be mistaken for `DATETIME_TAG`, as in the
+/// JSON an entry is converted to
+pub fn escaped_keys(v: Json) -> Json {
+    match v {
+        Json::Object(o) => {
+            Json::Object(o.into_iter().map(|(k, v)| (escape_key(&k), escaped_keys(v))).collect())
+        },
+        Json::Array(a) => Json::Array(a.into_iter().map(escaped_keys).collect()),
+        v => v,
+    }
+}
+
+/// Merge the fields of the object `o` into `t`, keeping the fields of `t` which `o` does not
+/// have. Fields which are `null` in `o` are removed from `t`, objects are merged into tables.
+pub fn merge_json(t: &mut BTreeMap<String, Value>, o: BTreeMap<String, Json>) -> Result<()> {
+    for (k, v) in o.into_iter() {
+        let k = unescape_key(&k);
+        match v {
+            Json::Null => {
+                t.remove(&k);
+            },
+            Json::Object(o) if !o.contains_key(DATETIME_TAG) => {
+                if let Some(&mut Value::Table(ref mut inner)) = t.get_mut(&k) {
+                    try!(merge_json(inner, o));
+                    continue;
+                }
+                t.insert(k, try!(json_to_toml(&without_nulls(Json::Object(o)))));
+            },
+            v => {
+                t.insert(k, try!(json_to_toml(&without_nulls(v))));
+            },
+        }
+    }
+    Ok(())
+}
+
+pub fn toml_to_yaml(v: &Value) -> Yaml {
+    match *v {
+        Value::String(ref s)   => Yaml::String(s.clone()),
+        Value::Integer(i)      => Yaml::Integer(i),
+        Value::Float(f)        => Yaml::Real(float_to_yaml(f)),
+        Value::Boolean(b)      => Yaml::Boolean(b),
+        Value::Datetime(ref s) => {
+            let mut h = YamlHash::new();
+            h.insert(Yaml::String(String::from(DATETIME_TAG)), Yaml::String(s.clone()));
+            Yaml::Hash(h)
+        },
+        Value::Array(ref a)    => Yaml::Array(a.iter().map(toml_to_yaml).collect()),
+        Value::Table(ref t)    => {
+            let mut h = YamlHash::new();
+            for (k, v) in t.iter() {
+                h.insert(Yaml::String(escape_key(k)), toml_to_yaml(v));
+            }
+            Yaml::Hash(h)
+        },
+    }
+}
+
+/// Convert `v` to TOML, fails with `YamlError` for `null`, aliases, keys which are not strings,
+/// datetimes which are malformed and hashes with `DATETIME_TAG` and other keys
+pub fn yaml_to_toml(v: &Yaml) -> Result<Value> {
+    let tag = Yaml::String(String::from(DATETIME_TAG));
+
+    match *v {
+        Yaml::Boolean(b)     => Ok(Value::Boolean(b)),
+        Yaml::Integer(i)     => Ok(Value::Integer(i)),
+        Yaml::Real(ref s)    => float_from_yaml(s).map(Value::Float),
+        Yaml::String(ref s)  => Ok(Value::String(s.clone())),
+        Yaml::Array(ref a)   => a.iter().map(yaml_to_toml).collect::<Result<_>>().map(Value::Array),
+        Yaml::Hash(ref h) if h.len() == 1 && h.contains_key(&tag) => {
+            let datetime = match h.get(&tag) {
+                Some(&Yaml::String(ref s)) => tagged_to_toml(s),
+                _ => None,
+            };
+            datetime.ok_or(SEK::YamlError.into_error())
+        },
+        Yaml::Hash(ref h) if h.contains_key(&tag) => Err(SEK::YamlError.into_error()),
+        Yaml::Hash(ref h)    => {
+            let mut t = BTreeMap::new();
+            for (k, v) in h.iter() {
+                match *k {
+                    Yaml::String(ref k) => { t.insert(unescape_key(k), try!(yaml_to_toml(v))); },
+                    _ => return Err(SEK::YamlError.into_error()),
+                }
+            }
+            Ok(Value::Table(t))
+        },
+        _ => Err(SEK::YamlError.into_error()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use toml::Value;
+
+    use serde_json::Value as Json;
+
+    use super::{json_to_toml, toml_to_json, yaml_to_toml, toml_to_yaml, untagged};
+
+    #[test]
+    fn test_round_trip() {
+        let mut t = BTreeMap::new();
+        t.insert(String::from("s"), Value::String(String::from("string")));
+        t.insert(String::from("i"), Value::Integer(-42));
+        t.insert(String::from("f"), Value::Float(1.5));
+        t.insert(String::from("g"), Value::Float(2.0));
+        t.insert(String::from("b"), Value::Boolean(true));
+        t.insert(String::from("d"), Value::Datetime(String::from("2016-08-01T12:00:00Z")));
+        t.insert(String::from("a"), Value::Array(vec![Value::Integer(1), Value::Integer(2)]));
+        let v = Value::Table(t);
+
+        assert_eq!(json_to_toml(&toml_to_json(&v)).unwrap(), v);
+        assert_eq!(yaml_to_toml(&toml_to_yaml(&v)).unwrap(), v);
+    }
+
+    #[test]
+    fn test_datetimes_are_tagged() {
+        let d = Value::Datetime(String::from("2016-08-01T12:00:00Z"));
+        let s = Value::String(String::from("2016-08-01T12:00:00Z"));
+
+        let json = toml_to_json(&d);
+        assert!(json.as_object().map(|o| o.contains_key("$datetime")).unwrap_or(false));
+        assert_eq!(untagged(json), Json::String(String::from("2016-08-01T12:00:00Z")));
+
+        assert_eq!(json_to_toml(&toml_to_json(&s)).unwrap(), s);
+        assert_eq!(yaml_to_toml(&toml_to_yaml(&s)).unwrap(), s);
+
+        let mut malformed = BTreeMap::new();
+        malformed.insert(String::from("$datetime"), Json::String(String::from("yesterday")));
+        assert!(json_to_toml(&Json::Object(malformed)).is_err());
+    }
+
+    #[test]
+    fn test_keys_like_the_tag_are_escaped() {
+        let datetime = String::from("2016-08-01T12:00:00Z");
+        let mut t = BTreeMap::new();
+        t.insert(String::from("$datetime"), Value::String(datetime.clone()));
+        t.insert(String::from("$$datetime"), Value::Integer(2));
+        let mut inner = BTreeMap::new();
+        inner.insert(String::from("$datetime"), Value::String(datetime.clone()));
+        t.insert(String::from("inner"), Value::Table(inner));
+        let v = Value::Table(t);
+
+        let json = toml_to_json(&v);
+        let keys : Vec<&str> = json.as_object().unwrap().keys().map(|k| &k[..]).collect();
+        assert_eq!(keys, vec!["$$$datetime", "$$datetime", "inner"]);
+        assert_eq!(json_to_toml(&json).unwrap(), v);
+        assert_eq!(yaml_to_toml(&toml_to_yaml(&v)).unwrap(), v);
+
+        let inner = untagged(json).as_object().unwrap().get("inner").cloned().unwrap();
+        let mut expected = BTreeMap::new();
+        expected.insert(String::from("$datetime"), Json::String(datetime));
+        assert_eq!(inner, Json::Object(expected));
+    }
+
+}
@@ -0,0 +1,211 @@
+//! Garbage in the store
+//!
+//! Deleting and moving entries can leave directories behind which have no entries anymore, and
+//! files in the store can be broken by other programs. `Store::gc()` finds (and removes) these.
+//! Unparsable files are not deleted but moved to `<store>/.lost+found`, so nothing is lost.
+//! Objects (see `object`) which no entry and no snapshot refers to anymore are removed, too.
+//!
+//! Links between entries are not known to the store, see `libimagentrylink::gc` for them.
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::fmt::Error as FmtError;
+use std::path::{Path, PathBuf};
+use std::result::Result as RResult;
+
+use backend::{BackendObject, StoreBackend, is_staged};
+use error::StoreErrorKind as SEK;
+use error::MapErrInto;
+use store::{Entry, Result, LOCKS_DIR};
+use storeid::{StoreId, is_internal_path, free_id};
+
+use libimagerror::into::IntoError;
+
+/// The name of the directory (inside the store) unparsable files are moved to
+pub static LOST_FOUND_DIR : &'static str = ".lost+found";
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Garbage {
+    /// A directory without any entries below it
+    EmptyDirectory(PathBuf),
+
+    /// A file which cannot be parsed as an entry
+    Unparsable(StoreId),
+
+    /// An object which is not referred to, by its hash
+    UnusedObject(String),
+}
+
+impl Display for Garbage {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        match *self {
+            Garbage::EmptyDirectory(ref p) => write!(fmt, "empty directory {}", p.display()),
+            Garbage::Unparsable(ref id)    => write!(fmt, "unparsable      {}", id),
+            Garbage::UnusedObject(ref h)   => write!(fmt, "unused object   {}", h),
+        }
+    }
+
+}
+
+/// Get the path `file` (an entry in the store at `location`) is moved to if it is unparsable
+pub fn lost_found_path(location: &Path, file: &Path) -> Option<PathBuf> {
+    file.strip_prefix(location).ok().map(|rel| {
+        let mut path = location.to_path_buf();
+        path.push(LOST_FOUND_DIR);
+        path.push(rel);
+        path
+    })
+}
+
+/// Find the directories without entries, the files which cannot be parsed and the unused
+/// objects in the store at `location`
+///
+/// `read` reads the raw content of an entry.
+pub fn find<F>(backend: &StoreBackend, location: &Path, read: F) -> Result<Vec<Garbage>>
+    where F: Fn(&StoreId) -> Result<Vec<u8>>
+{
+    let objects = try!(backend.walk(location).map_err_into(SEK::FileError));
+
+    let mut files = vec![];
+    let mut dirs  = vec![];
+    for obj in objects {
+        match obj {
+            BackendObject::File(p) => files.push(p),
+            BackendObject::Directory(p) => {
+                if p != location && !is_internal_path(location, &p) {
+                    dirs.push(p);
+                }
+            },
+        }
+    }
+
+    let mut garbage    = vec![];
+    let mut referenced = BTreeSet::new();
+    for file in files.iter() {
+        if is_internal_path(location, file) {
+            continue;
+        }
+
+        // Staged files of transactions refer to objects, too
+        referenced.extend(try!(backend
+                               .referenced_objects(file)
+                               .map_err_into(SEK::ObjectError)));
+        if is_staged(file) {
+            continue;
+        }
+
+        let id    = StoreId::from(file.clone());
+        let bytes = try!(read(&id));
+        let parses = String::from_utf8(bytes)
+            .map(|text| Entry::from_str(id.clone(), &text).is_ok())
+            .unwrap_or(false);
+        if !parses {
+            garbage.push(Garbage::Unparsable(id));
+        }
+    }
+
+    for snapshot in try!(::snapshot::load_all(backend, location)) {
+        referenced.extend(snapshot.entries.into_iter().map(|(_, hash)| hash));
+    }
+    let unused = try!(::object::unused(backend, location, &referenced)
+                      .map_err_into(SEK::ObjectError));
+    garbage.extend(unused.into_iter().map(Garbage::UnusedObject));
+
+    // Children sort after their parents, so in reverse order they are removed first
+    dirs.sort();
+    for dir in dirs.into_iter().rev() {
+        if !files.iter().any(|f| f.starts_with(&dir)) {
+            garbage.push(Garbage::EmptyDirectory(dir));
+        }
+    }
+
+    Ok(garbage)
+}
+
+/// Remove `garbage` (see `find()`) from the store at `location`, moving unparsable files to
+/// the lost+found directory
+///
+/// Lock files nobody holds are removed, too.
+pub fn remove(backend: &StoreBackend, location: &Path, garbage: &[Garbage]) -> Result<()> {
+    remove_stale_locks(backend, location);
+
+    for g in garbage {
+        let result = match *g {
+            Garbage::EmptyDirectory(ref dir) => backend.remove_dir(dir),
+            Garbage::Unparsable(ref id) => {
+                let target = try!(lost_found_path(location, id)
+                                  .ok_or(SEK::StorePathError.into_error()));
+                let target = if backend.is_file(&target) {
+                    free_id(backend, &StoreId::from(target)).into()
+                } else {
+                    target
+                };
+                let created = match target.parent() {
+                    Some(parent) => backend.create_dir_all(parent),
+                    None => Ok(()),
+                };
+                created.and_then(|_| backend.rename(id, &target))
+            },
+            Garbage::UnusedObject(ref hash) => {
+                backend.remove(&::object::object_path(location, hash))
+            },
+        };
+        try!(result.map_err_into(SEK::FileError));
+    }
+
+    Ok(())
+}
+
+/// Remove the lock files in the store at `location` which nobody holds
+///
+/// Lock files are removed when the lock is released, so these are left over by processes
+/// which died (or by older versions of the store). Taking the lock and releasing it removes
+/// the file.
+fn remove_stale_locks(backend: &StoreBackend, location: &Path) {
+    let mut locks_dir = location.to_path_buf();
+    locks_dir.push(LOCKS_DIR);
+    if !backend.exists(&locks_dir) {
+        return;
+    }
+
+    let files = match backend.walk(&locks_dir) {
+        Ok(objects) => objects,
+        Err(e) => {
+            warn!("Cannot list the lock files");
+            debug!("{:?}", e);
+            return;
+        },
+    };
+
+    for obj in files {
+        let file = match obj {
+            BackendObject::File(p) => p,
+            BackendObject::Directory(_) => continue,
+        };
+        match backend.try_lock(&file) {
+            Ok(Some(_)) => debug!("Removed stale lock file {:?}", file),
+            Ok(None)    => debug!("Lock {:?} is held", file),
+            Err(e) => {
+                warn!("Cannot check lock file {:?}", file);
+                debug!("{:?}", e);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::lost_found_path;
+
+    #[test]
+    fn test_lost_found_path() {
+        let store = PathBuf::from("/store");
+        assert_eq!(lost_found_path(&store, &PathBuf::from("/store/notes/a~0.2.0")),
+                   Some(PathBuf::from("/store/.lost+found/notes/a~0.2.0")));
+        assert_eq!(lost_found_path(&store, &PathBuf::from("/elsewhere")), None);
+    }
+
+}
@@ -0,0 +1,579 @@
+//! Paths into entry headers
+//!
+//! Besides plain dotted paths (`imag.links.0`), header paths support:
+//!
+//! * Quoted keys, for keys containing the separator: `a."b.c"` or `a["b.c"]`
+//! * Array indices, negative ones counting from the end: `tags.0`, `tags[0]`, `tags[-1]`
+//! * Wildcards, matching all values of a table or an array: `links.*`, `links[*]`
+//! * Slices of arrays: `tags[1:3]`, `tags[:-1]`, `tags[2:]`
+//! * Predicates, matching the values of an array (or table) they hold for:
+//!   `tags[?value=="work"]`, `todos[?value.prio>=2].done`. `value` is the value itself, a path
+//!   following it selects from the value. Values can be compared with `==`, `!=`, `<`, `<=`, `>`
+//!   and `>=` to (double quoted) strings, integers, floats and booleans.
+//!
+//! Paths with wildcards, slices or predicates can match many values, see
+//! `EntryHeader::read_all()`, `EntryHeader::set_all()` and `EntryHeader::delete_all()`.
+//! `EntryHeader::read()` and friends only accept paths which denote a single field.
+
+use std::cmp::{Ordering, max, min};
+use std::str::FromStr;
+
+use toml::Value;
+
+use error::{StoreError as SE, StoreErrorKind as SEK};
+use store::Result;
+
+/// A step into a header: the key of a table or the index of an array
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Token {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Key(String),
+    Index(isize),
+    Wildcard,
+    Slice(Option<isize>, Option<isize>),
+    Filter(Predicate),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Predicate {
+    path: HeaderPath,
+    comparison: Comparison,
+    value: Value,
+}
+
+/// A parsed header path
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderPath {
+    selectors: Vec<Selector>,
+}
+
+impl HeaderPath {
+
+    pub fn parse(spec: &str) -> Result<HeaderPath> {
+        HeaderPath::parse_with_sep(spec, '.')
+    }
+
+    /// Parse `spec`, with `sep` separating the keys instead of a dot
+    pub fn parse_with_sep(spec: &str, sep: char) -> Result<HeaderPath> {
+        let mut parser = Parser {
+            chars: spec.chars().collect(),
+            pos: 0,
+            sep: sep,
+            depth: 0,
+        };
+
+        let path = try!(parser.path(true));
+        if parser.peek().is_some() {
+            return Err(syntax_error());
+        }
+        Ok(path)
+    }
+
+    /// The keys of this path, if it consists of table keys only
+    pub fn keys(&self) -> Option<Vec<String>> {
+        self.selectors
+            .iter()
+            .map(|s| match *s {
+                Selector::Key(ref k) => Some(k.clone()),
+                _                    => None,
+            })
+            .collect()
+    }
+
+    /// The tokens of this path, if it denotes a single field in every header
+    pub fn tokens(&self) -> Option<Vec<Token>> {
+        self.selectors
+            .iter()
+            .map(|s| match *s {
+                Selector::Key(ref k)         => Some(Token::Key(k.clone())),
+                Selector::Index(i) if i >= 0 => Some(Token::Index(i as usize)),
+                _                            => None,
+            })
+            .collect()
+    }
+
+    /// Resolve this path in `header` to the tokens of the single field it denotes
+    ///
+    /// The field itself does not have to exist, but negative indices can only be resolved in
+    /// existing arrays.
+    pub fn resolve(&self, header: &Value) -> Result<Vec<Token>> {
+        let mut tokens  = vec![];
+        let mut current = Some(header);
+        for selector in self.selectors.iter() {
+            let token = match (selector, current) {
+                (&Selector::Key(ref k), _)         => Token::Key(k.clone()),
+                (&Selector::Index(i), _) if i >= 0 => Token::Index(i as usize),
+                (&Selector::Index(i), Some(&Value::Array(ref a))) => {
+                    match index(i, a.len()) {
+                        Some(i) => Token::Index(i),
+                        None    => return Err(SE::new(SEK::HeaderKeyNotFound, None)),
+                    }
+                },
+                (&Selector::Index(_), Some(_)) => {
+                    return Err(SE::new(SEK::HeaderPathTypeFailure, None));
+                },
+                (&Selector::Index(_), None) => {
+                    return Err(SE::new(SEK::HeaderKeyNotFound, None));
+                },
+                _ => return Err(SE::new(SEK::HeaderPathNotSingular, None)),
+            };
+
+            current = current.and_then(|v| step(v, &token));
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
+
+    /// Find the values in `header` this path matches, with the tokens leading to them
+    pub fn matches<'a>(&self, header: &'a Value) -> Vec<(Vec<Token>, &'a Value)> {
+        let mut found = vec![(vec![], header)];
+        for selector in self.selectors.iter() {
+            let mut next = vec![];
+            for (tokens, value) in found {
+                for (token, v) in selector.select(value) {
+                    let mut tokens = tokens.clone();
+                    tokens.push(token);
+                    next.push((tokens, v));
+                }
+            }
+            found = next;
+        }
+        found
+    }
+
+}
+
+impl FromStr for HeaderPath {
+    type Err = SE;
+
+    fn from_str(s: &str) -> Result<HeaderPath> {
+        HeaderPath::parse(s)
+    }
+
+}
+
+impl Selector {
+
+    fn select<'a>(&self, v: &'a Value) -> Vec<(Token, &'a Value)> {
+        match (self, v) {
+            (&Selector::Key(ref k), &Value::Table(ref t)) => {
+                t.get(k).map(|v| (Token::Key(k.clone()), v)).into_iter().collect()
+            },
+            (&Selector::Index(i), &Value::Array(ref a)) => {
+                index(i, a.len()).map(|i| (Token::Index(i), &a[i])).into_iter().collect()
+            },
+            (&Selector::Slice(from, to), &Value::Array(ref a)) => {
+                let (from, to) = slice_bounds(from, to, a.len());
+                (from..to).map(|i| (Token::Index(i), &a[i])).collect()
+            },
+            (&Selector::Wildcard, _) => children(v),
+            (&Selector::Filter(ref p), _) => {
+                children(v).into_iter().filter(|&(_, v)| p.holds(v)).collect()
+            },
+            _ => vec![],
+        }
+    }
+
+}
+
+impl Predicate {
+
+    fn holds(&self, v: &Value) -> bool {
+        self.path.matches(v).into_iter().any(|(_, found)| {
+            match self.comparison {
+                Comparison::Eq => equals(found, &self.value),
+                Comparison::Ne => !equals(found, &self.value),
+                c => compare(found, &self.value).map(|o| c.accepts(o)).unwrap_or(false),
+            }
+        })
+    }
+
+}
+
+impl Comparison {
+
+    fn accepts(&self, o: Ordering) -> bool {
+        match *self {
+            Comparison::Eq => o == Ordering::Equal,
+            Comparison::Ne => o != Ordering::Equal,
+            Comparison::Lt => o == Ordering::Less,
+            Comparison::Le => o != Ordering::Greater,
+            Comparison::Gt => o == Ordering::Greater,
+            Comparison::Ge => o != Ordering::Less,
+        }
+    }
+
+}
+
+fn syntax_error() -> SE {
+    SE::new(SEK::HeaderPathSyntaxError, None)
+}
+
+/// Normalize the (possibly negative) index `i` into an array of length `len`
+fn index(i: isize, len: usize) -> Option<usize> {
+    let i = if i < 0 { len as isize + i } else { i };
+    if i >= 0 && (i as usize) < len {
+        Some(i as usize)
+    } else {
+        None
+    }
+}
+
+fn slice_bounds(from: Option<isize>, to: Option<isize>, len: usize) -> (usize, usize) {
+    let bound = |i: isize| {
+        if i < 0 {
+            max(len as isize + i, 0) as usize
+        } else {
+            min(i as usize, len)
+        }
+    };
+
+    let from = from.map(&bound).unwrap_or(0);
+    let to   = to.map(&bound).unwrap_or(len);
+    (from, max(to, from))
+}
+
+fn step<'a>(v: &'a Value, token: &Token) -> Option<&'a Value> {
+    match (v, token) {
+        (&Value::Table(ref t), &Token::Key(ref k)) => t.get(k),
+        (&Value::Array(ref a), &Token::Index(i))   => a.get(i),
+        _ => None,
+    }
+}
+
+fn children(v: &Value) -> Vec<(Token, &Value)> {
+    match *v {
+        Value::Table(ref t) => t.iter().map(|(k, v)| (Token::Key(k.clone()), v)).collect(),
+        Value::Array(ref a) => a.iter().enumerate().map(|(i, v)| (Token::Index(i), v)).collect(),
+        _ => vec![],
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (&Value::Integer(a), &Value::Integer(b))   => Some(a.cmp(&b)),
+        (&Value::Integer(a), &Value::Float(b))     => (a as f64).partial_cmp(&b),
+        (&Value::Float(a), &Value::Integer(b))     => a.partial_cmp(&(b as f64)),
+        (&Value::Float(a), &Value::Float(b))       => a.partial_cmp(&b),
+        (&Value::Boolean(a), &Value::Boolean(b))   => Some(a.cmp(&b)),
+        (&Value::String(ref a), &Value::String(ref b))   |
+        (&Value::Datetime(ref a), &Value::String(ref b)) |
+        (&Value::Datetime(ref a), &Value::Datetime(ref b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn equals(a: &Value, b: &Value) -> bool {
+    compare(a, b).map(|o| o == Ordering::Equal).unwrap_or_else(|| a == b)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    sep: char,
+
+    /// How many predicates deep the parser is
+    depth: usize,
+}
+
+impl Parser {
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.eat(c) { Ok(()) } else { Err(syntax_error()) }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.eat(' ') {}
+    }
+
+    /// Parse a path, which starts with a key unless it is `leading` the path of a predicate
+    fn path(&mut self, leading: bool) -> Result<HeaderPath> {
+        let mut selectors = vec![];
+        if leading {
+            selectors.push(try!(self.segment()));
+        }
+
+        let sep = self.sep;
+        loop {
+            if self.eat(sep) {
+                selectors.push(try!(self.segment()));
+            } else if self.eat('[') {
+                selectors.push(try!(self.bracket()));
+            } else {
+                break;
+            }
+        }
+
+        Ok(HeaderPath { selectors: selectors })
+    }
+
+    /// Parse a segment between two separators
+    fn segment(&mut self) -> Result<Selector> {
+        if self.eat('"') {
+            return self.quoted().map(Selector::Key);
+        }
+        if self.eat('*') {
+            return Ok(Selector::Wildcard);
+        }
+
+        let mut key = String::new();
+        while let Some(c) = self.peek() {
+            let ends_predicate = self.depth > 0 && "]=!<> ".contains(c);
+            if c == self.sep || c == '[' || ends_predicate {
+                break;
+            }
+            key.push(c);
+            self.pos += 1;
+        }
+
+        if key.is_empty() {
+            Err(syntax_error())
+        } else {
+            Ok(isize::from_str(&key).map(Selector::Index).unwrap_or(Selector::Key(key)))
+        }
+    }
+
+    /// Parse what is inside brackets, after the `[`
+    fn bracket(&mut self) -> Result<Selector> {
+        let selector = if self.eat('*') {
+            Selector::Wildcard
+        } else if self.eat('"') {
+            Selector::Key(try!(self.quoted()))
+        } else if self.eat('?') {
+            Selector::Filter(try!(self.predicate()))
+        } else {
+            let from = try!(self.integer());
+            if self.eat(':') {
+                Selector::Slice(from, try!(self.integer()))
+            } else {
+                match from {
+                    Some(i) => Selector::Index(i),
+                    None    => return Err(syntax_error()),
+                }
+            }
+        };
+
+        try!(self.expect(']'));
+        Ok(selector)
+    }
+
+    /// Parse a predicate, after the `?`
+    fn predicate(&mut self) -> Result<Predicate> {
+        self.skip_spaces();
+        for c in "value".chars() {
+            try!(self.expect(c));
+        }
+
+        self.depth += 1;
+        let path = try!(self.path(false));
+        self.depth -= 1;
+
+        self.skip_spaces();
+        let comparison = try!(self.comparison());
+        self.skip_spaces();
+        let value = try!(self.literal());
+        self.skip_spaces();
+
+        Ok(Predicate {
+            path: path,
+            comparison: comparison,
+            value: value,
+        })
+    }
+
+    fn comparison(&mut self) -> Result<Comparison> {
+        if self.eat('=') {
+            self.expect('=').map(|_| Comparison::Eq)
+        } else if self.eat('!') {
+            self.expect('=').map(|_| Comparison::Ne)
+        } else if self.eat('<') {
+            Ok(if self.eat('=') { Comparison::Le } else { Comparison::Lt })
+        } else if self.eat('>') {
+            Ok(if self.eat('=') { Comparison::Ge } else { Comparison::Gt })
+        } else {
+            Err(syntax_error())
+        }
+    }
+
+    fn literal(&mut self) -> Result<Value> {
+        if self.eat('"') {
+            return self.quoted().map(Value::String);
+        }
+
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            if c == ']' || c == ' ' {
+                break;
+            }
+            s.push(c);
+            self.pos += 1;
+        }
+
+        match &s[..] {
+            "true"  => Ok(Value::Boolean(true)),
+            "false" => Ok(Value::Boolean(false)),
+            _ => i64::from_str(&s)
+                .map(Value::Integer)
+                .or_else(|_| f64::from_str(&s).map(Value::Float))
+                .map_err(|_| syntax_error()),
+        }
+    }
+
+    /// Parse an optional integer
+    fn integer(&mut self) -> Result<Option<isize>> {
+        let mut s = String::new();
+        if self.eat('-') {
+            s.push('-');
+        }
+        while let Some(c) = self.peek() {
+            if !c.is_digit(10) {
+                break;
+            }
+            s.push(c);
+            self.pos += 1;
+        }
+
+        match &s[..] {
+            ""  => Ok(None),
+            "-" => Err(syntax_error()),
+            _   => isize::from_str(&s).map(Some).map_err(|_| syntax_error()),
+        }
+    }
+
+    /// Parse a quoted string, after the opening quote
+    fn quoted(&mut self) -> Result<String> {
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None       => return Err(syntax_error()),
+                Some('"')  => {
+                    self.pos += 1;
+                    return Ok(s);
+                },
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(c) => s.push(c),
+                        None    => return Err(syntax_error()),
+                    }
+                },
+                Some(c) => s.push(c),
+            }
+            self.pos += 1;
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use toml::Value;
+
+    use super::{HeaderPath, Token};
+
+    fn header() -> Value {
+        let mut todo_a = BTreeMap::new();
+        todo_a.insert(String::from("prio"), Value::Integer(1));
+        todo_a.insert(String::from("done"), Value::Boolean(true));
+
+        let mut todo_b = BTreeMap::new();
+        todo_b.insert(String::from("prio"), Value::Integer(3));
+        todo_b.insert(String::from("done"), Value::Boolean(false));
+
+        let mut h = BTreeMap::new();
+        h.insert(String::from("tags"), Value::Array(vec![
+            Value::String(String::from("home")),
+            Value::String(String::from("work")),
+            Value::String(String::from("misc")),
+        ]));
+        h.insert(String::from("todos"), Value::Array(vec![
+            Value::Table(todo_a),
+            Value::Table(todo_b),
+        ]));
+        h.insert(String::from("a.b"), Value::Integer(42));
+        Value::Table(h)
+    }
+
+    fn values(spec: &str) -> Vec<Value> {
+        let header = header();
+        HeaderPath::parse(spec)
+            .unwrap()
+            .matches(&header)
+            .into_iter()
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(String::from(s))
+    }
+
+    #[test]
+    fn test_plain_paths() {
+        assert_eq!(values("tags.1"), vec![string("work")]);
+        assert_eq!(values("tags[-1]"), vec![string("misc")]);
+        assert_eq!(values("\"a.b\""), vec![Value::Integer(42)]);
+        assert_eq!(values("todos[\"prio\"]"), vec![]);
+        assert!(values("nothing.here").is_empty());
+    }
+
+    #[test]
+    fn test_multiple_matches() {
+        assert_eq!(values("tags[1:]"), vec![string("work"), string("misc")]);
+        assert_eq!(values("tags[:-2]"), vec![string("home")]);
+        assert_eq!(values("todos.*.prio"), vec![Value::Integer(1), Value::Integer(3)]);
+        assert_eq!(values("tags[?value==\"work\"]"), vec![string("work")]);
+        assert_eq!(values("tags[?value != \"work\"]"), vec![string("home"), string("misc")]);
+        assert_eq!(values("todos[?value.prio>=2].done"), vec![Value::Boolean(false)]);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let header = header();
+        let tokens = HeaderPath::parse("todos[-1].done").unwrap().resolve(&header).unwrap();
+        assert_eq!(tokens, vec![Token::Key(String::from("todos")),
+                                Token::Index(1),
+                                Token::Key(String::from("done"))]);
+
+        assert!(HeaderPath::parse("todos.*.done").unwrap().resolve(&header).is_err());
+        assert!(HeaderPath::parse("new.field").unwrap().resolve(&header).is_ok());
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        for spec in ["", "a..b", "a[", "a[1", "a[?foo==1]", "a[?value==]", "\"a"].iter() {
+            assert!(HeaderPath::parse(spec).is_err(), "'{}' should not parse", spec);
+        }
+    }
+
+}
@@ -16,11 +16,17 @@ pub trait NonMutableHookDataAccessor : Debug + Send {
     fn access(&self, &FileLockEntry) -> HookResult<()>;
 }
 
+/// Accessor for the move positions, gets the old and the new `StoreId`
+pub trait MoveAccessor : Debug + Send {
+    fn access_move(&self, old: &StoreId, new: &StoreId) -> HookResult<()>;
+}
+
 #[derive(Debug)]
 pub enum HookDataAccessor<'a> {
     StoreIdAccess(&'a StoreIdAccessor),
     MutableAccess(&'a MutableHookDataAccessor),
     NonMutableAccess(&'a NonMutableHookDataAccessor),
+    MoveAccess(&'a MoveAccessor),
 }
 
 pub trait HookDataAccessorProvider {
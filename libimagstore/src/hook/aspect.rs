@@ -5,7 +5,8 @@ use store::FileLockEntry;
 use storeid::StoreId;
 use hook::Hook;
 use hook::result::HookResult;
-use hook::accessor::{StoreIdAccessor, MutableHookDataAccessor, NonMutableHookDataAccessor};
+use hook::accessor::{StoreIdAccessor, MutableHookDataAccessor, NonMutableHookDataAccessor,
+                     MoveAccessor};
 use hook::accessor::HookDataAccessor as HDA;
 
 use hook::error::HookError as HE;
@@ -84,6 +85,10 @@ impl MutableHookDataAccessor for Aspect {
                 &HDA::StoreIdAccess(ref accessor)    => accessor.access(fle.get_location()),
                 &HDA::MutableAccess(ref accessor)    => accessor.access_mut(fle),
                 &HDA::NonMutableAccess(ref accessor) => accessor.access(fle),
+                &HDA::MoveAccess(_) => {
+                    warn!("Denied execution of Move-Accessing Hook");
+                    Err(HE::new(HEK::AccessTypeViolation, None))
+                },
             };
             trace_hook_errors(res)
         })
@@ -115,6 +120,31 @@ impl NonMutableHookDataAccessor for Aspect {
     }
 }
 
+impl MoveAccessor for Aspect {
+    fn access_move(&self, old: &StoreId, new: &StoreId) -> HookResult<()> {
+        let accessors : Vec<HDA> = self.hooks.iter().map(|h| h.accessor()).collect();
+        if !accessors.iter().all(|a| {
+            let x = is_match!(*a, HDA::MoveAccess(_));
+            if !x {
+                warn!("Denied execution of None-Move-Accessing Hook");
+                debug!("Accessor: {:?}", a);
+                debug!("in MoveAccess-Aspect execution: {:?}", self);
+            }
+            x
+        }) {
+            return Err(HE::new(HEK::AccessTypeViolation, None));
+        }
+
+        accessors.iter().fold_defresult(|accessor| {
+            let res = match accessor {
+                &HDA::MoveAccess(accessor) => accessor.access_move(old, new),
+                _ => unreachable!(),
+            };
+            trace_hook_errors(res)
+        })
+    }
+}
+
 fn trace_hook_errors(res: HookResult<()>) -> HookResult<()> {
     res.or_else(|e| {
         if !e.is_aborting() {
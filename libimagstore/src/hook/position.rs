@@ -10,4 +10,9 @@ pub enum HookPosition {
     PostUpdate,
     PreDelete,
     PostDelete,
+
+    /// Hooks in the move positions get the old and the new `StoreId` of the moved entry, see
+    /// `hook::accessor::MoveAccessor`
+    PreMove,
+    PostMove,
 }
@@ -0,0 +1,479 @@
+//! Secondary index over header fields
+//!
+//! The store can keep an index for header paths configured in the `[store]` section (see
+//! `Store::query_index()`):
+//!
+//! ```toml
+//! [store]
+//! index = [ "imag.tags", "ref.content_hash", "note.name" ]
+//! ```
+//!
+//! For each of these paths the index maps the values found at the path to the entries which
+//! contain them. Arrays are indexed element-wise, tables are not indexed at all. Values are keyed
+//! by their type as well, so `1` and `"1"` are different keys. The index of a path is persisted
+//! in `<store>/.index/<path>`.
+//!
+//! Like the search index (see `search`), the changes of one process are merged into the index on
+//! disk when the store is dropped, so processes working on the store at the same time do not drop
+//! each others changes. Only the files of the paths whose index changed are written.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use toml::{Parser, Value};
+
+use backend::StoreBackend;
+use error::StoreErrorKind as SEK;
+use error::MapErrInto;
+use store::{EntryHeader, Result};
+
+/// The name of the directory (inside the store) where the index is kept
+pub static INDEX_DIR : &'static str = ".index";
+
+/// A value under which entries are indexed
+///
+/// Floats and datetimes are keyed by their textual representation.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IndexKey {
+    String(String),
+    Integer(i64),
+    Float(String),
+    Boolean(bool),
+    Datetime(String),
+}
+
+impl IndexKey {
+
+    /// Get the keys under which the value `v` is indexed
+    pub fn of(v: &Value) -> Vec<IndexKey> {
+        match *v {
+            Value::String(ref s)   => vec![IndexKey::String(s.clone())],
+            Value::Integer(i)      => vec![IndexKey::Integer(i)],
+            Value::Float(f)        => vec![IndexKey::Float(f.to_string())],
+            Value::Boolean(b)      => vec![IndexKey::Boolean(b)],
+            Value::Datetime(ref s) => vec![IndexKey::Datetime(s.clone())],
+            Value::Array(ref a)    => a.iter().flat_map(|v| IndexKey::of(v)).collect(),
+            Value::Table(_)        => vec![],
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match *self {
+            IndexKey::String(ref s)   => Value::String(s.clone()),
+            IndexKey::Integer(i)      => Value::Integer(i),
+            IndexKey::Float(ref s)    => Value::Float(s.parse().unwrap_or(0.0)),
+            IndexKey::Boolean(b)      => Value::Boolean(b),
+            IndexKey::Datetime(ref s) => Value::Datetime(s.clone()),
+        }
+    }
+
+    fn from_value(v: &Value) -> Option<IndexKey> {
+        match *v {
+            Value::Array(_) | Value::Table(_) => None,
+            _ => IndexKey::of(v).pop(),
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderIndex {
+    /// header path -> value -> entries
+    paths: BTreeMap<String, BTreeMap<IndexKey, BTreeSet<PathBuf>>>,
+
+    /// The entries which were (re)indexed or removed since the index was loaded or saved
+    touched: BTreeSet<PathBuf>,
+
+    /// Whether the index was cleared since it was loaded or saved
+    cleared: bool,
+}
+
+impl HeaderIndex {
+
+    /// Create an empty index for `paths`
+    pub fn new(paths: Vec<String>) -> HeaderIndex {
+        HeaderIndex {
+            paths: paths.into_iter().map(|p| (p, BTreeMap::new())).collect(),
+            touched: BTreeSet::new(),
+            cleared: false,
+        }
+    }
+
+    /// Load the index for `paths` from the store at `location`
+    ///
+    /// Paths for which no index was persisted yet (or for which the index cannot be read) start
+    /// out empty, they can be filled with `Store::rebuild_index()`.
+    pub fn load(backend: &StoreBackend, location: &Path, paths: Vec<String>) -> HeaderIndex {
+        let mut index = HeaderIndex::new(paths);
+
+        for (path, values) in index.paths.iter_mut() {
+            if let Some(v) = load_values(backend, location, path) {
+                *values = v;
+            }
+        }
+
+        index
+    }
+
+    /// Whether the index was changed since it was loaded or saved
+    pub fn has_changes(&self) -> bool {
+        self.cleared || !self.touched.is_empty()
+    }
+
+    /// Merge the changes to the index into the index of the store at `location` and write it
+    ///
+    /// The entries changed in this index replace what is indexed for them on disk, everything
+    /// else is kept from the index on disk (which may have been written by another process), so
+    /// the store has to be locked while saving. Afterwards this index is the merged index.
+    pub fn save(&mut self, backend: &StoreBackend, location: &Path) -> Result<()> {
+        if !self.has_changes() {
+            return Ok(());
+        }
+
+        for (path, values) in self.paths.iter_mut() {
+            let on_disk = load_values(backend, location, path);
+            let merged  = if self.cleared {
+                values.clone()
+            } else {
+                merge(on_disk.clone().unwrap_or(BTreeMap::new()), values, &self.touched)
+            };
+
+            if on_disk.as_ref() != Some(&merged) {
+                debug!("Writing index for '{}'", path);
+                let text = ::toml::encode_str(&values_to_value(&merged));
+                try!(backend.write(&index_file(location, path), text.as_bytes())
+                     .map_err_into(SEK::IndexError));
+            }
+            *values = merged;
+        }
+
+        self.touched.clear();
+        self.cleared = false;
+        Ok(())
+    }
+
+    /// Whether the header path `path` is indexed
+    pub fn is_indexed(&self, path: &str) -> bool {
+        self.paths.contains_key(path)
+    }
+
+    /// All indexed header paths
+    pub fn paths(&self) -> Vec<String> {
+        self.paths.keys().cloned().collect()
+    }
+
+    /// Get the keys under which `header` would be indexed for `path`
+    pub fn keys_in_header(header: &EntryHeader, path: &str) -> Vec<IndexKey> {
+        match header.read(path) {
+            Ok(Some(v)) => IndexKey::of(&v),
+            _ => vec![],
+        }
+    }
+
+    /// Index the entry `id` with `header`, replacing what was indexed for it before
+    ///
+    /// Returns the header paths for which the index changed.
+    pub fn update(&mut self, id: &Path, header: &EntryHeader) -> Vec<String> {
+        // Even if nothing changed here, the index on disk may differ
+        self.touched.insert(id.to_path_buf());
+        let mut changed = vec![];
+
+        for (path, values) in self.paths.iter_mut() {
+            let keys : BTreeSet<IndexKey> = HeaderIndex::keys_in_header(header, path)
+                .into_iter()
+                .collect();
+
+            let old : BTreeSet<IndexKey> = values.iter()
+                .filter(|&(_, ids)| ids.contains(id))
+                .map(|(k, _)| k.clone())
+                .collect();
+
+            if keys == old {
+                continue;
+            }
+
+            for k in old.difference(&keys) {
+                remove_from(values, k, id);
+            }
+            for k in keys.difference(&old) {
+                values.entry(k.clone()).or_insert_with(BTreeSet::new).insert(id.to_path_buf());
+            }
+            changed.push(path.clone());
+        }
+
+        changed
+    }
+
+    /// Remove the entry `id` from the index
+    ///
+    /// Returns the header paths for which the index changed.
+    pub fn remove(&mut self, id: &Path) -> Vec<String> {
+        self.touched.insert(id.to_path_buf());
+        let mut changed = vec![];
+
+        for (path, values) in self.paths.iter_mut() {
+            let keys : Vec<IndexKey> = values.iter()
+                .filter(|&(_, ids)| ids.contains(id))
+                .map(|(k, _)| k.clone())
+                .collect();
+
+            if keys.is_empty() {
+                continue;
+            }
+
+            for k in keys.iter() {
+                remove_from(values, k, id);
+            }
+            changed.push(path.clone());
+        }
+
+        changed
+    }
+
+    /// Move everything indexed for `old` to `new`
+    ///
+    /// Returns the header paths for which the index changed.
+    pub fn rename(&mut self, old: &Path, new: &Path) -> Vec<String> {
+        self.touched.insert(old.to_path_buf());
+        self.touched.insert(new.to_path_buf());
+        let mut changed = vec![];
+
+        for (path, values) in self.paths.iter_mut() {
+            let mut found = false;
+            for ids in values.values_mut() {
+                if ids.remove(old) {
+                    ids.insert(new.to_path_buf());
+                    found = true;
+                }
+            }
+
+            if found {
+                changed.push(path.clone());
+            }
+        }
+
+        changed
+    }
+
+    /// Get the entries which are indexed under `key` for `path`
+    pub fn lookup(&self, path: &str, key: &IndexKey) -> Vec<PathBuf> {
+        self.paths
+            .get(path)
+            .and_then(|values| values.get(key))
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or(vec![])
+    }
+
+    /// Remove everything from the index
+    pub fn clear(&mut self) {
+        for values in self.paths.values_mut() {
+            values.clear();
+        }
+        self.touched.clear();
+        self.cleared = true;
+    }
+
+}
+
+fn index_file(location: &Path, path: &str) -> PathBuf {
+    let mut file = location.to_path_buf();
+    file.push(INDEX_DIR);
+    file.push(path);
+    file
+}
+
+/// Load the index of `path` from the store at `location`, `None` if there is none or it cannot
+/// be read
+fn load_values(backend: &StoreBackend, location: &Path, path: &str)
+    -> Option<BTreeMap<IndexKey, BTreeSet<PathBuf>>>
+{
+    let file = index_file(location, path);
+    if !backend.is_file(&file) {
+        debug!("No index for '{}' yet", path);
+        return None;
+    }
+
+    let loaded = backend
+        .read(&file)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|text| Parser::new(&text).parse())
+        .and_then(values_from_table);
+
+    if loaded.is_none() {
+        warn!("Could not load index for '{}', run a reindex", path);
+    }
+    loaded
+}
+
+/// Replace what is indexed for the `touched` entries in `on_disk` with what `values` has for them
+fn merge(mut on_disk: BTreeMap<IndexKey, BTreeSet<PathBuf>>,
+         values: &BTreeMap<IndexKey, BTreeSet<PathBuf>>,
+         touched: &BTreeSet<PathBuf>)
+    -> BTreeMap<IndexKey, BTreeSet<PathBuf>>
+{
+    for id in touched.iter() {
+        remove_id(&mut on_disk, id);
+    }
+    for (key, ids) in values.iter() {
+        for id in ids.iter().filter(|id| touched.contains(*id)) {
+            on_disk.entry(key.clone()).or_insert_with(BTreeSet::new).insert(id.clone());
+        }
+    }
+    on_disk
+}
+
+/// Remove `id` from all keys in `values`
+fn remove_id(values: &mut BTreeMap<IndexKey, BTreeSet<PathBuf>>, id: &Path) {
+    let keys : Vec<IndexKey> = values.iter()
+        .filter(|&(_, ids)| ids.contains(id))
+        .map(|(k, _)| k.clone())
+        .collect();
+
+    for k in keys.iter() {
+        remove_from(values, k, id);
+    }
+}
+
+fn remove_from(values: &mut BTreeMap<IndexKey, BTreeSet<PathBuf>>, key: &IndexKey, id: &Path) {
+    let now_empty = match values.get_mut(key) {
+        Some(ids) => {
+            ids.remove(id);
+            ids.is_empty()
+        },
+        None => false,
+    };
+
+    if now_empty {
+        values.remove(key);
+    }
+}
+
+fn values_to_value(values: &BTreeMap<IndexKey, BTreeSet<PathBuf>>) -> Value {
+    let entries = values.iter()
+        .map(|(k, ids)| {
+            let ids = ids.iter()
+                .filter_map(|id| id.to_str().map(String::from))
+                .map(Value::String)
+                .collect();
+
+            let mut entry = BTreeMap::new();
+            entry.insert(String::from("key"), k.to_value());
+            entry.insert(String::from("ids"), Value::Array(ids));
+            Value::Table(entry)
+        })
+        .collect();
+
+    let mut t = BTreeMap::new();
+    t.insert(String::from("entries"), Value::Array(entries));
+    Value::Table(t)
+}
+
+/// Read the index of one path, `None` if `t` is not an index (for example an index written by an
+/// older version, which did not know about typed keys)
+fn values_from_table(t: BTreeMap<String, Value>) -> Option<BTreeMap<IndexKey, BTreeSet<PathBuf>>> {
+    let entries = match t.get("entries") {
+        Some(&Value::Array(ref entries)) => entries,
+        _ => return None,
+    };
+
+    let mut values = BTreeMap::new();
+    for entry in entries {
+        let entry = match *entry {
+            Value::Table(ref entry) => entry,
+            _ => return None,
+        };
+
+        let key = match entry.get("key").and_then(IndexKey::from_value) {
+            Some(key) => key,
+            None => return None,
+        };
+
+        let ids = match entry.get("ids") {
+            Some(&Value::Array(ref a)) => a.iter()
+                .filter_map(|id| match *id {
+                    Value::String(ref s) => Some(PathBuf::from(s)),
+                    _ => None,
+                })
+                .collect(),
+            _ => BTreeSet::new(),
+        };
+
+        values.insert(key, ids);
+    }
+
+    Some(values)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+
+    use toml::Value;
+
+    use super::{HeaderIndex, IndexKey, values_from_table, values_to_value};
+    use store::EntryHeader;
+
+    fn key(s: &str) -> IndexKey {
+        IndexKey::String(String::from(s))
+    }
+
+    fn header_with_tags(tags: Vec<&str>) -> EntryHeader {
+        let mut header = EntryHeader::new();
+        header.insert("tag", Value::Table(BTreeMap::new())).unwrap();
+        let tags = tags.into_iter().map(|t| Value::String(String::from(t))).collect();
+        header.insert("tag.values", Value::Array(tags)).unwrap();
+        header
+    }
+
+    #[test]
+    fn test_index_update_lookup_remove() {
+        let mut index = HeaderIndex::new(vec![String::from("tag.values")]);
+        let a = Path::new("/store/a");
+        let b = Path::new("/store/b");
+
+        assert_eq!(index.update(a, &header_with_tags(vec!["x", "y"])), vec!["tag.values"]);
+        assert_eq!(index.update(b, &header_with_tags(vec!["y"])), vec!["tag.values"]);
+        assert!(index.update(b, &header_with_tags(vec!["y"])).is_empty());
+
+        assert_eq!(index.lookup("tag.values", &key("x")), vec![PathBuf::from("/store/a")]);
+        assert_eq!(index.lookup("tag.values", &key("y")).len(), 2);
+
+        index.update(a, &header_with_tags(vec!["y"]));
+        assert!(index.lookup("tag.values", &key("x")).is_empty());
+
+        index.remove(b);
+        index.rename(a, Path::new("/store/c"));
+        assert_eq!(index.lookup("tag.values", &key("y")), vec![PathBuf::from("/store/c")]);
+    }
+
+    #[test]
+    fn test_index_keys_are_typed() {
+        let mut index = HeaderIndex::new(vec![String::from("a.b")]);
+
+        let mut one = EntryHeader::new();
+        one.insert("a", Value::Table(BTreeMap::new())).unwrap();
+        one.insert("a.b", Value::Integer(1)).unwrap();
+
+        let mut one_str = EntryHeader::new();
+        one_str.insert("a", Value::Table(BTreeMap::new())).unwrap();
+        one_str.insert("a.b", Value::String(String::from("1"))).unwrap();
+
+        index.update(Path::new("/store/int"), &one);
+        index.update(Path::new("/store/str"), &one_str);
+
+        assert_eq!(index.lookup("a.b", &IndexKey::Integer(1)),
+                   vec![PathBuf::from("/store/int")]);
+        assert_eq!(index.lookup("a.b", &key("1")), vec![PathBuf::from("/store/str")]);
+
+        // The keys survive being persisted
+        let values = index.paths.get("a.b").unwrap();
+        let saved = match values_to_value(values) {
+            Value::Table(t) => t,
+            _ => panic!("Index is not a table"),
+        };
+        assert_eq!(values_from_table(saved).as_ref(), Some(values));
+    }
+
+}
@@ -1,7 +1,8 @@
 use error::{MapErrInto, StoreError as SE, StoreErrorKind as SEK};
 use std::io::{Seek, SeekFrom};
-use std::path::{Path, PathBuf};
-use std::fs::{File, OpenOptions, create_dir_all};
+use std::path::PathBuf;
+
+use backend::{StoreBackend, BackendFile};
 
 /// `LazyFile` type
 ///
@@ -9,21 +10,7 @@ use std::fs::{File, OpenOptions, create_dir_all};
 #[derive(Debug)]
 pub enum LazyFile {
     Absent(PathBuf),
-    File(File)
-}
-
-fn open_file<A: AsRef<Path>>(p: A) -> ::std::io::Result<File> {
-    OpenOptions::new().write(true).read(true).open(p)
-}
-
-fn create_file<A: AsRef<Path>>(p: A) -> ::std::io::Result<File> {
-    if let Some(parent) = p.as_ref().parent() {
-        debug!("Implicitely creating directory: {:?}", parent);
-        if let Err(e) = create_dir_all(parent) {
-            return Err(e);
-        }
-    }
-    OpenOptions::new().write(true).read(true).create(true).open(p)
+    File(Box<BackendFile>)
 }
 
 impl LazyFile {
@@ -31,7 +18,7 @@ impl LazyFile {
     /**
      * Get the mutable file behind a LazyFile object
      */
-    pub fn get_file_mut(&mut self) -> Result<&mut File, SE> {
+    pub fn get_file_mut(&mut self, backend: &StoreBackend) -> Result<&mut Box<BackendFile>, SE> {
         debug!("Getting lazy file: {:?}", self);
         let file = match *self {
             LazyFile::File(ref mut f) => return {
@@ -41,7 +28,7 @@ impl LazyFile {
                     .map_err_into(SEK::FileNotCreated)
                     .map(|_| f)
             },
-            LazyFile::Absent(ref p) => try!(open_file(p).map_err_into(SEK::FileNotFound)),
+            LazyFile::Absent(ref p) => try!(backend.open(p).map_err_into(SEK::FileNotFound)),
         };
         *self = LazyFile::File(file);
         if let LazyFile::File(ref mut f) = *self {
@@ -53,11 +40,11 @@ impl LazyFile {
     /**
      * Create a file out of this LazyFile object
      */
-    pub fn create_file(&mut self) -> Result<&mut File, SE> {
+    pub fn create_file(&mut self, backend: &StoreBackend) -> Result<&mut Box<BackendFile>, SE> {
         debug!("Creating lazy file: {:?}", self);
         let file = match *self {
             LazyFile::File(ref mut f) => return Ok(f),
-            LazyFile::Absent(ref p) => try!(create_file(p).map_err_into(SEK::FileNotFound)),
+            LazyFile::Absent(ref p) => try!(backend.create(p).map_err_into(SEK::FileNotFound)),
         };
         *self = LazyFile::File(file);
         if let LazyFile::File(ref mut f) = *self {
@@ -73,6 +60,7 @@ mod test {
     use std::io::{Read, Write};
     use std::path::PathBuf;
     use tempdir::TempDir;
+    use backend::FileSystemBackend;
 
     fn get_dir() -> TempDir {
         TempDir::new("test-image").unwrap()
@@ -85,7 +73,7 @@ mod test {
         path.set_file_name("test1");
         let mut lf = LazyFile::Absent(path);
 
-        write!(lf.create_file().unwrap(), "Hello World").unwrap();
+        write!(lf.create_file(&FileSystemBackend::new()).unwrap(), "Hello World").unwrap();
         dir.close().unwrap();
     }
 
@@ -94,17 +82,18 @@ mod test {
         let dir = get_dir();
         let mut path = PathBuf::from(dir.path());
         path.set_file_name("test2");
+        let backend = FileSystemBackend::new();
         let mut lf = LazyFile::Absent(path.clone());
 
         {
-            let mut file = lf.create_file().unwrap();
+            let mut file = lf.create_file(&backend).unwrap();
 
             file.write(b"Hello World").unwrap();
             file.sync_all().unwrap();
         }
 
         {
-            let mut file = lf.get_file_mut().unwrap();
+            let mut file = lf.get_file_mut(&backend).unwrap();
             let mut s = Vec::new();
             file.read_to_end(&mut s).unwrap();
             assert_eq!(s, "Hello World".to_string().into_bytes());
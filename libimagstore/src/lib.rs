@@ -15,22 +15,45 @@
 #[macro_use] extern crate log;
 #[macro_use] extern crate version;
 extern crate fs2;
-extern crate glob;
 #[macro_use] extern crate lazy_static;
 extern crate regex;
 extern crate toml;
 #[cfg(test)] extern crate tempdir;
+#[cfg(test)] #[macro_use] extern crate serde_derive;
 extern crate semver;
 extern crate crossbeam;
+extern crate notify;
+extern crate crypto;
+extern crate rand;
+extern crate tar;
+extern crate serde;
+extern crate serde_json;
+extern crate yaml_rust;
 extern crate walkdir;
 
 #[macro_use] extern crate libimagerror;
 #[macro_use] extern crate libimagutil;
 
 pub mod storeid;
+pub mod archive;
+pub mod attachment;
+pub mod backend;
+pub mod changelog;
+pub mod encryption;
 pub mod error;
+pub mod format;
+pub mod gc;
+pub mod headerpath;
 pub mod hook;
+pub mod index;
+pub mod migration;
+pub mod object;
+pub mod revision;
+pub mod schema;
+pub mod search;
+pub mod snapshot;
 pub mod store;
+pub mod stream;
 mod configuration;
 mod lazyfile;
 
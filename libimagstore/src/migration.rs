@@ -0,0 +1,204 @@
+//! Header migrations
+//!
+//! Every entry records the version of imag it was written with in `imag.version`. When a module
+//! changes the layout of its header, it declares a `Migration` from the last version with the old
+//! layout to the first version with the new layout and adds it to a `MigrationRegistry`.
+//!
+//! `Store::migrate()` walks the store and applies the pending migrations of the module an entry
+//! belongs to (the first component of the entry path inside the store), and bumps `imag.version`
+//! afterwards. A migration is only applied to entries written with a version from its `from` up
+//! to (but not including) its `to`, and the migrations are applied in a chain, each one starting
+//! at the version the previous one ended with. If the chain has a gap, so a later migration of the
+//! module cannot be reached, migrating the entry fails.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::fmt::Error as FmtError;
+use std::result::Result as RResult;
+
+use semver::Version;
+
+use store::{EntryHeader, EntryContent, Result};
+
+/// The function which transforms an entry from the old to the new layout
+pub type MigrationFn = Box<Fn(&mut EntryHeader, &mut EntryContent) -> Result<()>>;
+
+/// A migration of the entries of one module
+pub struct Migration {
+    module: String,
+    from: Version,
+    to: Version,
+    func: MigrationFn,
+}
+
+impl Migration {
+
+    /// Create a migration for the entries of `module`, which were written with a version
+    /// from `from` up to (but not including) `to`.
+    pub fn new<S: Into<String>>(module: S, from: Version, to: Version, func: MigrationFn)
+        -> Migration
+    {
+        Migration {
+            module: module.into(),
+            from: from,
+            to: to,
+            func: func,
+        }
+    }
+
+    pub fn module(&self) -> &String {
+        &self.module
+    }
+
+    pub fn from(&self) -> &Version {
+        &self.from
+    }
+
+    pub fn to(&self) -> &Version {
+        &self.to
+    }
+
+    pub fn apply(&self, header: &mut EntryHeader, content: &mut EntryContent) -> Result<()> {
+        (self.func)(header, content)
+    }
+
+}
+
+impl Debug for Migration {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        write!(fmt, "Migration({}: {} -> {})", self.module, self.from, self.to)
+    }
+
+}
+
+/// The set of all known migrations
+#[derive(Debug)]
+pub struct MigrationRegistry {
+    migrations: Vec<Migration>,
+}
+
+impl MigrationRegistry {
+
+    pub fn new() -> MigrationRegistry {
+        MigrationRegistry {
+            migrations: vec![],
+        }
+    }
+
+    /// Add a migration to the registry
+    pub fn register(&mut self, migration: Migration) {
+        debug!("Registering {:?}", migration);
+        self.migrations.push(migration);
+        self.migrations.sort_by(|a, b| a.to.cmp(&b.to));
+    }
+
+    /// Add all migrations of a module to the registry
+    pub fn register_all<I: IntoIterator<Item = Migration>>(&mut self, migrations: I) {
+        for migration in migrations {
+            self.register(migration);
+        }
+    }
+
+    /// Get the migrations which have to be applied to an entry of `module` which was written with
+    /// `version`, in the order they have to be applied
+    ///
+    /// The chain ends at the first gap, see `latest()`.
+    pub fn pending(&self, module: &str, version: &Version) -> Vec<&Migration> {
+        let mut version = version.clone();
+        let mut pending = vec![];
+        for m in self.migrations.iter().filter(|m| m.module == module) {
+            if m.from <= version && version < m.to {
+                version = m.to.clone();
+                pending.push(m);
+            }
+        }
+        pending
+    }
+
+    /// Get the version the last migration of `module` migrates to
+    ///
+    /// An entry whose pending migrations end at an older version could not be migrated completely.
+    pub fn latest(&self, module: &str) -> Option<&Version> {
+        self.migrations
+            .iter()
+            .filter(|m| m.module == module)
+            .map(|m| &m.to)
+            .last()
+    }
+
+}
+
+/// The result of migrating one entry
+#[derive(Debug, Clone)]
+pub struct MigrationReport {
+    /// The version the entry had before
+    pub from: Version,
+
+    /// The version the entry has afterwards
+    pub to: Version,
+
+    /// The migrations which were applied, as pairs of versions
+    pub applied: Vec<(Version, Version)>,
+}
+
+impl Display for MigrationReport {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        try!(write!(fmt, "{} -> {}", self.from, self.to));
+        for &(ref from, ref to) in self.applied.iter() {
+            try!(write!(fmt, " [{} -> {}]", from, to));
+        }
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use semver::Version;
+
+    use super::{Migration, MigrationRegistry};
+
+    fn migration(module: &str, from: &str, to: &str) -> Migration {
+        Migration::new(module,
+                       Version::parse(from).unwrap(),
+                       Version::parse(to).unwrap(),
+                       Box::new(|_, _| Ok(())))
+    }
+
+    #[test]
+    fn test_pending_migrations_are_ordered() {
+        let mut registry = MigrationRegistry::new();
+        registry.register(migration("counter", "0.2.0", "0.3.0"));
+        registry.register(migration("counter", "0.1.0", "0.2.0"));
+        registry.register(migration("notes", "0.1.0", "0.2.0"));
+
+        let pending = registry.pending("counter", &Version::parse("0.1.0").unwrap());
+        let targets : Vec<String> = pending.iter().map(|m| format!("{}", m.to())).collect();
+        assert_eq!(targets, vec!["0.2.0", "0.3.0"]);
+
+        assert_eq!(registry.pending("counter", &Version::parse("0.2.0").unwrap()).len(), 1);
+        assert!(registry.pending("counter", &Version::parse("0.3.0").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_pending_migrations_stop_at_gap() {
+        let mut registry = MigrationRegistry::new();
+        registry.register(migration("counter", "0.1.0", "0.2.0"));
+        registry.register(migration("counter", "0.3.0", "0.4.0"));
+
+        let targets = |version: &str| -> Vec<String> {
+            registry.pending("counter", &Version::parse(version).unwrap())
+                .iter()
+                .map(|m| format!("{}", m.to()))
+                .collect()
+        };
+        assert_eq!(targets("0.1.0"), vec!["0.2.0"]);
+        assert!(targets("0.0.1").is_empty());
+        assert!(targets("0.2.5").is_empty());
+        assert_eq!(targets("0.3.1"), vec!["0.4.0"]);
+        assert_eq!(registry.latest("counter"), Some(&Version::parse("0.4.0").unwrap()));
+        assert_eq!(registry.latest("notes"), None);
+    }
+
+}
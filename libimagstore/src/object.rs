@@ -0,0 +1,448 @@
+//! Content-addressed object layer
+//!
+//! Objects are immutable blobs kept in `<store>/.objects`, named by the SHA-256 hash of their
+//! content (see `attachment::content_hash()`), so identical data is stored only once and can be
+//! checked for corruption by hashing it again.
+//!
+//! With `object-layer = true` in the `[store]` section of the configuration, the runtime wraps
+//! the backend of the store in an `ObjectBackend`. It stores the header and the content of each
+//! entry as objects and replaces the entry file by a tree which lists their hashes:
+//!
+//! ```text
+//! imag-objects 1
+//! <hash of the header part>
+//! <hash of the content part>
+//! ```
+//!
+//! Identical headers and contents are thus shared between entries, copying an entry only copies
+//! its tree, and two entries are equal if their trees are. Entry files which are not trees (for
+//! example from before the object layer was enabled) are read as they are and turned into trees
+//! the next time they are written.
+//!
+//! Objects are never changed, so when an entry is written, the objects of its old version stay.
+//! `Store::gc()` removes the objects which neither an entry nor a snapshot refers to (see
+//! `unused()`). Writing an entry and collecting garbage take the lock `.locks/.objects`, so
+//! objects are not removed between being stored and being referred to.
+
+use std::fmt::{Debug, Formatter};
+use std::fmt::Error as FmtError;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::io::Result as IoResult;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeSet;
+use std::result::Result as RResult;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use toml::Value;
+
+use attachment::content_hash;
+use backend::{StoreBackend, BackendFile, BackendObject, BackendLock, BackendWatcher};
+use backend::{EntryCodec, CodecFile};
+
+/// The name of the directory (inside the store) where the objects are kept
+pub static OBJECTS_DIR : &'static str = ".objects";
+
+/// The first line of a tree
+static TREE_MAGIC : &'static str = "imag-objects 1";
+
+/// The name of the lock (inside `store::LOCKS_DIR`) held while objects are stored or removed
+static OBJECTS_LOCK_FILE : &'static str = ".objects";
+
+/// How long to wait for the objects lock before giving up
+const OBJECTS_LOCK_TIMEOUT_SECS : u64 = 60;
+
+/// Check whether the object layer is enabled in the `[store]` section of the configuration
+///
+/// ```toml
+/// [store]
+/// object-layer = true
+/// ```
+///
+/// It is disabled by default.
+pub fn is_object_layer_enabled(config: &Option<Value>) -> bool {
+    match *config {
+        Some(Value::Table(ref t)) => match t.get("object-layer") {
+            Some(&Value::Boolean(b)) => b,
+            Some(_) => {
+                warn!("'object-layer' should be a Boolean, ignoring it");
+                false
+            },
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Get the path of the object with `hash` in the store at `location`
+pub fn object_path(location: &Path, hash: &str) -> PathBuf {
+    let mut path = location.to_path_buf();
+    path.push(OBJECTS_DIR);
+    if hash.len() > 2 {
+        path.push(&hash[..2]);
+        path.push(&hash[2..]);
+    } else {
+        path.push(hash);
+    }
+    path
+}
+
+/// Store `data` as an object in the store at `location`, unless it is there already
+///
+/// Returns the hash of the object.
+pub fn put(backend: &StoreBackend, location: &Path, data: &[u8]) -> IoResult<String> {
+    let hash = content_hash(data);
+    let path = object_path(location, &hash);
+    if !backend.is_file(&path) {
+        try!(backend.write(&path, data));
+    }
+    Ok(hash)
+}
+
+/// Get the object with `hash` from the store at `location`
+///
+/// Fails with `ErrorKind::InvalidData` if the object does not match its hash.
+pub fn get(backend: &StoreBackend, location: &Path, hash: &str) -> IoResult<Vec<u8>> {
+    backend.read(&object_path(location, hash)).and_then(|data| {
+        if content_hash(&data) == hash {
+            Ok(data)
+        } else {
+            Err(IoError::new(IoErrorKind::InvalidData, format!("Object {} is corrupted", hash)))
+        }
+    })
+}
+
+/// Get the hashes of all objects in the store at `location`
+pub fn list(backend: &StoreBackend, location: &Path) -> IoResult<Vec<String>> {
+    let mut dir = location.to_path_buf();
+    dir.push(OBJECTS_DIR);
+    if !backend.exists(&dir) {
+        return Ok(vec![]);
+    }
+
+    backend.list(&dir).map(|paths| {
+        paths.into_iter()
+            .filter_map(|p| {
+                p.strip_prefix(&dir)
+                    .ok()
+                    .and_then(|rel| rel.to_str())
+                    .map(|rel| rel.replace("/", "").replace("\\", ""))
+            })
+            .collect()
+    })
+}
+
+/// Get the hashes of the objects in the store at `location` which are not in `referenced`
+///
+/// The caller has to hold the lock at `objects_lock_path()`, or objects which are about to be
+/// referred to are reported as well.
+pub fn unused(backend: &StoreBackend, location: &Path, referenced: &BTreeSet<String>)
+    -> IoResult<Vec<String>>
+{
+    list(backend, location).map(|hashes| {
+        hashes.into_iter().filter(|hash| !referenced.contains(hash)).collect()
+    })
+}
+
+/// Get the path of the lock which is held while objects are stored or removed
+pub fn objects_lock_path(location: &Path) -> PathBuf {
+    let mut path = location.to_path_buf();
+    path.push(::store::LOCKS_DIR);
+    path.push(OBJECTS_LOCK_FILE);
+    path
+}
+
+/// Get the hashes of all objects in the store at `location` whose content does not match their
+/// hash (anymore)
+pub fn corrupted(backend: &StoreBackend, location: &Path) -> IoResult<Vec<String>> {
+    list(backend, location).map(|hashes| {
+        hashes.into_iter()
+            .filter(|hash| get(backend, location, hash).is_err())
+            .collect()
+    })
+}
+
+/// Split the text of an entry into the header part (including the `---` lines) and the content
+pub fn split_entry(data: &[u8]) -> Vec<&[u8]> {
+    let delim = b"\n---\n";
+    if data.starts_with(b"---\n") {
+        let end = data.windows(delim.len())
+            .skip(3)
+            .position(|w| w == delim)
+            .map(|pos| pos + 3 + delim.len());
+
+        if let Some(end) = end {
+            return vec![&data[..end], &data[end..]];
+        }
+    }
+    vec![data]
+}
+
+fn tree_text(hashes: &[String]) -> String {
+    let mut text = String::from(TREE_MAGIC);
+    for hash in hashes {
+        text.push('\n');
+        text.push_str(hash);
+    }
+    text.push('\n');
+    text
+}
+
+/// Parse a tree, returns `None` if `data` is no tree
+fn parse_tree(data: &[u8]) -> Option<Vec<String>> {
+    let text = match ::std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return None,
+    };
+
+    let mut lines = text.lines();
+    if lines.next() != Some(TREE_MAGIC) {
+        return None;
+    }
+    Some(lines.filter(|l| !l.is_empty()).map(String::from).collect())
+}
+
+/// A backend which keeps the entries of a store as trees of objects in another backend
+///
+/// Files inside the store whose first path component starts with a dot (revisions, indexes,
+/// locks, the objects themselves, ...) are passed through to the wrapped backend unchanged.
+#[derive(Clone)]
+pub struct ObjectBackend {
+    location: PathBuf,
+    inner: Arc<Mutex<Box<StoreBackend>>>,
+}
+
+impl ObjectBackend {
+
+    /// Wrap `inner` for the store at `location`
+    pub fn new(location: PathBuf, inner: Box<StoreBackend>) -> ObjectBackend {
+        ObjectBackend {
+            location: location,
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    fn with_inner<T, F>(&self, f: F) -> IoResult<T>
+        where F: FnOnce(&StoreBackend) -> IoResult<T>
+    {
+        self.inner
+            .lock()
+            .map_err(|_| IoError::new(IoErrorKind::Other, "Object backend lock poisoned"))
+            .and_then(|inner| f(&**inner))
+    }
+
+    /// Wait for the objects lock and take it
+    ///
+    /// The wrapped backend is not kept locked while waiting, so other threads can finish with
+    /// the objects in the meantime.
+    fn lock_objects(&self) -> IoResult<Box<BackendLock>> {
+        let path  = objects_lock_path(&self.location);
+        let start = Instant::now();
+        loop {
+            if let Some(lock) = try!(self.with_inner(|inner| inner.try_lock(&path))) {
+                return Ok(lock);
+            }
+            if start.elapsed() >= Duration::from_secs(OBJECTS_LOCK_TIMEOUT_SECS) {
+                return Err(IoError::new(IoErrorKind::Other, "Objects are locked"));
+            }
+            sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Whether `path` is an entry (and thus kept as a tree)
+    fn is_entry(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.location)
+            .ok()
+            .and_then(|rel| rel.components().next())
+            .and_then(|c| c.as_os_str().to_str())
+            .map(|c| !c.starts_with('.'))
+            .unwrap_or(false)
+    }
+
+}
+
+impl EntryCodec for ObjectBackend {
+
+    fn read_entry(&self, path: &Path) -> IoResult<Vec<u8>> {
+        self.with_inner(|inner| {
+            let data = try!(inner.read(path));
+            match parse_tree(&data) {
+                None => Ok(data),
+                Some(hashes) => {
+                    let mut content = vec![];
+                    for hash in hashes {
+                        content.extend(try!(get(inner, &self.location, &hash)));
+                    }
+                    Ok(content)
+                },
+            }
+        })
+    }
+
+    fn write_entry(&self, path: &Path, content: &[u8]) -> IoResult<()> {
+        let _lock = try!(self.lock_objects());
+        self.with_inner(|inner| {
+            // Empty parts (of new entries) are left out instead of being stored as objects
+            let mut hashes = vec![];
+            for part in split_entry(content).into_iter().filter(|p| !p.is_empty()) {
+                hashes.push(try!(put(inner, &self.location, part)));
+            }
+            inner.write(path, tree_text(&hashes).as_bytes())
+        })
+    }
+
+}
+
+impl Debug for ObjectBackend {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        write!(fmt, "ObjectBackend {{ location: {:?} }}", self.location)
+    }
+
+}
+
+impl StoreBackend for ObjectBackend {
+
+    fn open(&self, path: &Path) -> IoResult<Box<BackendFile>> {
+        if !self.is_entry(path) {
+            return self.with_inner(|inner| inner.open(path));
+        }
+
+        CodecFile::open(self.clone(), path).map(|f| Box::new(f) as Box<BackendFile>)
+    }
+
+    fn create(&self, path: &Path) -> IoResult<Box<BackendFile>> {
+        if !self.is_entry(path) {
+            return self.with_inner(|inner| inner.create(path));
+        }
+
+        if !self.is_file(path) {
+            try!(self.write_entry(path, b""));
+        }
+        self.open(path)
+    }
+
+    fn remove(&self, path: &Path) -> IoResult<()> {
+        self.with_inner(|inner| inner.remove(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> IoResult<()> {
+        self.with_inner(|inner| inner.rename(from, to))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> IoResult<()> {
+        self.with_inner(|inner| inner.create_dir_all(path))
+    }
+
+    fn remove_dir(&self, path: &Path) -> IoResult<()> {
+        self.with_inner(|inner| inner.remove_dir(path))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.with_inner(|inner| Ok(inner.exists(path))).unwrap_or(false)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.with_inner(|inner| Ok(inner.is_file(path))).unwrap_or(false)
+    }
+
+    fn walk(&self, path: &Path) -> IoResult<Vec<BackendObject>> {
+        self.with_inner(|inner| inner.walk(path))
+    }
+
+    fn try_lock(&self, path: &Path) -> IoResult<Option<Box<BackendLock>>> {
+        self.with_inner(|inner| inner.try_lock(path))
+    }
+
+    fn watch(&self, path: &Path) -> IoResult<Box<BackendWatcher>> {
+        self.with_inner(|inner| inner.watch(path))
+    }
+
+    fn read(&self, path: &Path) -> IoResult<Vec<u8>> {
+        if self.is_entry(path) {
+            self.read_entry(path)
+        } else {
+            self.with_inner(|inner| inner.read(path))
+        }
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> IoResult<()> {
+        if self.is_entry(path) {
+            self.write_entry(path, content)
+        } else {
+            self.with_inner(|inner| inner.write(path, content))
+        }
+    }
+
+    /// Copying an entry only copies its tree
+    fn copy(&self, from: &Path, to: &Path) -> IoResult<()> {
+        if self.is_entry(from) && self.is_entry(to) {
+            self.with_inner(|inner| inner.copy(from, to))
+        } else {
+            self.read(from).and_then(|content| self.write(to, &content))
+        }
+    }
+
+    fn referenced_objects(&self, path: &Path) -> IoResult<Vec<String>> {
+        if !self.is_entry(path) {
+            return self.with_inner(|inner| inner.referenced_objects(path));
+        }
+
+        self.with_inner(|inner| inner.read(path))
+            .map(|data| parse_tree(&data).unwrap_or_else(Vec::new))
+    }
+
+    /// The tree of an entry identifies its content, so it is hashed instead of the content
+    fn content_id(&self, path: &Path) -> IoResult<String> {
+        if !self.is_entry(path) {
+            return self.with_inner(|inner| inner.content_id(path));
+        }
+
+        self.with_inner(|inner| inner.read(path)).map(|data| {
+            match parse_tree(&data) {
+                Some(_) => content_hash(&data),
+                None => {
+                    let hashes : Vec<String> = split_entry(&data)
+                        .into_iter()
+                        .map(content_hash)
+                        .collect();
+                    content_hash(tree_text(&hashes).as_bytes())
+                },
+            }
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use backend::{StoreBackend, InMemoryBackend};
+
+    use super::{ObjectBackend, list, corrupted, object_path};
+
+    #[test]
+    fn test_entries_are_deduplicated() {
+        let inner   = InMemoryBackend::new();
+        let backend = ObjectBackend::new(PathBuf::from("/store"), Box::new(inner.clone()));
+        let a       = PathBuf::from("/store/notes/a");
+        let b       = PathBuf::from("/store/notes/b");
+        let entry   = b"---\n[imag]\nversion = \"0.2.0\"\n---\nSame content";
+
+        backend.write(&a, entry).unwrap();
+        backend.write(&b, entry).unwrap();
+
+        assert_eq!(backend.read(&a).unwrap(), entry.to_vec());
+        assert!(inner.read(&a).unwrap().starts_with(b"imag-objects 1\n"));
+        assert_eq!(list(&inner, &PathBuf::from("/store")).unwrap().len(), 2);
+        assert_eq!(backend.content_id(&a).unwrap(), backend.content_id(&b).unwrap());
+
+        let hash = list(&inner, &PathBuf::from("/store")).unwrap().remove(0);
+        inner.write(&object_path(&PathBuf::from("/store"), &hash), b"garbage").unwrap();
+        assert_eq!(corrupted(&inner, &PathBuf::from("/store")).unwrap(), vec![hash]);
+        assert!(backend.read(&a).is_err());
+    }
+
+}
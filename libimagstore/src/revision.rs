@@ -0,0 +1,100 @@
+//! Revisions of store entries
+//!
+//! Each time an entry is written, the content it had before is kept as a numbered revision in
+//! `<store>/.revisions/<entry path>/<number>`. Revision numbers start at 1 and increase
+//! monotonically per entry.
+
+use std::fmt::{Display, Formatter};
+use std::fmt::Error as FmtError;
+use std::result::Result as RResult;
+
+/// The number of a revision of an entry
+pub type Revision = u64;
+
+/// The name of the directory (inside the store) where revisions are kept
+pub static REVISIONS_DIR : &'static str = ".revisions";
+
+/// One line of a diff between two revisions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Unchanged(String),
+    Added(String),
+    Removed(String),
+}
+
+impl Display for DiffLine {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        match *self {
+            DiffLine::Unchanged(ref s) => write!(fmt, " {}", s),
+            DiffLine::Added(ref s)     => write!(fmt, "+{}", s),
+            DiffLine::Removed(ref s)   => write!(fmt, "-{}", s),
+        }
+    }
+
+}
+
+/// Compute a line-based diff from `old` to `new`
+pub fn diff(old: &str, new: &str) -> Vec<DiffLine> {
+    use std::cmp::max;
+
+    let a : Vec<&str> = old.lines().collect();
+    let b : Vec<&str> = new.lines().collect();
+    let (n, m) = (a.len(), b.len());
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a[i] == b[j] {
+            result.push(DiffLine::Unchanged(String::from(a[i])));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            result.push(DiffLine::Removed(String::from(a[i])));
+            i += 1;
+        } else {
+            result.push(DiffLine::Added(String::from(b[j])));
+            j += 1;
+        }
+    }
+
+    result.extend(a[i..].iter().map(|l| DiffLine::Removed(String::from(*l))));
+    result.extend(b[j..].iter().map(|l| DiffLine::Added(String::from(*l))));
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diff, DiffLine};
+
+    #[test]
+    fn test_diff_equal() {
+        let d = diff("a\nb", "a\nb");
+        assert_eq!(d, vec![DiffLine::Unchanged("a".into()), DiffLine::Unchanged("b".into())]);
+    }
+
+    #[test]
+    fn test_diff_changed_line() {
+        let d = diff("a\nb\nc", "a\nx\nc\nd");
+        assert_eq!(d, vec![
+            DiffLine::Unchanged("a".into()),
+            DiffLine::Removed("b".into()),
+            DiffLine::Added("x".into()),
+            DiffLine::Unchanged("c".into()),
+            DiffLine::Added("d".into()),
+        ]);
+    }
+
+}
@@ -0,0 +1,439 @@
+//! Header schemas
+//!
+//! Modules can register a `HeaderSchema` for their header namespace (the top-level table of the
+//! header which is named like the module, for example `[counter]`). The schema states which keys
+//! are required, which type their values have and which range the values are allowed to be in.
+//!
+//! The store checks the schemas whenever an entry is written and `Store::verify()` reports
+//! entries which violate them. An entry which does not have the namespace table at all is not
+//! checked against the schema.
+//!
+//! Registered schemas are persisted in `<store>/.schemas/<namespace>`, so tools which do not
+//! link the module (like `imag-store verify`) know them as well.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt::Error as FmtError;
+use std::result::Result as RResult;
+
+use toml::Value;
+
+use store::EntryHeader;
+
+/// The name of the directory (inside the store) where schemas are kept
+pub static SCHEMAS_DIR : &'static str = ".schemas";
+
+/// The type a header value is expected to have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Datetime,
+    Array,
+    Table,
+}
+
+impl FieldType {
+
+    /// Get the type of a value
+    pub fn of(v: &Value) -> FieldType {
+        match *v {
+            Value::String(_)   => FieldType::String,
+            Value::Integer(_)  => FieldType::Integer,
+            Value::Float(_)    => FieldType::Float,
+            Value::Boolean(_)  => FieldType::Boolean,
+            Value::Datetime(_) => FieldType::Datetime,
+            Value::Array(_)    => FieldType::Array,
+            Value::Table(_)    => FieldType::Table,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match *self {
+            FieldType::String   => "string",
+            FieldType::Integer  => "integer",
+            FieldType::Float    => "float",
+            FieldType::Boolean  => "boolean",
+            FieldType::Datetime => "datetime",
+            FieldType::Array    => "array",
+            FieldType::Table    => "table",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<FieldType> {
+        match s {
+            "string"   => Some(FieldType::String),
+            "integer"  => Some(FieldType::Integer),
+            "float"    => Some(FieldType::Float),
+            "boolean"  => Some(FieldType::Boolean),
+            "datetime" => Some(FieldType::Datetime),
+            "array"    => Some(FieldType::Array),
+            "table"    => Some(FieldType::Table),
+            _          => None,
+        }
+    }
+
+}
+
+impl Display for FieldType {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        write!(fmt, "{}", self.as_str())
+    }
+
+}
+
+/// The specification of one field in a header namespace
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSpec {
+    path: String,
+    kind: FieldType,
+    required: bool,
+
+    /// For integers and floats the allowed value range, for strings and arrays the allowed length
+    min: Option<i64>,
+    max: Option<i64>,
+}
+
+impl FieldSpec {
+
+    /// The path of the field, relative to the namespace
+    pub fn path(&self) -> &String {
+        &self.path
+    }
+
+    pub fn kind(&self) -> FieldType {
+        self.kind
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    fn in_range(&self, v: &Value) -> bool {
+        let (min, max) = (self.min, self.max);
+        let check = |n: f64| {
+            min.map(|m| n >= m as f64).unwrap_or(true) && max.map(|m| n <= m as f64).unwrap_or(true)
+        };
+
+        match *v {
+            Value::Integer(i)     => check(i as f64),
+            Value::Float(f)       => check(f),
+            Value::String(ref s)  => check(s.chars().count() as f64),
+            Value::Array(ref a)   => check(a.len() as f64),
+            _                     => true,
+        }
+    }
+
+}
+
+/// The schema of one header namespace
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderSchema {
+    namespace: String,
+    fields: Vec<FieldSpec>,
+}
+
+impl HeaderSchema {
+
+    /// Create an empty schema for the header namespace `namespace`
+    pub fn new<S: Into<String>>(namespace: S) -> HeaderSchema {
+        HeaderSchema {
+            namespace: namespace.into(),
+            fields: vec![],
+        }
+    }
+
+    pub fn namespace(&self) -> &String {
+        &self.namespace
+    }
+
+    pub fn fields(&self) -> &Vec<FieldSpec> {
+        &self.fields
+    }
+
+    /// Add a required field of type `kind` at `path` (relative to the namespace)
+    pub fn require<S: Into<String>>(self, path: S, kind: FieldType) -> HeaderSchema {
+        self.with_field(path.into(), kind, true)
+    }
+
+    /// Add an optional field of type `kind` at `path` (relative to the namespace)
+    pub fn optional<S: Into<String>>(self, path: S, kind: FieldType) -> HeaderSchema {
+        self.with_field(path.into(), kind, false)
+    }
+
+    /// Restrict the field at `path` to a range
+    ///
+    /// For integers and floats this is the range of the value, for strings and arrays the range
+    /// of the length. Both bounds are inclusive. Does nothing if there is no field at `path`.
+    pub fn range<S: AsRef<str>>(mut self, path: S, min: Option<i64>, max: Option<i64>)
+        -> HeaderSchema
+    {
+        if let Some(f) = self.fields.iter_mut().find(|f| f.path == path.as_ref()) {
+            f.min = min;
+            f.max = max;
+        }
+        self
+    }
+
+    fn with_field(mut self, path: String, kind: FieldType, required: bool) -> HeaderSchema {
+        self.fields.retain(|f| f.path != path);
+        self.fields.push(FieldSpec {
+            path: path,
+            kind: kind,
+            required: required,
+            min: None,
+            max: None,
+        });
+        self
+    }
+
+    /// Check `header` against this schema, returning all violations
+    ///
+    /// A header without the namespace table does not violate the schema.
+    pub fn validate(&self, header: &EntryHeader) -> Vec<SchemaViolation> {
+        match header.read(&self.namespace) {
+            Ok(Some(Value::Table(_))) => {},
+            Ok(Some(v)) => {
+                return vec![SchemaViolation::WrongType {
+                    path: self.namespace.clone(),
+                    expected: FieldType::Table,
+                    found: FieldType::of(&v),
+                }];
+            },
+            _ => return vec![],
+        }
+
+        self.fields
+            .iter()
+            .filter_map(|field| {
+                let path = format!("{}.{}", self.namespace, field.path);
+                match header.read(&path) {
+                    Ok(Some(v)) => {
+                        if FieldType::of(&v) != field.kind {
+                            Some(SchemaViolation::WrongType {
+                                path: path,
+                                expected: field.kind,
+                                found: FieldType::of(&v),
+                            })
+                        } else if !field.in_range(&v) {
+                            Some(SchemaViolation::OutOfRange {
+                                path: path,
+                                min: field.min,
+                                max: field.max,
+                            })
+                        } else {
+                            None
+                        }
+                    },
+                    _ => if field.required {
+                        Some(SchemaViolation::Missing(path))
+                    } else {
+                        None
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Serialize the schema, so it can be persisted in the store
+    ///
+    /// ```toml
+    /// namespace = "counter"
+    ///
+    /// [[fields]]
+    /// path = "value"
+    /// type = "integer"
+    /// required = true
+    /// ```
+    pub fn to_value(&self) -> Value {
+        let fields = self.fields
+            .iter()
+            .map(|f| {
+                let mut t = BTreeMap::new();
+                t.insert(String::from("path"), Value::String(f.path.clone()));
+                t.insert(String::from("type"), Value::String(String::from(f.kind.as_str())));
+                t.insert(String::from("required"), Value::Boolean(f.required));
+                if let Some(min) = f.min {
+                    t.insert(String::from("min"), Value::Integer(min));
+                }
+                if let Some(max) = f.max {
+                    t.insert(String::from("max"), Value::Integer(max));
+                }
+                Value::Table(t)
+            })
+            .collect();
+
+        let mut t = BTreeMap::new();
+        t.insert(String::from("namespace"), Value::String(self.namespace.clone()));
+        t.insert(String::from("fields"), Value::Array(fields));
+        Value::Table(t)
+    }
+
+    /// Deserialize a schema which was serialized with `HeaderSchema::to_value()`
+    pub fn from_value(v: &Value) -> Option<HeaderSchema> {
+        let t = match *v {
+            Value::Table(ref t) => t,
+            _ => return None,
+        };
+
+        let namespace = match t.get("namespace") {
+            Some(&Value::String(ref s)) => s.clone(),
+            _ => return None,
+        };
+
+        let fields = match t.get("fields") {
+            Some(&Value::Array(ref a)) => a,
+            _ => return None,
+        };
+
+        let mut schema = HeaderSchema::new(namespace);
+        for field in fields {
+            let field = match *field {
+                Value::Table(ref t) => t,
+                _ => return None,
+            };
+
+            let path = match field.get("path") {
+                Some(&Value::String(ref s)) => s.clone(),
+                _ => return None,
+            };
+
+            let kind = match field.get("type") {
+                Some(&Value::String(ref s)) => match FieldType::from_str(s) {
+                    Some(k) => k,
+                    None => return None,
+                },
+                _ => return None,
+            };
+
+            let required = match field.get("required") {
+                Some(&Value::Boolean(b)) => b,
+                _ => false,
+            };
+
+            let bound = |key: &str| match field.get(key) {
+                Some(&Value::Integer(i)) => Some(i),
+                _ => None,
+            };
+            let (min, max) = (bound("min"), bound("max"));
+
+            schema = schema.with_field(path.clone(), kind, required).range(path, min, max);
+        }
+
+        Some(schema)
+    }
+
+}
+
+/// A violation of a `HeaderSchema`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaViolation {
+    Missing(String),
+    WrongType { path: String, expected: FieldType, found: FieldType },
+    OutOfRange { path: String, min: Option<i64>, max: Option<i64> },
+}
+
+impl Display for SchemaViolation {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        match *self {
+            SchemaViolation::Missing(ref path) => {
+                write!(fmt, "'{}' is missing", path)
+            },
+            SchemaViolation::WrongType { ref path, ref expected, ref found } => {
+                write!(fmt, "'{}' should be {}, but is {}", path, expected, found)
+            },
+            SchemaViolation::OutOfRange { ref path, ref min, ref max } => {
+                let bound = |b: &Option<i64>| b.map(|b| b.to_string()).unwrap_or(String::from("_"));
+                write!(fmt, "'{}' is out of range {}..{}", path, bound(min), bound(max))
+            },
+        }
+    }
+
+}
+
+/// All violations found in one header, usable as cause of a `StoreError`
+#[derive(Debug, Clone)]
+pub struct SchemaViolations(pub Vec<SchemaViolation>);
+
+impl Display for SchemaViolations {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        let strs : Vec<String> = self.0.iter().map(|v| format!("{}", v)).collect();
+        write!(fmt, "{}", strs.join(", "))
+    }
+
+}
+
+impl Error for SchemaViolations {
+
+    fn description(&self) -> &str {
+        "Header violates schema"
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use toml::Value;
+
+    use super::{HeaderSchema, FieldType, SchemaViolation};
+    use store::EntryHeader;
+
+    fn counter_schema() -> HeaderSchema {
+        HeaderSchema::new("counter")
+            .require("name", FieldType::String)
+            .require("value", FieldType::Integer)
+            .optional("unit", FieldType::String)
+            .range("unit", Some(1), Some(10))
+    }
+
+    fn header_with(value: Value) -> EntryHeader {
+        let mut header = EntryHeader::new();
+        header.insert("counter", Value::Table(BTreeMap::new())).unwrap();
+        header.insert("counter.name", Value::String(String::from("c"))).unwrap();
+        header.insert("counter.value", value).unwrap();
+        header
+    }
+
+    #[test]
+    fn test_schema_validate() {
+        let schema = counter_schema();
+
+        assert!(schema.validate(&header_with(Value::Integer(1))).is_empty());
+        assert!(schema.validate(&EntryHeader::new()).is_empty());
+
+        let v = schema.validate(&header_with(Value::String(String::from("1"))));
+        assert_eq!(v, vec![SchemaViolation::WrongType {
+            path: String::from("counter.value"),
+            expected: FieldType::Integer,
+            found: FieldType::String,
+        }]);
+
+        let mut header = header_with(Value::Integer(1));
+        header.delete("counter.name").unwrap();
+        header.insert("counter.unit", Value::String(String::new())).unwrap();
+        let v = schema.validate(&header);
+        assert_eq!(v, vec![
+            SchemaViolation::Missing(String::from("counter.name")),
+            SchemaViolation::OutOfRange {
+                path: String::from("counter.unit"),
+                min: Some(1),
+                max: Some(10),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_schema_roundtrip() {
+        let schema = counter_schema();
+        assert_eq!(Some(schema.clone()), HeaderSchema::from_value(&schema.to_value()));
+    }
+
+}
@@ -0,0 +1,578 @@
+//! Full-text search over the content of entries
+//!
+//! The search index maps each token of the content of an entry to the entries (and positions in
+//! them) it occurs at. Tokens are the alphanumeric parts of the content, case-folded. The index is
+//! kept in `<store>/.search` if `search-index = true` is set in the `[store]` section (see
+//! `is_search_index_enabled()`). It is updated by the hooks in `libimagstorestdhook::search` at
+//! the post-create, post-update, post-delete and post-move positions. Dropping a `FileLockEntry`
+//! writes it without executing hooks, so the store indexes entries written that way itself.
+//! Encrypted entries (see `encryption`) are never indexed.
+//!
+//! The changes of one process are merged into the index on disk when the store is dropped, so
+//! processes working on the store at the same time do not drop each others changes. Hits are
+//! checked against the content of the entry when searching, but entries which are missing from the
+//! index (for example because they were written by a program without the hooks, or while the index
+//! was disabled) are not found until the index is rebuilt with `Store::rebuild_search_index()`
+//! (`imag-store reindex`).
+//!
+//! A query is a list of terms, all of which have to match:
+//!
+//!  * `word` matches entries containing the token `word`
+//!  * `wor*` matches entries containing a token starting with `wor`
+//!  * `"some words"` matches entries containing these tokens in this order
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use backend::StoreBackend;
+use encryption::EncryptionSettings;
+use error::StoreErrorKind as SEK;
+use error::MapErrInto;
+use store::Result;
+use storeid::StoreId;
+
+use libimagerror::into::IntoError;
+
+pub use configuration::is_search_index_enabled;
+
+/// The name of the file (inside the store) where the search index is kept
+pub static SEARCH_INDEX_FILE : &'static str = ".search";
+
+/// Split `text` into case-folded tokens
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+/// One term of a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryTerm {
+    Word(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+}
+
+/// Parse a query, see the module documentation for the syntax
+pub fn parse_query(query: &str) -> Vec<QueryTerm> {
+    let mut terms = vec![];
+
+    for (i, part) in query.split('"').enumerate() {
+        if i % 2 == 1 {
+            // inside of quotes
+            let tokens = tokenize(part);
+            match tokens.len() {
+                0 => {},
+                1 => terms.push(QueryTerm::Word(tokens[0].clone())),
+                _ => terms.push(QueryTerm::Phrase(tokens)),
+            }
+            continue;
+        }
+
+        for word in part.split_whitespace() {
+            let is_prefix = word.ends_with('*');
+            for token in tokenize(word) {
+                if is_prefix {
+                    terms.push(QueryTerm::Prefix(token));
+                } else {
+                    terms.push(QueryTerm::Word(token));
+                }
+            }
+        }
+    }
+
+    terms
+}
+
+/// An entry found by a search
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub id: StoreId,
+
+    /// The number of matches of the query terms, higher is better
+    pub score: usize,
+
+    /// The first line of the content which contains a match
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchIndex {
+    /// token -> entry -> positions of the token in the entry
+    tokens: BTreeMap<String, BTreeMap<PathBuf, Vec<usize>>>,
+
+    /// The entries which were (re)indexed or removed since the index was loaded or saved
+    touched: BTreeSet<PathBuf>,
+
+    /// Whether the index was cleared since it was loaded or saved
+    cleared: bool,
+
+    /// The location of the store and what is encrypted in it, see `exclude_encrypted()`
+    location: PathBuf,
+    encryption: EncryptionSettings,
+}
+
+impl SearchIndex {
+
+    pub fn new() -> SearchIndex {
+        SearchIndex {
+            tokens: BTreeMap::new(),
+            touched: BTreeSet::new(),
+            cleared: false,
+            location: PathBuf::new(),
+            encryption: EncryptionSettings::default(),
+        }
+    }
+
+    /// Never index the entries which `encryption` encrypts in the store at `location`
+    ///
+    /// The index is written in the clear, so it must not contain their content.
+    pub fn exclude_encrypted(&mut self, location: &Path, encryption: EncryptionSettings) {
+        self.location   = location.to_path_buf();
+        self.encryption = encryption;
+    }
+
+    fn is_encrypted(&self, id: &Path) -> bool {
+        id.strip_prefix(&self.location)
+            .map(|rel| self.encryption.covers(rel))
+            .unwrap_or(false)
+    }
+
+    /// Load the search index of the store at `location`, or an empty one if there is none
+    ///
+    /// Each line of the index file is `<token>\t<entry>\t<position>,<position>,...`, with
+    /// backslashes, tabs and line breaks in the token and the entry escaped (see `escape()`).
+    pub fn load(backend: &StoreBackend, location: &Path) -> SearchIndex {
+        let mut index = SearchIndex::new();
+        let file = index_file(location);
+        if !backend.is_file(&file) {
+            return index;
+        }
+
+        let text = match backend.read(&file).ok().and_then(|b| String::from_utf8(b).ok()) {
+            Some(text) => text,
+            None => {
+                warn!("Could not load search index, run a reindex");
+                return index;
+            },
+        };
+
+        for line in text.lines() {
+            let mut split = line.split('\t');
+            let fields = (split.next().and_then(unescape),
+                          split.next().and_then(unescape),
+                          split.next(),
+                          split.next());
+            match fields {
+                (Some(token), Some(path), Some(positions), None) => {
+                    let positions = positions.split(',')
+                        .filter_map(|p| usize::from_str(p).ok())
+                        .collect();
+                    index.tokens
+                        .entry(token)
+                        .or_insert_with(BTreeMap::new)
+                        .insert(PathBuf::from(path), positions);
+                },
+                _ => warn!("Ignoring malformed line in search index: '{}'", line),
+            }
+        }
+
+        index
+    }
+
+    /// Whether the index was changed since it was loaded or saved
+    pub fn has_changes(&self) -> bool {
+        self.cleared || !self.touched.is_empty()
+    }
+
+    /// Merge the changes to the index into the index of the store at `location` and write it
+    ///
+    /// The entries changed in this index replace what is indexed for them on disk, everything
+    /// else is kept from the index on disk (which may have been written by another process), so
+    /// the store has to be locked while saving. Afterwards this index is the merged index.
+    pub fn save(&mut self, backend: &StoreBackend, location: &Path) -> Result<()> {
+        if !self.has_changes() {
+            return Ok(());
+        }
+
+        if !self.cleared {
+            let mut merged = SearchIndex::load(backend, location);
+            for id in self.touched.iter() {
+                merged.remove(id);
+            }
+            for (token, entries) in self.tokens.iter() {
+                let changed = entries.iter().filter(|&(p, _)| self.touched.contains(p));
+                for (path, positions) in changed {
+                    merged.tokens
+                        .entry(token.clone())
+                        .or_insert_with(BTreeMap::new)
+                        .insert(path.clone(), positions.clone());
+                }
+            }
+            self.tokens = merged.tokens;
+        }
+
+        let mut text = String::new();
+        for (token, entries) in self.tokens.iter() {
+            for (path, positions) in entries.iter() {
+                let positions : Vec<String> = positions.iter().map(|p| p.to_string()).collect();
+                text.push_str(&format!("{}\t{}\t{}\n",
+                                       escape(token),
+                                       escape(&path.to_string_lossy()),
+                                       positions.join(",")));
+            }
+        }
+
+        try!(backend.write(&index_file(location), text.as_bytes())
+             .map_err_into(SEK::SearchIndexError));
+        self.touched.clear();
+        self.cleared = false;
+        Ok(())
+    }
+
+    /// Index `content` for the entry `id`, replacing what was indexed for it before
+    ///
+    /// Encrypted entries are only removed, see `exclude_encrypted()`.
+    pub fn index(&mut self, id: &Path, content: &str) {
+        self.remove(id);
+        if self.is_encrypted(id) {
+            debug!("Not indexing encrypted entry {:?}", id);
+            return;
+        }
+
+        for (pos, token) in tokenize(content).into_iter().enumerate() {
+            self.tokens
+                .entry(token)
+                .or_insert_with(BTreeMap::new)
+                .entry(id.to_path_buf())
+                .or_insert_with(Vec::new)
+                .push(pos);
+        }
+    }
+
+    /// Remove the entry `id` from the index
+    pub fn remove(&mut self, id: &Path) {
+        self.touched.insert(id.to_path_buf());
+
+        let mut emptied = vec![];
+        for (token, entries) in self.tokens.iter_mut() {
+            if entries.remove(id).is_some() {
+                if entries.is_empty() {
+                    emptied.push(token.clone());
+                }
+            }
+        }
+
+        for token in emptied {
+            self.tokens.remove(&token);
+        }
+    }
+
+    /// Move everything indexed for `old` to `new`
+    pub fn rename(&mut self, old: &Path, new: &Path) {
+        if self.is_encrypted(new) {
+            self.remove(old);
+            self.remove(new);
+            return;
+        }
+
+        self.touched.insert(old.to_path_buf());
+        self.touched.insert(new.to_path_buf());
+
+        for entries in self.tokens.values_mut() {
+            if let Some(positions) = entries.remove(old) {
+                entries.insert(new.to_path_buf(), positions);
+            }
+        }
+    }
+
+    /// Remove everything from the index
+    pub fn clear(&mut self) {
+        self.tokens.clear();
+        self.touched.clear();
+        self.cleared = true;
+    }
+
+    /// Get the entries matching all `terms`, best matches first
+    pub fn query(&self, terms: &[QueryTerm]) -> Vec<(PathBuf, usize)> {
+        let mut result : Option<BTreeMap<PathBuf, usize>> = None;
+
+        for term in terms {
+            let matches = self.query_term(term);
+            result = Some(match result {
+                None => matches,
+                Some(prev) => prev.into_iter()
+                    .filter_map(|(p, score)| matches.get(&p).map(|s| (p, score + s)))
+                    .collect(),
+            });
+        }
+
+        let mut result : Vec<(PathBuf, usize)> = result.unwrap_or(BTreeMap::new())
+            .into_iter()
+            .collect();
+        result.sort_by(|a, b| match b.1.cmp(&a.1) {
+            Ordering::Equal => a.0.cmp(&b.0),
+            other           => other,
+        });
+        result
+    }
+
+    /// Get the entries matching `term`, with the number of matches
+    fn query_term(&self, term: &QueryTerm) -> BTreeMap<PathBuf, usize> {
+        let mut matches = BTreeMap::new();
+
+        match *term {
+            QueryTerm::Word(ref w) => {
+                if let Some(entries) = self.tokens.get(w) {
+                    for (p, positions) in entries.iter() {
+                        matches.insert(p.clone(), positions.len());
+                    }
+                }
+            },
+
+            QueryTerm::Prefix(ref prefix) => {
+                let tokens = self.tokens
+                    .iter()
+                    .filter(|&(t, _)| t.starts_with(&prefix[..]));
+
+                for (_, entries) in tokens {
+                    for (p, positions) in entries.iter() {
+                        *matches.entry(p.clone()).or_insert(0) += positions.len();
+                    }
+                }
+            },
+
+            QueryTerm::Phrase(ref words) => {
+                let first = match self.tokens.get(&words[0]) {
+                    Some(entries) => entries,
+                    None => return matches,
+                };
+
+                for (p, positions) in first.iter() {
+                    let n = positions.iter()
+                        .filter(|&&start| {
+                            words.iter().enumerate().skip(1).all(|(i, w)| {
+                                self.tokens
+                                    .get(w)
+                                    .and_then(|entries| entries.get(p))
+                                    .map(|pos| pos.contains(&(start + i)))
+                                    .unwrap_or(false)
+                            })
+                        })
+                        .count();
+
+                    if n > 0 {
+                        matches.insert(p.clone(), n);
+                    }
+                }
+            },
+        }
+
+        matches
+    }
+
+}
+
+/// Get the first line of `content` containing a token matched by one of `terms`
+pub fn snippet(content: &str, terms: &[QueryTerm]) -> String {
+    let matches_token = |token: &String| terms.iter().any(|term| match *term {
+        QueryTerm::Word(ref w)     => token == w,
+        QueryTerm::Prefix(ref p)   => token.starts_with(&p[..]),
+        QueryTerm::Phrase(ref ws)  => ws.contains(token),
+    });
+
+    content.lines()
+        .find(|line| tokenize(line).iter().any(|t| matches_token(t)))
+        .map(|line| line.trim().chars().take(80).collect())
+        .unwrap_or(String::new())
+}
+
+/// Search `index` for `query`
+///
+/// `read` reads the content of an entry. Hits are checked against the current content of the
+/// entry, entries which do not match anymore are reindexed on the way.
+pub fn search<F>(index: &Mutex<SearchIndex>, query: &str, read: F) -> Result<Vec<SearchHit>>
+    where F: Fn(&StoreId) -> Result<String>
+{
+    let terms = parse_query(query);
+    let found = try!(index.lock().map_err(|_| SEK::LockPoisoned.into_error())).query(&terms);
+
+    let mut hits  = vec![];
+    let mut stale = vec![];
+    for (path, score) in found {
+        let id = StoreId::from(path);
+        match read(&id) {
+            Ok(content) => {
+                let mut single = SearchIndex::new();
+                single.index(&id, &content);
+                if single.query(&terms).is_empty() {
+                    debug!("Search index is stale for {:?}", id);
+                    stale.push((id, Some(content)));
+                    continue;
+                }
+
+                hits.push(SearchHit {
+                    snippet: snippet(&content, &terms),
+                    id: id,
+                    score: score,
+                });
+            },
+            Err(_) => {
+                debug!("Search index is stale, {:?} cannot be read", id);
+                stale.push((id, None));
+            },
+        }
+    }
+
+    if !stale.is_empty() {
+        let mut index = try!(index.lock().map_err(|_| SEK::LockPoisoned.into_error()));
+        for (id, content) in stale {
+            match content {
+                Some(content) => index.index(&id, &content),
+                None          => index.remove(&id),
+            }
+        }
+    }
+
+    Ok(hits)
+}
+
+/// Clear `index` and index the entries `ids`
+///
+/// `read` reads the content of an entry. Entries which cannot be read are skipped.
+pub fn rebuild<F>(index: &mut SearchIndex, ids: Vec<StoreId>, read: F)
+    where F: Fn(&StoreId) -> Result<String>
+{
+    index.clear();
+    for id in ids {
+        match read(&id) {
+            Ok(content) => index.index(&id, &content),
+            Err(e) => {
+                warn!("Cannot index {:?}, skipping it", id);
+                debug!("{:?}", e);
+            },
+        }
+    }
+}
+
+fn index_file(location: &Path) -> PathBuf {
+    let mut file = location.to_path_buf();
+    file.push(SEARCH_INDEX_FILE);
+    file
+}
+
+/// Escape backslashes, tabs and line breaks in `s`, so it fits into a field of the index file
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c    => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Undo `escape()`, `None` if `s` is not escaped properly
+fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('t')  => unescaped.push('\t'),
+            Some('n')  => unescaped.push('\n'),
+            Some('r')  => unescaped.push('\r'),
+            _          => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use backend::InMemoryBackend;
+    use encryption::EncryptionSettings;
+    use super::{SearchIndex, QueryTerm, parse_query, snippet};
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(parse_query("Foo bar* \"Some words\""), vec![
+            QueryTerm::Word(String::from("foo")),
+            QueryTerm::Prefix(String::from("bar")),
+            QueryTerm::Phrase(vec![String::from("some"), String::from("words")]),
+        ]);
+    }
+
+    #[test]
+    fn test_query() {
+        let mut index = SearchIndex::new();
+        index.index(Path::new("/store/a"), "The quick brown fox.\nThe lazy dog.");
+        index.index(Path::new("/store/b"), "A quick, quick fox jumps");
+
+        let ids = |q: &str| -> Vec<PathBuf> {
+            index.query(&parse_query(q)).into_iter().map(|(p, _)| p).collect()
+        };
+
+        assert_eq!(ids("quick"), vec![PathBuf::from("/store/b"), PathBuf::from("/store/a")]);
+        assert_eq!(ids("\"brown fox\""), vec![PathBuf::from("/store/a")]);
+        assert_eq!(ids("\"fox brown\""), Vec::<PathBuf>::new());
+        assert_eq!(ids("jump*"), vec![PathBuf::from("/store/b")]);
+        assert_eq!(ids("quick lazy"), vec![PathBuf::from("/store/a")]);
+
+        assert_eq!(snippet("The quick brown fox.\nThe lazy dog.", &parse_query("dog")),
+                   "The lazy dog.");
+    }
+
+    #[test]
+    fn test_save_escapes_fields() {
+        let backend  = InMemoryBackend::new();
+        let location = Path::new("/store");
+        let strange  = Path::new("/store/notes/tab\there\nnewline\\backslash");
+
+        let mut index = SearchIndex::new();
+        index.index(strange, "some content");
+        index.index(Path::new("/store/notes/plain"), "other content");
+        index.save(&backend, location).unwrap();
+
+        let loaded = SearchIndex::load(&backend, location);
+        let ids : Vec<PathBuf> = loaded.query(&parse_query("content"))
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&strange.to_path_buf()));
+        assert!(ids.contains(&PathBuf::from("/store/notes/plain")));
+    }
+
+    #[test]
+    fn test_encrypted_entries_are_not_indexed() {
+        let mut index = SearchIndex::new();
+        index.exclude_encrypted(Path::new("/store"), EncryptionSettings {
+            modules: vec![String::from("secret")],
+            ..EncryptionSettings::default()
+        });
+
+        index.index(Path::new("/store/secret/a"), "hidden words");
+        index.index(Path::new("/store/notes/a"), "open words");
+        assert_eq!(index.query(&parse_query("hidden")), vec![]);
+
+        index.rename(Path::new("/store/notes/a"), Path::new("/store/secret/b"));
+        assert_eq!(index.query(&parse_query("open")), vec![]);
+    }
+
+}
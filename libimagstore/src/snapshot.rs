@@ -0,0 +1,356 @@
+//! Snapshots of the store
+//!
+//! A snapshot records the state of all entries (or the entries of one module) at one point in
+//! time, see `Store::snapshot()`. The entries are kept as objects (see `object`), so entries which
+//! did not change between two snapshots are stored only once. A snapshot itself is a manifest in
+//! `<store>/.snapshots/<name>`:
+//!
+//! ```text
+//! created\t<seconds since the UNIX epoch>
+//! module\t<module, or - for the whole store>
+//! entry\t<object hash>\t<path of the entry inside the store>
+//! ...
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fmt::Error as FmtError;
+use std::path::{Path, PathBuf};
+use std::result::Result as RResult;
+use std::str::FromStr;
+
+use semver::Version;
+
+use attachment::content_hash;
+use backend::StoreBackend;
+use error::StoreErrorKind as SEK;
+use error::MapErrInto;
+use store::{Entry, Result, Store};
+use storeid::StoreId;
+
+use libimagerror::into::IntoError;
+
+/// The name of the directory (inside the store) where the snapshots are kept
+pub static SNAPSHOTS_DIR : &'static str = ".snapshots";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub name: String,
+
+    /// Seconds since the UNIX epoch
+    pub created: u64,
+
+    /// The module the snapshot was taken of, `None` for the whole store
+    pub module: Option<String>,
+
+    /// The paths of the entries inside the store, with the hashes of their objects
+    pub entries: BTreeMap<PathBuf, String>,
+}
+
+impl Snapshot {
+
+    /// Whether the entry at `path` (inside the store) is in the scope the snapshot was taken of
+    pub fn covers(&self, path: &Path) -> bool {
+        match self.module {
+            None => true,
+            Some(ref m) => path.starts_with(m),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("created\t{}\nmodule\t{}\n",
+                               self.created,
+                               self.module.as_ref().map(|m| &m[..]).unwrap_or("-"));
+        for (path, hash) in self.entries.iter() {
+            text.push_str(&format!("entry\t{}\t{}\n", hash, path.display()));
+        }
+        text
+    }
+
+    fn from_text(name: &str, text: &str) -> Option<Snapshot> {
+        let mut snapshot = Snapshot {
+            name: String::from(name),
+            created: 0,
+            module: None,
+            entries: BTreeMap::new(),
+        };
+
+        for line in text.lines() {
+            let parts : Vec<&str> = line.splitn(3, '\t').collect();
+            match (parts.get(0), parts.get(1), parts.get(2)) {
+                (Some(&"created"), Some(ts), None) => {
+                    snapshot.created = match u64::from_str(ts) {
+                        Ok(ts) => ts,
+                        Err(_) => return None,
+                    };
+                },
+                (Some(&"module"), Some(&"-"), None) => snapshot.module = None,
+                (Some(&"module"), Some(m), None)    => snapshot.module = Some(String::from(*m)),
+                (Some(&"entry"), Some(hash), Some(path)) => {
+                    snapshot.entries.insert(PathBuf::from(path), String::from(*hash));
+                },
+                _ => return None,
+            }
+        }
+
+        Some(snapshot)
+    }
+
+}
+
+impl Display for Snapshot {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        write!(fmt, "{} ({}, {} entries, {})",
+               self.name,
+               self.created,
+               self.entries.len(),
+               self.module.as_ref().map(|m| &m[..]).unwrap_or("all modules"))
+    }
+
+}
+
+/// A difference between the store and a snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotChange {
+    /// The entry was created after the snapshot was taken
+    Added(StoreId),
+
+    /// The entry was deleted after the snapshot was taken
+    Removed(StoreId),
+
+    /// The entry was changed after the snapshot was taken
+    Modified(StoreId),
+}
+
+impl Display for SnapshotChange {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FmtError> {
+        match *self {
+            SnapshotChange::Added(ref id)    => write!(fmt, "+ {}", id),
+            SnapshotChange::Removed(ref id)  => write!(fmt, "- {}", id),
+            SnapshotChange::Modified(ref id) => write!(fmt, "~ {}", id),
+        }
+    }
+
+}
+
+/// Check whether the entry at `rel` (inside the store) is at or below `path`
+///
+/// Entries are kept as `<name>~<version>`, so `notes/a` is the path of the entry
+/// `notes/a~0.2.0`, too.
+pub fn is_at_or_below(rel: &Path, path: &Path) -> bool {
+    if rel.starts_with(path) {
+        return true;
+    }
+
+    let name = match rel.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+    match name.rfind('~') {
+        Some(pos) if Version::parse(&name[(pos + 1)..]).is_ok() => {
+            rel.with_file_name(&name[..pos]) == path
+        },
+        _ => false,
+    }
+}
+
+/// Check whether `name` can be used as the name of a snapshot
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains('/') && !name.contains('\\')
+}
+
+pub fn snapshot_path(location: &Path, name: &str) -> PathBuf {
+    let mut path = location.to_path_buf();
+    path.push(SNAPSHOTS_DIR);
+    path.push(name);
+    path
+}
+
+/// Write `snapshot` to the store at `location`
+pub fn save(backend: &StoreBackend, location: &Path, snapshot: &Snapshot) -> Result<()> {
+    backend.write(&snapshot_path(location, &snapshot.name), snapshot.to_text().as_bytes())
+        .map_err_into(SEK::SnapshotError)
+}
+
+/// Load the snapshot `name` from the store at `location`
+pub fn load(backend: &StoreBackend, location: &Path, name: &str) -> Result<Snapshot> {
+    let path = snapshot_path(location, name);
+    if !backend.is_file(&path) {
+        return Err(SEK::SnapshotNotFound.into_error());
+    }
+
+    let bytes = try!(backend.read(&path).map_err_into(SEK::SnapshotError));
+    let text  = try!(String::from_utf8(bytes).map_err_into(SEK::SnapshotError));
+    Snapshot::from_text(name, &text).ok_or(SEK::SnapshotError.into_error())
+}
+
+/// Load all snapshots of the store at `location`, oldest first
+pub fn load_all(backend: &StoreBackend, location: &Path) -> Result<Vec<Snapshot>> {
+    let mut dir = location.to_path_buf();
+    dir.push(SNAPSHOTS_DIR);
+    if !backend.exists(&dir) {
+        return Ok(vec![]);
+    }
+
+    let paths = try!(backend.list(&dir).map_err_into(SEK::SnapshotError));
+    let mut snapshots = vec![];
+    for path in paths {
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            snapshots.push(try!(load(backend, location, name)));
+        }
+    }
+    snapshots.sort_by(|a, b| a.created.cmp(&b.created));
+    Ok(snapshots)
+}
+
+/// Take a snapshot called `name` of `entries` (by their path inside the store at `location`),
+/// which are the entries of `module`
+///
+/// `read` reads the raw content of an entry.
+pub fn take<F>(backend: &StoreBackend, location: &Path, name: &str, module: Option<&str>,
+               entries: BTreeMap<PathBuf, StoreId>, read: F) -> Result<Snapshot>
+    where F: Fn(&StoreId) -> Result<Vec<u8>>
+{
+    if !is_valid_name(name) {
+        return Err(SEK::SnapshotError.into_error());
+    }
+    if backend.is_file(&snapshot_path(location, name)) {
+        return Err(SEK::SnapshotExists.into_error());
+    }
+
+    let mut hashes = BTreeMap::new();
+    for (rel, id) in entries {
+        let data = try!(read(&id));
+        let hash = try!(::object::put(backend, location, &data).map_err_into(SEK::ObjectError));
+        hashes.insert(rel, hash);
+    }
+
+    let snapshot = Snapshot {
+        name: String::from(name),
+        created: ::changelog::now(),
+        module: module.map(String::from),
+        entries: hashes,
+    };
+    save(backend, location, &snapshot).map(|_| snapshot)
+}
+
+/// Check whether restoring `path` (inside the store) from `snapshot` can restore anything, that
+/// is whether `path` and the scope the snapshot was taken of overlap
+pub fn overlaps(snapshot: &Snapshot, path: &Path) -> bool {
+    snapshot.covers(path) || snapshot.module
+        .as_ref()
+        .map(|m| Path::new(m).starts_with(path))
+        .unwrap_or(true)
+}
+
+/// Compare `snapshot` with `current`, the entries (by their path inside the store at `location`)
+/// of the scope it was taken of
+///
+/// `read` reads the raw content of an entry.
+pub fn changes<F>(snapshot: &Snapshot, location: &Path, current: &BTreeMap<PathBuf, StoreId>,
+                  read: F) -> Result<Vec<SnapshotChange>>
+    where F: Fn(&StoreId) -> Result<Vec<u8>>
+{
+    let mut changes = vec![];
+    for (rel, id) in current.iter() {
+        match snapshot.entries.get(rel) {
+            None => changes.push(SnapshotChange::Added(id.clone())),
+            Some(hash) => {
+                let data = try!(read(id));
+                if content_hash(&data) != *hash {
+                    changes.push(SnapshotChange::Modified(id.clone()));
+                }
+            },
+        }
+    }
+
+    for rel in snapshot.entries.keys().filter(|rel| !current.contains_key(*rel)) {
+        let mut path = location.to_path_buf();
+        path.push(rel);
+        changes.push(SnapshotChange::Removed(StoreId::from(path)));
+    }
+
+    Ok(changes)
+}
+
+/// Undo `changes` (see `changes()`) in `store` by writing the entries as they were when
+/// `snapshot` was taken
+///
+/// Entries which were added since are deleted. The entries are written through `store`, so their
+/// current state is kept as a revision.
+pub fn restore(store: &Store, backend: &StoreBackend, snapshot: &Snapshot,
+               changes: &[SnapshotChange]) -> Result<()> {
+    for change in changes {
+        try!(match *change {
+            SnapshotChange::Added(ref id) => store.delete(id.clone()),
+            SnapshotChange::Removed(ref id) | SnapshotChange::Modified(ref id) => {
+                restore_entry(store, backend, snapshot, id)
+            },
+        });
+    }
+    Ok(())
+}
+
+/// Write the entry `id` as it was when `snapshot` was taken
+fn restore_entry(store: &Store, backend: &StoreBackend, snapshot: &Snapshot, id: &StoreId)
+    -> Result<()>
+{
+    let location = store.path();
+    let rel  = try!(id.strip_prefix(location).map_err_into(SEK::StorePathError));
+    let hash = try!(snapshot.entries.get(rel).ok_or(SEK::SnapshotError.into_error()));
+
+    let data = try!(::object::get(backend, location, hash).map_err_into(SEK::ObjectError));
+    let text = try!(String::from_utf8(data).map_err_into(SEK::EncodingError));
+    let old  = try!(Entry::from_str(id.clone(), &text[..]));
+
+    let mut fle = try!(store.retrieve(id.clone()));
+    *fle.get_header_mut()  = old.get_header().clone();
+    *fle.get_content_mut() = old.get_content().clone();
+    store.update(fle)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+
+    use super::{Snapshot, is_valid_name, is_at_or_below};
+
+    #[test]
+    fn test_snapshot_text() {
+        let mut entries = BTreeMap::new();
+        entries.insert(PathBuf::from("notes/a"), String::from("abc"));
+        entries.insert(PathBuf::from("notes/sub/b"), String::from("def"));
+
+        let snapshot = Snapshot {
+            name: String::from("before-migration"),
+            created: 1234,
+            module: Some(String::from("notes")),
+            entries: entries,
+        };
+
+        let text = snapshot.to_text();
+        assert_eq!(Snapshot::from_text("before-migration", &text), Some(snapshot.clone()));
+        assert!(snapshot.covers(Path::new("notes/c")));
+        assert!(!snapshot.covers(Path::new("diary/c")));
+
+        assert!(is_valid_name("before-migration"));
+        assert!(!is_valid_name("../etc"));
+        assert!(!is_valid_name(".hidden"));
+    }
+
+    #[test]
+    fn test_is_at_or_below() {
+        let entry = Path::new("notes/sub/a~0.2.0");
+
+        assert!(is_at_or_below(entry, Path::new("notes")));
+        assert!(is_at_or_below(entry, Path::new("notes/sub/a~0.2.0")));
+        assert!(is_at_or_below(entry, Path::new("notes/sub/a")));
+        assert!(!is_at_or_below(entry, Path::new("notes/sub/a~0.3.0")));
+        assert!(!is_at_or_below(entry, Path::new("notes/su")));
+        assert!(!is_at_or_below(Path::new("notes/a~b"), Path::new("notes/a")));
+    }
+
+}
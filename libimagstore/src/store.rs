@@ -1,12 +1,12 @@
 use std::collections::HashMap;
-use std::fs::{File, remove_file};
+use std::fs::File;
 use std::ops::Drop;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result::Result as RResult;
 use std::sync::Arc;
 use std::sync::RwLock;
-use std::collections::BTreeMap;
-use std::io::{Seek, SeekFrom};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{Read, Write, Seek, SeekFrom};
 use std::convert::From;
 use std::convert::Into;
 use std::sync::Mutex;
@@ -18,32 +18,66 @@ use std::fmt::Error as FMTError;
 
 use toml::{Table, Value};
 use regex::Regex;
-use glob::glob;
-use walkdir::WalkDir;
-use walkdir::Iter as WalkDirIter;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+use yaml_rust::yaml::Hash as YamlHash;
 
 use error::{ParserErrorKind, ParserError};
 use error::{StoreError as SE, StoreErrorKind as SEK};
 use error::MapErrInto;
 use storeid::{IntoStoreId, StoreId, StoreIdIterator};
 use lazyfile::LazyFile;
+use format::{Format, toml_to_json, json_to_toml, toml_to_yaml, yaml_to_toml};
+use format::{without_nulls, untagged, escaped_keys, merge_json};
+use backend::{StoreBackend, BackendObject, BackendLock, BackendEvent, BackendWatcher};
+use backend::{STAGED_SUFFIX, is_staged};
+use changelog::{ChangeEvent, Operation};
+use changelog::Position as ChangelogPosition;
+use encryption::EncryptionSettings;
+use archive::{ConflictStrategy, ImportOutcome, Manifest};
+use attachment::{Attachment, attachments_of};
+use revision::{Revision, DiffLine, REVISIONS_DIR};
+use index::{HeaderIndex, IndexKey};
+use migration::{MigrationRegistry, MigrationReport};
+use search::{SearchIndex, SearchHit};
+use snapshot::{Snapshot, SnapshotChange};
+use stream::Selection;
+use gc::Garbage;
+use headerpath::{HeaderPath, Token};
+use schema::{HeaderSchema, SchemaViolation, SchemaViolations, SCHEMAS_DIR};
 
 use hook::aspect::Aspect;
 use hook::error::HookErrorKind;
 use hook::result::HookResult;
 use hook::accessor::{ MutableHookDataAccessor,
-            StoreIdAccessor};
+            StoreIdAccessor,
+            MoveAccessor};
 use hook::position::HookPosition;
 use hook::Hook;
 
 use libimagerror::into::IntoError;
 use libimagutil::iter::FoldResult;
 
-use self::glob_store_iter::*;
-
 /// The Result Type returned by any interaction with the store that could fail
 pub type Result<T> = RResult<T, SE>;
 
+/// The name of the directory (inside the store) where the lock files are kept
+///
+/// The lock of an entry is the file at the same path inside this directory, the store-wide lock
+/// is `STORE_LOCK_FILE` in it.
+pub static LOCKS_DIR : &'static str = ".locks";
+
+/// The name of the store-wide lock file inside `LOCKS_DIR`
+pub static STORE_LOCK_FILE : &'static str = ".store";
+
+/// How long to sleep between two attempts to take a lock held by another process
+const LOCK_POLL_INTERVAL_MS : u64 = 50;
+
+/// How long a `Watch` waits for the store to note a change before deciding whether the store
+/// made it
+const WATCH_GRACE_MS : u64 = 200;
+
 
 #[derive(Debug, PartialEq)]
 enum StoreEntryStatus {
@@ -58,55 +92,50 @@ struct StoreEntry {
     id: StoreId,
     file: LazyFile,
     status: StoreEntryStatus,
+
+    /// The OS lock on the entry, held while it is borrowed
+    lock: Option<Box<BackendLock>>,
 }
 
+/// The "lock" read-only stores hand out instead of OS locks
+#[derive(Debug)]
+struct NoLock;
+
+impl BackendLock for NoLock {}
+
 pub enum StoreObject {
     Id(StoreId),
     Collection(PathBuf),
 }
 
 pub struct Walk {
-    dirwalker: WalkDirIter,
+    objects: ::std::vec::IntoIter<BackendObject>,
 }
 
 impl Walk {
 
-    fn new(mut store_path: PathBuf, mod_name: &str) -> Walk {
+    fn new(mut store_path: PathBuf, mod_name: &str, backend: &StoreBackend) -> Walk {
         store_path.push(mod_name);
+        let objects = backend.walk(&store_path).unwrap_or_else(|e| {
+            warn!("Error in Walker");
+            debug!("{:?}", e);
+            vec![]
+        });
+
         Walk {
-            dirwalker: WalkDir::new(store_path).into_iter(),
+            objects: objects.into_iter(),
         }
     }
 }
 
-impl ::std::ops::Deref for Walk {
-    type Target = WalkDirIter;
-
-    fn deref(&self) -> &Self::Target {
-        &self.dirwalker
-    }
-}
-
 impl Iterator for Walk {
     type Item = StoreObject;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(something) = self.dirwalker.next() {
-            match something {
-                Ok(next) => if next.file_type().is_dir() {
-                                return Some(StoreObject::Collection(next.path().to_path_buf()))
-                            } else if next.file_type().is_file() {
-                                return Some(StoreObject::Id(next.path().to_path_buf().into()))
-                            },
-                Err(e) => {
-                    warn!("Error in Walker");
-                    debug!("{:?}", e);
-                    return None;
-                }
-            }
-        }
-
-        return None;
+        self.objects.next().map(|obj| match obj {
+            BackendObject::Directory(p) => StoreObject::Collection(p),
+            BackendObject::File(p)      => StoreObject::Id(p.into()),
+        })
     }
 }
 
@@ -118,6 +147,7 @@ impl StoreEntry {
             id: id.clone(),
             file: LazyFile::Absent(id.into()),
             status: StoreEntryStatus::Present,
+            lock: None,
         }
     }
 
@@ -127,9 +157,9 @@ impl StoreEntry {
         self.status == StoreEntryStatus::Borrowed
     }
 
-    fn get_entry(&mut self) -> Result<Entry> {
+    fn get_entry(&mut self, backend: &StoreBackend) -> Result<Entry> {
         if !self.is_borrowed() {
-            let file = self.file.get_file_mut();
+            let file = self.file.get_file_mut(backend);
             if let Err(err) = file {
                 if err.err_type() == SEK::FileNotFound {
                     Ok(Entry::new(self.id.clone()))
@@ -138,8 +168,8 @@ impl StoreEntry {
                 }
             } else {
                 // TODO:
-                let mut file = file.unwrap();
-                let entry = Entry::from_file(self.id.clone(), &mut file);
+                let file = file.unwrap();
+                let entry = Entry::from_reader(self.id.clone(), file);
                 file.seek(SeekFrom::Start(0)).ok();
                 entry
             }
@@ -148,14 +178,12 @@ impl StoreEntry {
         }
     }
 
-    fn write_entry(&mut self, entry: &Entry) -> Result<()> {
+    fn write_entry(&mut self, entry: &Entry, backend: &StoreBackend) -> Result<()> {
         if self.is_borrowed() {
-            use std::io::Write;
-            let file = try!(self.file.create_file());
+            let file = try!(self.file.create_file(backend));
 
             assert_eq!(self.id, entry.location);
-            try!(file.set_len(0).map_err_into(SEK::FileError));
-            file.write_all(entry.to_str().as_bytes()).map_err_into(SEK::FileError)
+            file.write_entry(entry.to_str().as_bytes()).map_err_into(SEK::FileError)
         } else {
             Ok(())
         }
@@ -166,6 +194,11 @@ impl StoreEntry {
 pub struct Store {
     location: PathBuf,
 
+    /**
+     * The backend the store reads from and writes to
+     */
+    backend: Box<StoreBackend>,
+
     /**
      * Configuration object of the store
      */
@@ -188,6 +221,42 @@ pub struct Store {
     pre_move_aspects      : Arc<Mutex<Vec<Aspect>>>,
     post_move_aspects     : Arc<Mutex<Vec<Aspect>>>,
 
+    /**
+     * Registered header schemas, by namespace
+     */
+    schemas: Arc<RwLock<BTreeMap<String, HeaderSchema>>>,
+
+    /**
+     * Index over the configured header paths
+     */
+    index: Arc<RwLock<HeaderIndex>>,
+
+    /**
+     * Full-text index over the content of the entries, updated when entries are written
+     */
+    search_index: Arc<Mutex<SearchIndex>>,
+
+    /**
+     * What is encrypted (see `encryption`), which is kept out of the indexes
+     */
+    encryption: EncryptionSettings,
+
+    /**
+     * The name of the program using the store, recorded in the change log
+     */
+    origin: Option<String>,
+
+    /**
+     * Whether the store was opened with `Store::new_read_only()`
+     */
+    read_only: bool,
+
+    /**
+     * The entries this store changed since it is watched, with the content id they had
+     * afterwards (`None` if they were removed), so `Watch` can tell its own changes apart
+     */
+    own_changes: Arc<Mutex<Option<BTreeMap<StoreId, Option<String>>>>>,
+
     /**
      * Internal Path->File cache map
      *
@@ -201,8 +270,34 @@ pub struct Store {
 impl Store {
 
     /// Create a new Store object
-    pub fn new(location: PathBuf, store_config: Option<Value>) -> Result<Store> {
-        use std::fs::create_dir_all;
+    ///
+    /// All storage access goes through `backend`, normally this is a
+    /// `backend::FileSystemBackend`.
+    pub fn new(location: PathBuf, store_config: Option<Value>, backend: Box<StoreBackend>)
+        -> Result<Store>
+    {
+        Store::open(location, store_config, backend, false)
+    }
+
+    /// Open an existing Store read-only
+    ///
+    /// Nothing is written to `location`: operations which would write fail with `StoreReadOnly`,
+    /// no OS locks are taken and a pending transaction journal is not replayed. Entries are read
+    /// with `Store::get_read_only()`.
+    pub fn new_read_only(location: PathBuf,
+                         store_config: Option<Value>,
+                         backend: Box<StoreBackend>)
+        -> Result<Store>
+    {
+        Store::open(location, store_config, backend, true)
+    }
+
+    fn open(location: PathBuf,
+            store_config: Option<Value>,
+            backend: Box<StoreBackend>,
+            read_only: bool)
+        -> Result<Store>
+    {
         use configuration::*;
 
         debug!("Validating Store configuration");
@@ -211,18 +306,42 @@ impl Store {
         }
 
         debug!("Building new Store object");
-        if !location.exists() {
+        if read_only && !backend.exists(&location) {
+            debug!("Store path does not exist");
+            return Err(SEK::StoreReadOnly.into_error());
+        } else if !backend.exists(&location) {
             debug!("Creating store path");
-            let c = create_dir_all(location.clone());
+            let c = backend.create_dir_all(&location);
             if c.is_err() {
                 debug!("Failed");
                 return Err(SEK::StorePathCreate.into_error_with_cause(Box::new(c.unwrap_err())));
             }
-        } else if location.is_file() {
+        } else if backend.is_file(&location) {
             debug!("Store path exists as file");
             return Err(SEK::StorePathExists.into_error());
         }
 
+        let journal = transaction_journal_path(&location);
+        if read_only && backend.is_file(&journal) {
+            warn!("Found transaction journal, but the store is opened read-only: {:?}", journal);
+            warn!("Entries may be in the state before the interrupted transaction");
+        } else if backend.is_file(&journal) {
+            debug!("Found transaction journal, replaying: {:?}", journal);
+            if let Err(e) = apply_transaction_journal(&*backend, &journal) {
+                return Err(SEK::JournalReplayError.into_error_with_cause(Box::new(e)));
+            }
+        }
+
+        let encryption = try!(EncryptionSettings::from_config(&store_config));
+        let schemas = load_schemas(&*backend, &location);
+        let index   = HeaderIndex::load(&*backend, &location, get_index_paths(&store_config));
+        let mut search = if is_search_index_enabled(&store_config) {
+            SearchIndex::load(&*backend, &location)
+        } else {
+            SearchIndex::new()
+        };
+        search.exclude_encrypted(&location, encryption.clone());
+
         let store_unload_aspects = get_store_unload_aspect_names(&store_config)
             .into_iter().map(|n| {
                 let cfg = AspectConfig::get_for(&store_config, n.clone());
@@ -291,6 +410,7 @@ impl Store {
 
         let store = Store {
             location: location.clone(),
+            backend: backend,
             configuration: store_config,
 
             store_unload_aspects  : Arc::new(Mutex::new(store_unload_aspects)),
@@ -305,6 +425,13 @@ impl Store {
             post_delete_aspects   : Arc::new(Mutex::new(post_delete_aspects)),
             pre_move_aspects    : Arc::new(Mutex::new(pre_move_aspects)),
             post_move_aspects   : Arc::new(Mutex::new(post_move_aspects)),
+            schemas: Arc::new(RwLock::new(schemas)),
+            index: Arc::new(RwLock::new(index)),
+            search_index: Arc::new(Mutex::new(search)),
+            encryption: encryption,
+            origin: None,
+            read_only: read_only,
+            own_changes: Arc::new(Mutex::new(None)),
             entries: Arc::new(RwLock::new(HashMap::new())),
         };
 
@@ -321,58 +448,117 @@ impl Store {
         self.configuration.as_ref()
     }
 
+    /// Whether the store was opened with `Store::new_read_only()`
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Set the name of the program which uses the store, it is recorded in the change log
+    pub fn set_origin(&mut self, origin: String) {
+        self.origin = Some(origin);
+    }
+
     /// Verify the store.
     ///
     /// This function is not intended to be called by normal programs but only by `imag-store`.
     #[cfg(feature = "verify")]
     pub fn verify(&self) -> bool {
+        let _lock = match self.lock_store() {
+            Ok(lock) => lock,
+            Err(e) => {
+                info!("Cannot verify: {}", e);
+                return false;
+            },
+        };
+
         info!("Header | Content length | Path");
         info!("-------+----------------+-----");
 
-        WalkDir::new(self.location.clone())
+        let objects = match self.backend.walk(&self.location) {
+            Ok(objs) => objs,
+            Err(e) => {
+                debug!("{:?}", e);
+                return false;
+            },
+        };
+
+        let objects_ok = match ::object::corrupted(&*self.backend, &self.location) {
+            Ok(corrupted) => {
+                for hash in corrupted.iter() {
+                    info!("{: >6} | {: >14} | {}", "object", "corrupted", hash);
+                }
+                corrupted.is_empty()
+            },
+            Err(e) => {
+                debug!("{:?}", e);
+                false
+            },
+        };
+
+        objects
             .into_iter()
-            .map(|res| {
-                match res {
-                    Ok(dent) => {
-                        if dent.file_type().is_file() {
-                            match self.get(PathBuf::from(dent.path())) {
-                                Ok(Some(fle)) => {
-                                    let p           = fle.get_location();
-                                    let content_len = fle.get_content().len();
-                                    let header      = if fle.get_header().verify().is_ok() {
-                                        "ok"
-                                    } else {
-                                        "broken"
-                                    };
-
-                                    info!("{: >6} | {: >14} | {:?}", header, content_len, p.deref());
-                                },
-
-                                Ok(None) => {
-                                    info!("{: >6} | {: >14} | {:?}", "?", "couldn't load", dent.path());
-                                },
-
-                                Err(e) => {
-                                    debug!("{:?}", e);
-                                },
-                            }
-                        } else {
-                            info!("{: >6} | {: >14} | {:?}", "?", "<no file>", dent.path());
+            .filter(|obj| match *obj {
+                BackendObject::File(ref p) | BackendObject::Directory(ref p) => {
+                    !self.is_internal_path(p)
+                },
+            })
+            .map(|obj| {
+                match obj {
+                    BackendObject::File(path) => {
+                        match self.get(path.clone()) {
+                            Ok(Some(fle)) => {
+                                let p           = fle.get_location();
+                                let content_len = fle.get_content().len();
+                                let violations  = self.schema_violations(fle.get_header())
+                                    .unwrap_or(vec![]);
+                                let attachments = ::attachment::problems(&*self.backend,
+                                                                         &self.location,
+                                                                         fle.get_header());
+                                let header      = if fle.get_header().verify().is_err() {
+                                    "broken"
+                                } else if !violations.is_empty() {
+                                    "schema"
+                                } else if !attachments.is_empty() {
+                                    "attach"
+                                } else {
+                                    "ok"
+                                };
+
+                                info!("{: >6} | {: >14} | {:?}", header, content_len, p.deref());
+                                for v in violations.iter() {
+                                    info!("{: >6} | {: >14} | {}", "", "", v);
+                                }
+                                for problem in attachments.iter() {
+                                    info!("{: >6} | {: >14} | {}", "", "", problem);
+                                }
+
+                                header == "ok"
+                            },
+
+                            Ok(None) => {
+                                info!("{: >6} | {: >14} | {:?}", "?", "couldn't load", path);
+                                true
+                            },
+
+                            Err(e) => {
+                                debug!("{:?}", e);
+                                true
+                            },
                         }
                     },
 
-                    Err(e) => {
-                        debug!("{:?}", e);
+                    BackendObject::Directory(path) => {
+                        info!("{: >6} | {: >14} | {:?}", "?", "<no file>", path);
+                        true
                     },
                 }
-
-                true
             })
-            .all(|b| b)
+            .fold(objects_ok, |acc, b| acc && b)
     }
 
     /// Creates the Entry at the given location (inside the entry)
     pub fn create<'a, S: IntoStoreId>(&'a self, id: S) -> Result<FileLockEntry<'a>> {
+        try!(self.check_writable().map_err_into(SEK::CreateCallError));
         let id = id.into_storeid().storified(self);
         if let Err(e) = self.execute_hooks_for_id(self.pre_create_aspects.clone(), &id) {
             return Err(e)
@@ -389,9 +575,11 @@ impl Store {
         if hsmap.contains_key(&id) {
             return Err(SEK::EntryAlreadyExists.into_error()).map_err_into(SEK::CreateCallError);
         }
+        let lock = try!(self.lock_entry(&id).map_err_into(SEK::CreateCallError));
         hsmap.insert(id.clone(), {
             let mut se = StoreEntry::new(id.clone());
             se.status = StoreEntryStatus::Borrowed;
+            se.lock = Some(lock);
             se
         });
 
@@ -404,11 +592,14 @@ impl Store {
     }
 
     /// Borrow a given Entry. When the `FileLockEntry` is either `update`d or
-    /// dropped, the new Entry is written to disk
+    /// dropped, the new Entry is written to disk, if it was modified
     ///
     /// Implicitely creates a entry in the store if there is no entry with the id `id`. For a
     /// non-implicitely-create look at `Store::get`.
+    ///
+    /// Fails with `StoreReadOnly` on read-only stores, see `Store::get_read_only()`.
     pub fn retrieve<'a, S: IntoStoreId>(&'a self, id: S) -> Result<FileLockEntry<'a>> {
+        try!(self.check_writable().map_err_into(SEK::RetrieveCallError));
         let id = id.into_storeid().storified(self);
         if let Err(e) = self.execute_hooks_for_id(self.pre_retrieve_aspects.clone(), &id) {
             return Err(e)
@@ -421,9 +612,28 @@ impl Store {
             .write()
             .map_err(|_| SE::new(SEK::LockPoisoned, None))
             .and_then(|mut es| {
-                let mut se = es.entry(id.clone()).or_insert_with(|| StoreEntry::new(id.clone()));
-                let entry = se.get_entry();
-                se.status = StoreEntryStatus::Borrowed;
+                // The entry is only put into the map once it is locked, so a failed retrieve does
+                // not leave it behind
+                let held = es.get(&id).map(|se| se.lock.is_some()).unwrap_or(false);
+                let lock = if held { None } else { Some(try!(self.lock_entry(&id))) };
+
+                let entry = {
+                    let mut se = es.entry(id.clone())
+                        .or_insert_with(|| StoreEntry::new(id.clone()));
+                    if lock.is_some() {
+                        se.lock = lock;
+                    }
+
+                    let entry = se.get_entry(&*self.backend);
+                    if entry.is_ok() {
+                        se.status = StoreEntryStatus::Borrowed;
+                    }
+                    entry
+                };
+
+                if entry.is_err() && !held {
+                    es.remove(&id);
+                }
                 entry
             })
             .map(|e| FileLockEntry::new(self, e))
@@ -440,104 +650,119 @@ impl Store {
     ///
     /// This executes the {pre,post}_retrieve_aspects hooks.
     pub fn get<'a, S: IntoStoreId + Clone>(&'a self, id: S) -> Result<Option<FileLockEntry<'a>>> {
-        if !id.clone().into_storeid().storified(self).exists() {
+        if !self.backend.exists(&id.clone().into_storeid().storified(self)) {
             debug!("Does not exist: {:?}", id.clone().into_storeid());
             return Ok(None);
         }
         self.retrieve(id).map(Some).map_err_into(SEK::GetCallError)
     }
 
-    /// Same as `Store::get()` but also tries older versions of the entry, returning an iterator
-    /// over all versions of the entry.
-    pub fn get_all_versions<'a, S: IntoStoreId>(&'a self, id: S) -> Result<StoreIdIterator>
-    {
-        // get PathBuf component from storeid, but not version component
-        fn path_component<S: IntoStoreId>(id: S) -> Result<PathBuf> {
-            let p : PathBuf = id.into_storeid().into();
-            match p.to_str() {
-                Some(s) => {
-                    let mut split       = s.split("~");
-                    let path_element    = match split.next() {
-                        Some(s) => s,
-                        None => return Err(SE::new(SEK::StorePathError, None))
-                            .map_err_into(SEK::GetAllVersionsCallError),
-                    };
-
-                    Ok(PathBuf::from(path_element))
-                },
-
-                None => Err(SE::new(SEK::StorePathError, None))
-                    .map_err_into(SEK::GetAllVersionsCallError),
-            }
-        }
-
-        fn build_glob_pattern(mut pb: PathBuf) -> Option<String> {
-            pb.push("~*.*.*");
-            pb.to_str().map(String::from)
-        }
-
-        match path_component(id).map(build_glob_pattern) {
-            Err(e) => Err(SEK::StorePathError.into_error_with_cause(Box::new(e)))
-                .map_err_into(SEK::GetAllVersionsCallError),
-            Ok(None) => Err(SE::new(SEK::StorePathError, None))
-                .map_err_into(SEK::GetAllVersionsCallError),
-            Ok(Some(pattern)) => {
-                glob(&pattern[..])
-                    .map(|paths| GlobStoreIdIterator::new(paths).into())
-                    .map_err_into(SEK::GlobError)
-                    .map_err_into(SEK::GetAllVersionsCallError)
-            }
-        }
-
-    }
-
     /// Iterate over all StoreIds for one module name
+    ///
+    /// Staged files of transactions are left out, see `backend::STAGED_SUFFIX`.
     pub fn retrieve_for_module(&self, mod_name: &str) -> Result<StoreIdIterator> {
         let mut path = self.path().clone();
         path.push(mod_name);
 
-        path.to_str()
-            .ok_or(SE::new(SEK::EncodingError, None))
-            .and_then(|path| {
-                let path = [ path, "/**/*" ].join("");
-                debug!("glob()ing with '{}'", path);
-                glob(&path[..]).map_err_into(SEK::GlobError)
+        debug!("Listing ids in '{:?}'", path);
+        self.backend
+            .list(&path)
+            .map(|paths| {
+                let ids = paths
+                    .into_iter()
+                    .filter(|p| !is_staged(p))
+                    .map(StoreId::from)
+                    .collect::<Vec<StoreId>>();
+                StoreIdIterator::new(Box::new(ids.into_iter()))
             })
-            .map(|paths| GlobStoreIdIterator::new(paths).into())
-            .map_err_into(SEK::GlobError)
+            .map_err_into(SEK::FileError)
             .map_err_into(SEK::RetrieveForModuleCallError)
     }
 
+    /// Iterate over the ids of all entries in the store
+    pub fn ids(&self) -> Result<StoreIdIterator> {
+        self.entries_in(None)
+            .map(|entries| {
+                let ids = entries.into_iter().map(|(_, id)| id).collect::<Vec<StoreId>>();
+                StoreIdIterator::new(Box::new(ids.into_iter()))
+            })
+            .map_err_into(SEK::IdsCallError)
+    }
+
+    /// Select the entries of a module for iterating over them lazily, see `stream`
+    pub fn select<'a>(&'a self, mod_name: &str) -> Result<Selection<'a>> {
+        self.entries_in(Some(mod_name))
+            .map(|entries| Selection::new(self, entries.into_iter().map(|(_, id)| id).collect()))
+            .map_err_into(SEK::SelectCallError)
+    }
+
+    /// Read the header of the entry `id`, without reading its content
+    pub fn read_header<S: IntoStoreId>(&self, id: S) -> Result<EntryHeader> {
+        let id = id.into_storeid().storified(self);
+        self.read_entry_header(&id).map_err_into(SEK::ReadHeaderCallError)
+    }
+
+    /// Read the entry `id` as it is stored, without parsing it
+    pub fn read_raw<S: IntoStoreId>(&self, id: S) -> Result<String> {
+        let id = id.into_storeid().storified(self);
+        self.read_entry_bytes(&id)
+            .and_then(|bytes| String::from_utf8(bytes).map_err_into(SEK::EncodingError))
+            .map_err_into(SEK::ReadRawCallError)
+    }
+
     // Walk the store tree for the module
     pub fn walk<'a>(&'a self, mod_name: &str) -> Walk {
-        Walk::new(self.path().clone(), mod_name)
+        Walk::new(self.path().clone(), mod_name, &*self.backend)
     }
 
     /// Return the `FileLockEntry` and write to disk
-    pub fn update<'a>(&'a self, mut entry: FileLockEntry<'a>) -> Result<()> {
+    ///
+    /// If the entry was not modified since it was retrieved, it is not written and the update
+    /// hooks are not executed, see `Entry::is_modified()`.
+    pub fn update<'a>(&'a self, entry: FileLockEntry<'a>) -> Result<()> {
+        self.update_entry(entry, false).map_err_into(SEK::UpdateCallError)
+    }
+
+    /// Return the `FileLockEntry` and write to disk, even if it was not modified
+    pub fn force_update<'a>(&'a self, entry: FileLockEntry<'a>) -> Result<()> {
+        self.update_entry(entry, true).map_err_into(SEK::ForceUpdateCallError)
+    }
+
+    fn update_entry<'a>(&'a self, mut entry: FileLockEntry<'a>, force: bool) -> Result<()> {
+        if !force && !entry.is_modified() {
+            debug!("Entry was not modified, not updating: {:?}", entry.get_location());
+            return self._update(&entry, false);
+        }
+
         if let Err(e) = self.execute_hooks_for_mut_file(self.pre_update_aspects.clone(), &mut entry) {
+            // The update is aborted, dropping the entry only releases it
+            entry.entry.modified = false;
             return Err(e)
                 .map_err_into(SEK::PreHookExecuteError)
-                .map_err_into(SEK::HookExecutionError)
-                .map_err_into(SEK::UpdateCallError);
+                .map_err_into(SEK::HookExecutionError);
         }
 
-        if let Err(e) = self._update(&entry) {
-            return Err(e).map_err_into(SEK::UpdateCallError);
-        }
+        let res = self._update(&entry, true);
+
+        // The entry is released, even if writing failed, dropping it must not write it again
+        entry.entry.modified = false;
+        try!(res);
 
         self.execute_hooks_for_mut_file(self.post_update_aspects.clone(), &mut entry)
             .map_err_into(SEK::PostHookExecuteError)
             .map_err_into(SEK::HookExecutionError)
-            .map_err_into(SEK::UpdateCallError)
     }
 
     /// Internal method to write to the filesystem store.
     ///
+    /// Unless `force` is set, an entry which was not modified is released without writing it. The
+    /// entry is released if writing fails, too.
+    ///
     /// # Assumptions
     /// This method assumes that entry is dropped _right after_ the call, hence
     /// it is not public.
-    fn _update<'a>(&'a self, entry: &FileLockEntry<'a>) -> Result<()> {
+    fn _update<'a>(&'a self, entry: &FileLockEntry<'a>, force: bool) -> Result<()> {
+        try!(self.check_writable());
         let mut hsmap = match self.entries.write() {
             Err(_) => return Err(SE::new(SEK::LockPoisoned, None)),
             Ok(e) => e,
@@ -545,18 +770,48 @@ impl Store {
 
         let mut se = try!(hsmap.get_mut(&entry.location).ok_or(SE::new(SEK::IdNotFound, None)));
 
-        assert!(se.is_borrowed(), "Tried to update a non borrowed entry.");
+        if !force && !entry.is_modified() {
+            debug!("Releasing unmodified Entry");
+            se.status = StoreEntryStatus::Present;
+            se.lock = None;
+            return Ok(());
+        }
 
-        debug!("Verifying Entry");
-        try!(entry.entry.verify());
+        assert!(se.is_borrowed(), "Tried to update a non borrowed entry.");
 
-        debug!("Writing Entry");
-        try!(se.write_entry(&entry.entry));
+        let existed = self.backend.is_file(&entry.location);
+        let res     = self.write_borrowed_entry(se, &entry.entry);
         se.status = StoreEntryStatus::Present;
+        se.lock = None;
+        if let Err(e) = res {
+            warn!("Could not write {:?}, changes are lost", entry.location);
+            return Err(e);
+        }
+        self.update_index(&entry.location, Some(entry.get_header()));
+        self.update_search_index(&entry.location, entry.get_content());
+
+        let op = if existed { Operation::Update } else { Operation::Create };
+        self.record_change(op, &entry.location, None);
 
         Ok(())
     }
 
+    /// Verify the borrowed entry `entry`, keep a revision of it and write it
+    fn write_borrowed_entry(&self, se: &mut StoreEntry, entry: &Entry) -> Result<()> {
+        debug!("Verifying Entry");
+        try!(entry.verify());
+        try!(self.check_schemas(entry.get_header()));
+
+        debug!("Keeping revision of Entry");
+        if let Err(e) = self.save_revision(&entry.location, &entry.to_str()) {
+            warn!("Could not keep revision of {:?}", entry.location);
+            debug!("{:?}", e);
+        }
+
+        debug!("Writing Entry");
+        se.write_entry(entry, &*self.backend)
+    }
+
     /// Retrieve a copy of a given entry, this cannot be used to mutate
     /// the one on disk
     pub fn retrieve_copy<S: IntoStoreId>(&self, id: S) -> Result<Entry> {
@@ -574,11 +829,31 @@ impl Store {
             return Err(SE::new(SEK::IdLocked, None)).map_err_into(SEK::RetrieveCopyCallError);
         }
 
-        StoreEntry::new(id).get_entry()
+        StoreEntry::new(id).get_entry(&*self.backend)
+    }
+
+    /// Get a read-only handle to an entry, if it exists
+    ///
+    /// Unlike `Store::get()`, this does not borrow the entry and works on read-only stores. The
+    /// handle is never written back and no retrieve hooks are executed. If this process borrows
+    /// the entry, the content on disk is read.
+    pub fn get_read_only<S: IntoStoreId>(&self, id: S) -> Result<Option<ReadOnlyEntry>> {
+        let id = id.into_storeid().storified(self);
+        if !self.backend.is_file(&id) {
+            debug!("Does not exist: {:?}", id);
+            return Ok(None);
+        }
+
+        self.read_entry_bytes(&id)
+            .and_then(|bytes| String::from_utf8(bytes).map_err_into(SEK::EncodingError))
+            .and_then(|text| Entry::from_str(id, &text[..]))
+            .map(|entry| Some(ReadOnlyEntry::new(entry)))
+            .map_err_into(SEK::GetReadOnlyCallError)
     }
 
     /// Delete an entry
     pub fn delete<S: IntoStoreId>(&self, id: S) -> Result<()> {
+        try!(self.check_writable().map_err_into(SEK::DeleteCallError));
         let id = id.into_storeid().storified(self);
         if let Err(e) = self.execute_hooks_for_id(self.pre_delete_aspects.clone(), &id) {
             return Err(e)
@@ -599,12 +874,18 @@ impl Store {
                 .map_err_into(SEK::DeleteCallError);
         }
 
+        // nor if another process modifies it
+        let _lock = try!(self.lock_entry(&id).map_err_into(SEK::DeleteCallError));
+
         // remove the entry first, then the file
         entries.remove(&id);
-        if let Err(e) = remove_file(&id) {
+        if let Err(e) = self.backend.remove(&id) {
             return Err(SEK::FileError.into_error_with_cause(Box::new(e)))
                 .map_err_into(SEK::DeleteCallError);
         }
+        self.remove_revisions(&id);
+        self.update_index(&id, None);
+        self.record_change(Operation::Delete, &id, None);
 
         self.execute_hooks_for_id(self.post_delete_aspects.clone(), &id)
             .map_err_into(SEK::PostHookExecuteError)
@@ -613,1526 +894,4566 @@ impl Store {
     }
 
     /// Save a copy of the Entry in another place
-    /// Executes the post_move_aspects for the new id
+    ///
+    /// The copy is a new entry, so the pre_create_aspects and post_create_aspects are executed for
+    /// it (and the update aspects, when it is written), the move aspects are not.
     pub fn save_to(&self, entry: &FileLockEntry, new_id: StoreId) -> Result<()> {
-        self.save_to_other_location(entry, new_id, false)
+        try!(self.check_writable().map_err_into(SEK::SaveToCallError));
+        let new_id = new_id.storified(self);
+
+        if self.backend.is_file(&new_id) {
+            return Err(SE::new(SEK::EntryAlreadyExists, None)).map_err_into(SEK::SaveToCallError);
+        }
+
+        self.create(new_id)
+            .and_then(|mut copy| {
+                *copy.get_header_mut()  = entry.get_header().clone();
+                *copy.get_content_mut() = entry.get_content().clone();
+                self.update(copy)
+            })
+            .map_err_into(SEK::SaveToCallError)
     }
 
     /// Save an Entry in another place
     /// Removes the original entry
-    /// Executes the post_move_aspects for the new id
-    pub fn save_as(&self, entry: FileLockEntry, new_id: StoreId) -> Result<()> {
-        self.save_to_other_location(&entry, new_id, true)
-    }
+    /// Executes the pre_move_aspects and post_move_aspects with the old and the new id
+    ///
+    /// The entry is written as it is in memory, so changes which were not written yet are saved at
+    /// the new place. Links to the entry are not rewritten, see `move_by_id()`.
+    pub fn save_as(&self, mut entry: FileLockEntry, new_id: StoreId) -> Result<()> {
+        try!(self.check_writable().map_err_into(SEK::MoveCallError));
+        let new_id = new_id.storified(self);
+        let old_id = entry.get_location().clone();
 
-    fn save_to_other_location(&self, entry: &FileLockEntry, new_id: StoreId, remove_old: bool)
-        -> Result<()>
-    {
-        use std::fs::copy;
-        use std::fs::remove_file;
+        {
+            let _store_lock = try!(self.lock_store().map_err_into(SEK::MoveCallError));
 
-        let new_id = new_id.storified(self);
-        let hsmap = self.entries.write();
-        if hsmap.is_err() {
-            return Err(SE::new(SEK::LockPoisoned, None)).map_err_into(SEK::MoveCallError)
-        }
-        if hsmap.unwrap().contains_key(&new_id) {
-            return Err(SE::new(SEK::EntryAlreadyExists, None)).map_err_into(SEK::MoveCallError)
-        }
+            let exists = match self.entries.read() {
+                Err(_) => return Err(SE::new(SEK::LockPoisoned, None))
+                    .map_err_into(SEK::MoveCallError),
+                Ok(hsmap) => hsmap.contains_key(&new_id),
+            };
+            if exists || self.backend.is_file(&new_id) {
+                return Err(SE::new(SEK::EntryAlreadyExists, None)).map_err_into(SEK::MoveCallError)
+            }
 
-        let old_id = entry.get_location().clone();
+            // The old entry is locked by `entry` already
+            let _new_lock = try!(self.lock_entry(&new_id).map_err_into(SEK::MoveCallError));
 
-        copy(old_id.clone(), new_id.clone())
-            .and_then(|_| {
-                if remove_old {
-                    remove_file(old_id)
-                } else {
-                    Ok(())
+            let pre_hooks = self.execute_hooks_for_move(self.pre_move_aspects.clone(),
+                                                        &old_id, &new_id);
+            if let Err(e) = pre_hooks {
+                return Err(e)
+                    .map_err_into(SEK::PreHookExecuteError)
+                    .map_err_into(SEK::HookExecutionError)
+                    .map_err_into(SEK::MoveCallError)
+            }
+
+            {
+                let mut hsmap = match self.entries.write() {
+                    Err(_) => return Err(SE::new(SEK::LockPoisoned, None))
+                        .map_err_into(SEK::MoveCallError),
+                    Ok(hsmap) => hsmap,
+                };
+
+                // Write the entry before moving it, then the store forgets the old id, so
+                // dropping `entry` does not write it to the old place again
+                {
+                    let se = try!(hsmap
+                                  .get_mut(&old_id)
+                                  .ok_or(SE::new(SEK::IdNotFound, None))
+                                  .map_err_into(SEK::MoveCallError));
+                    try!(self.write_borrowed_entry(se, &entry.entry)
+                         .map_err_into(SEK::MoveCallError));
                 }
-            })
-            .map_err_into(SEK::FileError)
-            .and_then(|_| self.execute_hooks_for_id(self.post_move_aspects.clone(), &new_id)
-                    .map_err_into(SEK::PostHookExecuteError)
-                    .map_err_into(SEK::HookExecutionError))
+                if let Err(e) = self.backend.rename(&old_id, &new_id) {
+                    return Err(SEK::EntryRenameError.into_error_with_cause(Box::new(e)))
+                        .map_err_into(SEK::MoveCallError);
+                }
+                hsmap.remove(&old_id);
+            }
+            entry.entry.modified = false;
+
+            self.update_index(&new_id, Some(entry.get_header()));
+            self.update_index(&old_id, None);
+            // The post_move_aspects move it to the new id in the search index
+            self.update_search_index(&old_id, entry.get_content());
+            self.move_revisions(&old_id, &new_id);
+            self.record_change(Operation::Move, &old_id, Some(&new_id));
+        }
+
+        // The locks are released, so the hooks can retrieve the moved entry
+        self.execute_hooks_for_move(self.post_move_aspects.clone(), &old_id, &new_id)
+            .map_err_into(SEK::PostHookExecuteError)
+            .map_err_into(SEK::HookExecutionError)
             .map_err_into(SEK::MoveCallError)
     }
 
     /// Move an entry without loading
+    /// Executes the pre_move_aspects and post_move_aspects with the old and the new id
+    ///
+    /// The entries are locked (and the old one is checked to not be borrowed) before the
+    /// pre_move_aspects are executed, so the hooks are not executed for a move which cannot
+    /// happen.
+    ///
+    /// Only the entry is moved, entries linking to it (in their header or content) keep pointing
+    /// to the old id. `libimagentrylink::internal::move_entry()` rewrites these links.
     pub fn move_by_id(&self, old_id: StoreId, new_id: StoreId) -> Result<()> {
-        use std::fs::rename;
-
+        try!(self.check_writable().map_err_into(SEK::MoveByIdCallError));
         let new_id = new_id.storified(self);
         let old_id = old_id.storified(self);
 
-        if let Err(e) = self.execute_hooks_for_id(self.pre_move_aspects.clone(), &old_id) {
-            return Err(e)
-                .map_err_into(SEK::PreHookExecuteError)
-                .map_err_into(SEK::HookExecutionError)
-                .map_err_into(SEK::MoveByIdCallError)
-        }
+        {
+            let _store_lock = try!(self.lock_store().map_err_into(SEK::MoveByIdCallError));
 
-        let hsmap = self.entries.write();
-        if hsmap.is_err() {
-            return Err(SE::new(SEK::LockPoisoned, None))
-        }
-        if hsmap.unwrap().contains_key(&old_id) {
-            return Err(SE::new(SEK::EntryAlreadyBorrowed, None));
-        } else {
-            match rename(old_id, new_id.clone()) {
-                Err(e) => return Err(SEK::EntryRenameError.into_error_with_cause(Box::new(e))),
-                _ => {
-                    debug!("Rename worked");
-                },
+            let borrowed = match self.entries.read() {
+                Err(_) => return Err(SE::new(SEK::LockPoisoned, None))
+                    .map_err_into(SEK::MoveByIdCallError),
+                Ok(hsmap) => hsmap.get(&old_id).map(|se| se.is_borrowed()).unwrap_or(false),
+            };
+            if borrowed {
+                return Err(SE::new(SEK::EntryAlreadyBorrowed, None))
+                    .map_err_into(SEK::MoveByIdCallError);
             }
+
+            // Neither entry may be borrowed by another process while it is moved
+            let _old_lock = try!(self.lock_entry(&old_id).map_err_into(SEK::MoveByIdCallError));
+            let _new_lock = try!(self.lock_entry(&new_id).map_err_into(SEK::MoveByIdCallError));
+
+            let pre_hooks = self.execute_hooks_for_move(self.pre_move_aspects.clone(),
+                                                        &old_id, &new_id);
+            if let Err(e) = pre_hooks {
+                return Err(e)
+                    .map_err_into(SEK::PreHookExecuteError)
+                    .map_err_into(SEK::HookExecutionError)
+                    .map_err_into(SEK::MoveByIdCallError)
+            }
+
+            if let Err(e) = self.backend.rename(&old_id, &new_id) {
+                return Err(SEK::EntryRenameError.into_error_with_cause(Box::new(e)))
+                    .map_err_into(SEK::MoveByIdCallError);
+            }
+            debug!("Rename worked");
+            match self.entries.write() {
+                Ok(mut hsmap) => { hsmap.remove(&old_id); },
+                Err(_) => warn!("Store lock poisoned, cannot forget {:?}", old_id),
+            }
+            self.move_revisions(&old_id, &new_id);
+            self.rename_in_index(&old_id, &new_id);
+            self.record_change(Operation::Move, &old_id, Some(&new_id));
         }
 
-        self.execute_hooks_for_id(self.pre_move_aspects.clone(), &new_id)
+        // The locks are released, so the hooks can retrieve the moved entry
+        self.execute_hooks_for_move(self.post_move_aspects.clone(), &old_id, &new_id)
             .map_err_into(SEK::PostHookExecuteError)
             .map_err_into(SEK::HookExecutionError)
             .map_err_into(SEK::MoveByIdCallError)
     }
 
-    /// Gets the path where this store is on the disk
-    pub fn path(&self) -> &PathBuf {
-        &self.location
+    /// Start a new transaction on the store
+    ///
+    /// See `Transaction` for details.
+    pub fn transaction<'a>(&'a self) -> Transaction<'a> {
+        Transaction::new(self)
     }
 
-    pub fn register_hook(&mut self,
-                         position: HookPosition,
-                         aspect_name: &str,
-                         mut h: Box<Hook>)
-        -> Result<()>
-    {
-        debug!("Registering hook: {:?}", h);
-        debug!("     in position: {:?}", position);
-        debug!("     with aspect: {:?}", aspect_name);
-
-        let guard = match position {
-                HookPosition::StoreUnload  => self.store_unload_aspects.clone(),
+    /// Check whether the entry at `path` is encrypted (see `encryption`), so it is kept out of the
+    /// header and search indexes, which are written in the clear
+    fn is_encrypted(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.location)
+            .map(|rel| self.encryption.covers(rel))
+            .unwrap_or(false)
+    }
 
-                HookPosition::PreCreate    => self.pre_create_aspects.clone(),
-                HookPosition::PostCreate   => self.post_create_aspects.clone(),
-                HookPosition::PreRetrieve  => self.pre_retrieve_aspects.clone(),
-                HookPosition::PostRetrieve => self.post_retrieve_aspects.clone(),
-                HookPosition::PreUpdate    => self.pre_update_aspects.clone(),
-                HookPosition::PostUpdate   => self.post_update_aspects.clone(),
-                HookPosition::PreDelete    => self.pre_delete_aspects.clone(),
-                HookPosition::PostDelete   => self.post_delete_aspects.clone(),
-            };
+    /// Check whether `path` is used by the store itself rather than being an entry, see
+    /// `storeid::is_internal_path()`
+    fn is_internal_path(&self, path: &Path) -> bool {
+        ::storeid::is_internal_path(&self.location, path)
+    }
 
-        let mut guard = match guard.deref().lock().map_err(|_| SE::new(SEK::LockError, None)) {
-            Err(e) => return Err(SEK::HookRegisterError.into_error_with_cause(Box::new(e))),
-            Ok(g) => g,
-        };
+    /// Get the revisions of an entry, oldest first
+    pub fn revisions<S: IntoStoreId>(&self, id: S) -> Result<Vec<Revision>> {
+        let id = id.into_storeid().storified(self);
+        self.revisions_dir(&id)
+            .and_then(|dir| self.list_revisions(&dir))
+            .map_err_into(SEK::RevisionsCallError)
+    }
 
-        for mut aspect in guard.deref_mut() {
-            if aspect.name().clone() == aspect_name.clone() {
-                debug!("Trying to find configuration for hook: {:?}", h);
-                self.get_config_for_hook(h.name()).map(|config| h.set_config(config));
-                debug!("Trying to register hook in aspect: {:?} <- {:?}", aspect, h);
-                aspect.register_hook(h);
-                return Ok(());
-            }
-        }
+    /// Get a copy of the entry as it was at revision `rev`
+    pub fn retrieve_revision<S: IntoStoreId>(&self, id: S, rev: Revision) -> Result<Entry> {
+        let id = id.into_storeid().storified(self);
+        self.revisions_dir(&id)
+            .and_then(|mut path| {
+                path.push(rev.to_string());
+                if !self.backend.is_file(&path) {
+                    return Err(SE::new(SEK::RevisionNotFound, None));
+                }
 
-        let annfe = SEK::AspectNameNotFoundError.into_error();
-        Err(SEK::HookRegisterError.into_error_with_cause(Box::new(annfe)))
+                self.backend
+                    .read(&path)
+                    .map_err_into(SEK::FileError)
+                    .and_then(|bytes| String::from_utf8(bytes).map_err_into(SEK::EncodingError))
+            })
+            .and_then(|text| Entry::from_str(id, &text[..]))
+            .map_err_into(SEK::RetrieveRevisionCallError)
     }
 
-    fn get_config_for_hook(&self, name: &str) -> Option<&Value> {
-        match self.configuration {
-            Some(Value::Table(ref tabl)) => {
-                debug!("Trying to head 'hooks' section from {:?}", tabl);
-                tabl.get("hooks")
-                    .map(|hook_section| {
-                        debug!("Found hook section:  {:?}", hook_section);
-                        debug!("Reading section key: {:?}", name);
-                        match *hook_section {
-                            Value::Table(ref tabl) => tabl.get(name),
-                            _ => None
-                        }
-                    })
-                    .unwrap_or(None)
-            },
-            _ => None,
-        }
-    }
-
-    fn execute_hooks_for_id(&self,
-                            aspects: Arc<Mutex<Vec<Aspect>>>,
-                            id: &StoreId)
-        -> HookResult<()>
+    /// Diff two revisions of an entry
+    pub fn diff_revisions<S: IntoStoreId>(&self, id: S, old: Revision, new: Revision)
+        -> Result<Vec<DiffLine>>
     {
-        match aspects.lock() {
-            Err(_) => return Err(HookErrorKind::HookExecutionError.into()),
-            Ok(g) => g
-        }.iter().fold_defresult(|aspect| {
-            debug!("[Aspect][exec]: {:?}", aspect);
-            (aspect as &StoreIdAccessor).access(id)
-        }).map_err(Box::new)
-            .map_err(|e| HookErrorKind::HookExecutionError.into_error_with_cause(e))
+        let id = id.into_storeid().storified(self);
+        self.retrieve_revision(id.clone(), old)
+            .and_then(|old| {
+                self.retrieve_revision(id, new)
+                    .map(|new| ::revision::diff(&old.to_str(), &new.to_str()))
+            })
+            .map_err_into(SEK::DiffRevisionsCallError)
     }
 
-    fn execute_hooks_for_mut_file(&self,
-                                  aspects: Arc<Mutex<Vec<Aspect>>>,
-                                  fle: &mut FileLockEntry)
-        -> HookResult<()>
-    {
-        match aspects.lock() {
-            Err(_) => return Err(HookErrorKind::HookExecutionError.into()),
-            Ok(g) => g
-        }.iter().fold_defresult(|aspect| {
-            debug!("[Aspect][exec]: {:?}", aspect);
-            aspect.access_mut(fle)
-        }).map_err(Box::new)
-            .map_err(|e| HookErrorKind::HookExecutionError.into_error_with_cause(e))
+    /// Restore the entry to revision `rev`
+    ///
+    /// The current state of the entry is kept as a new revision, so restoring can be undone.
+    pub fn restore_revision<S: IntoStoreId>(&self, id: S, rev: Revision) -> Result<()> {
+        let id = id.into_storeid().storified(self);
+        self.retrieve_revision(id.clone(), rev)
+            .and_then(|old| {
+                self.retrieve(id).and_then(|mut fle| {
+                    *fle.get_header_mut()  = old.get_header().clone();
+                    *fle.get_content_mut() = old.get_content().clone();
+                    self.update(fle)
+                })
+            })
+            .map_err_into(SEK::RestoreRevisionCallError)
     }
 
-}
+    fn revisions_dir(&self, id: &StoreId) -> Result<PathBuf> {
+        id.strip_prefix(&self.location)
+            .map(|relative| {
+                let mut path = self.location.clone();
+                path.push(REVISIONS_DIR);
+                path.push(relative);
+                path
+            })
+            .map_err_into(SEK::StorePathError)
+    }
 
-impl Debug for Store {
+    fn list_revisions(&self, dir: &Path) -> Result<Vec<Revision>> {
+        use std::str::FromStr;
 
-    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FMTError> {
-        try!(write!(fmt, " --- Store ---\n"));
-        try!(write!(fmt, "\n"));
-        try!(write!(fmt, " - location               : {:?}\n", self.location));
-        try!(write!(fmt, " - configuration          : {:?}\n", self.configuration));
-        try!(write!(fmt, " - pre_create_aspects     : {:?}\n", self.pre_create_aspects    ));
-        try!(write!(fmt, " - post_create_aspects    : {:?}\n", self.post_create_aspects   ));
-        try!(write!(fmt, " - pre_retrieve_aspects   : {:?}\n", self.pre_retrieve_aspects  ));
-        try!(write!(fmt, " - post_retrieve_aspects  : {:?}\n", self.post_retrieve_aspects ));
-        try!(write!(fmt, " - pre_update_aspects     : {:?}\n", self.pre_update_aspects    ));
-        try!(write!(fmt, " - post_update_aspects    : {:?}\n", self.post_update_aspects   ));
-        try!(write!(fmt, " - pre_delete_aspects     : {:?}\n", self.pre_delete_aspects    ));
-        try!(write!(fmt, " - post_delete_aspects    : {:?}\n", self.post_delete_aspects   ));
-        try!(write!(fmt, "\n"));
-        try!(write!(fmt, "Entries:\n"));
-        try!(write!(fmt, "{:?}", self.entries));
-        try!(write!(fmt, "\n"));
-        Ok(())
+        self.backend
+            .list(dir)
+            .map(|paths| {
+                let mut revs : Vec<Revision> = paths
+                    .into_iter()
+                    .filter(|p| p.parent() == Some(dir))
+                    .filter_map(|p| {
+                        p.file_name()
+                            .and_then(|n| n.to_str())
+                            .and_then(|n| Revision::from_str(n).ok())
+                    })
+                    .collect();
+                revs.sort();
+                revs
+            })
+            .map_err_into(SEK::RevisionError)
     }
 
-}
+    /// Keep the current on-disk content of `id` as new revision, if it differs from `new_content`
+    ///
+    /// Old revisions are removed if there are more than configured with `revisions-keep`.
+    fn save_revision(&self, id: &StoreId, new_content: &str) -> Result<()> {
+        use configuration::get_revisions_keep;
 
-impl Drop for Store {
+        let keep = get_revisions_keep(&self.configuration);
+        if keep == 0 || !self.backend.is_file(id) {
+            return Ok(());
+        }
 
-    /**
-     * Unlock all files on drop
-     *
-     * TODO: Unlock them
-     */
-    fn drop(&mut self) {
-        let store_id = StoreId::from(self.location.clone());
-        if let Err(e) = self.execute_hooks_for_id(self.store_unload_aspects.clone(), &store_id) {
-            debug!("Store-load hooks execution failed. Cannot create store object.");
-            warn!("Store Unload Hook error: {:?}", e);
+        let old = try!(self.backend.read(id).map_err_into(SEK::FileError));
+        if &old[..] == new_content.as_bytes() {
+            debug!("Content unchanged, not keeping a revision");
+            return Ok(());
         }
 
-        debug!("Dropping store");
+        let dir      = try!(self.revisions_dir(id));
+        let mut revs = try!(self.list_revisions(&dir));
+        let next     = revs.last().map(|r| r + 1).unwrap_or(1);
+
+        let mut path = dir.clone();
+        path.push(next.to_string());
+        debug!("Keeping revision {} of {:?} in {:?}", next, id, path);
+        try!(self.backend.write(&path, &old).map_err_into(SEK::RevisionError));
+        revs.push(next);
+
+        let n = revs.len();
+        if n > keep {
+            for rev in &revs[..(n - keep)] {
+                let mut path = dir.clone();
+                path.push(rev.to_string());
+                debug!("Removing old revision {:?}", path);
+                try!(self.backend.remove(&path).map_err_into(SEK::RevisionError));
+            }
+        }
+
+        Ok(())
     }
 
-}
+    /// Move the revisions of `old_id` to `new_id`, warning on failure
+    fn move_revisions(&self, old_id: &StoreId, new_id: &StoreId) {
+        let dirs = self.revisions_dir(old_id)
+            .and_then(|old| self.revisions_dir(new_id).map(|new| (old, new)));
+
+        let (old_dir, new_dir) = match dirs {
+            Ok(dirs) => dirs,
+            Err(e) => {
+                warn!("Could not move revisions of {:?}", old_id);
+                debug!("{:?}", e);
+                return;
+            },
+        };
 
-/// A struct that allows you to borrow an Entry
-#[derive(Debug)]
-pub struct FileLockEntry<'a> {
-    store: &'a Store,
-    entry: Entry,
-}
+        for rev in self.list_revisions(&old_dir).unwrap_or(vec![]) {
+            let mut from = old_dir.clone();
+            from.push(rev.to_string());
+            let mut to = new_dir.clone();
+            to.push(rev.to_string());
 
-impl<'a> FileLockEntry<'a, > {
-    fn new(store: &'a Store, entry: Entry) -> FileLockEntry<'a> {
-        FileLockEntry {
-            store: store,
-            entry: entry,
+            let res = self.backend
+                .create_dir_all(&new_dir)
+                .and_then(|_| self.backend.rename(&from, &to));
+
+            if let Err(e) = res {
+                warn!("Could not move revision {:?} -> {:?}", from, to);
+                debug!("{:?}", e);
+            }
         }
     }
-}
 
-impl<'a> Deref for FileLockEntry<'a> {
-    type Target = Entry;
+    /// Remove the revisions of the deleted entry `id`, warning on failure
+    ///
+    /// Otherwise, an entry created with the same id later would inherit them.
+    fn remove_revisions(&self, id: &StoreId) {
+        let res = self.revisions_dir(id).and_then(|dir| {
+            if !self.backend.exists(&dir) {
+                return Ok(());
+            }
+            for rev in try!(self.list_revisions(&dir)) {
+                let mut path = dir.clone();
+                path.push(rev.to_string());
+                debug!("Removing revision {:?}", path);
+                try!(self.backend.remove(&path).map_err_into(SEK::RevisionError));
+            }
+            self.backend.remove_dir(&dir).map_err_into(SEK::RevisionError)
+        });
 
-    fn deref(&self) -> &Self::Target {
-        &self.entry
+        if let Err(e) = res {
+            warn!("Could not remove revisions of {:?}", id);
+            debug!("{:?}", e);
+        }
     }
-}
 
-impl<'a> DerefMut for FileLockEntry<'a> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.entry
+    /// Register the schema for a header namespace
+    ///
+    /// Replaces the schema which was registered for the namespace before. The schema is persisted
+    /// in the store, so it is also known to programs which do not register it themselves. On
+    /// read-only stores, the schema is only registered with this `Store` object.
+    pub fn register_schema(&self, schema: HeaderSchema) -> Result<()> {
+        let mut schemas = match self.schemas.write() {
+            Err(_) => return Err(SE::new(SEK::LockPoisoned, None))
+                .map_err_into(SEK::RegisterSchemaCallError),
+            Ok(s) => s,
+        };
+
+        if schemas.get(schema.namespace()) == Some(&schema) {
+            return Ok(());
+        }
+        if self.read_only {
+            debug!("Registering schema for '{}' without persisting it", schema.namespace());
+            schemas.insert(schema.namespace().clone(), schema);
+            return Ok(());
+        }
+
+        let mut path = self.location.clone();
+        path.push(SCHEMAS_DIR);
+        path.push(schema.namespace());
+
+        debug!("Registering schema for '{}' in {:?}", schema.namespace(), path);
+        let text = ::toml::encode_str(&schema.to_value());
+        try!(self.backend
+             .write(&path, text.as_bytes())
+             .map_err_into(SEK::SchemaError)
+             .map_err_into(SEK::RegisterSchemaCallError));
+
+        schemas.insert(schema.namespace().clone(), schema);
+        Ok(())
     }
-}
 
-impl<'a> Drop for FileLockEntry<'a> {
-    /// This will silently ignore errors, use `Store::update` if you want to catch the errors
-    fn drop(&mut self) {
-        let _ = self.store._update(self);
+    /// Get all violations of registered schemas in `header`
+    pub fn schema_violations(&self, header: &EntryHeader) -> Result<Vec<SchemaViolation>> {
+        self.schemas
+            .read()
+            .map_err(|_| SE::new(SEK::LockPoisoned, None))
+            .map(|schemas| schemas.values().flat_map(|s| s.validate(header)).collect())
     }
-}
 
-/// `EntryContent` type
-pub type EntryContent = String;
+    fn check_schemas(&self, header: &EntryHeader) -> Result<()> {
+        let violations = try!(self.schema_violations(header));
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            let cause = Box::new(SchemaViolations(violations));
+            Err(SEK::SchemaViolation.into_error_with_cause(cause))
+        }
+    }
 
-/// `EntryHeader`
-///
-/// This is basically a wrapper around `toml::Table` which provides convenience to the user of the
-/// library.
-#[derive(Debug, Clone)]
-pub struct EntryHeader {
-    header: Value,
-}
+    /// Apply the pending migrations of `registry` to all entries of the store
+    ///
+    /// Returns, for each entry which has pending migrations or an outdated `imag.version`, either
+    /// what was done or why it failed. A failing entry does not abort the run. With `dry_run`,
+    /// nothing is written.
+    pub fn migrate(&self, registry: &MigrationRegistry, dry_run: bool)
+        -> Result<Vec<(StoreId, Result<MigrationReport>)>>
+    {
+        if !dry_run {
+            try!(self.check_writable().map_err_into(SEK::MigrateCallError));
+        }
+        let _lock = try!(self.lock_store().map_err_into(SEK::MigrateCallError));
+        let objects = try!(self.backend
+                           .walk(&self.location)
+                           .map_err_into(SEK::FileError)
+                           .map_err_into(SEK::MigrateCallError));
 
-pub type EntryResult<V> = RResult<V, ParserError>;
+        let results = objects
+            .into_iter()
+            .filter_map(|obj| match obj {
+                BackendObject::File(p) => Some(p),
+                BackendObject::Directory(_) => None,
+            })
+            .filter(|p| !self.is_internal_path(p) && !is_staged(p))
+            .filter_map(|p| {
+                let id = StoreId::from(p);
+                match self.migrate_entry(registry, &id, dry_run) {
+                    Ok(None)         => None,
+                    Ok(Some(report)) => Some((id, Ok(report))),
+                    Err(e)           => Some((id, Err(e).map_err_into(SEK::MigrationError))),
+                }
+            })
+            .collect();
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum Token {
-    Key(String),
-    Index(usize),
-}
+        Ok(results)
+    }
 
-/**
- * Wrapper type around file header (TOML) object
- */
-impl EntryHeader {
+    fn migrate_entry(&self, registry: &MigrationRegistry, id: &StoreId, dry_run: bool)
+        -> Result<Option<MigrationReport>>
+    {
+        use semver::Version;
 
-    pub fn new() -> EntryHeader {
-        EntryHeader {
-            header: build_default_header()
+        let parse_version = |s: &str| {
+            Version::parse(s).map_err(|_| SE::new(SEK::MigrationError, None))
+        };
+
+        let current = try!(parse_version(version!()));
+        let module = try!(id.strip_prefix(&self.location)
+            .map_err_into(SEK::StorePathError)
+            .and_then(|rel| {
+                rel.components()
+                    .next()
+                    .and_then(|c| c.as_os_str().to_str())
+                    .map(String::from)
+                    .ok_or(SE::new(SEK::StorePathError, None))
+            }));
+
+        let mut entry = try!(self.retrieve_copy(id.clone()));
+        let old = match try!(entry.get_header().read("imag.version")) {
+            Some(Value::String(s)) => try!(parse_version(&s)),
+            _ => return Err(SE::new(SEK::HeaderTypeFailure, None)),
+        };
+
+        let mut new     = old.clone();
+        let mut applied = vec![];
+        for migration in registry.pending(&module, &old) {
+            debug!("Applying {:?} to {:?}", migration, id);
+            try!(migration.apply(&mut entry.header, &mut entry.content));
+            applied.push((migration.from().clone(), migration.to().clone()));
+            new = migration.to().clone();
         }
-    }
 
-    pub fn header(&self) -> &Value {
-        &self.header
-    }
+        if registry.latest(&module).map(|latest| new < *latest).unwrap_or(false) {
+            debug!("No migration of {:?} from {}", id, new);
+            return Err(SE::new(SEK::MigrationError, None));
+        }
 
-    fn from_table(t: Table) -> EntryHeader {
-        EntryHeader {
-            header: Value::Table(t)
+        if new < current {
+            new = current;
         }
-    }
 
-    pub fn parse(s: &str) -> EntryResult<EntryHeader> {
-        use toml::Parser;
+        if new == old {
+            return Ok(None);
+        }
 
-        let mut parser = Parser::new(s);
-        parser.parse()
-            .ok_or(ParserErrorKind::TOMLParserErrors.into())
-            .and_then(verify_header_consistency)
-            .map(EntryHeader::from_table)
-    }
+        try!(entry.get_header_mut().set("imag.version", Value::String(format!("{}", new))));
+        try!(entry.verify());
+        try!(self.check_schemas(entry.get_header()));
 
-    pub fn verify(&self) -> Result<()> {
-        match self.header {
-            Value::Table(ref t) => verify_header(&t),
-            _ => Err(SE::new(SEK::HeaderTypeFailure, None)),
+        if !dry_run {
+            let mut fle = try!(self.retrieve(id.clone()));
+            *fle.get_header_mut()  = entry.get_header().clone();
+            *fle.get_content_mut() = entry.get_content().clone();
+            try!(self.update(fle));
         }
+
+        Ok(Some(MigrationReport {
+            from: old,
+            to: new,
+            applied: applied,
+        }))
     }
 
-    /**
-     * Insert a header field by a string-spec
-     *
-     * ```ignore
-     *  insert("something.in.a.field", Boolean(true));
-     * ```
-     *
-     * If an array field was accessed which is _out of bounds_ of the array available, the element
-     * is appended to the array.
-     *
-     * Inserts a Boolean in the section "something" -> "in" -> "a" -> "field"
-     * A JSON equivalent would be
-     *
-     *  {
-     *      something: {
-     *          in: {
-     *              a: {
-     *                  field: true
-     *              }
-     *          }
-     *      }
-     *  }
-     *
-     * Returns true if header field was set, false if there is already a value
-     */
-    pub fn insert(&mut self, spec: &str, v: Value) -> Result<bool> {
-        self.insert_with_sep(spec, '.', v)
+    /// Whether the header path `path` is configured to be indexed, see `query_index()`
+    pub fn is_indexed(&self, path: &str) -> bool {
+        self.index
+            .read()
+            .map(|index| index.is_indexed(path))
+            .unwrap_or(false)
     }
 
-    pub fn insert_with_sep(&mut self, spec: &str, sep: char, v: Value) -> Result<bool> {
-        let tokens = match EntryHeader::tokenize(spec, sep) {
-            Err(e) => return Err(e),
-            Ok(t) => t
-        };
+    /// Get the entries which have `value` at the header path `path`
+    ///
+    /// `path` has to be configured to be indexed, see `index`. If `value` is an array, entries
+    /// which have any of its elements are returned. Entries found in the index are checked
+    /// against their header, stale index entries are fixed on the way.
+    pub fn query_index(&self, path: &str, value: &Value) -> Result<StoreIdIterator> {
+        let candidates = {
+            let index = try!(self.index
+                             .read()
+                             .map_err(|_| SE::new(SEK::LockPoisoned, None))
+                             .map_err_into(SEK::QueryIndexCallError));
+
+            if !index.is_indexed(path) {
+                return Err(SE::new(SEK::NotIndexed, None)).map_err_into(SEK::QueryIndexCallError);
+            }
 
-        let destination = match tokens.iter().last() {
-            None => return Err(SE::new(SEK::HeaderPathSyntaxError, None)),
-            Some(d) => d,
+            let mut candidates = vec![];
+            for key in IndexKey::of(value) {
+                for id in index.lookup(path, &key) {
+                    candidates.push((key.clone(), id));
+                }
+            }
+            candidates
         };
 
-        let path_to_dest = tokens[..(tokens.len() - 1)].into(); // N - 1 tokens
+        let mut ids : Vec<StoreId> = vec![];
+        for (key, id) in candidates {
+            let id = StoreId::from(id);
+            if ids.contains(&id) {
+                continue;
+            }
 
-        // walk N-1 tokens
-        let value = match EntryHeader::walk_header(&mut self.header, path_to_dest) {
-            Err(e) => return Err(e),
-            Ok(v) => v
-        };
+            if !self.backend.is_file(&id) {
+                debug!("Index is stale, {:?} does not exist anymore", id);
+                self.update_index(&id, None);
+                continue;
+            }
 
-        // There is already an value at this place
-        if EntryHeader::extract(value, destination).is_ok() {
-            return Ok(false);
+            match self.retrieve_copy(id.clone()) {
+                Ok(entry) => {
+                    let header = entry.get_header();
+                    if HeaderIndex::keys_in_header(header, path).contains(&key) {
+                        ids.push(id);
+                    } else {
+                        debug!("Index is stale, reindexing {:?}", id);
+                        self.update_index(&id, Some(header));
+                    }
+                },
+                // The entry is borrowed at the moment, so it is indexed when it is written
+                Err(_) => ids.push(id),
+            }
         }
 
-        match *destination {
-            Token::Key(ref s) => { // if the destination shall be an map key
-                match *value {
-                    /*
-                     * Put it in there if we have a map
-                     */
-                    Value::Table(ref mut t) => {
-                        t.insert(s.clone(), v);
+        Ok(StoreIdIterator::new(Box::new(ids.into_iter())))
+    }
+
+    /// Get the entries (of `module`, if given) which have `value` at the header path `path`
+    ///
+    /// Uses the index if `path` is indexed (see `query_index()`), otherwise the headers of all
+    /// entries are read. Values are compared like in the index, so an array matches if any of its
+    /// elements matches.
+    pub fn find_by_header(&self, module: Option<&str>, path: &str, value: &Value)
+        -> Result<StoreIdIterator>
+    {
+        let mut prefix = self.location.clone();
+        if let Some(module) = module {
+            prefix.push(module);
+        }
+
+        let entries = try!(self.entries_in(module).map_err_into(SEK::FindByHeaderCallError));
+        let mut ids = vec![];
+        let scanned = if self.is_indexed(path) {
+            debug!("Looking up '{}' in the index", path);
+            ids = try!(self.query_index(path, value).map_err_into(SEK::FindByHeaderCallError))
+                .filter(|id| id.starts_with(&prefix))
+                .collect();
+
+            // Encrypted entries are not in the index
+            entries.into_iter().filter(|&(_, ref id)| self.is_encrypted(id)).collect()
+        } else {
+            debug!("'{}' is not indexed, reading all headers", path);
+            entries
+        };
+
+        let keys = IndexKey::of(value);
+        for (_, id) in scanned {
+            match self.read_entry_header(&id) {
+                Ok(header) => {
+                    if HeaderIndex::keys_in_header(&header, path).iter().any(|k| keys.contains(k)) {
+                        ids.push(id);
                     }
+                },
+                Err(e) => {
+                    warn!("Cannot read header of {:?}, skipping it", id);
+                    debug!("{:?}", e);
+                },
+            }
+        }
 
-                    /*
-                     * Fail if there is no map here
-                     */
-                    _ => return Err(SE::new(SEK::HeaderPathTypeFailure, None)),
-                }
-            },
+        Ok(StoreIdIterator::new(Box::new(ids.into_iter())))
+    }
 
-            Token::Index(i) => { // if the destination shall be an array
-                match *value {
+    /// Throw away the index and build it from all entries of the store
+    pub fn rebuild_index(&self) -> Result<()> {
+        try!(self.check_writable().map_err_into(SEK::RebuildIndexCallError));
+        let _lock = try!(self.lock_store().map_err_into(SEK::RebuildIndexCallError));
+        let objects = try!(self.backend
+                           .walk(&self.location)
+                           .map_err_into(SEK::FileError)
+                           .map_err_into(SEK::RebuildIndexCallError));
+
+        let mut index = try!(self.index
+                             .write()
+                             .map_err(|_| SE::new(SEK::LockPoisoned, None))
+                             .map_err_into(SEK::RebuildIndexCallError));
+
+        index.clear();
+        for obj in objects {
+            let path = match obj {
+                BackendObject::File(ref p) if !self.is_internal_path(p) => p.clone(),
+                _ => continue,
+            };
 
-                    /*
-                     * Put it in there if we have an array
-                     */
-                    Value::Array(ref mut a) => {
-                        a.push(v); // push to the end of the array
+            if self.is_encrypted(&path) {
+                continue;
+            }
 
-                        // if the index is inside the array, we swap-remove the element at this
-                        // index
-                        if a.len() < i {
-                            a.swap_remove(i);
-                        }
-                    },
+            match StoreEntry::new(StoreId::from(path.clone())).get_entry(&*self.backend) {
+                Ok(entry) => {
+                    index.update(&path, entry.get_header());
+                },
+                Err(e) => {
+                    warn!("Cannot index {:?}, skipping it", path);
+                    debug!("{:?}", e);
+                },
+            }
+        }
 
-                    /*
-                     * Fail if there is no array here
-                     */
-                    _ => return Err(SE::new(SEK::HeaderPathTypeFailure, None)),
-                }
+        index.save(&*self.backend, &self.location)
+            .map_err_into(SEK::RebuildIndexCallError)
+    }
+
+    /// Update the index for `id`, `None` removes it from the index
+    ///
+    /// Encrypted entries are removed, too. The changes are written when the store is dropped. The
+    /// index is only a cache, so failing to update it is not an error.
+    fn update_index(&self, id: &StoreId, header: Option<&EntryHeader>) {
+        match self.index.write() {
+            Err(_) => warn!("Index lock poisoned, cannot update index for {:?}", id),
+            Ok(mut index) => {
+                match header {
+                    Some(header) if !self.is_encrypted(id) => index.update(id, header),
+                    _ => index.remove(id),
+                };
             },
         }
+    }
 
-        Ok(true)
+    fn rename_in_index(&self, old_id: &StoreId, new_id: &StoreId) {
+        match self.index.write() {
+            Err(_) => warn!("Index lock poisoned, cannot update index for {:?}", new_id),
+            Ok(mut index) => {
+                if self.is_encrypted(new_id) {
+                    index.remove(old_id);
+                } else {
+                    index.rename(old_id, new_id);
+                }
+            },
+        }
     }
 
-    /**
-     * Set a header field by a string-spec
-     *
-     * ```ignore
-     *  set("something.in.a.field", Boolean(true));
-     * ```
-     *
-     * Sets a Boolean in the section "something" -> "in" -> "a" -> "field"
-     * A JSON equivalent would be
-     *
-     *  {
-     *      something: {
-     *          in: {
-     *              a: {
-     *                  field: true
-     *              }
-     *          }
-     *      }
-     *  }
-     *
-     * If there is already a value at this place, this value will be overridden and the old value
-     * will be returned
-     */
-    pub fn set(&mut self, spec: &str, v: Value) -> Result<Option<Value>> {
-        self.set_with_sep(spec, '.', v)
+    /// Attach `data` to `entry` as `name`, replacing an attachment of the same name
+    ///
+    /// The data is written right away (unless the store has the same data already), the header of
+    /// the entry is written when `entry` is. The data is not encrypted, even if the entry is (see
+    /// `encryption`).
+    pub fn add_attachment(&self, entry: &mut FileLockEntry, name: &str, mime: &str, data: &[u8])
+        -> Result<Attachment>
+    {
+        if self.is_encrypted(entry.get_location()) {
+            warn!("Attachments are not encrypted, '{}' of {:?} is written in the clear",
+                  name, entry.get_location());
+        }
+
+        ::attachment::add(&*self.backend, &self.location, entry.get_header_mut(), name, mime, data)
+            .map_err_into(SEK::AddAttachmentCallError)
     }
 
-    pub fn set_with_sep(&mut self, spec: &str, sep: char, v: Value) -> Result<Option<Value>> {
-        let tokens = match EntryHeader::tokenize(spec, sep) {
-            Err(e) => return Err(e),
-            Ok(t) => t,
-        };
-        debug!("tokens = {:?}", tokens);
+    /// Get the attachments of `entry`
+    pub fn attachments(&self, entry: &Entry) -> Result<Vec<Attachment>> {
+        attachments_of(entry.get_header())
+    }
 
-        let destination = match tokens.iter().last() {
-            None => return Err(SE::new(SEK::HeaderPathSyntaxError, None)),
-            Some(d) => d
-        };
-        debug!("destination = {:?}", destination);
+    /// Read the attachment `name` of `entry`
+    ///
+    /// Fails with `AttachmentCorrupted` if the data does not match the hash in the header.
+    pub fn read_attachment(&self, entry: &Entry, name: &str) -> Result<Vec<u8>> {
+        ::attachment::read(&*self.backend, &self.location, entry.get_header(), name)
+            .map_err_into(SEK::ReadAttachmentCallError)
+    }
 
-        let path_to_dest = tokens[..(tokens.len() - 1)].into(); // N - 1 tokens
-        // walk N-1 tokens
-        let value = match EntryHeader::walk_header(&mut self.header, path_to_dest) {
-            Err(e) => return Err(e),
-            Ok(v) => v
-        };
-        debug!("walked value = {:?}", value);
+    /// Remove the attachment `name` from `entry`
+    ///
+    /// Returns whether there was such an attachment. The data stays in the store until
+    /// `Store::gc_attachments()` is called.
+    pub fn remove_attachment(&self, entry: &mut FileLockEntry, name: &str) -> Result<bool> {
+        ::attachment::remove(entry.get_header_mut(), name)
+            .map_err_into(SEK::RemoveAttachmentCallError)
+    }
 
-        match *destination {
-            Token::Key(ref s) => { // if the destination shall be an map key->value
-                match *value {
-                    /*
-                     * Put it in there if we have a map
-                     */
-                    Value::Table(ref mut t) => {
-                        debug!("Matched Key->Table");
-                        return Ok(t.insert(s.clone(), v));
-                    }
+    /// Remove the attachment data which no entry references anymore
+    ///
+    /// Returns the hashes of the removed data, nothing is removed with `dry_run`. Entries which
+    /// are borrowed by another process are waited for, as their header could be about to change.
+    /// Fails with `IdLocked` if this process borrows entries. Files which cannot be parsed are
+    /// skipped with `dry_run` and fail otherwise, as the data they refer to is not known (`gc()`
+    /// moves them to the lost+found directory).
+    pub fn gc_attachments(&self, dry_run: bool) -> Result<Vec<String>> {
+        if !dry_run {
+            try!(self.check_writable().map_err_into(SEK::GcAttachmentsCallError));
+        }
+        let _lock = try!(self.lock_store().map_err_into(SEK::GcAttachmentsCallError));
 
-                    /*
-                     * Fail if there is no map here
-                     */
-                    _ => {
-                        debug!("Matched Key->NON-Table");
-                        return Err(SE::new(SEK::HeaderPathTypeFailure, None));
-                    }
-                }
-            },
+        {
+            let entries = try!(self.entries
+                               .read()
+                               .map_err(|_| SE::new(SEK::LockPoisoned, None))
+                               .map_err_into(SEK::GcAttachmentsCallError));
+            if entries.values().any(|se| se.is_borrowed()) {
+                return Err(SE::new(SEK::IdLocked, None)).map_err_into(SEK::GcAttachmentsCallError);
+            }
+        }
 
-            Token::Index(i) => { // if the destination shall be an array
-                match *value {
+        let entries = try!(self.entries_in(None).map_err_into(SEK::GcAttachmentsCallError));
 
-                    /*
-                     * Put it in there if we have an array
-                     */
-                    Value::Array(ref mut a) => {
-                        debug!("Matched Index->Array");
-                        a.push(v); // push to the end of the array
+        let mut referenced = BTreeSet::new();
+        for (_, id) in entries {
+            let _entry_lock = try!(self.lock_entry(&id).map_err_into(SEK::GcAttachmentsCallError));
+            let attachments = StoreEntry::new(id.clone())
+                .get_entry(&*self.backend)
+                .and_then(|entry| attachments_of(entry.get_header()));
 
-                        // if the index is inside the array, we swap-remove the element at this
-                        // index
-                        if a.len() > i {
-                            debug!("Swap-Removing in Array {:?}[{:?}] <- {:?}", a, i, a[a.len()-1]);
-                            return Ok(Some(a.swap_remove(i)));
-                        }
+            match attachments {
+                Ok(attachments) => referenced.extend(attachments.into_iter().map(|a| a.hash)),
+                Err(e) => if dry_run {
+                    warn!("Cannot read the attachments of {:?}, skipping it", id);
+                    debug!("{:?}", e);
+                } else {
+                    return Err(e).map_err_into(SEK::GcAttachmentsCallError);
+                },
+            }
+        }
 
-                        debug!("Appended");
-                        return Ok(None);
-                    },
+        ::attachment::remove_unreferenced(&*self.backend, &self.location, &referenced, dry_run)
+            .map_err_into(SEK::GcAttachmentsCallError)
+    }
 
-                    /*
-                     * Fail if there is no array here
-                     */
-                    _ => {
-                        debug!("Matched Index->NON-Array");
-                        return Err(SE::new(SEK::HeaderPathTypeFailure, None));
-                    },
-                }
-            },
+    /// Find the directories without entries, the files which cannot be parsed and the unused
+    /// objects, see `gc`
+    ///
+    /// Unless `dry_run` is set, the directories and objects are removed and the files moved to the
+    /// lost+found directory of the store. Returns what was found.
+    pub fn gc(&self, dry_run: bool) -> Result<Vec<Garbage>> {
+        if !dry_run {
+            try!(self.check_writable().map_err_into(SEK::GcCallError));
+        }
+        let _lock = try!(self.lock_store().map_err_into(SEK::GcCallError));
+        let _objects_lock = try!(self.acquire_lock(&::object::objects_lock_path(&self.location),
+                                                   SEK::ObjectError)
+                                 .map_err_into(SEK::GcCallError));
+
+        let garbage = try!(::gc::find(&*self.backend, &self.location,
+                                      |id| self.read_entry_bytes(id))
+                           .map_err_into(SEK::GcCallError));
+        if dry_run {
+            return Ok(garbage);
         }
 
-        Ok(None)
+        ::gc::remove(&*self.backend, &self.location, &garbage)
+            .map(|_| garbage)
+            .map_err_into(SEK::GcCallError)
     }
 
-    /**
-     * Read a header field by a string-spec
-     *
-     * ```ignore
-     *  let value = read("something.in.a.field");
-     * ```
-     *
-     * Reads a Value in the section "something" -> "in" -> "a" -> "field"
-     * A JSON equivalent would be
-     *
-     *  {
-     *      something: {
-     *          in: {
-     *              a: {
-     *                  field: true
-     *              }
-     *          }
-     *      }
-     *  }
-     *
-     * If there is no a value at this place, None will be returned. This also holds true for Arrays
-     * which are accessed at an index which is not yet there, even if the accessed index is much
-     * larger than the array length.
-     */
-    pub fn read(&self, spec: &str) -> Result<Option<Value>> {
-        self.read_with_sep(spec, '.')
+    /// Check whether the entries `a` and `b` have the same content (header included)
+    ///
+    /// This is cheap with the object layer (see `object`), which only has to compare the trees
+    /// of the entries.
+    pub fn same_content<A: IntoStoreId, B: IntoStoreId>(&self, a: A, b: B) -> Result<bool> {
+        let a = a.into_storeid().storified(self);
+        let b = b.into_storeid().storified(self);
+
+        self.backend
+            .content_id(&a)
+            .and_then(|id_a| self.backend.content_id(&b).map(|id_b| id_a == id_b))
+            .map_err_into(SEK::FileError)
+            .map_err_into(SEK::SameContentCallError)
     }
 
-    pub fn read_with_sep(&self, spec: &str, splitchr: char) -> Result<Option<Value>> {
-        let tokens = match EntryHeader::tokenize(spec, splitchr) {
-            Err(e) => return Err(e),
-            Ok(t) => t,
-        };
+    /// Take a snapshot called `name` of the entries of `module`, or of all entries with `None`
+    ///
+    /// The entries are kept as objects (see `snapshot`), so entries which did not change since an
+    /// earlier snapshot cost nothing but a line in the manifest. Entries which are borrowed in
+    /// this process are recorded as they are on disk.
+    pub fn snapshot(&self, name: &str, module: Option<&str>) -> Result<Snapshot> {
+        try!(self.check_writable().map_err_into(SEK::SnapshotCallError));
+        let _lock   = try!(self.lock_store().map_err_into(SEK::SnapshotCallError));
+        let entries = try!(self.entries_in(module).map_err_into(SEK::SnapshotCallError));
+
+        ::snapshot::take(&*self.backend, &self.location, name, module, entries,
+                         |id| self.read_entry_bytes(id))
+            .map_err_into(SEK::SnapshotCallError)
+    }
 
-        let mut header_clone = self.header.clone(); // we clone as READing is simpler this way
-        // walk N-1 tokens
-        match EntryHeader::walk_header(&mut header_clone, tokens) {
-            Err(e) => match e.err_type() {
-                // We cannot find the header key, as there is no path to it
-                SEK::HeaderKeyNotFound => Ok(None),
-                _ => Err(e),
-            },
-            Ok(v) => Ok(Some(v.clone())),
+    /// Get all snapshots of the store, oldest first
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>> {
+        ::snapshot::load_all(&*self.backend, &self.location).map_err_into(SEK::SnapshotsCallError)
+    }
+
+    /// Get the differences between the store and the snapshot `name`
+    ///
+    /// Only the entries the snapshot was taken of are compared, so for a snapshot of a module,
+    /// entries of other modules are not reported.
+    pub fn diff_snapshot(&self, name: &str) -> Result<Vec<SnapshotChange>> {
+        ::snapshot::load(&*self.backend, &self.location, name)
+            .and_then(|snapshot| self.snapshot_changes(&snapshot))
+            .map_err_into(SEK::DiffSnapshotCallError)
+    }
+
+    /// Restore the entries at or below `path` (relative to the store, a module or a single entry,
+    /// with or without its version) to their state in the snapshot `name`, or all entries of the
+    /// snapshot with `None`
+    ///
+    /// Entries which were created after the snapshot was taken are deleted. The restored entries
+    /// are written like any other change, so their current state is kept as a revision. Returns
+    /// the changes which were undone. Fails with `SnapshotScopeError` if the snapshot was not
+    /// taken of `path`.
+    pub fn restore_snapshot(&self, name: &str, path: Option<&str>) -> Result<Vec<SnapshotChange>> {
+        use snapshot::{overlaps, is_at_or_below, restore};
+
+        try!(self.check_writable().map_err_into(SEK::RestoreSnapshotCallError));
+        let _lock = try!(self.lock_store().map_err_into(SEK::RestoreSnapshotCallError));
+        let snapshot = try!(::snapshot::load(&*self.backend, &self.location, name)
+                            .map_err_into(SEK::RestoreSnapshotCallError));
+
+        if path.map(|p| !overlaps(&snapshot, Path::new(p))).unwrap_or(false) {
+            return Err(SE::new(SEK::SnapshotScopeError, None))
+                .map_err_into(SEK::RestoreSnapshotCallError);
         }
+
+        let changes = try!(self.snapshot_changes(&snapshot)
+                           .map_err_into(SEK::RestoreSnapshotCallError))
+            .into_iter()
+            .filter(|change| {
+                let id = match *change {
+                    SnapshotChange::Added(ref id)    => id,
+                    SnapshotChange::Removed(ref id)  => id,
+                    SnapshotChange::Modified(ref id) => id,
+                };
+                match (path, id.strip_prefix(&self.location)) {
+                    (None, _)            => true,
+                    (Some(p), Ok(rel))   => is_at_or_below(rel, Path::new(p)),
+                    (Some(_), Err(_))    => false,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        restore(self, &*self.backend, &snapshot, &changes)
+            .map(|_| changes)
+            .map_err_into(SEK::RestoreSnapshotCallError)
     }
 
-    pub fn delete(&mut self, spec: &str) -> Result<Option<Value>> {
-        let tokens = match EntryHeader::tokenize(spec, '.') {
-            Err(e) => return Err(e),
-            Ok(t) => t
-        };
+    /// Write the entries of `modules` (all entries if `modules` is empty) and their attachments
+    /// to `out`, as an archive which can be imported into another store (see `archive`)
+    ///
+    /// Returns the manifest of the archive. Encrypted entries are written to it in the clear.
+    pub fn export<W: Write>(&self, modules: &[&str], out: W) -> Result<Manifest> {
+        let _lock = try!(self.lock_store().map_err_into(SEK::ExportCallError));
 
-        let destination = match tokens.iter().last() {
-            None => return Err(SE::new(SEK::HeaderPathSyntaxError, None)),
-            Some(d) => d
+        let ids = if modules.is_empty() {
+            try!(self.entries_in(None).map_err_into(SEK::ExportCallError))
+        } else {
+            let mut ids = BTreeMap::new();
+            for module in modules {
+                let found = try!(self.entries_in(Some(*module)).map_err_into(SEK::ExportCallError));
+                ids.extend(found.into_iter());
+            }
+            ids
         };
-        debug!("destination = {:?}", destination);
+        if ids.values().any(|id| self.is_encrypted(id)) {
+            warn!("The archive is not encrypted, encrypted entries are written to it in the clear");
+        }
 
-        let path_to_dest = tokens[..(tokens.len() - 1)].into(); // N - 1 tokens
-        // walk N-1 tokens
-        let mut value = match EntryHeader::walk_header(&mut self.header, path_to_dest) {
-            Err(e) => return Err(e),
-            Ok(v) => v
-        };
-        debug!("walked value = {:?}", value);
+        ::archive::export(&*self.backend, &self.location, ids, |id| self.read_entry_bytes(id), out)
+            .map_err_into(SEK::ExportCallError)
+    }
 
-        match *destination {
-            Token::Key(ref s) => { // if the destination shall be an map key->value
-                match *value {
-                    Value::Table(ref mut t) => {
-                        debug!("Matched Key->Table, removing {:?}", s);
-                        return Ok(t.remove(s));
-                    },
-                    _ => {
-                        debug!("Matched Key->NON-Table");
-                        return Err(SE::new(SEK::HeaderPathTypeFailure, None));
-                    }
-                }
-            },
+    /// Merge the archive from `input` (see `export()`) into the store
+    ///
+    /// Entries which exist in the store with another content are handled as told by `strategy`.
+    /// The whole archive is checked before anything is written, so nothing is imported from a
+    /// corrupted archive. Imported entries are created through the store, so the hooks run for
+    /// them.
+    pub fn import<R: Read>(&self, input: R, strategy: ConflictStrategy)
+        -> Result<Vec<ImportOutcome>>
+    {
+        try!(self.check_writable().map_err_into(SEK::ImportCallError));
+        let import = try!(::archive::check(&self.location, input)
+                          .map_err_into(SEK::ImportCallError));
 
-            Token::Index(i) => { // if the destination shall be an array
-                match *value {
-                    Value::Array(ref mut a) => {
-                        // if the index is inside the array, we swap-remove the element at this
-                        // index
-                        if a.len() > i {
-                            debug!("Removing in Array {:?}[{:?}]", a, i);
-                            return Ok(Some(a.remove(i)));
-                        } else {
-                            return Ok(None);
-                        }
-                    },
-                    _ => {
-                        debug!("Matched Index->NON-Array");
-                        return Err(SE::new(SEK::HeaderPathTypeFailure, None));
-                    },
-                }
-            },
+        let _lock = try!(self.lock_store().map_err_into(SEK::ImportCallError));
+        ::archive::merge(self, &*self.backend, import, strategy, |id| self.read_entry_bytes(id))
+            .map_err_into(SEK::ImportCallError)
+    }
+
+    /// Compare the entries `snapshot` was taken of with their current state
+    fn snapshot_changes(&self, snapshot: &Snapshot) -> Result<Vec<SnapshotChange>> {
+        let module  = snapshot.module.as_ref().map(|m| &m[..]);
+        let current = try!(self.entries_in(module));
+        ::snapshot::changes(snapshot, &self.location, &current, |id| self.read_entry_bytes(id))
+    }
+
+    /// Get the entries of `module` (all entries with `None`), by their path inside the store
+    fn entries_in(&self, module: Option<&str>) -> Result<BTreeMap<PathBuf, StoreId>> {
+        let mut dir = self.location.clone();
+        if let Some(module) = module {
+            dir.push(module);
+        }
+        if !self.backend.exists(&dir) {
+            return Ok(BTreeMap::new());
         }
 
-        Ok(None)
+        let objects = try!(self.backend.walk(&dir).map_err_into(SEK::FileError));
+        let mut entries = BTreeMap::new();
+        for obj in objects {
+            let path = match obj {
+                BackendObject::File(ref p) if !self.is_internal_path(p) => p.clone(),
+                _ => continue,
+            };
+            if is_staged(&path) {
+                continue;
+            }
+
+            let rel = try!(path.strip_prefix(&self.location)
+                           .map(|rel| rel.to_path_buf())
+                           .map_err_into(SEK::StorePathError));
+            entries.insert(rel, StoreId::from(path));
+        }
+        Ok(entries)
     }
 
-    fn tokenize(spec: &str, splitchr: char) -> Result<Vec<Token>> {
-        use std::str::FromStr;
+    /// Read the raw content of the entry `id` from the backend
+    ///
+    /// The OS lock of the entry is held while reading, unless this process borrows the entry (in
+    /// which case the content on disk is read).
+    fn read_entry_bytes(&self, id: &StoreId) -> Result<Vec<u8>> {
+        let _lock = try!(self.lock_entry_unless_borrowed(id));
+        self.backend.read(id).map_err_into(SEK::FileError)
+    }
 
-        spec.split(splitchr)
-            .map(|s| {
-                usize::from_str(s)
-                    .map(Token::Index)
-                    .or_else(|_| Ok(Token::Key(String::from(s))))
-            })
-            .collect()
+    /// Read the header of the entry `id` from the backend, stopping at its end
+    ///
+    /// Locks like `read_entry_bytes()`.
+    fn read_entry_header(&self, id: &StoreId) -> Result<EntryHeader> {
+        use std::io::{BufRead, BufReader};
+
+        let _lock = try!(self.lock_entry_unless_borrowed(id));
+        let file  = try!(self.backend.open(id).map_err_into(SEK::FileError));
+
+        let mut header    = String::new();
+        let mut in_header = false;
+        for line in BufReader::new(file).lines() {
+            let line = try!(line.map_err_into(SEK::IoError));
+            match (in_header, &line[..]) {
+                (false, "---") => in_header = true,
+                (false, _)     => break,
+                (true, "---")  => return EntryHeader::parse(&header[..]).map_err(SE::from),
+                (true, _)      => {
+                    header.push_str(&line[..]);
+                    header.push('\n');
+                },
+            }
+        }
+        Err(SE::new(SEK::MalformedEntry, None))
     }
 
-    fn walk_header(v: &mut Value, tokens: Vec<Token>) -> Result<&mut Value> {
-        use std::vec::IntoIter;
+    /// Take the OS lock of the entry `id`, unless this process borrows the entry
+    fn lock_entry_unless_borrowed(&self, id: &StoreId) -> Result<Option<Box<BackendLock>>> {
+        let borrowed = try!(self.entries
+                            .read()
+                            .map_err(|_| SE::new(SEK::LockPoisoned, None)))
+            .get(id)
+            .map(|se| se.is_borrowed())
+            .unwrap_or(false);
+
+        if borrowed {
+            Ok(None)
+        } else {
+            self.lock_entry(id).map(Some)
+        }
+    }
 
-        fn walk_iter<'a>(v: Result<&'a mut Value>, i: &mut IntoIter<Token>) -> Result<&'a mut Value> {
-            let next = i.next();
-            v.and_then(move |value| {
-                if let Some(token) = next {
-                    walk_iter(EntryHeader::extract(value, &token), i)
-                } else {
-                    Ok(value)
-                }
+    /// Get the full-text index, so hooks can update it
+    ///
+    /// The index is written to disk when the store is dropped.
+    pub fn search_index(&self) -> Arc<Mutex<SearchIndex>> {
+        self.search_index.clone()
+    }
+
+    /// Search the content of all entries, see `search` for the query syntax
+    ///
+    /// Hits are checked against the current content of the entry, entries which do not match
+    /// anymore are reindexed on the way. Entries which are missing from the index are not found,
+    /// see `rebuild_search_index()`.
+    pub fn search(&self, query: &str) -> Result<Vec<SearchHit>> {
+        ::search::search(&self.search_index, query, |id| self.read_entry_content(id))
+            .map_err_into(SEK::SearchCallError)
+    }
+
+    /// Throw away the full-text index and build it from all entries of the store
+    pub fn rebuild_search_index(&self) -> Result<()> {
+        try!(self.check_writable().map_err_into(SEK::RebuildSearchIndexCallError));
+        let _lock = try!(self.lock_store().map_err_into(SEK::RebuildSearchIndexCallError));
+        let objects = try!(self.backend
+                           .walk(&self.location)
+                           .map_err_into(SEK::FileError)
+                           .map_err_into(SEK::RebuildSearchIndexCallError));
+        let ids = objects.into_iter()
+            .filter_map(|obj| match obj {
+                BackendObject::File(p) => Some(p),
+                BackendObject::Directory(_) => None,
             })
+            .filter(|p| !self.is_internal_path(p) && !self.is_encrypted(p))
+            .map(StoreId::from)
+            .collect();
+
+        let mut index = try!(self.search_index
+                             .lock()
+                             .map_err(|_| SE::new(SEK::LockPoisoned, None))
+                             .map_err_into(SEK::RebuildSearchIndexCallError));
+
+        ::search::rebuild(&mut index, ids, |id| self.read_entry_content(id));
+        index.save(&*self.backend, &self.location)
+            .map_err_into(SEK::RebuildSearchIndexCallError)
+    }
+
+    /// Read the content of the entry `id` from the backend, without locking it
+    fn read_entry_content(&self, id: &StoreId) -> Result<String> {
+        StoreEntry::new(id.clone())
+            .get_entry(&*self.backend)
+            .map(|entry| entry.get_content().clone())
+    }
+
+    /// Index `content` as the content of `id` in the full-text index
+    ///
+    /// The index is kept up to date by the hooks in `libimagstorestdhook::search`, but dropping a
+    /// `FileLockEntry` writes it without executing hooks, so every write is indexed here. The
+    /// changes are written when the store is dropped. The index is only a cache, so failing to
+    /// update it is not an error.
+    fn update_search_index(&self, id: &StoreId, content: &str) {
+        use configuration::is_search_index_enabled;
+
+        if !is_search_index_enabled(&self.configuration) {
+            return;
         }
 
-        walk_iter(Ok(v), &mut tokens.into_iter())
+        match self.search_index.lock() {
+            Err(_) => warn!("Search index lock poisoned, cannot update it for {:?}", id),
+            Ok(mut index) => index.index(id, content),
+        }
     }
 
-    fn extract_from_table<'a>(v: &'a mut Value, s: &str) -> Result<&'a mut Value> {
-        match *v {
-            Value::Table(ref mut t) => {
-                t.get_mut(&s[..])
-                    .ok_or(SE::new(SEK::HeaderKeyNotFound, None))
+    /// Get the changes to the store at or after `since` (in seconds since the UNIX epoch), oldest
+    /// first
+    ///
+    /// See `changelog` for what is recorded.
+    pub fn changes_since(&self, since: u64) -> Result<Vec<ChangeEvent>> {
+        self.acquire_lock(&self.changelog_lock_path(), SEK::ChangelogError)
+            .and_then(|_lock| ::changelog::read(&*self.backend, &self.location, since))
+            .map_err_into(SEK::ChangesSinceCallError)
+    }
+
+    /// Get the changes to the store which were recorded after `pos`, oldest first, and the
+    /// position to continue from
+    ///
+    /// This only reads what was appended to the change log since, so it is meant for programs
+    /// which poll the store for changes. Start with `changelog::Position::default()`.
+    pub fn changes_from(&self, pos: &ChangelogPosition)
+        -> Result<(Vec<ChangeEvent>, ChangelogPosition)>
+    {
+        self.acquire_lock(&self.changelog_lock_path(), SEK::ChangelogError)
+            .and_then(|_lock| ::changelog::read_from(&*self.backend, &self.location, pos))
+            .map_err_into(SEK::ChangesFromCallError)
+    }
+
+    /// Remember the content id the entry `id` has now, if the store is watched
+    fn note_own_change(&self, id: &StoreId) {
+        match self.own_changes.lock() {
+            Err(_) => warn!("Lock poisoned, cannot note change of {:?} for watching", id),
+            Ok(mut own) => if let Some(ref mut own) = *own {
+                own.insert(id.clone(), self.backend.content_id(id).ok());
             },
-            _ => Err(SE::new(SEK::HeaderPathTypeFailure, None)),
         }
     }
 
-    fn extract_from_array(v: &mut Value, i: usize) -> Result<&mut Value> {
-        match *v {
-            Value::Array(ref mut a) => {
-                if a.len() < i {
-                    Err(SE::new(SEK::HeaderKeyNotFound, None))
-                } else {
-                    Ok(&mut a[i])
-                }
+    /// The lock which is held while the change log is appended to or read
+    fn changelog_lock_path(&self) -> PathBuf {
+        let mut lock_path = self.location.clone();
+        lock_path.push(LOCKS_DIR);
+        lock_path.push(::changelog::CHANGELOG_FILE);
+        lock_path
+    }
+
+    /// Watch the entries of `module` for changes which were not made through the store
+    ///
+    /// The returned `Watch` blocks until the next change. From now on, the store remembers the
+    /// content it wrote to entries, so changes which leave an entry as this store wrote it are
+    /// not reported. Only backends which can be changed from the outside support watching.
+    pub fn watch<'a>(&'a self, module: &str) -> Result<Watch<'a>> {
+        let mut path = self.location.clone();
+        path.push(module);
+
+        match self.own_changes.lock() {
+            Err(_) => return Err(SE::new(SEK::LockPoisoned, None)),
+            Ok(mut own) => if own.is_none() {
+                *own = Some(BTreeMap::new());
             },
-            _ => Err(SE::new(SEK::HeaderPathTypeFailure, None)),
         }
+
+        self.backend
+            .create_dir_all(&path)
+            .and_then(|_| self.backend.watch(&path))
+            .map(|watcher| Watch {
+                store: self,
+                watcher: watcher,
+                queue: VecDeque::new(),
+            })
+            .map_err_into(SEK::WatchError)
+            .map_err_into(SEK::WatchCallError)
     }
 
-    fn extract<'a>(v: &'a mut Value, token: &Token) -> Result<&'a mut Value> {
-        match *token {
-            Token::Key(ref s)  => EntryHeader::extract_from_table(v, s),
-            Token::Index(i)    => EntryHeader::extract_from_array(v, i),
+    /// Record a change this store made: note it for `Watch` and append an event to the change
+    /// log, if it is enabled
+    ///
+    /// Failing to do so is not fatal for the operation which caused the change, so errors are
+    /// only logged.
+    fn record_change(&self, op: Operation, id: &StoreId, new_id: Option<&StoreId>) {
+        use configuration::{is_changelog_enabled, get_changelog_max_size};
+
+        self.note_own_change(id);
+        if let Some(new_id) = new_id {
+            self.note_own_change(new_id);
+        }
+
+        if !is_changelog_enabled(&self.configuration) {
+            return;
+        }
+
+        let event = ChangeEvent::new(op, id.clone(), new_id.cloned(), self.origin.clone());
+        let res = self.acquire_lock(&self.changelog_lock_path(), SEK::ChangelogError)
+            .and_then(|_lock| {
+                let max_size = get_changelog_max_size(&self.configuration);
+                ::changelog::append(&*self.backend, &self.location, &event, max_size)
+            });
+
+        if let Err(e) = res {
+            warn!("Could not record change in change log: {}", event);
+            debug!("{:?}", e);
         }
     }
 
-}
+    /// Take the OS lock of the entry `id`
+    ///
+    /// If another process holds it, this waits as configured with `lock-timeout` (see
+    /// `configuration::get_lock_timeout()`) and fails with `EntryLockedByOtherProcess` afterwards.
+    fn lock_entry(&self, id: &StoreId) -> Result<Box<BackendLock>> {
+        let rel = try!(id.strip_prefix(&self.location)
+                       .map_err(|_| SE::new(SEK::StorePathError, None)));
+        let mut path = self.location.clone();
+        path.push(LOCKS_DIR);
+        path.push(rel);
+        self.acquire_lock(&path, SEK::EntryLockedByOtherProcess)
+    }
 
-impl Into<Table> for EntryHeader {
+    /// Take the store-wide lock, which is held during moves and operations on the whole store
+    ///
+    /// Fails with `StoreLockedByOtherProcess` if another process holds it longer than the
+    /// configured `lock-timeout`.
+    fn lock_store(&self) -> Result<Box<BackendLock>> {
+        let mut path = self.location.clone();
+        path.push(LOCKS_DIR);
+        path.push(STORE_LOCK_FILE);
+        self.acquire_lock(&path, SEK::StoreLockedByOtherProcess)
+    }
 
-    fn into(self) -> Table {
-        match self.header {
-            Value::Table(t) => t,
-            _ => panic!("EntryHeader is not a table!"),
+    /// Fail with `StoreReadOnly` if the store was opened with `Store::new_read_only()`
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(SE::new(SEK::StoreReadOnly, None))
+        } else {
+            Ok(())
         }
     }
 
-}
+    /// Take the OS lock at `path`
+    ///
+    /// Read-only stores do not take OS locks, as the lock files cannot be created on read-only
+    /// file systems.
+    fn acquire_lock(&self, path: &Path, kind: SEK) -> Result<Box<BackendLock>> {
+        use std::thread::sleep;
+        use std::time::{Duration, Instant};
+        use configuration::get_lock_timeout;
+
+        if self.read_only {
+            debug!("Not locking {:?} in read-only store", path);
+            return Ok(Box::new(NoLock));
+        }
 
-impl From<Table> for EntryHeader {
+        let timeout = get_lock_timeout(&self.configuration);
+        let start   = Instant::now();
+        let mut waiting = false;
+
+        loop {
+            match self.backend.try_lock(path) {
+                Ok(Some(lock)) => return Ok(lock),
+                Ok(None) => {
+                    if timeout.map(|t| start.elapsed() >= t).unwrap_or(false) {
+                        debug!("Giving up waiting for lock {:?}", path);
+                        return Err(kind.into_error());
+                    }
+                    if !waiting {
+                        info!("{:?} is locked by another process, waiting", path);
+                        waiting = true;
+                    }
+                    sleep(Duration::from_millis(LOCK_POLL_INTERVAL_MS));
+                },
+                Err(e) => return Err(SEK::LockError.into_error_with_cause(Box::new(e))),
+            }
+        }
+    }
 
-    fn from(t: Table) -> EntryHeader {
-        EntryHeader { header: Value::Table(t) }
+    /// Gets the path where this store is on the disk
+    pub fn path(&self) -> &PathBuf {
+        &self.location
     }
 
-}
+    pub fn register_hook(&mut self,
+                         position: HookPosition,
+                         aspect_name: &str,
+                         mut h: Box<Hook>)
+        -> Result<()>
+    {
+        debug!("Registering hook: {:?}", h);
+        debug!("     in position: {:?}", position);
+        debug!("     with aspect: {:?}", aspect_name);
 
-fn build_default_header() -> Value { // BTreeMap<String, Value>
-    let mut m = BTreeMap::new();
+        let guard = match position {
+                HookPosition::StoreUnload  => self.store_unload_aspects.clone(),
 
-    m.insert(String::from("imag"), {
-        let mut imag_map = BTreeMap::<String, Value>::new();
+                HookPosition::PreCreate    => self.pre_create_aspects.clone(),
+                HookPosition::PostCreate   => self.post_create_aspects.clone(),
+                HookPosition::PreRetrieve  => self.pre_retrieve_aspects.clone(),
+                HookPosition::PostRetrieve => self.post_retrieve_aspects.clone(),
+                HookPosition::PreUpdate    => self.pre_update_aspects.clone(),
+                HookPosition::PostUpdate   => self.post_update_aspects.clone(),
+                HookPosition::PreDelete    => self.pre_delete_aspects.clone(),
+                HookPosition::PostDelete   => self.post_delete_aspects.clone(),
+                HookPosition::PreMove      => self.pre_move_aspects.clone(),
+                HookPosition::PostMove     => self.post_move_aspects.clone(),
+            };
 
-        imag_map.insert(String::from("version"), Value::String(String::from(version!())));
-        imag_map.insert(String::from("links"), Value::Array(vec![]));
+        let mut guard = match guard.deref().lock().map_err(|_| SE::new(SEK::LockError, None)) {
+            Err(e) => return Err(SEK::HookRegisterError.into_error_with_cause(Box::new(e))),
+            Ok(g) => g,
+        };
 
-        Value::Table(imag_map)
-    });
+        for mut aspect in guard.deref_mut() {
+            if aspect.name().clone() == aspect_name.clone() {
+                debug!("Trying to find configuration for hook: {:?}", h);
+                self.get_config_for_hook(h.name()).map(|config| h.set_config(config));
+                debug!("Trying to register hook in aspect: {:?} <- {:?}", aspect, h);
+                aspect.register_hook(h);
+                return Ok(());
+            }
+        }
 
-    Value::Table(m)
-}
-fn verify_header(t: &Table) -> Result<()> {
-    if !has_main_section(t) {
-        Err(SE::from(ParserErrorKind::MissingMainSection.into_error()))
-    } else if !has_imag_version_in_main_section(t) {
-        Err(SE::from(ParserErrorKind::MissingVersionInfo.into_error()))
-    } else if !has_only_tables(t) {
-        debug!("Could not verify that it only has tables in its base table");
-        Err(SE::from(ParserErrorKind::NonTableInBaseTable.into_error()))
-    } else {
-        Ok(())
+        let annfe = SEK::AspectNameNotFoundError.into_error();
+        Err(SEK::HookRegisterError.into_error_with_cause(Box::new(annfe)))
     }
-}
 
-fn verify_header_consistency(t: Table) -> EntryResult<Table> {
-    verify_header(&t)
-        .map_err(Box::new)
-        .map_err(|e| ParserErrorKind::HeaderInconsistency.into_error_with_cause(e))
-        .map(|_| t)
-}
+    fn get_config_for_hook(&self, name: &str) -> Option<&Value> {
+        match self.configuration {
+            Some(Value::Table(ref tabl)) => {
+                debug!("Trying to head 'hooks' section from {:?}", tabl);
+                tabl.get("hooks")
+                    .map(|hook_section| {
+                        debug!("Found hook section:  {:?}", hook_section);
+                        debug!("Reading section key: {:?}", name);
+                        match *hook_section {
+                            Value::Table(ref tabl) => tabl.get(name),
+                            _ => None
+                        }
+                    })
+                    .unwrap_or(None)
+            },
+            _ => None,
+        }
+    }
 
-fn has_only_tables(t: &Table) -> bool {
-    debug!("Verifying that table has only tables");
-    t.iter().all(|(_, x)| if let Value::Table(_) = *x { true } else { false })
-}
+    fn execute_hooks_for_id(&self,
+                            aspects: Arc<Mutex<Vec<Aspect>>>,
+                            id: &StoreId)
+        -> HookResult<()>
+    {
+        match aspects.lock() {
+            Err(_) => return Err(HookErrorKind::HookExecutionError.into()),
+            Ok(g) => g
+        }.iter().fold_defresult(|aspect| {
+            debug!("[Aspect][exec]: {:?}", aspect);
+            (aspect as &StoreIdAccessor).access(id)
+        }).map_err(Box::new)
+            .map_err(|e| HookErrorKind::HookExecutionError.into_error_with_cause(e))
+    }
 
-fn has_main_section(t: &Table) -> bool {
-    t.contains_key("imag") &&
-        match t.get("imag") {
-            Some(&Value::Table(_)) => true,
-            Some(_)                => false,
-            None                   => false,
-        }
-}
+    fn execute_hooks_for_move(&self,
+                              aspects: Arc<Mutex<Vec<Aspect>>>,
+                              old_id: &StoreId,
+                              new_id: &StoreId)
+        -> HookResult<()>
+    {
+        match aspects.lock() {
+            Err(_) => return Err(HookErrorKind::HookExecutionError.into()),
+            Ok(g) => g
+        }.iter().fold_defresult(|aspect| {
+            debug!("[Aspect][exec]: {:?}", aspect);
+            aspect.access_move(old_id, new_id)
+        }).map_err(Box::new)
+            .map_err(|e| HookErrorKind::HookExecutionError.into_error_with_cause(e))
+    }
+
+    fn execute_hooks_for_mut_file(&self,
+                                  aspects: Arc<Mutex<Vec<Aspect>>>,
+                                  fle: &mut FileLockEntry)
+        -> HookResult<()>
+    {
+        match aspects.lock() {
+            Err(_) => return Err(HookErrorKind::HookExecutionError.into()),
+            Ok(g) => g
+        }.iter().fold_defresult(|aspect| {
+            debug!("[Aspect][exec]: {:?}", aspect);
+            aspect.access_mut(fle)
+        }).map_err(Box::new)
+            .map_err(|e| HookErrorKind::HookExecutionError.into_error_with_cause(e))
+    }
+
+}
+
+impl Debug for Store {
+
+    fn fmt(&self, fmt: &mut Formatter) -> RResult<(), FMTError> {
+        try!(write!(fmt, " --- Store ---\n"));
+        try!(write!(fmt, "\n"));
+        try!(write!(fmt, " - location               : {:?}\n", self.location));
+        try!(write!(fmt, " - backend                : {:?}\n", self.backend));
+        try!(write!(fmt, " - configuration          : {:?}\n", self.configuration));
+        try!(write!(fmt, " - pre_create_aspects     : {:?}\n", self.pre_create_aspects    ));
+        try!(write!(fmt, " - post_create_aspects    : {:?}\n", self.post_create_aspects   ));
+        try!(write!(fmt, " - pre_retrieve_aspects   : {:?}\n", self.pre_retrieve_aspects  ));
+        try!(write!(fmt, " - post_retrieve_aspects  : {:?}\n", self.post_retrieve_aspects ));
+        try!(write!(fmt, " - pre_update_aspects     : {:?}\n", self.pre_update_aspects    ));
+        try!(write!(fmt, " - post_update_aspects    : {:?}\n", self.post_update_aspects   ));
+        try!(write!(fmt, " - pre_delete_aspects     : {:?}\n", self.pre_delete_aspects    ));
+        try!(write!(fmt, " - post_delete_aspects    : {:?}\n", self.post_delete_aspects   ));
+        try!(write!(fmt, " - pre_move_aspects       : {:?}\n", self.pre_move_aspects      ));
+        try!(write!(fmt, " - post_move_aspects      : {:?}\n", self.post_move_aspects     ));
+        try!(write!(fmt, " - schemas                : {:?}\n", self.schemas               ));
+        try!(write!(fmt, "\n"));
+        try!(write!(fmt, "Entries:\n"));
+        try!(write!(fmt, "{:?}", self.entries));
+        try!(write!(fmt, "\n"));
+        Ok(())
+    }
+
+}
+
+impl Drop for Store {
+
+    /**
+     * Unlock all files on drop
+     *
+     * TODO: Unlock them
+     */
+    fn drop(&mut self) {
+        use configuration::is_search_index_enabled;
+
+        if self.read_only {
+            debug!("Dropping read-only store");
+            return;
+        }
+
+        let store_id = StoreId::from(self.location.clone());
+        if let Err(e) = self.execute_hooks_for_id(self.store_unload_aspects.clone(), &store_id) {
+            debug!("Store-load hooks execution failed. Cannot create store object.");
+            warn!("Store Unload Hook error: {:?}", e);
+        }
+
+        // Other processes may have changed the indexes on disk, the changes of this one are
+        // merged into them. Hooks may update the search index even if it is disabled, it is not
+        // written then.
+        let index_changed = self.index
+            .read()
+            .map(|index| index.has_changes())
+            .unwrap_or(false);
+        let search_changed = is_search_index_enabled(&self.configuration) &&
+            self.search_index
+                .lock()
+                .map(|index| index.has_changes())
+                .unwrap_or(false);
+        if index_changed || search_changed {
+            match self.lock_store() {
+                Err(e) => {
+                    warn!("Could not lock the store to write the indexes");
+                    debug!("{:?}", e);
+                },
+                Ok(_lock) => {
+                    let saved = self.index
+                        .write()
+                        .map_err(|_| SE::new(SEK::LockPoisoned, None))
+                        .and_then(|mut index| index.save(&*self.backend, &self.location));
+                    if let Err(e) = saved {
+                        warn!("Could not write index");
+                        debug!("{:?}", e);
+                    }
+
+                    if search_changed {
+                        let saved = self.search_index
+                            .lock()
+                            .map_err(|_| SE::new(SEK::LockPoisoned, None))
+                            .and_then(|mut index| index.save(&*self.backend, &self.location));
+                        if let Err(e) = saved {
+                            warn!("Could not write search index");
+                            debug!("{:?}", e);
+                        }
+                    }
+                },
+            }
+        }
+
+        debug!("Dropping store");
+    }
+
+}
+
+/// A struct that allows you to borrow an Entry
+#[derive(Debug)]
+pub struct FileLockEntry<'a> {
+    store: &'a Store,
+    entry: Entry,
+}
+
+impl<'a> FileLockEntry<'a, > {
+    fn new(store: &'a Store, entry: Entry) -> FileLockEntry<'a> {
+        FileLockEntry {
+            store: store,
+            entry: entry,
+        }
+    }
+}
+
+impl<'a> Deref for FileLockEntry<'a> {
+    type Target = Entry;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entry
+    }
+}
+
+impl<'a> DerefMut for FileLockEntry<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entry
+    }
+}
+
+impl<'a> Drop for FileLockEntry<'a> {
+    /// This will silently ignore errors, use `Store::update` if you want to catch the errors
+    fn drop(&mut self) {
+        let _ = self.store._update(self, false);
+    }
+}
+
+/// A read-only handle to an Entry, see `Store::get_read_only()`
+///
+/// Unlike `FileLockEntry`, the entry cannot be modified through it and is never written back.
+#[derive(Debug)]
+pub struct ReadOnlyEntry {
+    entry: Entry,
+}
+
+impl ReadOnlyEntry {
+    fn new(entry: Entry) -> ReadOnlyEntry {
+        ReadOnlyEntry {
+            entry: entry,
+        }
+    }
+
+    /// Get an owned copy of the entry
+    pub fn into_entry(self) -> Entry {
+        self.entry
+    }
+}
+
+impl Deref for ReadOnlyEntry {
+    type Target = Entry;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entry
+    }
+}
+
+/// A set of changes to the store which is committed all-or-nothing
+///
+/// Entries obtained from `Store::create()` or `Store::retrieve()` are handed to the transaction
+/// with `Transaction::update()`, deletions are recorded with `Transaction::delete()` and moves with
+/// `Transaction::move_by_id()`. Nothing is written to disk before `Transaction::commit()` is
+/// called.
+///
+/// On commit, the pre-update (resp. -delete, -move) hooks are executed once for each entry before
+/// anything is written, so an aborting hook leaves the store untouched. Then all entries are
+/// verified, written to staging files, a journal is written and the staging files are renamed
+/// over the original files. If the process dies after the journal is written, the journal is
+/// replayed by `Store::new()`. The post-update (resp. -delete, -move) hooks are executed once the
+/// changes are applied.
+///
+/// Dropping a `Transaction` without committing it aborts it.
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    store: &'a Store,
+    entries: Vec<FileLockEntry<'a>>,
+    deletes: Vec<StoreId>,
+    moves: Vec<(StoreId, StoreId)>,
+    committed: bool,
+}
+
+impl<'a> Transaction<'a> {
+
+    fn new(store: &'a Store) -> Transaction<'a> {
+        Transaction {
+            store: store,
+            entries: vec![],
+            deletes: vec![],
+            moves: vec![],
+            committed: false,
+        }
+    }
+
+    /// Add an entry to the transaction, it gets written when the transaction is committed
+    pub fn update(&mut self, entry: FileLockEntry<'a>) {
+        debug!("Adding entry to transaction: {:?}", entry.get_location());
+        self.entries.push(entry);
+    }
+
+    /// Get an entry which was already added to the transaction
+    pub fn get_mut(&mut self, id: &StoreId) -> Option<&mut FileLockEntry<'a>> {
+        self.entries.iter_mut().find(|fle| fle.get_location() == id)
+    }
+
+    /// Delete an entry when the transaction is committed
+    pub fn delete<S: IntoStoreId>(&mut self, id: S) {
+        let id = id.into_storeid().storified(self.store);
+        debug!("Adding deletion to transaction: {:?}", id);
+        self.deletes.push(id);
+    }
+
+    /// Move the entry `old` to `new` when the transaction is committed
+    ///
+    /// The move happens before the entries of the transaction are written, `old` must not be
+    /// borrowed and `new` must not exist.
+    pub fn move_by_id<S: IntoStoreId>(&mut self, old: S, new: S) {
+        let old = old.into_storeid().storified(self.store);
+        let new = new.into_storeid().storified(self.store);
+        debug!("Adding move to transaction: {:?} -> {:?}", old, new);
+        self.moves.push((old, new));
+    }
+
+    /// Commit the transaction
+    ///
+    /// The post-update, post-delete and post-move hooks are executed after the changes are
+    /// written, an error of theirs is returned although the transaction is committed.
+    pub fn commit(mut self) -> Result<()> {
+        let res = self.write_changes();
+        self.committed = res.is_ok();
+        self.release_entries();
+        try!(res.map_err_into(SEK::CommitCallError));
+        self.execute_post_hooks().map_err_into(SEK::CommitCallError)
+    }
+
+    /// Abort the transaction, leaving all entries on disk as they were
+    pub fn abort(self) {
+        debug!("Aborting transaction");
+        // Dropping does the work
+    }
+
+    fn write_changes(&mut self) -> Result<()> {
+        let store = self.store;
+        try!(store.check_writable());
+
+        {
+            let hsmap = match store.entries.read() {
+                Err(_) => return Err(SE::new(SEK::LockPoisoned, None)),
+                Ok(e) => e,
+            };
+
+            let moved = self.moves.iter().map(|&(ref old, _)| old);
+            for id in self.deletes.iter().chain(moved) {
+                if hsmap.get(id).map(|e| e.is_borrowed()).unwrap_or(false) {
+                    return Err(SE::new(SEK::IdLocked, None))
+                        .map_err_into(SEK::TransactionError);
+                }
+            }
+
+            for &(_, ref new) in self.moves.iter() {
+                if hsmap.contains_key(new) || store.backend.is_file(new) {
+                    return Err(SE::new(SEK::EntryAlreadyExists, None))
+                        .map_err_into(SEK::TransactionError);
+                }
+            }
+        }
+
+        // The updated entries are locked by their `FileLockEntry`, the deleted and moved ones
+        // have to be locked until the journal is applied
+        let _locks : Vec<Box<BackendLock>> = try!(self.deletes
+            .iter()
+            .chain(self.moves.iter().flat_map(|&(ref old, ref new)| vec![old, new]))
+            .map(|id| store.lock_entry(id))
+            .collect::<Result<Vec<_>>>()
+            .map_err_into(SEK::TransactionError));
+
+        for fle in self.entries.iter_mut() {
+            try!(store.execute_hooks_for_mut_file(store.pre_update_aspects.clone(), fle)
+                .map_err_into(SEK::PreHookExecuteError)
+                .map_err_into(SEK::HookExecutionError));
+        }
+
+        for id in self.deletes.iter() {
+            try!(store.execute_hooks_for_id(store.pre_delete_aspects.clone(), id)
+                .map_err_into(SEK::PreHookExecuteError)
+                .map_err_into(SEK::HookExecutionError));
+        }
+
+        for &(ref old, ref new) in self.moves.iter() {
+            try!(store.execute_hooks_for_move(store.pre_move_aspects.clone(), old, new)
+                .map_err_into(SEK::PreHookExecuteError)
+                .map_err_into(SEK::HookExecutionError));
+        }
+
+        for fle in self.entries.iter() {
+            try!(fle.verify());
+            try!(store.check_schemas(fle.get_header()));
+        }
+
+        // Moves come first, so entries of the transaction can be written to the new location
+        let mut journal = String::new();
+        for &(ref old, ref new) in self.moves.iter() {
+            journal.push_str(&format!("move\t{}\t{}\n", old, new));
+        }
+
+        for fle in self.entries.iter() {
+            let staged = staged_path(fle.get_location());
+            if let Err(e) = store.backend.write(&staged, fle.to_str().as_bytes()) {
+                self.remove_staged_files();
+                return Err(e).map_err_into(SEK::TransactionError);
+            }
+            journal.push_str(&format!("update\t{}\n", fle.get_location()));
+        }
+
+        for id in self.deletes.iter() {
+            journal.push_str(&format!("delete\t{}\n", id));
+        }
+
+        for fle in self.entries.iter() {
+            if let Err(e) = store.save_revision(fle.get_location(), &fle.to_str()) {
+                warn!("Could not keep revision of {:?}", fle.get_location());
+                debug!("{:?}", e);
+            }
+        }
+
+        let existed : Vec<bool> = self.entries
+            .iter()
+            .map(|fle| store.backend.is_file(fle.get_location()))
+            .collect();
+
+        let journal_path = transaction_journal_path(store.path());
+        if let Err(e) = write_transaction_journal(&*store.backend, &journal_path, &journal) {
+            self.remove_staged_files();
+            return Err(e).map_err_into(SEK::TransactionError);
+        }
+
+        // From here on, the transaction is committed. If applying it fails, the journal is
+        // replayed the next time the store is opened.
+        try!(apply_transaction_journal(&*store.backend, &journal_path)
+             .map_err_into(SEK::TransactionError));
+
+        for (fle, existed) in self.entries.iter().zip(existed.into_iter()) {
+            store.update_index(fle.get_location(), Some(fle.get_header()));
+            let op = if existed { Operation::Update } else { Operation::Create };
+            store.record_change(op, fle.get_location(), None);
+        }
+        for id in self.deletes.iter() {
+            store.remove_revisions(id);
+            store.update_index(id, None);
+            store.record_change(Operation::Delete, id, None);
+        }
+        for &(ref old, ref new) in self.moves.iter() {
+            store.move_revisions(old, new);
+            store.rename_in_index(old, new);
+            store.record_change(Operation::Move, old, Some(new));
+        }
+
+        Ok(())
+    }
+
+    fn execute_post_hooks(&mut self) -> Result<()> {
+        let store = self.store;
+
+        for fle in self.entries.iter_mut() {
+            try!(store.execute_hooks_for_mut_file(store.post_update_aspects.clone(), fle)
+                .map_err_into(SEK::PostHookExecuteError)
+                .map_err_into(SEK::HookExecutionError));
+        }
+
+        for id in self.deletes.iter() {
+            try!(store.execute_hooks_for_id(store.post_delete_aspects.clone(), id)
+                .map_err_into(SEK::PostHookExecuteError)
+                .map_err_into(SEK::HookExecutionError));
+        }
+
+        for &(ref old, ref new) in self.moves.iter() {
+            try!(store.execute_hooks_for_move(store.post_move_aspects.clone(), old, new)
+                .map_err_into(SEK::PostHookExecuteError)
+                .map_err_into(SEK::HookExecutionError));
+        }
+
+        Ok(())
+    }
+
+    fn remove_staged_files(&self) {
+        for fle in self.entries.iter() {
+            let staged = staged_path(fle.get_location());
+            if self.store.backend.exists(&staged) {
+                if let Err(e) = self.store.backend.remove(&staged) {
+                    warn!("Could not remove staged file {:?}: {:?}", staged, e);
+                }
+            }
+        }
+    }
+
+    /// Remove the entries of this transaction from the store-internal cache, so the
+    /// `FileLockEntry` objects do not write back when they are dropped and the next retrieve reads
+    /// from disk again.
+    fn release_entries(&mut self) {
+        match self.store.entries.write() {
+            Err(_) => warn!("Store lock poisoned, cannot release entries of transaction"),
+            Ok(mut hsmap) => {
+                for fle in self.entries.iter() {
+                    hsmap.remove(fle.get_location());
+                }
+
+                if self.committed {
+                    for id in self.deletes.iter() {
+                        hsmap.remove(id);
+                    }
+                    for &(ref old, _) in self.moves.iter() {
+                        hsmap.remove(old);
+                    }
+                }
+            },
+        }
+    }
+
+}
+
+impl<'a> Drop for Transaction<'a> {
+
+    fn drop(&mut self) {
+        if !self.committed {
+            debug!("Transaction was not committed, discarding changes");
+            self.release_entries();
+        }
+    }
+
+}
+
+/// A change to an entry, reported by `Watch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    Created(StoreId),
+    Modified(StoreId),
+    Deleted(StoreId),
+    Renamed(StoreId, StoreId),
+}
+
+impl WatchEvent {
+
+    fn ids(&self) -> Vec<&StoreId> {
+        match *self {
+            WatchEvent::Created(ref id)  |
+            WatchEvent::Modified(ref id) |
+            WatchEvent::Deleted(ref id)  => vec![id],
+            WatchEvent::Renamed(ref from, ref to) => vec![from, to],
+        }
+    }
+
+}
+
+/// The changes to the entries of one module which were not made through the store, see
+/// `Store::watch()`
+///
+/// Iterating blocks until the next change is reported.
+#[derive(Debug)]
+pub struct Watch<'a> {
+    store: &'a Store,
+    watcher: Box<BackendWatcher>,
+    queue: VecDeque<WatchEvent>,
+}
+
+impl<'a> Watch<'a> {
+
+    fn translate(event: BackendEvent) -> Option<WatchEvent> {
+        match event {
+            BackendEvent::Created(ref p)  if is_staged(p) => None,
+            BackendEvent::Modified(ref p) if is_staged(p) => None,
+            BackendEvent::Removed(ref p)  if is_staged(p) => None,
+
+            BackendEvent::Created(p)  => Some(WatchEvent::Created(StoreId::from(p))),
+            BackendEvent::Modified(p) => Some(WatchEvent::Modified(StoreId::from(p))),
+            BackendEvent::Removed(p)  => Some(WatchEvent::Deleted(StoreId::from(p))),
+
+            // a transaction renames its staged files over the entries
+            BackendEvent::Renamed(ref from, ref to) if is_staged(from) => {
+                Some(WatchEvent::Modified(StoreId::from(to.clone())))
+            },
+            BackendEvent::Renamed(_, ref to) if is_staged(to) => None,
+            BackendEvent::Renamed(from, to) => {
+                Some(WatchEvent::Renamed(StoreId::from(from), StoreId::from(to)))
+            },
+        }
+    }
+
+    /// Whether all entries `event` is about are as this store left them
+    ///
+    /// Entries which were changed by someone else since are forgotten.
+    fn is_own(&self, event: &WatchEvent) -> bool {
+        let mut own = match self.store.own_changes.lock() {
+            Ok(own) => own,
+            Err(_) => {
+                debug!("Lock poisoned, not filtering changes");
+                return false;
+            },
+        };
+        let own = match *own {
+            Some(ref mut own) => own,
+            None => return false,
+        };
+
+        event.ids().into_iter().all(|id| {
+            let current = self.store.backend.content_id(id).ok();
+            let unchanged = own.get(id).map(|content| *content == current);
+            if unchanged == Some(false) {
+                own.remove(id);
+            }
+            unchanged.unwrap_or(false)
+        })
+    }
+
+}
+
+impl<'a> Iterator for Watch<'a> {
+    type Item = WatchEvent;
+
+    fn next(&mut self) -> Option<WatchEvent> {
+        use std::thread::sleep;
+        use std::time::Duration;
+
+        while self.queue.is_empty() {
+            let mut events = match self.watcher.recv() {
+                Some(event) => vec![event],
+                None => return None,
+            };
+
+            // The store notes its changes after writing the entry, give it a moment
+            sleep(Duration::from_millis(WATCH_GRACE_MS));
+            while let Some(event) = self.watcher.try_recv() {
+                events.push(event);
+            }
+
+            for event in events.into_iter().filter_map(Watch::translate) {
+                if self.is_own(&event) {
+                    debug!("Ignoring change made by the store: {:?}", event);
+                } else {
+                    self.queue.push_back(event);
+                }
+            }
+        }
+
+        self.queue.pop_front()
+    }
+
+}
+
+/// Load the schemas persisted in the store, skipping (and warning about) unreadable ones
+fn load_schemas(backend: &StoreBackend, location: &Path) -> BTreeMap<String, HeaderSchema> {
+    use toml::Parser;
+
+    let mut dir = location.to_path_buf();
+    dir.push(SCHEMAS_DIR);
+
+    let mut schemas = BTreeMap::new();
+    if !backend.exists(&dir) {
+        return schemas;
+    }
+
+    for path in backend.list(&dir).unwrap_or(vec![]) {
+        let schema = backend
+            .read(&path)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .and_then(|text| Parser::new(&text).parse())
+            .and_then(|table| HeaderSchema::from_value(&Value::Table(table)));
+
+        match schema {
+            Some(schema) => {
+                debug!("Loaded schema for '{}'", schema.namespace());
+                schemas.insert(schema.namespace().clone(), schema);
+            },
+            None => warn!("Could not load header schema from {:?}", path),
+        }
+    }
+
+    schemas
+}
+
+fn transaction_journal_path(store_path: &Path) -> PathBuf {
+    let mut path = store_path.to_path_buf();
+    path.push(".imag-transaction");
+    path
+}
+
+fn staged_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut s = path.as_ref().as_os_str().to_os_string();
+    s.push(STAGED_SUFFIX);
+    PathBuf::from(s)
+}
+
+/// Write the journal atomically: first to a temporary file, which is then renamed.
+fn write_transaction_journal(backend: &StoreBackend, path: &Path, content: &str) -> Result<()> {
+    let tmp = staged_path(path);
+    backend.write(&tmp, content.as_bytes())
+        .and_then(|_| backend.rename(&tmp, path))
+        .map_err_into(SEK::FileError)
+}
+
+fn apply_transaction_journal(backend: &StoreBackend, path: &Path) -> Result<()> {
+    let journal = try!(backend.read(path).map_err_into(SEK::FileError));
+    let journal = try!(String::from_utf8(journal).map_err_into(SEK::EncodingError));
+
+    for line in journal.lines() {
+        let mut split = line.splitn(2, '\t');
+        match (split.next(), split.next()) {
+            (Some("update"), Some(entry)) => {
+                let staged = staged_path(entry);
+                if backend.is_file(&staged) {
+                    debug!("Renaming {:?} -> {:?}", staged, entry);
+                    try!(backend.rename(&staged, Path::new(entry)).map_err_into(SEK::FileError));
+                }
+            },
+            (Some("delete"), Some(entry)) => {
+                if backend.is_file(Path::new(entry)) {
+                    debug!("Removing {:?}", entry);
+                    try!(backend.remove(Path::new(entry)).map_err_into(SEK::FileError));
+                }
+            },
+            (Some("move"), Some(entries)) => {
+                let mut entries = entries.splitn(2, '\t');
+                match (entries.next().map(Path::new), entries.next().map(Path::new)) {
+                    (Some(old), Some(new)) => if backend.is_file(old) && !backend.is_file(new) {
+                        debug!("Renaming {:?} -> {:?}", old, new);
+                        try!(backend.rename(old, new).map_err_into(SEK::FileError));
+                    },
+                    _ => warn!("Ignoring malformed line in transaction journal: '{}'", line),
+                }
+            },
+            _ => warn!("Ignoring malformed line in transaction journal: '{}'", line),
+        }
+    }
+
+    backend.remove(path).map_err_into(SEK::FileError)
+}
+
+/// `EntryContent` type
+pub type EntryContent = String;
+
+/// `EntryHeader`
+///
+/// This is basically a wrapper around `toml::Table` which provides convenience to the user of the
+/// library.
+#[derive(Debug, Clone)]
+pub struct EntryHeader {
+    header: Value,
+}
+
+pub type EntryResult<V> = RResult<V, ParserError>;
+
+/**
+ * Wrapper type around file header (TOML) object
+ */
+impl EntryHeader {
+
+    pub fn new() -> EntryHeader {
+        EntryHeader {
+            header: build_default_header()
+        }
+    }
+
+    pub fn header(&self) -> &Value {
+        &self.header
+    }
+
+    fn from_table(t: Table) -> EntryHeader {
+        EntryHeader {
+            header: Value::Table(t)
+        }
+    }
+
+    pub fn parse(s: &str) -> EntryResult<EntryHeader> {
+        use toml::Parser;
+
+        let mut parser = Parser::new(s);
+        parser.parse()
+            .ok_or(ParserErrorKind::TOMLParserErrors.into())
+            .and_then(verify_header_consistency)
+            .map(EntryHeader::from_table)
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        match self.header {
+            Value::Table(ref t) => verify_header(&t),
+            _ => Err(SE::new(SEK::HeaderTypeFailure, None)),
+        }
+    }
+
+    /// Get the header as JSON object, see `format`
+    pub fn to_json(&self) -> Json {
+        toml_to_json(&self.header)
+    }
+
+    /// Build a header from a JSON object, see `format`
+    pub fn from_json(json: &Json) -> Result<EntryHeader> {
+        match try!(json_to_toml(json)) {
+            Value::Table(t) => Ok(EntryHeader::from_table(try!(verify_header_consistency(t)))),
+            _ => Err(SE::new(SEK::JsonError, None)),
+        }
+    }
+
+    /// Get the header as YAML mapping, see `format`
+    pub fn to_yaml(&self) -> Yaml {
+        toml_to_yaml(&self.header)
+    }
+
+    /// Build a header from a YAML mapping, see `format`
+    pub fn from_yaml(yaml: &Yaml) -> Result<EntryHeader> {
+        match try!(yaml_to_toml(yaml)) {
+            Value::Table(t) => Ok(EntryHeader::from_table(try!(verify_header_consistency(t)))),
+            _ => Err(SE::new(SEK::YamlError, None)),
+        }
+    }
+
+    /// Read the section `spec` (eg. "counter", see `read()`) of the header as a `T`
+    ///
+    /// Returns `None` if there is no such section. Fails with `HeaderSectionTypeError` (with the
+    /// reason as cause) if the section does not match `T`. Datetimes are read as strings.
+    pub fn read_section<T: Deserialize>(&self, spec: &str) -> Result<Option<T>> {
+        match try!(self.read(spec)) {
+            None => Ok(None),
+            Some(v) => ::serde_json::value::from_value(untagged(toml_to_json(&v)))
+                .map(Some)
+                .map_err_into(SEK::HeaderSectionTypeError),
+        }
+    }
+
+    /// Write `section` as section `spec` of the header
+    ///
+    /// If there is a table at `spec` already, the fields of `section` are merged into it, so
+    /// fields `T` does not know about are kept. Fields which are `None` are removed, as there is
+    /// nothing like `null` in TOML.
+    pub fn write_section<T: Serialize>(&mut self, spec: &str, section: &T) -> Result<()> {
+        let json = escaped_keys(::serde_json::value::to_value(section));
+        let value = match (try!(self.read(spec)), json) {
+            (Some(Value::Table(mut t)), Json::Object(o)) => {
+                try!(merge_json(&mut t, o).map_err_into(SEK::HeaderSectionTypeError));
+                Value::Table(t)
+            },
+            (_, json) => try!(json_to_toml(&without_nulls(json))
+                              .map_err_into(SEK::HeaderSectionTypeError)),
+        };
+        self.set(spec, value).map(|_| ())
+    }
+
+    /**
+     * Insert a header field by a string-spec
+     *
+     * ```ignore
+     *  insert("something.in.a.field", Boolean(true));
+     * ```
+     *
+     * If an array field was accessed which is _out of bounds_ of the array available, the element
+     * is appended to the array.
+     *
+     * Inserts a Boolean in the section "something" -> "in" -> "a" -> "field"
+     * A JSON equivalent would be
+     *
+     *  {
+     *      something: {
+     *          in: {
+     *              a: {
+     *                  field: true
+     *              }
+     *          }
+     *      }
+     *  }
+     *
+     * Returns true if header field was set, false if there is already a value
+     */
+    pub fn insert(&mut self, spec: &str, v: Value) -> Result<bool> {
+        self.insert_with_sep(spec, '.', v)
+    }
+
+    pub fn insert_with_sep(&mut self, spec: &str, sep: char, v: Value) -> Result<bool> {
+        let tokens = match self.resolve(spec, sep) {
+            Err(e) => return Err(e),
+            Ok(t) => t
+        };
+
+        let destination = match tokens.iter().last() {
+            None => return Err(SE::new(SEK::HeaderPathSyntaxError, None)),
+            Some(d) => d,
+        };
+
+        let path_to_dest = tokens[..(tokens.len() - 1)].into(); // N - 1 tokens
+
+        // walk N-1 tokens
+        let value = match EntryHeader::walk_header(&mut self.header, path_to_dest) {
+            Err(e) => return Err(e),
+            Ok(v) => v
+        };
+
+        // There is already an value at this place
+        if EntryHeader::extract(value, destination).is_ok() {
+            return Ok(false);
+        }
+
+        match *destination {
+            Token::Key(ref s) => { // if the destination shall be an map key
+                match *value {
+                    /*
+                     * Put it in there if we have a map
+                     */
+                    Value::Table(ref mut t) => {
+                        t.insert(s.clone(), v);
+                    }
+
+                    /*
+                     * Fail if there is no map here
+                     */
+                    _ => return Err(SE::new(SEK::HeaderPathTypeFailure, None)),
+                }
+            },
+
+            Token::Index(i) => { // if the destination shall be an array
+                match *value {
+
+                    /*
+                     * Put it in there if we have an array
+                     */
+                    Value::Array(ref mut a) => {
+                        a.push(v); // push to the end of the array
+
+                        // if the index is inside the array, we swap-remove the element at this
+                        // index
+                        if a.len() < i {
+                            a.swap_remove(i);
+                        }
+                    },
+
+                    /*
+                     * Fail if there is no array here
+                     */
+                    _ => return Err(SE::new(SEK::HeaderPathTypeFailure, None)),
+                }
+            },
+        }
+
+        Ok(true)
+    }
+
+    /**
+     * Set a header field by a string-spec
+     *
+     * ```ignore
+     *  set("something.in.a.field", Boolean(true));
+     * ```
+     *
+     * Sets a Boolean in the section "something" -> "in" -> "a" -> "field"
+     * A JSON equivalent would be
+     *
+     *  {
+     *      something: {
+     *          in: {
+     *              a: {
+     *                  field: true
+     *              }
+     *          }
+     *      }
+     *  }
+     *
+     * If there is already a value at this place, this value will be overridden and the old value
+     * will be returned
+     */
+    pub fn set(&mut self, spec: &str, v: Value) -> Result<Option<Value>> {
+        self.set_with_sep(spec, '.', v)
+    }
+
+    pub fn set_with_sep(&mut self, spec: &str, sep: char, v: Value) -> Result<Option<Value>> {
+        let tokens = match self.resolve(spec, sep) {
+            Err(e) => return Err(e),
+            Ok(t) => t,
+        };
+        debug!("tokens = {:?}", tokens);
+
+        let destination = match tokens.iter().last() {
+            None => return Err(SE::new(SEK::HeaderPathSyntaxError, None)),
+            Some(d) => d
+        };
+        debug!("destination = {:?}", destination);
+
+        let path_to_dest = tokens[..(tokens.len() - 1)].into(); // N - 1 tokens
+        // walk N-1 tokens
+        let value = match EntryHeader::walk_header(&mut self.header, path_to_dest) {
+            Err(e) => return Err(e),
+            Ok(v) => v
+        };
+        debug!("walked value = {:?}", value);
+
+        match *destination {
+            Token::Key(ref s) => { // if the destination shall be an map key->value
+                match *value {
+                    /*
+                     * Put it in there if we have a map
+                     */
+                    Value::Table(ref mut t) => {
+                        debug!("Matched Key->Table");
+                        return Ok(t.insert(s.clone(), v));
+                    }
+
+                    /*
+                     * Fail if there is no map here
+                     */
+                    _ => {
+                        debug!("Matched Key->NON-Table");
+                        return Err(SE::new(SEK::HeaderPathTypeFailure, None));
+                    }
+                }
+            },
+
+            Token::Index(i) => { // if the destination shall be an array
+                match *value {
+
+                    /*
+                     * Put it in there if we have an array
+                     */
+                    Value::Array(ref mut a) => {
+                        debug!("Matched Index->Array");
+                        a.push(v); // push to the end of the array
+
+                        // if the index is inside the array, we swap-remove the element at this
+                        // index
+                        if a.len() > i {
+                            debug!("Swap-Removing in Array {:?}[{:?}] <- {:?}", a, i, a[a.len()-1]);
+                            return Ok(Some(a.swap_remove(i)));
+                        }
+
+                        debug!("Appended");
+                        return Ok(None);
+                    },
+
+                    /*
+                     * Fail if there is no array here
+                     */
+                    _ => {
+                        debug!("Matched Index->NON-Array");
+                        return Err(SE::new(SEK::HeaderPathTypeFailure, None));
+                    },
+                }
+            },
+        }
+
+        Ok(None)
+    }
+
+    /**
+     * Read a header field by a string-spec
+     *
+     * ```ignore
+     *  let value = read("something.in.a.field");
+     * ```
+     *
+     * Reads a Value in the section "something" -> "in" -> "a" -> "field"
+     * A JSON equivalent would be
+     *
+     *  {
+     *      something: {
+     *          in: {
+     *              a: {
+     *                  field: true
+     *              }
+     *          }
+     *      }
+     *  }
+     *
+     * If there is no a value at this place, None will be returned. This also holds true for Arrays
+     * which are accessed at an index which is not yet there, even if the accessed index is much
+     * larger than the array length.
+     */
+    pub fn read(&self, spec: &str) -> Result<Option<Value>> {
+        self.read_with_sep(spec, '.')
+    }
+
+    pub fn read_with_sep(&self, spec: &str, splitchr: char) -> Result<Option<Value>> {
+        let tokens = match self.resolve(spec, splitchr) {
+            Err(e) => match e.err_type() {
+                // A negative index beyond the start of an array
+                SEK::HeaderKeyNotFound => return Ok(None),
+                _ => return Err(e),
+            },
+            Ok(t) => t,
+        };
+
+        let mut header_clone = self.header.clone(); // we clone as READing is simpler this way
+        // walk N-1 tokens
+        match EntryHeader::walk_header(&mut header_clone, tokens) {
+            Err(e) => match e.err_type() {
+                // We cannot find the header key, as there is no path to it
+                SEK::HeaderKeyNotFound => Ok(None),
+                _ => Err(e),
+            },
+            Ok(v) => Ok(Some(v.clone())),
+        }
+    }
+
+    pub fn delete(&mut self, spec: &str) -> Result<Option<Value>> {
+        let tokens = match self.resolve(spec, '.') {
+            Err(e) => return Err(e),
+            Ok(t) => t
+        };
+        self.delete_tokens(tokens)
+    }
+
+    fn delete_tokens(&mut self, tokens: Vec<Token>) -> Result<Option<Value>> {
+        let destination = match tokens.iter().last() {
+            None => return Err(SE::new(SEK::HeaderPathSyntaxError, None)),
+            Some(d) => d
+        };
+        debug!("destination = {:?}", destination);
+
+        let path_to_dest = tokens[..(tokens.len() - 1)].into(); // N - 1 tokens
+        // walk N-1 tokens
+        let mut value = match EntryHeader::walk_header(&mut self.header, path_to_dest) {
+            Err(e) => return Err(e),
+            Ok(v) => v
+        };
+        debug!("walked value = {:?}", value);
+
+        match *destination {
+            Token::Key(ref s) => { // if the destination shall be an map key->value
+                match *value {
+                    Value::Table(ref mut t) => {
+                        debug!("Matched Key->Table, removing {:?}", s);
+                        return Ok(t.remove(s));
+                    },
+                    _ => {
+                        debug!("Matched Key->NON-Table");
+                        return Err(SE::new(SEK::HeaderPathTypeFailure, None));
+                    }
+                }
+            },
+
+            Token::Index(i) => { // if the destination shall be an array
+                match *value {
+                    Value::Array(ref mut a) => {
+                        // if the index is inside the array, we swap-remove the element at this
+                        // index
+                        if a.len() > i {
+                            debug!("Removing in Array {:?}[{:?}]", a, i);
+                            return Ok(Some(a.remove(i)));
+                        } else {
+                            return Ok(None);
+                        }
+                    },
+                    _ => {
+                        debug!("Matched Index->NON-Array");
+                        return Err(SE::new(SEK::HeaderPathTypeFailure, None));
+                    },
+                }
+            },
+        }
+
+        Ok(None)
+    }
+
+    /// Get all values `path` matches, see `headerpath` for the path syntax
+    pub fn read_all(&self, path: &HeaderPath) -> Vec<Value> {
+        path.matches(&self.header).into_iter().map(|(_, v)| v.clone()).collect()
+    }
+
+    /// Replace all values `path` matches with `v`, returns how many were replaced
+    ///
+    /// Unlike `set()`, this does not create any fields.
+    pub fn set_all(&mut self, path: &HeaderPath, v: Value) -> Result<usize> {
+        let matches = EntryHeader::matching_tokens(&self.header, path);
+        for tokens in matches.iter() {
+            *try!(EntryHeader::walk_header(&mut self.header, tokens.clone())) = v.clone();
+        }
+        Ok(matches.len())
+    }
+
+    /// Delete all values `path` matches, returns the deleted values
+    pub fn delete_all(&mut self, path: &HeaderPath) -> Result<Vec<Value>> {
+        let mut matches = EntryHeader::matching_tokens(&self.header, path);
+
+        // Delete from the back, so the indices of the remaining matches stay valid and nested
+        // matches are deleted before the values containing them
+        matches.reverse();
+
+        let mut deleted = vec![];
+        for tokens in matches {
+            if let Some(v) = try!(self.delete_tokens(tokens)) {
+                deleted.push(v);
+            }
+        }
+        deleted.reverse();
+        Ok(deleted)
+    }
+
+    fn matching_tokens(header: &Value, path: &HeaderPath) -> Vec<Vec<Token>> {
+        let mut tokens : Vec<Vec<Token>> = path.matches(header)
+            .into_iter()
+            .map(|(tokens, _)| tokens)
+            .collect();
+        tokens.sort();
+        tokens.dedup();
+        tokens
+    }
+
+    /// Resolve `spec` to the tokens of the single field it denotes in this header
+    fn resolve(&self, spec: &str, sep: char) -> Result<Vec<Token>> {
+        HeaderPath::parse_with_sep(spec, sep).and_then(|path| path.resolve(&self.header))
+    }
+
+    fn walk_header(v: &mut Value, tokens: Vec<Token>) -> Result<&mut Value> {
+        use std::vec::IntoIter;
+
+        fn walk_iter<'a>(v: Result<&'a mut Value>, i: &mut IntoIter<Token>) -> Result<&'a mut Value> {
+            let next = i.next();
+            v.and_then(move |value| {
+                if let Some(token) = next {
+                    walk_iter(EntryHeader::extract(value, &token), i)
+                } else {
+                    Ok(value)
+                }
+            })
+        }
+
+        walk_iter(Ok(v), &mut tokens.into_iter())
+    }
+
+    fn extract_from_table<'a>(v: &'a mut Value, s: &str) -> Result<&'a mut Value> {
+        match *v {
+            Value::Table(ref mut t) => {
+                t.get_mut(&s[..])
+                    .ok_or(SE::new(SEK::HeaderKeyNotFound, None))
+            },
+            _ => Err(SE::new(SEK::HeaderPathTypeFailure, None)),
+        }
+    }
+
+    fn extract_from_array(v: &mut Value, i: usize) -> Result<&mut Value> {
+        match *v {
+            Value::Array(ref mut a) => {
+                if a.len() < i {
+                    Err(SE::new(SEK::HeaderKeyNotFound, None))
+                } else {
+                    Ok(&mut a[i])
+                }
+            },
+            _ => Err(SE::new(SEK::HeaderPathTypeFailure, None)),
+        }
+    }
+
+    fn extract<'a>(v: &'a mut Value, token: &Token) -> Result<&'a mut Value> {
+        match *token {
+            Token::Key(ref s)  => EntryHeader::extract_from_table(v, s),
+            Token::Index(i)    => EntryHeader::extract_from_array(v, i),
+        }
+    }
+
+}
+
+impl Into<Table> for EntryHeader {
+
+    fn into(self) -> Table {
+        match self.header {
+            Value::Table(t) => t,
+            _ => panic!("EntryHeader is not a table!"),
+        }
+    }
+
+}
+
+impl From<Table> for EntryHeader {
+
+    fn from(t: Table) -> EntryHeader {
+        EntryHeader { header: Value::Table(t) }
+    }
+
+}
+
+fn build_default_header() -> Value { // BTreeMap<String, Value>
+    let mut m = BTreeMap::new();
+
+    m.insert(String::from("imag"), {
+        let mut imag_map = BTreeMap::<String, Value>::new();
+
+        imag_map.insert(String::from("version"), Value::String(String::from(version!())));
+        imag_map.insert(String::from("links"), Value::Array(vec![]));
+
+        Value::Table(imag_map)
+    });
+
+    Value::Table(m)
+}
+fn verify_header(t: &Table) -> Result<()> {
+    if !has_main_section(t) {
+        Err(SE::from(ParserErrorKind::MissingMainSection.into_error()))
+    } else if !has_imag_version_in_main_section(t) {
+        Err(SE::from(ParserErrorKind::MissingVersionInfo.into_error()))
+    } else if !has_only_tables(t) {
+        debug!("Could not verify that it only has tables in its base table");
+        Err(SE::from(ParserErrorKind::NonTableInBaseTable.into_error()))
+    } else {
+        Ok(())
+    }
+}
+
+fn verify_header_consistency(t: Table) -> EntryResult<Table> {
+    verify_header(&t)
+        .map_err(Box::new)
+        .map_err(|e| ParserErrorKind::HeaderInconsistency.into_error_with_cause(e))
+        .map(|_| t)
+}
+
+fn has_only_tables(t: &Table) -> bool {
+    debug!("Verifying that table has only tables");
+    t.iter().all(|(_, x)| if let Value::Table(_) = *x { true } else { false })
+}
+
+fn has_main_section(t: &Table) -> bool {
+    t.contains_key("imag") &&
+        match t.get("imag") {
+            Some(&Value::Table(_)) => true,
+            Some(_)                => false,
+            None                   => false,
+        }
+}
+
+fn has_imag_version_in_main_section(t: &Table) -> bool {
+    use semver::Version;
+
+    match *t.get("imag").unwrap() {
+        Value::Table(ref sec) => {
+            sec.get("version")
+                .and_then(|v| {
+                    match *v {
+                        Value::String(ref s) => Some(Version::parse(&s[..]).is_ok()),
+                        _                    => Some(false),
+                    }
+                })
+            .unwrap_or(false)
+        }
+        _ => false,
+    }
+}
+
+/**
+ * An Entry of the store
+ *
+ * Contains location, header and content part.
+ */
+#[derive(Debug, Clone)]
+pub struct Entry {
+    location: StoreId,
+    header: EntryHeader,
+    content: EntryContent,
+
+    /// Whether header or content were borrowed mutably since the entry was read
+    modified: bool,
+}
+
+impl Entry {
+
+    /// Create a new, empty entry, it counts as modified as it is not stored yet
+    pub fn new(loc: StoreId) -> Entry {
+        Entry {
+            location: loc,
+            header: EntryHeader::new(),
+            content: EntryContent::new(),
+            modified: true,
+        }
+    }
+
+    pub fn from_file<S: IntoStoreId>(loc: S, file: &mut File) -> Result<Entry> {
+        Self::from_reader(loc, file)
+    }
+
+    pub fn from_reader<S: IntoStoreId, R: Read>(loc: S, file: &mut R) -> Result<Entry> {
+        let text = {
+            let mut s = String::new();
+            try!(file.read_to_string(&mut s));
+            s
+        };
+        Self::from_str(loc, &text[..])
+    }
+
+    pub fn from_str<S: IntoStoreId>(loc: S, s: &str) -> Result<Entry> {
+        debug!("Building entry from string");
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"(?smx)
+                ^---$
+                (?P<header>.*) # Header
+                ^---$\n
+                (?P<content>.*) # Content
+            ").unwrap();
+        }
+
+        let matches = match RE.captures(s) {
+            None    => return Err(SE::new(SEK::MalformedEntry, None)),
+            Some(s) => s,
+        };
+
+        let header = match matches.name("header") {
+            None    => return Err(SE::new(SEK::MalformedEntry, None)),
+            Some(s) => s
+        };
+
+        let content = matches.name("content").unwrap_or("");
+
+        debug!("Header and content found. Yay! Building Entry object now");
+        Ok(Entry {
+            location: loc.into_storeid(),
+            header: try!(EntryHeader::parse(header)),
+            content: content.into(),
+            modified: false,
+        })
+    }
+
+    pub fn to_str(&self) -> String {
+        format!("---{header}---\n{content}",
+                header  = ::toml::encode_str(&self.header.header),
+                content = self.content)
+    }
+
+    /// Get the entry as JSON object with the header and the content, see `format`
+    pub fn to_json(&self) -> Json {
+        let mut o = BTreeMap::new();
+        o.insert(String::from("header"), self.header.to_json());
+        o.insert(String::from("content"), Json::String(self.content.clone()));
+        Json::Object(o)
+    }
+
+    /// Build an entry from a JSON object with the header and the content, see `format`
+    pub fn from_json<S: IntoStoreId>(loc: S, json: &Json) -> Result<Entry> {
+        let o = match *json {
+            Json::Object(ref o) => o,
+            _ => return Err(SE::new(SEK::JsonError, None)),
+        };
+
+        let header  = try!(o.get("header")
+                           .ok_or(SE::new(SEK::JsonError, None))
+                           .and_then(EntryHeader::from_json));
+        let content = match o.get("content") {
+            Some(&Json::String(ref s)) => s.clone(),
+            None => EntryContent::new(),
+            Some(_) => return Err(SE::new(SEK::JsonError, None)),
+        };
+
+        Ok(Entry {
+            location: loc.into_storeid(),
+            header: header,
+            content: content,
+            modified: false,
+        })
+    }
+
+    /// Get the entry as YAML mapping with the header and the content, see `format`
+    pub fn to_yaml(&self) -> Yaml {
+        let mut h = YamlHash::new();
+        h.insert(Yaml::String(String::from("header")), self.header.to_yaml());
+        h.insert(Yaml::String(String::from("content")), Yaml::String(self.content.clone()));
+        Yaml::Hash(h)
+    }
+
+    /// Build an entry from a YAML mapping with the header and the content, see `format`
+    pub fn from_yaml<S: IntoStoreId>(loc: S, yaml: &Yaml) -> Result<Entry> {
+        let h = match *yaml {
+            Yaml::Hash(ref h) => h,
+            _ => return Err(SE::new(SEK::YamlError, None)),
+        };
+
+        let header  = try!(h.get(&Yaml::String(String::from("header")))
+                           .ok_or(SE::new(SEK::YamlError, None))
+                           .and_then(EntryHeader::from_yaml));
+        let content = match h.get(&Yaml::String(String::from("content"))) {
+            Some(&Yaml::String(ref s)) => s.clone(),
+            None => EntryContent::new(),
+            Some(_) => return Err(SE::new(SEK::YamlError, None)),
+        };
+
+        Ok(Entry {
+            location: loc.into_storeid(),
+            header: header,
+            content: content,
+            modified: false,
+        })
+    }
+
+    /// Get the entry as text in `format`
+    pub fn to_str_as(&self, format: Format) -> Result<String> {
+        match format {
+            Format::Toml => Ok(self.to_str()),
+            Format::Json => ::serde_json::to_string_pretty(&self.to_json())
+                .map_err_into(SEK::JsonError),
+            Format::Yaml => {
+                let mut out = String::new();
+                try!(YamlEmitter::new(&mut out)
+                     .dump(&self.to_yaml())
+                     .map_err(|_| SE::new(SEK::YamlError, None)));
+                out.push('\n');
+                Ok(out)
+            },
+        }
+    }
+
+    /// Build an entry from text in `format`
+    pub fn from_str_as<S: IntoStoreId>(loc: S, s: &str, format: Format) -> Result<Entry> {
+        match format {
+            Format::Toml => Entry::from_str(loc, s),
+            Format::Json => ::serde_json::from_str::<Json>(s)
+                .map_err_into(SEK::JsonError)
+                .and_then(|json| Entry::from_json(loc, &json)),
+            Format::Yaml => YamlLoader::load_from_str(s)
+                .map_err_into(SEK::YamlError)
+                .and_then(|docs| match docs.len() {
+                    1 => Entry::from_yaml(loc, &docs[0]),
+                    _ => Err(SE::new(SEK::YamlError, None)),
+                }),
+        }
+    }
+
+    pub fn get_location(&self) -> &StoreId {
+        &self.location
+    }
+
+    pub fn get_header(&self) -> &EntryHeader {
+        &self.header
+    }
+
+    pub fn get_header_mut(&mut self) -> &mut EntryHeader {
+        self.modified = true;
+        &mut self.header
+    }
+
+    pub fn get_content(&self) -> &EntryContent {
+        &self.content
+    }
+
+    pub fn get_content_mut(&mut self) -> &mut EntryContent {
+        self.modified = true;
+        &mut self.content
+    }
+
+    /// Whether the header or the content were borrowed mutably since the entry was read
+    ///
+    /// Unmodified entries are not written back by the store, see `Store::force_update()`.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        self.header.verify()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    extern crate env_logger;
+
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use super::{EntryHeader, FileLockEntry, Store};
+    use backend::InMemoryBackend;
+    use headerpath::{HeaderPath, Token};
+    use hook::Hook;
+    use hook::accessor::{HookDataAccessor, HookDataAccessorProvider, MoveAccessor,
+                         MutableHookDataAccessor, StoreIdAccessor};
+    use hook::error::{HookError, HookErrorKind};
+    use hook::position::HookPosition;
+    use hook::result::HookResult;
+    use storeid::StoreId;
+
+    use toml::Value;
+
+    #[test]
+    fn test_imag_section() {
+        use super::has_main_section;
+
+        let mut map = BTreeMap::new();
+        map.insert("imag".into(), Value::Table(BTreeMap::new()));
+
+        assert!(has_main_section(&map));
+    }
+
+    #[test]
+    fn test_imag_invalid_section_type() {
+        use super::has_main_section;
+
+        let mut map = BTreeMap::new();
+        map.insert("imag".into(), Value::Boolean(false));
+
+        assert!(!has_main_section(&map));
+    }
+
+    #[test]
+    fn test_imag_abscent_main_section() {
+        use super::has_main_section;
+
+        let mut map = BTreeMap::new();
+        map.insert("not_imag".into(), Value::Boolean(false));
+
+        assert!(!has_main_section(&map));
+    }
+
+    #[test]
+    fn test_main_section_without_version() {
+        use super::has_imag_version_in_main_section;
+
+        let mut map = BTreeMap::new();
+        map.insert("imag".into(), Value::Table(BTreeMap::new()));
+
+        assert!(!has_imag_version_in_main_section(&map));
+    }
+
+    #[test]
+    fn test_main_section_with_version() {
+        use super::has_imag_version_in_main_section;
+
+        let mut map = BTreeMap::new();
+        let mut sub = BTreeMap::new();
+        sub.insert("version".into(), Value::String("0.0.0".into()));
+        map.insert("imag".into(), Value::Table(sub));
+
+        assert!(has_imag_version_in_main_section(&map));
+    }
+
+    #[test]
+    fn test_main_section_with_version_in_wrong_type() {
+        use super::has_imag_version_in_main_section;
+
+        let mut map = BTreeMap::new();
+        let mut sub = BTreeMap::new();
+        sub.insert("version".into(), Value::Boolean(false));
+        map.insert("imag".into(), Value::Table(sub));
+
+        assert!(!has_imag_version_in_main_section(&map));
+    }
+
+    #[test]
+    fn test_verification_good() {
+        use super::verify_header_consistency;
+
+        let mut header = BTreeMap::new();
+        let sub = {
+            let mut sub = BTreeMap::new();
+            sub.insert("version".into(), Value::String(String::from("0.0.0")));
+
+            Value::Table(sub)
+        };
+
+        header.insert("imag".into(), sub);
+
+        assert!(verify_header_consistency(header).is_ok());
+    }
+
+    #[test]
+    fn test_verification_invalid_versionstring() {
+        use super::verify_header_consistency;
+
+        let mut header = BTreeMap::new();
+        let sub = {
+            let mut sub = BTreeMap::new();
+            sub.insert("version".into(), Value::String(String::from("000")));
+
+            Value::Table(sub)
+        };
+
+        header.insert("imag".into(), sub);
+
+        assert!(!verify_header_consistency(header).is_ok());
+    }
+
+
+    #[test]
+    fn test_verification_current_version() {
+        use super::verify_header_consistency;
+
+        let mut header = BTreeMap::new();
+        let sub = {
+            let mut sub = BTreeMap::new();
+            sub.insert("version".into(), Value::String(String::from(version!())));
+
+            Value::Table(sub)
+        };
+
+        header.insert("imag".into(), sub);
+
+        assert!(verify_header_consistency(header).is_ok());
+    }
+
+    static TEST_ENTRY : &'static str = "---
+[imag]
+version = \"0.0.3\"
+---
+Hai";
+
+    #[test]
+    fn test_entry_from_str() {
+        use super::Entry;
+        use std::path::PathBuf;
+        println!("{}", TEST_ENTRY);
+        let entry = Entry::from_str(PathBuf::from("/test/foo~1.3"),
+                                    TEST_ENTRY).unwrap();
+
+        assert_eq!(entry.content, "Hai");
+    }
+
+    #[test]
+    fn test_entry_to_str() {
+        use super::Entry;
+        use std::path::PathBuf;
+        println!("{}", TEST_ENTRY);
+        let entry = Entry::from_str(PathBuf::from("/test/foo~1.3"),
+                                    TEST_ENTRY).unwrap();
+        let string = entry.to_str();
+
+        assert_eq!(TEST_ENTRY, string);
+    }
+
+    #[test]
+    fn test_walk_header_simple() {
+        let tokens = HeaderPath::parse("a").unwrap().tokens().unwrap();
+        assert!(tokens.len() == 1, "1 token was expected, {} were parsed", tokens.len());
+        assert!(tokens.iter().next().unwrap() == &Token::Key(String::from("a")),
+                "'a' token was expected, {:?} was parsed", tokens.iter().next());
+
+        let mut header = BTreeMap::new();
+        header.insert(String::from("a"), Value::Integer(1));
+
+        let mut v_header = Value::Table(header);
+        let res = EntryHeader::walk_header(&mut v_header, tokens);
+        assert_eq!(&mut Value::Integer(1), res.unwrap());
+    }
+
+    #[test]
+    fn test_walk_header_with_array() {
+        let tokens = HeaderPath::parse("a.0").unwrap().tokens().unwrap();
+        assert!(tokens.len() == 2, "2 token was expected, {} were parsed", tokens.len());
+        assert!(tokens.iter().next().unwrap() == &Token::Key(String::from("a")),
+                "'a' token was expected, {:?} was parsed", tokens.iter().next());
+
+        let mut header = BTreeMap::new();
+        let ary = Value::Array(vec![Value::Integer(1)]);
+        header.insert(String::from("a"), ary);
+
+
+        let mut v_header = Value::Table(header);
+        let res = EntryHeader::walk_header(&mut v_header, tokens);
+        assert_eq!(&mut Value::Integer(1), res.unwrap());
+    }
+
+    #[test]
+    fn test_walk_header_extract_array() {
+        let tokens = HeaderPath::parse("a").unwrap().tokens().unwrap();
+        assert!(tokens.len() == 1, "1 token was expected, {} were parsed", tokens.len());
+        assert!(tokens.iter().next().unwrap() == &Token::Key(String::from("a")),
+                "'a' token was expected, {:?} was parsed", tokens.iter().next());
+
+        let mut header = BTreeMap::new();
+        let ary = Value::Array(vec![Value::Integer(1)]);
+        header.insert(String::from("a"), ary);
+
+        let mut v_header = Value::Table(header);
+        let res = EntryHeader::walk_header(&mut v_header, tokens);
+        assert_eq!(&mut Value::Array(vec![Value::Integer(1)]), res.unwrap());
+    }
+
+    /**
+     * Creates a big testing header.
+     *
+     * JSON equivalent:
+     *
+     * ```json
+     * {
+     *      "a": {
+     *          "array": [ 0, 1, 2, 3, 4, 5, 6, 7, 8, 9 ]
+     *      },
+     *      "b": {
+     *          "array": [ "string1", "string2", "string3", "string4" ]
+     *      },
+     *      "c": {
+     *          "array": [ 1, "string2", 3, "string4" ]
+     *      },
+     *      "d": {
+     *          "array": [
+     *              {
+     *                  "d1": 1
+     *              },
+     *              {
+     *                  "d2": 2
+     *              },
+     *              {
+     *                  "d3": 3
+     *              },
+     *          ],
+     *
+     *          "something": "else",
+     *
+     *          "and": {
+     *              "something": {
+     *                  "totally": "different"
+     *              }
+     *          }
+     *      }
+     * }
+     * ```
+     *
+     * The sections "a", "b", "c", "d" are created in the respective helper functions
+     * create_header_section_a, create_header_section_b, create_header_section_c and
+     * create_header_section_d.
+     *
+     * These functions can also be used for testing.
+     *
+     */
+    fn create_header() -> Value {
+        let a = create_header_section_a();
+        let b = create_header_section_b();
+        let c = create_header_section_c();
+        let d = create_header_section_d();
+
+        let mut header = BTreeMap::new();
+        header.insert(String::from("a"), a);
+        header.insert(String::from("b"), b);
+        header.insert(String::from("c"), c);
+        header.insert(String::from("d"), d);
+
+        Value::Table(header)
+    }
+
+    fn create_header_section_a() -> Value {
+        // 0..10 is exclusive 10
+        let a_ary = Value::Array((0..10).map(|x| Value::Integer(x)).collect());
+
+        let mut a_obj = BTreeMap::new();
+        a_obj.insert(String::from("array"), a_ary);
+        Value::Table(a_obj)
+    }
+
+    fn create_header_section_b() -> Value {
+        let b_ary = Value::Array((0..9)
+                                 .map(|x| Value::String(format!("string{}", x)))
+                                 .collect());
+
+        let mut b_obj = BTreeMap::new();
+        b_obj.insert(String::from("array"), b_ary);
+        Value::Table(b_obj)
+    }
+
+    fn create_header_section_c() -> Value {
+        let c_ary = Value::Array(
+            vec![
+                Value::Integer(1),
+                Value::String(String::from("string2")),
+                Value::Integer(3),
+                Value::String(String::from("string4"))
+            ]);
+
+        let mut c_obj = BTreeMap::new();
+        c_obj.insert(String::from("array"), c_ary);
+        Value::Table(c_obj)
+    }
+
+    fn create_header_section_d() -> Value {
+        let d_ary = Value::Array(
+            vec![
+                {
+                    let mut tab = BTreeMap::new();
+                    tab.insert(String::from("d1"), Value::Integer(1));
+                    tab
+                },
+                {
+                    let mut tab = BTreeMap::new();
+                    tab.insert(String::from("d2"), Value::Integer(2));
+                    tab
+                },
+                {
+                    let mut tab = BTreeMap::new();
+                    tab.insert(String::from("d3"), Value::Integer(3));
+                    tab
+                },
+            ].into_iter().map(Value::Table).collect());
+
+        let and_obj = Value::Table({
+            let mut tab = BTreeMap::new();
+            let something_tab = Value::Table({
+                let mut tab = BTreeMap::new();
+                tab.insert(String::from("totally"), Value::String(String::from("different")));
+                tab
+            });
+            tab.insert(String::from("something"), something_tab);
+            tab
+        });
+
+        let mut d_obj = BTreeMap::new();
+        d_obj.insert(String::from("array"), d_ary);
+        d_obj.insert(String::from("something"), Value::String(String::from("else")));
+        d_obj.insert(String::from("and"), and_obj);
+        Value::Table(d_obj)
+    }
+
+    #[test]
+    fn test_walk_header_big_a() {
+        test_walk_header_extract_section("a", &create_header_section_a());
+    }
+
+    #[test]
+    fn test_walk_header_big_b() {
+        test_walk_header_extract_section("b", &create_header_section_b());
+    }
+
+    #[test]
+    fn test_walk_header_big_c() {
+        test_walk_header_extract_section("c", &create_header_section_c());
+    }
+
+    #[test]
+    fn test_walk_header_big_d() {
+        test_walk_header_extract_section("d", &create_header_section_d());
+    }
+
+    fn test_walk_header_extract_section(secname: &str, expected: &Value) {
+        let tokens = HeaderPath::parse(secname).unwrap().tokens().unwrap();
+        assert!(tokens.len() == 1, "1 token was expected, {} were parsed", tokens.len());
+        assert!(tokens.iter().next().unwrap() == &Token::Key(String::from(secname)),
+                "'{}' token was expected, {:?} was parsed", secname, tokens.iter().next());
+
+        let mut header = create_header();
+        let res = EntryHeader::walk_header(&mut header, tokens);
+        assert_eq!(expected, res.unwrap());
+    }
+
+    #[test]
+    fn test_walk_header_extract_numbers() {
+        test_extract_number("a", 0, 0);
+        test_extract_number("a", 1, 1);
+        test_extract_number("a", 2, 2);
+        test_extract_number("a", 3, 3);
+        test_extract_number("a", 4, 4);
+        test_extract_number("a", 5, 5);
+        test_extract_number("a", 6, 6);
+        test_extract_number("a", 7, 7);
+        test_extract_number("a", 8, 8);
+        test_extract_number("a", 9, 9);
+
+        test_extract_number("c", 0, 1);
+        test_extract_number("c", 2, 3);
+    }
+
+    fn test_extract_number(sec: &str, idx: usize, exp: i64) {
+        let tokens = HeaderPath::parse(&format!("{}.array.{}", sec, idx)[..])
+            .unwrap()
+            .tokens()
+            .unwrap();
+        assert!(tokens.len() == 3, "3 token was expected, {} were parsed", tokens.len());
+        {
+            let mut iter = tokens.iter();
+
+            let tok = iter.next().unwrap();
+            let exp = Token::Key(String::from(sec));
+            assert!(tok == &exp, "'{}' token was expected, {:?} was parsed", sec, tok);
+
+            let tok = iter.next().unwrap();
+            let exp = Token::Key(String::from("array"));
+            assert!(tok == &exp, "'array' token was expected, {:?} was parsed", tok);
+
+            let tok = iter.next().unwrap();
+            let exp = Token::Index(idx);
+            assert!(tok == &exp, "'{}' token was expected, {:?} was parsed", idx, tok);
+        }
+
+        let mut header = create_header();
+        let res = EntryHeader::walk_header(&mut header, tokens);
+        assert_eq!(&mut Value::Integer(exp), res.unwrap());
+    }
+
+    #[test]
+    fn test_header_read() {
+        let v = create_header();
+        let h = match v {
+            Value::Table(t) => EntryHeader::from_table(t),
+            _ => panic!("create_header() doesn't return a table!"),
+        };
+
+        assert!(if let Ok(Some(Value::Table(_)))  = h.read("a") { true } else { false });
+        assert!(if let Ok(Some(Value::Array(_)))   = h.read("a.array") { true } else { false });
+        assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.array.1") { true } else { false });
+        assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.array.9") { true } else { false });
+
+        assert!(if let Ok(Some(Value::Table(_))) = h.read("c") { true } else { false });
+        assert!(if let Ok(Some(Value::Array(_)))  = h.read("c.array") { true } else { false });
+        assert!(if let Ok(Some(Value::String(_))) = h.read("c.array.1") { true } else { false });
+        assert!(if let Ok(None) = h.read("c.array.9") { true } else { false });
+
+        assert!(if let Ok(Some(Value::Integer(_))) = h.read("d.array.0.d1") { true } else { false });
+        assert!(if let Ok(None) = h.read("d.array.0.d2") { true } else { false });
+        assert!(if let Ok(None) = h.read("d.array.0.d3") { true } else { false });
+
+        assert!(if let Ok(None) = h.read("d.array.1.d1") { true } else { false });
+        assert!(if let Ok(Some(Value::Integer(_))) = h.read("d.array.1.d2") { true } else { false });
+        assert!(if let Ok(None) = h.read("d.array.1.d3") { true } else { false });
+
+        assert!(if let Ok(None) = h.read("d.array.2.d1") { true } else { false });
+        assert!(if let Ok(None) = h.read("d.array.2.d2") { true } else { false });
+        assert!(if let Ok(Some(Value::Integer(_))) = h.read("d.array.2.d3") { true } else { false });
+
+        assert!(if let Ok(Some(Value::String(_))) = h.read("d.something") { true } else { false });
+        assert!(if let Ok(Some(Value::Table(_))) = h.read("d.and") { true } else { false });
+        assert!(if let Ok(Some(Value::Table(_))) = h.read("d.and.something") { true } else { false });
+        assert!(if let Ok(Some(Value::String(_))) = h.read("d.and.something.totally") { true } else { false });
+    }
+
+    #[test]
+    fn test_header_set_override() {
+        let _ = env_logger::init();
+        let v = create_header();
+        let mut h = match v {
+            Value::Table(t) => EntryHeader::from_table(t),
+            _ => panic!("create_header() doesn't return a table!"),
+        };
+
+        println!("Testing index 0");
+        assert_eq!(h.read("a.array.0").unwrap().unwrap(), Value::Integer(0));
+
+        println!("Altering index 0");
+        assert_eq!(h.set("a.array.0", Value::Integer(42)).unwrap().unwrap(), Value::Integer(0));
+
+        println!("Values now: {:?}", h);
+
+        println!("Testing all indexes");
+        assert_eq!(h.read("a.array.0").unwrap().unwrap(), Value::Integer(42));
+        assert_eq!(h.read("a.array.1").unwrap().unwrap(), Value::Integer(1));
+        assert_eq!(h.read("a.array.2").unwrap().unwrap(), Value::Integer(2));
+        assert_eq!(h.read("a.array.3").unwrap().unwrap(), Value::Integer(3));
+        assert_eq!(h.read("a.array.4").unwrap().unwrap(), Value::Integer(4));
+        assert_eq!(h.read("a.array.5").unwrap().unwrap(), Value::Integer(5));
+        assert_eq!(h.read("a.array.6").unwrap().unwrap(), Value::Integer(6));
+        assert_eq!(h.read("a.array.7").unwrap().unwrap(), Value::Integer(7));
+        assert_eq!(h.read("a.array.8").unwrap().unwrap(), Value::Integer(8));
+        assert_eq!(h.read("a.array.9").unwrap().unwrap(), Value::Integer(9));
+    }
+
+    #[test]
+    fn test_header_set_new() {
+        let _ = env_logger::init();
+        let v = create_header();
+        let mut h = match v {
+            Value::Table(t) => EntryHeader::from_table(t),
+            _ => panic!("create_header() doesn't return a table!"),
+        };
 
-fn has_imag_version_in_main_section(t: &Table) -> bool {
-    use semver::Version;
+        assert!(h.read("a.foo").is_ok());
+        assert!(h.read("a.foo").unwrap().is_none());
 
-    match *t.get("imag").unwrap() {
-        Value::Table(ref sec) => {
-            sec.get("version")
-                .and_then(|v| {
-                    match *v {
-                        Value::String(ref s) => Some(Version::parse(&s[..]).is_ok()),
-                        _                    => Some(false),
-                    }
-                })
-            .unwrap_or(false)
+        {
+            let v = h.set("a.foo", Value::Integer(42));
+            assert!(v.is_ok());
+            assert!(v.unwrap().is_none());
+
+            assert!(if let Ok(Some(Value::Table(_))) = h.read("a") { true } else { false });
+            assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.foo") { true } else { false });
         }
-        _ => false,
-    }
-}
 
-/**
- * An Entry of the store
- *
- * Contains location, header and content part.
- */
-#[derive(Debug, Clone)]
-pub struct Entry {
-    location: StoreId,
-    header: EntryHeader,
-    content: EntryContent,
-}
+        {
+            let v = h.set("new", Value::Table(BTreeMap::new()));
+            assert!(v.is_ok());
+            assert!(v.unwrap().is_none());
 
-impl Entry {
+            let v = h.set("new.subset", Value::Table(BTreeMap::new()));
+            assert!(v.is_ok());
+            assert!(v.unwrap().is_none());
 
-    pub fn new(loc: StoreId) -> Entry {
-        Entry {
-            location: loc,
-            header: EntryHeader::new(),
-            content: EntryContent::new()
-        }
-    }
+            let v = h.set("new.subset.dest", Value::Integer(1337));
+            assert!(v.is_ok());
+            assert!(v.unwrap().is_none());
 
-    pub fn from_file<S: IntoStoreId>(loc: S, file: &mut File) -> Result<Entry> {
-        let text = {
-            use std::io::Read;
-            let mut s = String::new();
-            try!(file.read_to_string(&mut s));
-            s
-        };
-        Self::from_str(loc, &text[..])
+            assert!(if let Ok(Some(Value::Table(_))) = h.read("new") { true } else { false });
+            assert!(if let Ok(Some(Value::Table(_))) = h.read("new.subset") { true } else { false });
+            assert!(if let Ok(Some(Value::Integer(_))) = h.read("new.subset.dest") { true } else { false });
+        }
     }
 
-    pub fn from_str<S: IntoStoreId>(loc: S, s: &str) -> Result<Entry> {
-        debug!("Building entry from string");
-        lazy_static! {
-            static ref RE: Regex = Regex::new(r"(?smx)
-                ^---$
-                (?P<header>.*) # Header
-                ^---$\n
-                (?P<content>.*) # Content
-            ").unwrap();
-        }
 
-        let matches = match RE.captures(s) {
-            None    => return Err(SE::new(SEK::MalformedEntry, None)),
-            Some(s) => s,
+    #[test]
+    fn test_header_insert_override() {
+        let _ = env_logger::init();
+        let v = create_header();
+        let mut h = match v {
+            Value::Table(t) => EntryHeader::from_table(t),
+            _ => panic!("create_header() doesn't return a table!"),
         };
 
-        let header = match matches.name("header") {
-            None    => return Err(SE::new(SEK::MalformedEntry, None)),
-            Some(s) => s
-        };
+        println!("Testing index 0");
+        assert_eq!(h.read("a.array.0").unwrap().unwrap(), Value::Integer(0));
 
-        let content = matches.name("content").unwrap_or("");
+        println!("Altering index 0");
+        assert_eq!(h.insert("a.array.0", Value::Integer(42)).unwrap(), false);
+        println!("...should have failed");
 
-        debug!("Header and content found. Yay! Building Entry object now");
-        Ok(Entry {
-            location: loc.into_storeid(),
-            header: try!(EntryHeader::parse(header)),
-            content: content.into(),
-        })
+        println!("Testing all indexes");
+        assert_eq!(h.read("a.array.0").unwrap().unwrap(), Value::Integer(0));
+        assert_eq!(h.read("a.array.1").unwrap().unwrap(), Value::Integer(1));
+        assert_eq!(h.read("a.array.2").unwrap().unwrap(), Value::Integer(2));
+        assert_eq!(h.read("a.array.3").unwrap().unwrap(), Value::Integer(3));
+        assert_eq!(h.read("a.array.4").unwrap().unwrap(), Value::Integer(4));
+        assert_eq!(h.read("a.array.5").unwrap().unwrap(), Value::Integer(5));
+        assert_eq!(h.read("a.array.6").unwrap().unwrap(), Value::Integer(6));
+        assert_eq!(h.read("a.array.7").unwrap().unwrap(), Value::Integer(7));
+        assert_eq!(h.read("a.array.8").unwrap().unwrap(), Value::Integer(8));
+        assert_eq!(h.read("a.array.9").unwrap().unwrap(), Value::Integer(9));
     }
 
-    pub fn to_str(&self) -> String {
-        format!("---{header}---\n{content}",
-                header  = ::toml::encode_str(&self.header.header),
-                content = self.content)
-    }
+    #[test]
+    fn test_header_insert_new() {
+        let _ = env_logger::init();
+        let v = create_header();
+        let mut h = match v {
+            Value::Table(t) => EntryHeader::from_table(t),
+            _ => panic!("create_header() doesn't return a table!"),
+        };
 
-    pub fn get_location(&self) -> &StoreId {
-        &self.location
-    }
+        assert!(h.read("a.foo").is_ok());
+        assert!(h.read("a.foo").unwrap().is_none());
 
-    pub fn get_header(&self) -> &EntryHeader {
-        &self.header
-    }
+        {
+            let v = h.insert("a.foo", Value::Integer(42));
+            assert!(v.is_ok());
+            assert_eq!(v.unwrap(), true);
 
-    pub fn get_header_mut(&mut self) -> &mut EntryHeader {
-        &mut self.header
-    }
+            assert!(if let Ok(Some(Value::Table(_))) = h.read("a") { true } else { false });
+            assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.foo") { true } else { false });
+        }
 
-    pub fn get_content(&self) -> &EntryContent {
-        &self.content
-    }
+        {
+            let v = h.insert("new", Value::Table(BTreeMap::new()));
+            assert!(v.is_ok());
+            assert_eq!(v.unwrap(), true);
 
-    pub fn get_content_mut(&mut self) -> &mut EntryContent {
-        &mut self.content
-    }
+            let v = h.insert("new.subset", Value::Table(BTreeMap::new()));
+            assert!(v.is_ok());
+            assert_eq!(v.unwrap(), true);
 
-    pub fn verify(&self) -> Result<()> {
-        self.header.verify()
+            let v = h.insert("new.subset.dest", Value::Integer(1337));
+            assert!(v.is_ok());
+            assert_eq!(v.unwrap(), true);
+
+            assert!(if let Ok(Some(Value::Table(_))) = h.read("new") { true } else { false });
+            assert!(if let Ok(Some(Value::Table(_))) = h.read("new.subset") { true } else { false });
+            assert!(if let Ok(Some(Value::Integer(_))) = h.read("new.subset.dest") { true } else { false });
+        }
     }
 
-}
+    #[test]
+    fn test_header_delete() {
+        let _ = env_logger::init();
+        let v = create_header();
+        let mut h = match v {
+            Value::Table(t) => EntryHeader::from_table(t),
+            _ => panic!("create_header() doesn't return a table!"),
+        };
 
-mod glob_store_iter {
-    use std::fmt::{Debug, Formatter};
-    use std::fmt::Error as FmtError;
-    use glob::Paths;
-    use storeid::StoreId;
-    use storeid::StoreIdIterator;
+        assert!(if let Ok(Some(Value::Table(_)))   = h.read("a") { true } else { false });
+        assert!(if let Ok(Some(Value::Array(_)))   = h.read("a.array") { true } else { false });
+        assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.array.1") { true } else { false });
+        assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.array.9") { true } else { false });
+
+        assert!(if let Ok(Some(Value::Integer(1))) = h.delete("a.array.1") { true } else { false });
+        assert!(if let Ok(Some(Value::Integer(9))) = h.delete("a.array.8") { true } else { false });
+        assert!(if let Ok(Some(Value::Array(_)))   = h.delete("a.array") { true } else { false });
+        assert!(if let Ok(Some(Value::Table(_)))   = h.delete("a") { true } else { false });
 
-    pub struct GlobStoreIdIterator {
-        paths: Paths,
     }
 
-    impl Debug for GlobStoreIdIterator {
+    #[test]
+    fn test_header_multiple_matches() {
+        let _ = env_logger::init();
+        let v = create_header();
+        let mut h = match v {
+            Value::Table(t) => EntryHeader::from_table(t),
+            _ => panic!("create_header() doesn't return a table!"),
+        };
 
-        fn fmt(&self, fmt: &mut Formatter) -> Result<(), FmtError> {
-            write!(fmt, "GlobStoreIdIterator")
-        }
+        let big = HeaderPath::parse("a.array[?value>=8]").unwrap();
+        assert_eq!(h.set_all(&big, Value::Integer(0)).unwrap(), 2);
+        assert!(h.read_all(&big).is_empty());
+        assert_eq!(h.read("a.array.-1").unwrap(), Some(Value::Integer(0)));
 
+        let zeros = HeaderPath::parse("a.array[?value==0]").unwrap();
+        assert_eq!(h.delete_all(&zeros).unwrap(), vec![Value::Integer(0); 3]);
+        assert_eq!(h.read_all(&HeaderPath::parse("a.array.*").unwrap()).len(), 7);
+        assert_eq!(h.read("a.array[-1]").unwrap(), Some(Value::Integer(7)));
+        assert!(h.read("a.array.*").is_err());
     }
 
-    impl Into<StoreIdIterator> for GlobStoreIdIterator {
+    #[test]
+    fn test_transaction_journal_replay() {
+        use std::path::PathBuf;
+        use backend::{StoreBackend, InMemoryBackend};
+        use super::{Store, staged_path, transaction_journal_path};
+
+        let backend = InMemoryBackend::new();
+        let updated = PathBuf::from("/store/updated");
+        let deleted = PathBuf::from("/store/deleted");
 
-        fn into(self) -> StoreIdIterator {
-            StoreIdIterator::new(Box::new(self))
-        }
+        backend.write(&updated, b"old").unwrap();
+        backend.write(&staged_path(&updated), b"new").unwrap();
+        backend.write(&deleted, b"old").unwrap();
 
-    }
+        let journal = transaction_journal_path(&PathBuf::from("/store"));
+        let content = format!("update\t{}\ndelete\t{}\n", updated.display(), deleted.display());
+        backend.write(&journal, content.as_bytes()).unwrap();
 
-    impl GlobStoreIdIterator {
+        let store = Store::new(PathBuf::from("/store"), None, Box::new(backend.clone()));
+        assert!(store.is_ok());
 
-        pub fn new(paths: Paths) -> GlobStoreIdIterator {
-            GlobStoreIdIterator {
-                paths: paths,
-            }
-        }
+        assert_eq!(backend.read(&updated).unwrap(), b"new".to_vec());
+        assert!(!backend.exists(&staged_path(&updated)));
+        assert!(!backend.exists(&deleted));
+        assert!(!backend.exists(&journal));
+    }
 
+    #[test]
+    fn test_watch_translate() {
+        use std::path::PathBuf;
+        use backend::BackendEvent;
+        use storeid::StoreId;
+        use super::{Watch, WatchEvent};
+
+        let entry  = PathBuf::from("/store/notes/a");
+        let staged = PathBuf::from("/store/notes/a.imag-tx");
+
+        assert_eq!(Watch::translate(BackendEvent::Modified(staged.clone())), None);
+        assert_eq!(Watch::translate(BackendEvent::Renamed(staged, entry.clone())),
+                   Some(WatchEvent::Modified(StoreId::from(entry.clone()))));
+        assert_eq!(Watch::translate(BackendEvent::Removed(entry.clone())),
+                   Some(WatchEvent::Deleted(StoreId::from(entry))));
     }
 
-    impl Iterator for GlobStoreIdIterator {
-        type Item = StoreId;
+    #[test]
+    fn test_watch_ignores_own_changes() {
+        use std::collections::VecDeque;
+        use backend::{BackendEvent, BackendWatcher, StoreBackend};
+        use super::{Watch, WatchEvent};
 
-        fn next(&mut self) -> Option<StoreId> {
-            self.paths.next().and_then(|o| {
-                match o {
-                    Ok(o) => Some(o),
-                    Err(e) => {
-                        debug!("GlobStoreIdIterator error: {:?}", e);
-                        None
-                    },
-                }
-            }).map(|p| StoreId::from(p))
-        }
+        #[derive(Debug)]
+        struct NoWatcher;
 
-    }
+        impl BackendWatcher for NoWatcher {
+            fn recv(&mut self) -> Option<BackendEvent> { None }
+            fn try_recv(&mut self) -> Option<BackendEvent> { None }
+        }
 
-}
+        let (store, backend) = in_memory_store("");
+        let id = |s: &str| StoreId::from(PathBuf::from(s));
 
+        // The in-memory backend cannot be watched, so set up what Store::watch() does
+        *store.own_changes.lock().unwrap() = Some(BTreeMap::new());
+        let watch = Watch { store: &store, watcher: Box::new(NoWatcher), queue: VecDeque::new() };
 
-#[cfg(test)]
-mod test {
-    extern crate env_logger;
+        store.retrieve(PathBuf::from("notes/a")).unwrap();
+        assert!(watch.is_own(&WatchEvent::Created(id("/store/notes/a"))));
+        assert!(watch.is_own(&WatchEvent::Modified(id("/store/notes/a"))));
 
-    use std::collections::BTreeMap;
-    use super::EntryHeader;
-    use super::Token;
+        backend.write(&PathBuf::from("/store/notes/a"), b"---\n[imag]\nversion = \"0.2.0\"\n---\n")
+            .unwrap();
+        assert!(!watch.is_own(&WatchEvent::Modified(id("/store/notes/a"))));
 
-    use toml::Value;
+        store.move_by_id(id("/store/notes/a"), id("/store/notes/b")).unwrap();
+        assert!(watch.is_own(&WatchEvent::Renamed(id("/store/notes/a"), id("/store/notes/b"))));
 
-    #[test]
-    fn test_imag_section() {
-        use super::has_main_section;
+        store.delete(PathBuf::from("notes/b")).unwrap();
+        assert!(watch.is_own(&WatchEvent::Deleted(id("/store/notes/b"))));
+        assert!(!watch.is_own(&WatchEvent::Deleted(id("/store/notes/c"))));
+    }
 
-        let mut map = BTreeMap::new();
-        map.insert("imag".into(), Value::Table(BTreeMap::new()));
+    /// A store configuration without hooks, with `extra` added to it
+    fn hookless_config(extra: &str) -> Option<Value> {
+        config_with_test_aspect(&[], extra)
+    }
 
-        assert!(has_main_section(&map));
+    /// A store without hooks at "/store" on a new `InMemoryBackend`, with `extra` added to its
+    /// configuration, and the backend
+    fn in_memory_store(extra: &str) -> (Store, InMemoryBackend) {
+        let backend = InMemoryBackend::new();
+        let store   = Store::new(PathBuf::from("/store"), hookless_config(extra),
+                                 Box::new(backend.clone())).unwrap();
+        (store, backend)
     }
 
-    #[test]
-    fn test_imag_invalid_section_type() {
-        use super::has_main_section;
+    /// Set the content of the entry `id` (relative to the store), creating it if necessary
+    fn write_content(store: &Store, id: &str, content: &str) {
+        *store.retrieve(PathBuf::from(id)).unwrap().get_content_mut() = String::from(content);
+    }
 
-        let mut map = BTreeMap::new();
-        map.insert("imag".into(), Value::Boolean(false));
+    /// A store configuration with the aspect "test" at the hook positions `hooked` (eg.
+    /// "pre-update"), without other hooks and with `extra` added to it
+    fn config_with_test_aspect(hooked: &[&str], extra: &str) -> Option<Value> {
+        use toml::Parser;
 
-        assert!(!has_main_section(&map));
-    }
+        let positions = ["store-unload", "pre-create", "post-create", "pre-retrieve",
+                         "post-retrieve", "pre-update", "post-update", "pre-delete",
+                         "post-delete", "pre-move", "post-move"];
+        let aspects = positions
+            .iter()
+            .map(|pos| {
+                let names = if hooked.contains(pos) { "\"test\"" } else { "" };
+                format!("{}-hook-aspects = [{}]\n", pos, names)
+            })
+            .collect::<String>();
 
-    #[test]
-    fn test_imag_abscent_main_section() {
-        use super::has_main_section;
+        Parser::new(&format!(r#"
+            {}
+            {}
 
-        let mut map = BTreeMap::new();
-        map.insert("not_imag".into(), Value::Boolean(false));
+            [hooks]
+            [aspects.test]
+            parallel      = false
+            mutable_hooks = true
+        "#, aspects, extra)).parse().map(Value::Table)
+    }
 
-        assert!(!has_main_section(&map));
+    /// A hook recording the ids it is executed for, which fails if `fail` is set
+    #[derive(Debug)]
+    struct TestHook {
+        position: HookPosition,
+        fail: bool,
+        calls: Arc<Mutex<Vec<StoreId>>>,
     }
 
-    #[test]
-    fn test_main_section_without_version() {
-        use super::has_imag_version_in_main_section;
+    impl TestHook {
 
-        let mut map = BTreeMap::new();
-        map.insert("imag".into(), Value::Table(BTreeMap::new()));
+        fn record(&self, id: &StoreId) -> HookResult<()> {
+            self.calls.lock().unwrap().push(id.clone());
+            if self.fail {
+                Err(HookError::new(HookErrorKind::HookExecutionError, None))
+            } else {
+                Ok(())
+            }
+        }
 
-        assert!(!has_imag_version_in_main_section(&map));
     }
 
-    #[test]
-    fn test_main_section_with_version() {
-        use super::has_imag_version_in_main_section;
+    impl Hook for TestHook {
 
-        let mut map = BTreeMap::new();
-        let mut sub = BTreeMap::new();
-        sub.insert("version".into(), Value::String("0.0.0".into()));
-        map.insert("imag".into(), Value::Table(sub));
+        fn name(&self) -> &'static str {
+            "test_hook"
+        }
+
+        fn set_config(&mut self, _: &Value) {
+        }
 
-        assert!(has_imag_version_in_main_section(&map));
     }
 
-    #[test]
-    fn test_main_section_with_version_in_wrong_type() {
-        use super::has_imag_version_in_main_section;
+    impl HookDataAccessorProvider for TestHook {
+
+        fn accessor(&self) -> HookDataAccessor {
+            match self.position {
+                HookPosition::PostCreate   |
+                HookPosition::PostRetrieve |
+                HookPosition::PreUpdate    |
+                HookPosition::PostUpdate   => HookDataAccessor::MutableAccess(self),
+                HookPosition::PreMove      |
+                HookPosition::PostMove     => HookDataAccessor::MoveAccess(self),
+                _                          => HookDataAccessor::StoreIdAccess(self),
+            }
+        }
 
-        let mut map = BTreeMap::new();
-        let mut sub = BTreeMap::new();
-        sub.insert("version".into(), Value::Boolean(false));
-        map.insert("imag".into(), Value::Table(sub));
+    }
 
-        assert!(!has_imag_version_in_main_section(&map));
+    impl StoreIdAccessor for TestHook {
+        fn access(&self, id: &StoreId) -> HookResult<()> {
+            self.record(id)
+        }
     }
 
-    #[test]
-    fn test_verification_good() {
-        use super::verify_header_consistency;
+    impl MutableHookDataAccessor for TestHook {
+        fn access_mut(&self, fle: &mut FileLockEntry) -> HookResult<()> {
+            self.record(fle.get_location())
+        }
+    }
 
-        let mut header = BTreeMap::new();
-        let sub = {
-            let mut sub = BTreeMap::new();
-            sub.insert("version".into(), Value::String(String::from("0.0.0")));
+    impl MoveAccessor for TestHook {
+        fn access_move(&self, _: &StoreId, new: &StoreId) -> HookResult<()> {
+            self.record(new)
+        }
+    }
 
-            Value::Table(sub)
+    /// Register a `TestHook` in the aspect "test" at `position`, returning the ids it is called
+    /// for
+    fn register_test_hook(store: &mut Store, position: HookPosition, fail: bool)
+        -> Arc<Mutex<Vec<StoreId>>>
+    {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let hook  = TestHook {
+            position: position.clone(),
+            fail: fail,
+            calls: calls.clone(),
         };
+        store.register_hook(position, "test", Box::new(hook)).unwrap();
+        calls
+    }
 
-        header.insert("imag".into(), sub);
+    /// Write the entries `ids` with their id as content, set `counter.value` to 1 in them
+    fn write_counters(store: &Store, ids: &[&str]) {
+        use std::path::PathBuf;
 
-        assert!(verify_header_consistency(header).is_ok());
+        for id in ids {
+            let mut fle = store.retrieve(PathBuf::from(*id)).unwrap();
+            fle.get_header_mut().insert("counter", Value::Table(BTreeMap::new())).unwrap();
+            fle.get_header_mut().insert("counter.value", Value::Integer(1)).unwrap();
+            *fle.get_content_mut() = String::from(*id);
+        }
     }
 
     #[test]
-    fn test_verification_invalid_versionstring() {
-        use super::verify_header_consistency;
+    fn test_transaction_aborting_pre_hook() {
+        use std::path::PathBuf;
+        use backend::InMemoryBackend;
 
-        let mut header = BTreeMap::new();
-        let sub = {
-            let mut sub = BTreeMap::new();
-            sub.insert("version".into(), Value::String(String::from("000")));
+        let config    = config_with_test_aspect(&["pre-update", "post-update", "post-delete"], "");
+        let mut store = Store::new(PathBuf::from("/store"), config,
+                                   Box::new(InMemoryBackend::new())).unwrap();
+        write_counters(&store, &["c/a", "c/b", "c/deleted"]);
 
-            Value::Table(sub)
-        };
+        register_test_hook(&mut store, HookPosition::PreUpdate, true);
+        let post_update = register_test_hook(&mut store, HookPosition::PostUpdate, false);
+        let post_delete = register_test_hook(&mut store, HookPosition::PostDelete, false);
 
-        header.insert("imag".into(), sub);
+        {
+            let mut tx = store.transaction();
+            for id in &["c/a", "c/b"] {
+                let mut fle = store.retrieve(PathBuf::from(*id)).unwrap();
+                *fle.get_content_mut() = String::from("changed");
+                tx.update(fle);
+            }
+            tx.delete(PathBuf::from("c/deleted"));
+            assert!(tx.commit().is_err());
+        }
 
-        assert!(!verify_header_consistency(header).is_ok());
+        for id in &["c/a", "c/b", "c/deleted"] {
+            let entry = store.retrieve_copy(PathBuf::from(*id)).unwrap();
+            assert_eq!(entry.get_content(), id);
+        }
+        assert!(post_update.lock().unwrap().is_empty());
+        assert!(post_delete.lock().unwrap().is_empty());
     }
 
-
     #[test]
-    fn test_verification_current_version() {
-        use super::verify_header_consistency;
+    fn test_save_to_executes_create_hooks() {
+        use std::path::PathBuf;
+        use backend::InMemoryBackend;
 
-        let mut header = BTreeMap::new();
-        let sub = {
-            let mut sub = BTreeMap::new();
-            sub.insert("version".into(), Value::String(String::from(version!())));
+        let config    = config_with_test_aspect(&["pre-create", "post-create", "pre-move",
+                                                  "post-move"], "");
+        let mut store = Store::new(PathBuf::from("/store"), config,
+                                   Box::new(InMemoryBackend::new())).unwrap();
+        write_counters(&store, &["c/a"]);
 
-            Value::Table(sub)
-        };
+        let pre_create  = register_test_hook(&mut store, HookPosition::PreCreate, false);
+        let post_create = register_test_hook(&mut store, HookPosition::PostCreate, false);
+        let pre_move    = register_test_hook(&mut store, HookPosition::PreMove, false);
+        let post_move   = register_test_hook(&mut store, HookPosition::PostMove, false);
 
-        header.insert("imag".into(), sub);
+        {
+            let fle = store.retrieve(PathBuf::from("c/a")).unwrap();
+            store.save_to(&fle, StoreId::from(PathBuf::from("c/copy"))).unwrap();
+            assert!(store.save_to(&fle, StoreId::from(PathBuf::from("c/copy"))).is_err());
+        }
 
-        assert!(verify_header_consistency(header).is_ok());
+        let copy = StoreId::from(PathBuf::from("/store/c/copy"));
+        assert_eq!(*pre_create.lock().unwrap(), vec![copy.clone()]);
+        assert_eq!(*post_create.lock().unwrap(), vec![copy.clone()]);
+        assert!(pre_move.lock().unwrap().is_empty());
+        assert!(post_move.lock().unwrap().is_empty());
+        assert_eq!(store.retrieve_copy(copy).unwrap().get_content(), "c/a");
+        assert_eq!(store.retrieve_copy(PathBuf::from("c/a")).unwrap().get_content(), "c/a");
     }
 
-    static TEST_ENTRY : &'static str = "---
-[imag]
-version = \"0.0.3\"
----
-Hai";
-
     #[test]
-    fn test_entry_from_str() {
-        use super::Entry;
+    fn test_save_as_writes_changes_to_new_place() {
         use std::path::PathBuf;
-        println!("{}", TEST_ENTRY);
-        let entry = Entry::from_str(PathBuf::from("/test/foo~1.3"),
-                                    TEST_ENTRY).unwrap();
+        use backend::StoreBackend;
 
-        assert_eq!(entry.content, "Hai");
+        let (store, backend) = in_memory_store("");
+        write_content(&store, "notes/a", "old content");
+
+        {
+            let mut fle = store.retrieve(PathBuf::from("notes/a")).unwrap();
+            *fle.get_content_mut() = String::from("new content");
+            store.save_as(fle, StoreId::from(PathBuf::from("notes/b"))).unwrap();
+        }
+
+        assert!(!backend.exists(&PathBuf::from("/store/notes/a")));
+        assert!(store.get(PathBuf::from("notes/a")).unwrap().is_none());
+        assert_eq!(store.retrieve_copy(PathBuf::from("notes/b")).unwrap().get_content(),
+                   "new content");
+
+        // The target exists
+        write_content(&store, "notes/c", "other content");
+        let fle = store.retrieve(PathBuf::from("notes/b")).unwrap();
+        assert!(store.save_as(fle, StoreId::from(PathBuf::from("notes/c"))).is_err());
+        assert_eq!(store.retrieve_copy(PathBuf::from("notes/c")).unwrap().get_content(),
+                   "other content");
     }
 
     #[test]
-    fn test_entry_to_str() {
-        use super::Entry;
+    fn test_move_by_id_borrowed_entry_executes_no_hooks() {
         use std::path::PathBuf;
-        println!("{}", TEST_ENTRY);
-        let entry = Entry::from_str(PathBuf::from("/test/foo~1.3"),
-                                    TEST_ENTRY).unwrap();
-        let string = entry.to_str();
+        use backend::InMemoryBackend;
 
-        assert_eq!(TEST_ENTRY, string);
-    }
+        let config    = config_with_test_aspect(&["pre-move", "post-move"], "");
+        let mut store = Store::new(PathBuf::from("/store"), config,
+                                   Box::new(InMemoryBackend::new())).unwrap();
+        write_counters(&store, &["c/a"]);
 
-    #[test]
-    fn test_walk_header_simple() {
-        let tokens = EntryHeader::tokenize("a", '.').unwrap();
-        assert!(tokens.len() == 1, "1 token was expected, {} were parsed", tokens.len());
-        assert!(tokens.iter().next().unwrap() == &Token::Key(String::from("a")),
-                "'a' token was expected, {:?} was parsed", tokens.iter().next());
+        let pre_move  = register_test_hook(&mut store, HookPosition::PreMove, false);
+        let post_move = register_test_hook(&mut store, HookPosition::PostMove, false);
 
-        let mut header = BTreeMap::new();
-        header.insert(String::from("a"), Value::Integer(1));
+        {
+            let _fle = store.retrieve(PathBuf::from("c/a")).unwrap();
+            assert!(store.move_by_id(StoreId::from(PathBuf::from("c/a")),
+                                     StoreId::from(PathBuf::from("c/b"))).is_err());
+        }
+        assert!(pre_move.lock().unwrap().is_empty());
 
-        let mut v_header = Value::Table(header);
-        let res = EntryHeader::walk_header(&mut v_header, tokens);
-        assert_eq!(&mut Value::Integer(1), res.unwrap());
+        store.move_by_id(StoreId::from(PathBuf::from("c/a")), StoreId::from(PathBuf::from("c/b")))
+            .unwrap();
+        let moved = StoreId::from(PathBuf::from("/store/c/b"));
+        assert_eq!(*pre_move.lock().unwrap(), vec![moved.clone()]);
+        assert_eq!(*post_move.lock().unwrap(), vec![moved]);
     }
 
     #[test]
-    fn test_walk_header_with_array() {
-        let tokens = EntryHeader::tokenize("a.0", '.').unwrap();
-        assert!(tokens.len() == 2, "2 token was expected, {} were parsed", tokens.len());
-        assert!(tokens.iter().next().unwrap() == &Token::Key(String::from("a")),
-                "'a' token was expected, {:?} was parsed", tokens.iter().next());
+    fn test_transaction_schema_violation() {
+        use std::path::PathBuf;
+        use backend::InMemoryBackend;
+        use schema::{FieldType, HeaderSchema};
 
-        let mut header = BTreeMap::new();
-        let ary = Value::Array(vec![Value::Integer(1)]);
-        header.insert(String::from("a"), ary);
+        let config    = config_with_test_aspect(&["post-update", "post-delete"], "");
+        let mut store = Store::new(PathBuf::from("/store"), config,
+                                   Box::new(InMemoryBackend::new())).unwrap();
+        write_counters(&store, &["c/a", "c/b", "c/deleted"]);
+        store.register_schema(HeaderSchema::new("counter").require("value", FieldType::Integer))
+            .unwrap();
 
+        let post_update = register_test_hook(&mut store, HookPosition::PostUpdate, false);
+        let post_delete = register_test_hook(&mut store, HookPosition::PostDelete, false);
 
-        let mut v_header = Value::Table(header);
-        let res = EntryHeader::walk_header(&mut v_header, tokens);
-        assert_eq!(&mut Value::Integer(1), res.unwrap());
-    }
+        {
+            let mut tx = store.transaction();
+            let mut a = store.retrieve(PathBuf::from("c/a")).unwrap();
+            *a.get_content_mut() = String::from("changed");
+            tx.update(a);
 
-    #[test]
-    fn test_walk_header_extract_array() {
-        let tokens = EntryHeader::tokenize("a", '.').unwrap();
-        assert!(tokens.len() == 1, "1 token was expected, {} were parsed", tokens.len());
-        assert!(tokens.iter().next().unwrap() == &Token::Key(String::from("a")),
-                "'a' token was expected, {:?} was parsed", tokens.iter().next());
+            let mut b = store.retrieve(PathBuf::from("c/b")).unwrap();
+            b.get_header_mut().set("counter.value", Value::String(String::from("1"))).unwrap();
+            tx.update(b);
 
-        let mut header = BTreeMap::new();
-        let ary = Value::Array(vec![Value::Integer(1)]);
-        header.insert(String::from("a"), ary);
+            tx.delete(PathBuf::from("c/deleted"));
+            assert!(tx.commit().is_err());
+        }
 
-        let mut v_header = Value::Table(header);
-        let res = EntryHeader::walk_header(&mut v_header, tokens);
-        assert_eq!(&mut Value::Array(vec![Value::Integer(1)]), res.unwrap());
+        for id in &["c/a", "c/b", "c/deleted"] {
+            let entry = store.retrieve_copy(PathBuf::from(*id)).unwrap();
+            assert_eq!(entry.get_content(), id);
+            assert_eq!(entry.get_header().read("counter.value").unwrap(), Some(Value::Integer(1)));
+        }
+        assert!(post_update.lock().unwrap().is_empty());
+        assert!(post_delete.lock().unwrap().is_empty());
+
+        {
+            let mut tx = store.transaction();
+            let mut a = store.retrieve(PathBuf::from("c/a")).unwrap();
+            *a.get_content_mut() = String::from("changed");
+            tx.update(a);
+            tx.commit().unwrap();
+        }
+        assert_eq!(post_update.lock().unwrap().len(), 1);
+        assert_eq!(store.retrieve_copy(PathBuf::from("c/a")).unwrap().get_content(), "changed");
     }
 
-    /**
-     * Creates a big testing header.
-     *
-     * JSON equivalent:
-     *
-     * ```json
-     * {
-     *      "a": {
-     *          "array": [ 0, 1, 2, 3, 4, 5, 6, 7, 8, 9 ]
-     *      },
-     *      "b": {
-     *          "array": [ "string1", "string2", "string3", "string4" ]
-     *      },
-     *      "c": {
-     *          "array": [ 1, "string2", 3, "string4" ]
-     *      },
-     *      "d": {
-     *          "array": [
-     *              {
-     *                  "d1": 1
-     *              },
-     *              {
-     *                  "d2": 2
-     *              },
-     *              {
-     *                  "d3": 3
-     *              },
-     *          ],
-     *
-     *          "something": "else",
-     *
-     *          "and": {
-     *              "something": {
-     *                  "totally": "different"
-     *              }
-     *          }
-     *      }
-     * }
-     * ```
-     *
-     * The sections "a", "b", "c", "d" are created in the respective helper functions
-     * create_header_section_a, create_header_section_b, create_header_section_c and
-     * create_header_section_d.
-     *
-     * These functions can also be used for testing.
-     *
-     */
-    fn create_header() -> Value {
-        let a = create_header_section_a();
-        let b = create_header_section_b();
-        let c = create_header_section_c();
-        let d = create_header_section_d();
+    #[test]
+    fn test_failed_update_releases_entry() {
+        use std::path::PathBuf;
+        use backend::InMemoryBackend;
+        use schema::{FieldType, HeaderSchema};
 
-        let mut header = BTreeMap::new();
-        header.insert(String::from("a"), a);
-        header.insert(String::from("b"), b);
-        header.insert(String::from("c"), c);
-        header.insert(String::from("d"), d);
+        let store = Store::new(PathBuf::from("/store"), hookless_config("lock-timeout = 0"),
+                               Box::new(InMemoryBackend::new())).unwrap();
+        write_counters(&store, &["c/a"]);
+        store.register_schema(HeaderSchema::new("counter").require("value", FieldType::Integer))
+            .unwrap();
 
-        Value::Table(header)
+        {
+            let mut fle = store.retrieve(PathBuf::from("c/a")).unwrap();
+            fle.get_header_mut().set("counter.value", Value::String(String::from("1"))).unwrap();
+        }
+        {
+            let mut fle = store.retrieve(PathBuf::from("c/a")).unwrap();
+            fle.get_header_mut().set("counter.value", Value::String(String::from("1"))).unwrap();
+            assert!(store.update(fle).is_err());
+        }
+
+        let entry = store.retrieve_copy(PathBuf::from("c/a")).unwrap();
+        assert_eq!(entry.get_header().read("counter.value").unwrap(), Some(Value::Integer(1)));
+        assert!(store.delete(PathBuf::from("c/a")).is_ok());
     }
 
-    fn create_header_section_a() -> Value {
-        // 0..10 is exclusive 10
-        let a_ary = Value::Array((0..10).map(|x| Value::Integer(x)).collect());
+    #[test]
+    fn test_revisions() {
+        let (store, _) = in_memory_store("revisions-keep = 2");
+        let id = PathBuf::from("notes/a");
+        for content in &["one", "two", "three", "four"] {
+            write_content(&store, "notes/a", content);
+        }
 
-        let mut a_obj = BTreeMap::new();
-        a_obj.insert(String::from("array"), a_ary);
-        Value::Table(a_obj)
+        // The first write created the entry, the oldest revision is removed
+        assert_eq!(store.revisions(id.clone()).unwrap(), vec![2, 3]);
+        assert_eq!(store.retrieve_revision(id.clone(), 2).unwrap().get_content().trim(), "two");
+        assert!(store.retrieve_revision(id.clone(), 1).is_err());
+
+        store.restore_revision(id.clone(), 2).unwrap();
+        assert_eq!(store.retrieve_copy(id.clone()).unwrap().get_content().trim(), "two");
+        assert_eq!(store.revisions(id.clone()).unwrap(), vec![3, 4]);
+        assert_eq!(store.retrieve_revision(id.clone(), 4).unwrap().get_content().trim(), "four");
+
+        store.force_update(store.retrieve(id.clone()).unwrap()).unwrap();
+        assert_eq!(store.revisions(id.clone()).unwrap(), vec![3, 4]);
+
+        // A new entry with the same id starts without revisions
+        store.delete(id.clone()).unwrap();
+        assert!(store.revisions(id.clone()).unwrap().is_empty());
+        write_content(&store, "notes/a", "new");
+        write_content(&store, "notes/a", "newer");
+        assert_eq!(store.revisions(id.clone()).unwrap(), vec![1]);
+        assert_eq!(store.retrieve_revision(id, 1).unwrap().get_content().trim(), "new");
     }
 
-    fn create_header_section_b() -> Value {
-        let b_ary = Value::Array((0..9)
-                                 .map(|x| Value::String(format!("string{}", x)))
-                                 .collect());
+    #[test]
+    fn test_revisions_keep_default() {
+        let (store, _) = in_memory_store("");
+        for i in 0..12 {
+            write_content(&store, "notes/a", &format!("{}", i));
+        }
+        assert_eq!(store.revisions(PathBuf::from("notes/a")).unwrap(), (2..12).collect::<Vec<_>>());
+    }
 
-        let mut b_obj = BTreeMap::new();
-        b_obj.insert(String::from("array"), b_ary);
-        Value::Table(b_obj)
+    #[test]
+    fn test_migrate() {
+        use std::path::PathBuf;
+        use semver::Version;
+        use backend::{StoreBackend, InMemoryBackend, STAGED_SUFFIX};
+        use migration::{Migration, MigrationRegistry};
+
+        let backend = InMemoryBackend::new();
+        let store   = Store::new(PathBuf::from("/store"), hookless_config(""),
+                                 Box::new(backend.clone())).unwrap();
+        let path = PathBuf::from("/store/notes/a");
+        let old  = "---\n[imag]\nversion = \"0.1.0\"\n\n[note]\ntitle = \"a\"\n---\ntext";
+        backend.write(&path, old.as_bytes()).unwrap();
+
+        let mut registry = MigrationRegistry::new();
+        registry.register(Migration::new("notes",
+                                         Version::parse("0.1.0").unwrap(),
+                                         Version::parse("0.2.0").unwrap(),
+                                         Box::new(|header: &mut EntryHeader, _: &mut String| {
+            let title = try!(header.delete("note.title"));
+            header.insert("note.name", title.unwrap()).map(|_| ())
+        })));
+
+        let report = store.migrate(&registry, true).unwrap();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].1.as_ref().unwrap().applied.len(), 1);
+        assert_eq!(backend.read(&path).unwrap(), old.as_bytes());
+
+        assert_eq!(store.migrate(&registry, false).unwrap().len(), 1);
+        let entry = store.retrieve_copy(PathBuf::from("notes/a")).unwrap();
+        assert_eq!(entry.get_header().read("note.name").unwrap(),
+                   Some(Value::String(String::from("a"))));
+        assert_eq!(entry.get_header().read("note.title").unwrap(), None);
+        assert_eq!(entry.get_content(), "text");
+        assert!(store.migrate(&registry, false).unwrap().is_empty());
+
+        // Staged files of transactions are not entries
+        let staged = PathBuf::from(format!("/store/notes/b{}", STAGED_SUFFIX));
+        backend.write(&staged, old.as_bytes()).unwrap();
+        assert!(store.migrate(&registry, false).unwrap().is_empty());
+        assert_eq!(backend.read(&staged).unwrap(), old.as_bytes());
+
+        // The chain of counter migrations has a gap between 0.1.5 and 0.1.8
+        let counter = PathBuf::from("/store/counter/a");
+        let old     = "---\n[imag]\nversion = \"0.1.0\"\n---\n";
+        backend.write(&counter, old.as_bytes()).unwrap();
+        for &(from, to) in [("0.1.0", "0.1.5"), ("0.1.8", "0.2.0")].iter() {
+            registry.register(Migration::new("counter",
+                                             Version::parse(from).unwrap(),
+                                             Version::parse(to).unwrap(),
+                                             Box::new(|_: &mut EntryHeader, _: &mut String| {
+                                                 Ok(())
+                                             })));
+        }
+        let report = store.migrate(&registry, false).unwrap();
+        assert_eq!(report.len(), 1);
+        assert!(report[0].1.is_err());
+        assert_eq!(backend.read(&counter).unwrap(), old.as_bytes());
     }
 
-    fn create_header_section_c() -> Value {
-        let c_ary = Value::Array(
-            vec![
-                Value::Integer(1),
-                Value::String(String::from("string2")),
-                Value::Integer(3),
-                Value::String(String::from("string4"))
-            ]);
+    #[test]
+    fn test_entry_locked_by_other_process() {
+        use std::path::PathBuf;
+        use backend::InMemoryBackend;
+        use super::Store;
 
-        let mut c_obj = BTreeMap::new();
-        c_obj.insert(String::from("array"), c_ary);
-        Value::Table(c_obj)
-    }
+        let config = hookless_config("lock-timeout = 0");
 
-    fn create_header_section_d() -> Value {
-        let d_ary = Value::Array(
-            vec![
-                {
-                    let mut tab = BTreeMap::new();
-                    tab.insert(String::from("d1"), Value::Integer(1));
-                    tab
-                },
-                {
-                    let mut tab = BTreeMap::new();
-                    tab.insert(String::from("d2"), Value::Integer(2));
-                    tab
-                },
-                {
-                    let mut tab = BTreeMap::new();
-                    tab.insert(String::from("d3"), Value::Integer(3));
-                    tab
-                },
-            ].into_iter().map(Value::Table).collect());
+        // Two stores on clones of one backend behave like two processes
+        let backend = InMemoryBackend::new();
+        let a = Store::new(PathBuf::from("/store"), config.clone(), Box::new(backend.clone()))
+            .unwrap();
+        let b = Store::new(PathBuf::from("/store"), config, Box::new(backend)).unwrap();
 
-        let and_obj = Value::Table({
-            let mut tab = BTreeMap::new();
-            let something_tab = Value::Table({
-                let mut tab = BTreeMap::new();
-                tab.insert(String::from("totally"), Value::String(String::from("different")));
-                tab
-            });
-            tab.insert(String::from("something"), something_tab);
-            tab
-        });
+        {
+            let _fle = a.retrieve(PathBuf::from("test/entry")).unwrap();
+            assert!(b.retrieve(PathBuf::from("test/entry")).is_err());
+            assert!(b.retrieve(PathBuf::from("test/other")).is_ok());
+        }
 
-        let mut d_obj = BTreeMap::new();
-        d_obj.insert(String::from("array"), d_ary);
-        d_obj.insert(String::from("something"), Value::String(String::from("else")));
-        d_obj.insert(String::from("and"), and_obj);
-        Value::Table(d_obj)
+        assert!(b.retrieve(PathBuf::from("test/entry")).is_ok());
+
+        // Neither the failed retrieve nor the released entry keep it from being moved
+        b.move_by_id(StoreId::from(PathBuf::from("test/entry")),
+                     StoreId::from(PathBuf::from("test/moved"))).unwrap();
+        assert!(b.retrieve(PathBuf::from("test/moved")).is_ok());
     }
 
     #[test]
-    fn test_walk_header_big_a() {
-        test_walk_header_extract_section("a", &create_header_section_a());
+    fn test_entry_formats() {
+        use std::path::PathBuf;
+        use toml::Value;
+        use format::Format;
+        use storeid::StoreId;
+        use super::Entry;
+
+        let id = StoreId::from(PathBuf::from("/store/test"));
+        let mut entry = Entry::new(id.clone());
+        entry.get_header_mut().set("test.list", Value::Array(vec![Value::Integer(1)])).unwrap();
+        entry.get_header_mut().set("test.ratio", Value::Float(0.5)).unwrap();
+        *entry.get_content_mut() = String::from("Some\n\"quoted\" content\n");
+
+        for format in vec![Format::Toml, Format::Json, Format::Yaml] {
+            let text = entry.to_str_as(format).unwrap();
+            let back = Entry::from_str_as(id.clone(), &text, format).unwrap();
+            assert_eq!(back.get_header().header(), entry.get_header().header());
+            assert_eq!(back.get_content(), entry.get_content());
+        }
+
+        assert!(Entry::from_str_as(id.clone(), r#"{"header": {}}"#, Format::Json).is_err());
     }
 
-    #[test]
-    fn test_walk_header_big_b() {
-        test_walk_header_extract_section("b", &create_header_section_b());
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestSection {
+        name: String,
+        value: u32,
+        tags: Vec<String>,
+        note: Option<String>,
     }
 
     #[test]
-    fn test_walk_header_big_c() {
-        test_walk_header_extract_section("c", &create_header_section_c());
+    fn test_header_sections() {
+        use toml::Value;
+        use error::StoreErrorKind as SEK;
+        use super::EntryHeader;
+
+        let section = TestSection {
+            name: String::from("test"),
+            value: 42,
+            tags: vec![String::from("a")],
+            note: None,
+        };
+
+        let mut header = EntryHeader::new();
+        header.write_section("test", &section).unwrap();
+        assert_eq!(header.read("test.value").unwrap(), Some(Value::Integer(42)));
+        assert_eq!(header.read("test.note").unwrap(), None);
+        assert_eq!(header.read_section::<TestSection>("test").unwrap(), Some(section));
+        assert_eq!(header.read_section::<TestSection>("missing").unwrap(), None);
+
+        header.set("test.extra", Value::Boolean(true)).unwrap();
+        header.set("test.note", Value::String(String::from("old"))).unwrap();
+        header.write_section("test", &section).unwrap();
+        assert_eq!(header.read("test.extra").unwrap(), Some(Value::Boolean(true)));
+        assert_eq!(header.read("test.note").unwrap(), None);
+
+        header.set("test.value", Value::Integer(-1)).unwrap();
+        let err = header.read_section::<TestSection>("test").unwrap_err();
+        assert_eq!(err.err_type(), SEK::HeaderSectionTypeError);
     }
 
     #[test]
-    fn test_walk_header_big_d() {
-        test_walk_header_extract_section("d", &create_header_section_d());
-    }
+    fn test_header_section_with_datetime_key() {
+        use super::EntryHeader;
 
-    fn test_walk_header_extract_section(secname: &str, expected: &Value) {
-        let tokens = EntryHeader::tokenize(secname, '.').unwrap();
-        assert!(tokens.len() == 1, "1 token was expected, {} were parsed", tokens.len());
-        assert!(tokens.iter().next().unwrap() == &Token::Key(String::from(secname)),
-                "'{}' token was expected, {:?} was parsed", secname, tokens.iter().next());
+        // Not mistaken for a datetime
+        let mut section = BTreeMap::new();
+        section.insert(String::from("$datetime"), String::from("yesterday"));
 
-        let mut header = create_header();
-        let res = EntryHeader::walk_header(&mut header, tokens);
-        assert_eq!(expected, res.unwrap());
+        let mut header = EntryHeader::new();
+        header.write_section("test", &section).unwrap();
+        assert_eq!(header.read_section::<BTreeMap<String, String>>("test").unwrap(),
+                   Some(section));
     }
 
     #[test]
-    fn test_walk_header_extract_numbers() {
-        test_extract_number("a", 0, 0);
-        test_extract_number("a", 1, 1);
-        test_extract_number("a", 2, 2);
-        test_extract_number("a", 3, 3);
-        test_extract_number("a", 4, 4);
-        test_extract_number("a", 5, 5);
-        test_extract_number("a", 6, 6);
-        test_extract_number("a", 7, 7);
-        test_extract_number("a", 8, 8);
-        test_extract_number("a", 9, 9);
+    fn test_snapshot_diff_restore() {
+        use snapshot::SnapshotChange;
+
+        let (store, _) = in_memory_store("");
+        let id = |s: &str| StoreId::from(PathBuf::from(format!("/store/{}", s)));
+
+        write_content(&store, "notes/a", "a");
+        write_content(&store, "notes/b", "b");
+        write_content(&store, "notes/v~0.2.0", "v");
+        write_content(&store, "diary/x", "x");
+        let snapshot = store.snapshot("before", Some("notes")).unwrap();
+        assert_eq!(snapshot.entries.len(), 3);
+        assert!(store.snapshot("before", None).is_err());
+
+        write_content(&store, "notes/a", "changed");
+        write_content(&store, "notes/c", "c");
+        write_content(&store, "notes/v~0.2.0", "changed");
+        write_content(&store, "diary/x", "changed");
+        store.delete(PathBuf::from("notes/b")).unwrap();
+
+        assert_eq!(store.diff_snapshot("before").unwrap(), vec![
+            SnapshotChange::Modified(id("notes/a")),
+            SnapshotChange::Added(id("notes/c")),
+            SnapshotChange::Modified(id("notes/v~0.2.0")),
+            SnapshotChange::Removed(id("notes/b")),
+        ]);
+        assert!(store.restore_snapshot("before", Some("diary")).is_err());
+
+        let restored = store.restore_snapshot("before", Some("notes/a")).unwrap();
+        assert_eq!(restored, vec![SnapshotChange::Modified(id("notes/a"))]);
+        assert_eq!(store.retrieve_copy(PathBuf::from("notes/a")).unwrap().get_content(), "a");
+
+        // Versioned entries can be restored by their path without the version
+        let restored = store.restore_snapshot("before", Some("notes/v")).unwrap();
+        assert_eq!(restored, vec![SnapshotChange::Modified(id("notes/v~0.2.0"))]);
+        assert_eq!(store.retrieve_copy(PathBuf::from("notes/v~0.2.0")).unwrap().get_content(),
+                   "v");
+
+        assert_eq!(store.restore_snapshot("before", None).unwrap().len(), 2);
+        assert!(store.diff_snapshot("before").unwrap().is_empty());
+        assert_eq!(store.snapshots().unwrap(), vec![snapshot]);
+    }
 
-        test_extract_number("c", 0, 1);
-        test_extract_number("c", 2, 3);
+    #[test]
+    fn test_export_import() {
+        use archive::{ConflictStrategy, ImportOutcome};
+
+        let id = |s: &str| StoreId::from(PathBuf::from(format!("/store/{}", s)));
+
+        let (a, _) = in_memory_store("");
+        write_content(&a, "notes/a", "a");
+        write_content(&a, "notes/b", "b");
+        write_content(&a, "notes/v~0.2.0", "v");
+        write_content(&a, "diary/x", "x");
+
+        let mut archive = vec![];
+        let manifest = a.export(&["notes"], &mut archive).unwrap();
+        assert_eq!(manifest.entries.len(), 3);
+
+        let (b, _) = in_memory_store("");
+        write_content(&b, "notes/b", "other b");
+        write_content(&b, "notes/v~0.2.0", "other v");
+        let outcomes = b.import(&archive[..], ConflictStrategy::KeepBoth).unwrap();
+        assert_eq!(outcomes, vec![
+            ImportOutcome::Imported(id("notes/a")),
+            ImportOutcome::Renamed(id("notes/b"), id("notes/b-1")),
+            ImportOutcome::Renamed(id("notes/v~0.2.0"), id("notes/v-1~0.2.0")),
+        ]);
+        assert_eq!(b.retrieve_copy(PathBuf::from("notes/b-1")).unwrap().get_content(), "b");
+        assert_eq!(b.retrieve_copy(PathBuf::from("notes/b")).unwrap().get_content(), "other b");
+        assert_eq!(b.retrieve_copy(PathBuf::from("notes/v-1~0.2.0")).unwrap().get_content(), "v");
+
+        let outcomes = b.import(&archive[..], ConflictStrategy::Overwrite).unwrap();
+        assert_eq!(outcomes, vec![
+            ImportOutcome::Unchanged(id("notes/a")),
+            ImportOutcome::Overwritten(id("notes/b")),
+            ImportOutcome::Overwritten(id("notes/v~0.2.0")),
+        ]);
+        assert_eq!(b.retrieve_copy(PathBuf::from("notes/b")).unwrap().get_content(), "b");
+
+        archive.truncate(512);
+        assert!(b.import(&archive[..], ConflictStrategy::Skip).is_err());
     }
 
-    fn test_extract_number(sec: &str, idx: usize, exp: i64) {
-        let tokens = EntryHeader::tokenize(&format!("{}.array.{}", sec, idx)[..], '.').unwrap();
-        assert!(tokens.len() == 3, "3 token was expected, {} were parsed", tokens.len());
-        {
-            let mut iter = tokens.iter();
+    #[test]
+    fn test_gc() {
+        use backend::StoreBackend;
+        use gc::Garbage;
 
-            let tok = iter.next().unwrap();
-            let exp = Token::Key(String::from(sec));
-            assert!(tok == &exp, "'{}' token was expected, {:?} was parsed", sec, tok);
+        let (store, backend) = in_memory_store("");
+        let broken = PathBuf::from("/store/notes/broken");
 
-            let tok = iter.next().unwrap();
-            let exp = Token::Key(String::from("array"));
-            assert!(tok == &exp, "'array' token was expected, {:?} was parsed", tok);
+        store.retrieve(PathBuf::from("notes/a")).unwrap();
+        backend.write(&broken, b"no header").unwrap();
 
-            let tok = iter.next().unwrap();
-            let exp = Token::Index(idx);
-            assert!(tok == &exp, "'{}' token was expected, {:?} was parsed", idx, tok);
-        }
+        let garbage = vec![Garbage::Unparsable(StoreId::from(broken.clone()))];
+        assert_eq!(store.gc(true).unwrap(), garbage);
+        assert!(backend.is_file(&broken));
 
-        let mut header = create_header();
-        let res = EntryHeader::walk_header(&mut header, tokens);
-        assert_eq!(&mut Value::Integer(exp), res.unwrap());
+        assert_eq!(store.gc(false).unwrap(), garbage);
+        assert!(!backend.is_file(&broken));
+        assert!(backend.is_file(&PathBuf::from("/store/.lost+found/notes/broken")));
+        assert_eq!(store.ids().unwrap().count(), 1);
+        assert!(store.gc(true).unwrap().is_empty());
     }
 
     #[test]
-    fn test_header_read() {
-        let v = create_header();
-        let h = match v {
-            Value::Table(t) => EntryHeader::from_table(t),
-            _ => panic!("create_header() doesn't return a table!"),
-        };
-
-        assert!(if let Ok(Some(Value::Table(_)))  = h.read("a") { true } else { false });
-        assert!(if let Ok(Some(Value::Array(_)))   = h.read("a.array") { true } else { false });
-        assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.array.1") { true } else { false });
-        assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.array.9") { true } else { false });
+    fn test_gc_attachments() {
+        use backend::{StoreBackend, STAGED_SUFFIX};
 
-        assert!(if let Ok(Some(Value::Table(_))) = h.read("c") { true } else { false });
-        assert!(if let Ok(Some(Value::Array(_)))  = h.read("c.array") { true } else { false });
-        assert!(if let Ok(Some(Value::String(_))) = h.read("c.array.1") { true } else { false });
-        assert!(if let Ok(None) = h.read("c.array.9") { true } else { false });
+        let (store, backend) = in_memory_store("");
+        let staged = PathBuf::from(format!("/store/notes/a{}", STAGED_SUFFIX));
+        let broken = PathBuf::from("/store/notes/broken");
 
-        assert!(if let Ok(Some(Value::Integer(_))) = h.read("d.array.0.d1") { true } else { false });
-        assert!(if let Ok(None) = h.read("d.array.0.d2") { true } else { false });
-        assert!(if let Ok(None) = h.read("d.array.0.d3") { true } else { false });
+        {
+            let mut fle = store.retrieve(PathBuf::from("notes/a")).unwrap();
+            store.add_attachment(&mut fle, "used.bin", "application/octet-stream", b"used")
+                .unwrap();
+            store.add_attachment(&mut fle, "unused.bin", "application/octet-stream", b"unused")
+                .unwrap();
+            assert!(store.remove_attachment(&mut fle, "unused.bin").unwrap());
+        }
+        backend.write(&staged, b"---\n[imag]\n---\nstaged").unwrap();
+        backend.write(&broken, b"no header").unwrap();
 
-        assert!(if let Ok(None) = h.read("d.array.1.d1") { true } else { false });
-        assert!(if let Ok(Some(Value::Integer(_))) = h.read("d.array.1.d2") { true } else { false });
-        assert!(if let Ok(None) = h.read("d.array.1.d3") { true } else { false });
+        let unused = vec![::attachment::content_hash(b"unused")];
+        assert_eq!(store.gc_attachments(true).unwrap(), unused);
+        assert!(store.gc_attachments(false).is_err());
 
-        assert!(if let Ok(None) = h.read("d.array.2.d1") { true } else { false });
-        assert!(if let Ok(None) = h.read("d.array.2.d2") { true } else { false });
-        assert!(if let Ok(Some(Value::Integer(_))) = h.read("d.array.2.d3") { true } else { false });
+        store.gc(false).unwrap();
+        assert_eq!(store.gc_attachments(false).unwrap(), unused);
+        assert!(store.gc_attachments(true).unwrap().is_empty());
 
-        assert!(if let Ok(Some(Value::String(_))) = h.read("d.something") { true } else { false });
-        assert!(if let Ok(Some(Value::Table(_))) = h.read("d.and") { true } else { false });
-        assert!(if let Ok(Some(Value::Table(_))) = h.read("d.and.something") { true } else { false });
-        assert!(if let Ok(Some(Value::String(_))) = h.read("d.and.something.totally") { true } else { false });
+        let fle = store.retrieve(PathBuf::from("notes/a")).unwrap();
+        assert_eq!(store.read_attachment(&fle, "used.bin").unwrap(), b"used".to_vec());
     }
 
     #[test]
-    fn test_header_set_override() {
-        let _ = env_logger::init();
-        let v = create_header();
-        let mut h = match v {
-            Value::Table(t) => EntryHeader::from_table(t),
-            _ => panic!("create_header() doesn't return a table!"),
+    fn test_gc_removes_unused_objects() {
+        use std::path::PathBuf;
+        use backend::InMemoryBackend;
+        use gc::Garbage;
+        use object::{ObjectBackend, get};
+        use super::Store;
+
+        let inner    = InMemoryBackend::new();
+        let location = PathBuf::from("/store");
+        let backend  = ObjectBackend::new(location.clone(), Box::new(inner.clone()));
+        let store    = Store::new(location.clone(), hookless_config(""), Box::new(backend))
+            .unwrap();
+        let write    = |content: &str| {
+            *store.retrieve(PathBuf::from("notes/a")).unwrap().get_content_mut() =
+                String::from(content);
         };
 
-        println!("Testing index 0");
-        assert_eq!(h.read("a.array.0").unwrap().unwrap(), Value::Integer(0));
+        write("first");
+        write("second");
+        store.snapshot("s", None).unwrap();
 
-        println!("Altering index 0");
-        assert_eq!(h.set("a.array.0", Value::Integer(42)).unwrap().unwrap(), Value::Integer(0));
+        // The old content is left over, the objects of the snapshot are in use
+        let garbage = store.gc(true).unwrap();
+        assert_eq!(garbage.len(), 1);
+        let hash = match garbage[0] {
+            Garbage::UnusedObject(ref hash) => hash.clone(),
+            ref g => panic!("Unexpected garbage: {}", g),
+        };
+        assert_eq!(String::from_utf8(get(&inner, &location, &hash).unwrap()).unwrap().trim(),
+                   "first");
+
+        assert_eq!(store.gc(false).unwrap(), garbage);
+        assert!(get(&inner, &location, &hash).is_err());
+        assert!(store.gc(true).unwrap().is_empty());
+        assert_eq!(store.retrieve_copy(PathBuf::from("notes/a")).unwrap().get_content().trim(),
+                   "second");
+    }
 
-        println!("Values now: {:?}", h);
+    #[test]
+    fn test_select() {
+        use backend::StoreBackend;
+
+        let (store, backend) = in_memory_store("");
+        for i in 0..5 {
+            let mut fle = store.retrieve(PathBuf::from(format!("notes/{}", i))).unwrap();
+            fle.get_header_mut().set("imag.n", Value::Integer(i)).unwrap();
+            *fle.get_content_mut() = String::from("content");
+        }
+        store.retrieve(PathBuf::from("other/a")).unwrap();
+        backend.write(&PathBuf::from("/store/notes/broken"), b"no header").unwrap();
+
+        let selection = store.select("notes").unwrap();
+        assert_eq!(selection.len(), 6);
+
+        let headers = store.select("notes").unwrap().reverse().page(1, 2).headers()
+            .map(|r| r.unwrap().1.read("imag.n").unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(headers, vec![Some(Value::Integer(3)), Some(Value::Integer(2))]);
+
+        let contents = store.select("notes").unwrap()
+            .map_parallel(3, |entry| entry.get_content().len());
+        assert_eq!(contents.len(), 6);
+        assert!(contents[..5].iter().all(|r| r.as_ref().ok() == Some(&7)));
+        assert!(contents[5].is_err());
+    }
 
-        println!("Testing all indexes");
-        assert_eq!(h.read("a.array.0").unwrap().unwrap(), Value::Integer(42));
-        assert_eq!(h.read("a.array.1").unwrap().unwrap(), Value::Integer(1));
-        assert_eq!(h.read("a.array.2").unwrap().unwrap(), Value::Integer(2));
-        assert_eq!(h.read("a.array.3").unwrap().unwrap(), Value::Integer(3));
-        assert_eq!(h.read("a.array.4").unwrap().unwrap(), Value::Integer(4));
-        assert_eq!(h.read("a.array.5").unwrap().unwrap(), Value::Integer(5));
-        assert_eq!(h.read("a.array.6").unwrap().unwrap(), Value::Integer(6));
-        assert_eq!(h.read("a.array.7").unwrap().unwrap(), Value::Integer(7));
-        assert_eq!(h.read("a.array.8").unwrap().unwrap(), Value::Integer(8));
-        assert_eq!(h.read("a.array.9").unwrap().unwrap(), Value::Integer(9));
+    #[test]
+    fn test_retrieve_for_module_skips_staged_files() {
+        use backend::{StoreBackend, STAGED_SUFFIX};
+
+        let (store, backend) = in_memory_store("");
+        write_content(&store, "notes/a", "a");
+        let staged = PathBuf::from(format!("/store/notes/b{}", STAGED_SUFFIX));
+        backend.write(&staged, b"---\n[imag]\n---\nstaged").unwrap();
+
+        let ids = store.retrieve_for_module("notes").unwrap().collect::<Vec<_>>();
+        assert_eq!(ids, vec![StoreId::from(PathBuf::from("/store/notes/a"))]);
     }
 
     #[test]
-    fn test_header_set_new() {
-        let _ = env_logger::init();
-        let v = create_header();
-        let mut h = match v {
-            Value::Table(t) => EntryHeader::from_table(t),
-            _ => panic!("create_header() doesn't return a table!"),
-        };
+    fn test_find_by_header() {
+        use std::path::PathBuf;
+        use backend::InMemoryBackend;
+        use super::Store;
+
+        for config in vec![hookless_config(""), hookless_config(r#"index = [ "imag.tags" ]"#)] {
+            let store = Store::new(PathBuf::from("/store"), config,
+                                   Box::new(InMemoryBackend::new())).unwrap();
+            let tags = |v: Vec<Value>| {
+                let mut imag = BTreeMap::new();
+                imag.insert(String::from("tags"), Value::Array(v));
+                Value::Table(imag)
+            };
 
-        assert!(h.read("a.foo").is_ok());
-        assert!(h.read("a.foo").unwrap().is_none());
+            {
+                let mut fle = store.retrieve(PathBuf::from("notes/a")).unwrap();
+                fle.get_header_mut().set("imag", tags(vec![Value::String(String::from("1"))]))
+                    .unwrap();
+            }
+            {
+                let mut fle = store.retrieve(PathBuf::from("notes/b")).unwrap();
+                fle.get_header_mut().set("imag", tags(vec![Value::Integer(1)])).unwrap();
+            }
+            {
+                let mut fle = store.retrieve(PathBuf::from("other/c")).unwrap();
+                fle.get_header_mut().set("imag", tags(vec![Value::String(String::from("1"))]))
+                    .unwrap();
+            }
 
-        {
-            let v = h.set("a.foo", Value::Integer(42));
-            assert!(v.is_ok());
-            assert!(v.unwrap().is_none());
+            let find = |module: Option<&str>, value: Value| {
+                let mut ids = store.find_by_header(module, "imag.tags", &value)
+                    .unwrap()
+                    .map(|id| id.to_path_buf())
+                    .collect::<Vec<_>>();
+                ids.sort();
+                ids
+            };
 
-            assert!(if let Ok(Some(Value::Table(_))) = h.read("a") { true } else { false });
-            assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.foo") { true } else { false });
+            assert_eq!(find(None, Value::String(String::from("1"))),
+                       vec![PathBuf::from("/store/notes/a"), PathBuf::from("/store/other/c")]);
+            assert_eq!(find(Some("notes"), Value::String(String::from("1"))),
+                       vec![PathBuf::from("/store/notes/a")]);
+            assert_eq!(find(None, Value::Integer(1)), vec![PathBuf::from("/store/notes/b")]);
+            assert!(find(None, Value::Boolean(true)).is_empty());
         }
+    }
 
-        {
-            let v = h.set("new", Value::Table(BTreeMap::new()));
-            assert!(v.is_ok());
-            assert!(v.unwrap().is_none());
-
-            let v = h.set("new.subset", Value::Table(BTreeMap::new()));
-            assert!(v.is_ok());
-            assert!(v.unwrap().is_none());
+    #[test]
+    fn test_header_index_merges_changes_of_processes() {
+        use std::path::PathBuf;
+        use backend::InMemoryBackend;
+        use super::Store;
 
-            let v = h.set("new.subset.dest", Value::Integer(1337));
-            assert!(v.is_ok());
-            assert!(v.unwrap().is_none());
+        let backend = InMemoryBackend::new();
+        let open = || {
+            Store::new(PathBuf::from("/store"), hookless_config(r#"index = [ "imag.tags" ]"#),
+                       Box::new(backend.clone())).unwrap()
+        };
+        let tag = |store: &Store, id: &str, tag: &str| {
+            let mut fle = store.retrieve(PathBuf::from(id)).unwrap();
+            let tags = Value::Array(vec![Value::String(String::from(tag))]);
+            fle.get_header_mut().set("imag.tags", tags).unwrap();
+        };
+        let found = |store: &Store, tag: &str| {
+            store.query_index("imag.tags", &Value::String(String::from(tag)))
+                .unwrap()
+                .collect::<Vec<_>>()
+        };
+        let id = |s: &str| StoreId::from(PathBuf::from(s));
 
-            assert!(if let Ok(Some(Value::Table(_))) = h.read("new") { true } else { false });
-            assert!(if let Ok(Some(Value::Table(_))) = h.read("new.subset") { true } else { false });
-            assert!(if let Ok(Some(Value::Integer(_))) = h.read("new.subset.dest") { true } else { false });
+        {
+            let first  = open();
+            let second = open();
+            tag(&first, "notes/a", "x");
+            tag(&second, "notes/b", "y");
+            assert_eq!(found(&first, "x"), vec![id("/store/notes/a")]);
         }
-    }
 
+        // Both stores merged their changes into the index on disk
+        let store = open();
+        assert_eq!(found(&store, "x"), vec![id("/store/notes/a")]);
+        assert_eq!(found(&store, "y"), vec![id("/store/notes/b")]);
+    }
 
     #[test]
-    fn test_header_insert_override() {
-        let _ = env_logger::init();
-        let v = create_header();
-        let mut h = match v {
-            Value::Table(t) => EntryHeader::from_table(t),
-            _ => panic!("create_header() doesn't return a table!"),
+    fn test_search_index_is_updated_on_write() {
+        use std::path::PathBuf;
+        use backend::InMemoryBackend;
+        use super::Store;
+
+        let backend = InMemoryBackend::new();
+        let open = || {
+            Store::new(PathBuf::from("/store"), hookless_config("search-index = true"),
+                       Box::new(backend.clone())).unwrap()
+        };
+        let found = |store: &Store, query: &str| {
+            store.search(query).unwrap().into_iter().map(|hit| hit.id).collect::<Vec<_>>()
         };
+        let id = |s: &str| StoreId::from(PathBuf::from(s));
 
-        println!("Testing index 0");
-        assert_eq!(h.read("a.array.0").unwrap().unwrap(), Value::Integer(0));
+        {
+            let first  = open();
+            let second = open();
 
-        println!("Altering index 0");
-        assert_eq!(h.insert("a.array.0", Value::Integer(42)).unwrap(), false);
-        println!("...should have failed");
+            // Written by dropping the FileLockEntry
+            *first.retrieve(PathBuf::from("notes/a")).unwrap().get_content_mut() =
+                String::from("apples");
+            *second.retrieve(PathBuf::from("notes/b")).unwrap().get_content_mut() =
+                String::from("bananas");
 
-        println!("Testing all indexes");
-        assert_eq!(h.read("a.array.0").unwrap().unwrap(), Value::Integer(0));
-        assert_eq!(h.read("a.array.1").unwrap().unwrap(), Value::Integer(1));
-        assert_eq!(h.read("a.array.2").unwrap().unwrap(), Value::Integer(2));
-        assert_eq!(h.read("a.array.3").unwrap().unwrap(), Value::Integer(3));
-        assert_eq!(h.read("a.array.4").unwrap().unwrap(), Value::Integer(4));
-        assert_eq!(h.read("a.array.5").unwrap().unwrap(), Value::Integer(5));
-        assert_eq!(h.read("a.array.6").unwrap().unwrap(), Value::Integer(6));
-        assert_eq!(h.read("a.array.7").unwrap().unwrap(), Value::Integer(7));
-        assert_eq!(h.read("a.array.8").unwrap().unwrap(), Value::Integer(8));
-        assert_eq!(h.read("a.array.9").unwrap().unwrap(), Value::Integer(9));
+            assert_eq!(found(&first, "apples"), vec![id("/store/notes/a")]);
+            assert!(found(&first, "bananas").is_empty());
+        }
+
+        // Both stores merged their changes into the index on disk
+        let store = open();
+        assert_eq!(found(&store, "apples"), vec![id("/store/notes/a")]);
+        assert_eq!(found(&store, "bananas"), vec![id("/store/notes/b")]);
+
+        store.delete(PathBuf::from("notes/a")).unwrap();
+        assert!(found(&store, "apples").is_empty());
     }
 
     #[test]
-    fn test_header_insert_new() {
-        let _ = env_logger::init();
-        let v = create_header();
-        let mut h = match v {
-            Value::Table(t) => EntryHeader::from_table(t),
-            _ => panic!("create_header() doesn't return a table!"),
+    fn test_encrypted_entries_are_not_indexed() {
+        use backend::{BackendObject, StoreBackend};
+
+        let (store, backend) = in_memory_store(r#"
+            search-index = true
+            index = [ "imag.version" ]
+
+            [encryption]
+            modules = [ "diary" ]
+        "#);
+        let id = |s: &str| StoreId::from(PathBuf::from(s));
+
+        write_content(&store, "diary/a", "secret apples");
+        write_content(&store, "notes/b", "public apples");
+
+        let hits = store.search("apples").unwrap();
+        let found = hits.into_iter().map(|hit| hit.id).collect::<Vec<_>>();
+        assert_eq!(found, vec![id("/store/notes/b")]);
+
+        // Encrypted entries are found by reading their header
+        let version = store.read_header(PathBuf::from("diary/a")).unwrap()
+            .read("imag.version").unwrap().unwrap();
+        let mut found = store.find_by_header(None, "imag.version", &version).unwrap()
+            .collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, vec![id("/store/diary/a"), id("/store/notes/b")]);
+
+        store.rebuild_index().unwrap();
+        store.rebuild_search_index().unwrap();
+        drop(store);
+
+        let mut files = vec![PathBuf::from("/store/.search")];
+        for obj in backend.walk(&PathBuf::from("/store/.index")).unwrap() {
+            if let BackendObject::File(p) = obj {
+                files.push(p);
+            }
+        }
+        for file in files {
+            let text = String::from_utf8(backend.read(&file).unwrap()).unwrap();
+            assert!(!text.contains("diary") && !text.contains("secret"), "{:?}", file);
+        }
+    }
+
+    #[test]
+    fn test_changes_from() {
+        use changelog::{Operation, Position};
+
+        let (store, _) = in_memory_store("changelog-max-size = 1");
+        let changes = |pos: &Position| {
+            let (events, pos) = store.changes_from(pos).unwrap();
+            (events.into_iter().map(|e| (e.operation, e.id)).collect::<Vec<_>>(), pos)
         };
+        let id = |s: &str| StoreId::from(PathBuf::from(s));
 
-        assert!(h.read("a.foo").is_ok());
-        assert!(h.read("a.foo").unwrap().is_none());
+        let _ = store.create(PathBuf::from("notes/a")).unwrap();
+        let (events, pos) = changes(&Position::default());
+        assert_eq!(events, vec![(Operation::Create, id("/store/notes/a"))]);
 
-        {
-            let v = h.insert("a.foo", Value::Integer(42));
-            assert!(v.is_ok());
-            assert_eq!(v.unwrap(), true);
+        // The log is rotated after every change, so only the last change is kept
+        let _ = store.create(PathBuf::from("notes/b")).unwrap();
+        let (events, pos) = changes(&pos);
+        assert_eq!(events, vec![(Operation::Create, id("/store/notes/b"))]);
 
-            assert!(if let Ok(Some(Value::Table(_))) = h.read("a") { true } else { false });
-            assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.foo") { true } else { false });
-        }
+        let (events, _) = changes(&pos);
+        assert!(events.is_empty());
 
-        {
-            let v = h.insert("new", Value::Table(BTreeMap::new()));
-            assert!(v.is_ok());
-            assert_eq!(v.unwrap(), true);
+        let _ = store.create(PathBuf::from("notes/c")).unwrap();
+        let changed : Vec<_> = store.changes_since(0).unwrap().into_iter().map(|e| e.id).collect();
+        assert_eq!(changed, vec![id("/store/notes/c")]);
+    }
 
-            let v = h.insert("new.subset", Value::Table(BTreeMap::new()));
-            assert!(v.is_ok());
-            assert_eq!(v.unwrap(), true);
+    #[test]
+    fn test_read_only() {
+        use backend::StoreBackend;
 
-            let v = h.insert("new.subset.dest", Value::Integer(1337));
-            assert!(v.is_ok());
-            assert_eq!(v.unwrap(), true);
+        let backend = {
+            let (store, backend) = in_memory_store("");
+            write_content(&store, "notes/a", "content");
+            backend
+        };
 
-            assert!(if let Ok(Some(Value::Table(_))) = h.read("new") { true } else { false });
-            assert!(if let Ok(Some(Value::Table(_))) = h.read("new.subset") { true } else { false });
-            assert!(if let Ok(Some(Value::Integer(_))) = h.read("new.subset.dest") { true } else { false });
-        }
+        let store = Store::new_read_only(PathBuf::from("/store"), hookless_config(""),
+                                         Box::new(backend.clone())).unwrap();
+        assert!(store.is_read_only());
+
+        let entry = store.get_read_only(PathBuf::from("notes/a")).unwrap().unwrap();
+        assert_eq!(entry.get_content(), "content");
+        assert!(store.get_read_only(PathBuf::from("notes/b")).unwrap().is_none());
+
+        assert!(store.retrieve(PathBuf::from("notes/a")).is_err());
+        assert!(store.create(PathBuf::from("notes/b")).is_err());
+        assert!(store.delete(PathBuf::from("notes/a")).is_err());
+        assert!(backend.is_file(&PathBuf::from("/store/notes/a")));
+        assert!(!backend.exists(&PathBuf::from("/store/notes/b")));
+        assert!(store.gc(true).is_ok());
+        assert!(store.gc(false).is_err());
     }
 
     #[test]
-    fn test_header_delete() {
-        let _ = env_logger::init();
-        let v = create_header();
-        let mut h = match v {
-            Value::Table(t) => EntryHeader::from_table(t),
-            _ => panic!("create_header() doesn't return a table!"),
-        };
+    fn test_unmodified_entries_are_not_written() {
+        use backend::StoreBackend;
 
-        assert!(if let Ok(Some(Value::Table(_)))   = h.read("a") { true } else { false });
-        assert!(if let Ok(Some(Value::Array(_)))   = h.read("a.array") { true } else { false });
-        assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.array.1") { true } else { false });
-        assert!(if let Ok(Some(Value::Integer(_))) = h.read("a.array.9") { true } else { false });
+        let (store, backend) = in_memory_store("");
+        let path = PathBuf::from("/store/notes/a");
+        store.retrieve(PathBuf::from("notes/a")).unwrap();
 
-        assert!(if let Ok(Some(Value::Integer(1))) = h.delete("a.array.1") { true } else { false });
-        assert!(if let Ok(Some(Value::Integer(9))) = h.delete("a.array.8") { true } else { false });
-        assert!(if let Ok(Some(Value::Array(_)))   = h.delete("a.array") { true } else { false });
-        assert!(if let Ok(Some(Value::Table(_)))   = h.delete("a") { true } else { false });
+        // Comments are lost when the entry is written
+        let raw = String::from_utf8(backend.read(&path).unwrap()).unwrap();
+        let raw = format!("---\n# comment\n{}", &raw[4..]);
+        backend.write(&path, raw.as_bytes()).unwrap();
 
+        assert!(!store.retrieve(PathBuf::from("notes/a")).unwrap().is_modified());
+        store.update(store.retrieve(PathBuf::from("notes/a")).unwrap()).unwrap();
+        assert_eq!(backend.read(&path).unwrap(), raw.as_bytes());
+
+        store.force_update(store.retrieve(PathBuf::from("notes/a")).unwrap()).unwrap();
+        assert!(!String::from_utf8(backend.read(&path).unwrap()).unwrap().contains("# comment"));
+
+        {
+            let mut fle = store.retrieve(PathBuf::from("notes/a")).unwrap();
+            *fle.get_content_mut() = String::from("content");
+            assert!(fle.is_modified());
+            store.update(fle).unwrap();
+        }
+        assert_eq!(store.retrieve_copy(PathBuf::from("notes/a")).unwrap().get_content(),
+                   "content");
     }
 
 }
@@ -8,6 +8,7 @@ use std::fmt::{Display, Debug, Formatter};
 use std::fmt::Error as FmtError;
 use std::result::Result as RResult;
 
+use backend::StoreBackend;
 use error::StoreErrorKind as SEK;
 use store::Result;
 use store::Store;
@@ -132,6 +133,45 @@ pub fn build_entry_path(store: &Store, path_elem: &str) -> Result<PathBuf> {
     Ok(path)
 }
 
+/// Check whether `path` is used by the store at `location` itself (revisions, journals, ...)
+/// rather than being an entry
+///
+/// These paths live in directories or files starting with a dot directly inside the store.
+pub fn is_internal_path(location: &Path, path: &Path) -> bool {
+    path.strip_prefix(location)
+        .ok()
+        .and_then(|rel| rel.components().next())
+        .and_then(|c| c.as_os_str().to_str())
+        .map(|c| c.starts_with('.'))
+        .unwrap_or(false)
+}
+
+/// Get `id` with `-<n>` appended to its name
+///
+/// The version stays at the end, so `notes/b~0.2.0` becomes `notes/b-1~0.2.0`.
+pub fn numbered(id: &StoreId, n: usize) -> StoreId {
+    let name = id.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    let name = match name.rfind('~') {
+        Some(pos) if Version::parse(&name[(pos + 1)..]).is_ok() => {
+            format!("{}-{}{}", &name[..pos], n, &name[pos..])
+        },
+        _ => format!("{}-{}", name, n),
+    };
+    StoreId::from(id.with_file_name(name))
+}
+
+/// Get the first of `numbered(id, 1)`, `numbered(id, 2)`, ... which is not taken in `backend`
+pub fn free_id(backend: &StoreBackend, id: &StoreId) -> StoreId {
+    let mut n = 1;
+    loop {
+        let candidate = numbered(id, n);
+        if !backend.is_file(&candidate) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
 #[macro_export]
 macro_rules! module_entry_path_mod {
     ($name:expr, $version:expr) => (
@@ -215,8 +255,9 @@ impl Iterator for StoreIdIterator {
 
 #[cfg(test)]
 mod test {
+    use std::path::PathBuf;
 
-    use storeid::IntoStoreId;
+    use storeid::{IntoStoreId, StoreId, numbered};
 
     module_entry_path_mod!("test", "0.2.0-alpha+leet1337");
 
@@ -227,4 +268,13 @@ mod test {
         assert_eq!(p.into_storeid().to_str().unwrap(), "test/test~0.2.0-alpha+leet1337");
     }
 
+    #[test]
+    fn test_numbered() {
+        let versioned = StoreId::from(PathBuf::from("/store/notes/b~0.2.0"));
+        assert_eq!(numbered(&versioned, 1), StoreId::from(PathBuf::from("/store/notes/b-1~0.2.0")));
+
+        let plain = StoreId::from(PathBuf::from("/store/notes/b"));
+        assert_eq!(numbered(&plain, 2), StoreId::from(PathBuf::from("/store/notes/b-2")));
+    }
+
 }
@@ -0,0 +1,199 @@
+//! Lazy iteration over the entries of a module
+//!
+//! `Store::select()` lists the ids of the entries of a module, which can be sorted and paginated
+//! before any entry is read. The entries are then read one by one while iterating:
+//! `Selection::headers()` reads the headers only (up to the closing `---`), `Selection::entries()`
+//! reads whole entries. `Selection::map_parallel()` parses and processes the entries on a bounded
+//! pool of worker threads.
+
+use std::cmp::{Ordering, max, min};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, sync_channel};
+use std::vec::IntoIter;
+
+use crossbeam;
+
+use error::{StoreError as SE, StoreErrorKind as SEK};
+use store::{Entry, EntryHeader, Result, Store};
+use storeid::{StoreId, StoreIdIterator};
+
+/// A sorted selection of entries, see `Store::select()`
+#[derive(Debug)]
+pub struct Selection<'a> {
+    store: &'a Store,
+    ids: Vec<StoreId>,
+}
+
+impl<'a> Selection<'a> {
+
+    /// Select the entries `ids` of `store`, sorted by their ids
+    pub fn new(store: &'a Store, mut ids: Vec<StoreId>) -> Selection<'a> {
+        ids.sort();
+        Selection {
+            store: store,
+            ids: ids,
+        }
+    }
+
+    pub fn reverse(mut self) -> Selection<'a> {
+        self.ids.reverse();
+        self
+    }
+
+    pub fn sort_by<F>(mut self, cmp: F) -> Selection<'a>
+        where F: FnMut(&StoreId, &StoreId) -> Ordering
+    {
+        self.ids.sort_by(cmp);
+        self
+    }
+
+    /// Drop the first `n` entries of the selection
+    pub fn skip(mut self, n: usize) -> Selection<'a> {
+        let n = min(n, self.ids.len());
+        self.ids.drain(..n);
+        self
+    }
+
+    /// Keep the first `n` entries of the selection only
+    pub fn take(mut self, n: usize) -> Selection<'a> {
+        self.ids.truncate(n);
+        self
+    }
+
+    /// Keep the page `page` (counting from zero) with `per_page` entries only
+    pub fn page(self, page: usize, per_page: usize) -> Selection<'a> {
+        self.skip(page.saturating_mul(per_page)).take(per_page)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn ids(self) -> StoreIdIterator {
+        StoreIdIterator::new(Box::new(self.ids.into_iter()))
+    }
+
+    /// Iterate over the headers of the selected entries, their contents are not read
+    pub fn headers(self) -> HeaderIter<'a> {
+        HeaderIter {
+            store: self.store,
+            ids: self.ids.into_iter(),
+        }
+    }
+
+    /// Iterate over copies of the selected entries, see `Store::retrieve_copy()`
+    pub fn entries(self) -> EntryIter<'a> {
+        EntryIter {
+            store: self.store,
+            ids: self.ids.into_iter(),
+        }
+    }
+
+    /// Parse the selected entries and call `f` on them, on `workers` threads
+    ///
+    /// The entries are read by the calling thread, at most `workers` read entries wait for a
+    /// worker at any time. The results are returned in the order of the selection.
+    pub fn map_parallel<T, F>(self, workers: usize, f: F) -> Vec<Result<T>>
+        where T: Send,
+              F: Fn(Entry) -> T + Sync
+    {
+        let workers = max(workers, 1);
+        let (job_tx, job_rx) = sync_channel::<(usize, StoreId, String)>(workers);
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let (result_tx, result_rx) = channel();
+        let mut results : Vec<Option<Result<T>>> = self.ids.iter().map(|_| None).collect();
+        let store = self.store;
+        let ids   = self.ids;
+
+        crossbeam::scope(|scope| {
+            for _ in 0..workers {
+                let job_rx    = job_rx.clone();
+                let f         = &f;
+                let result_tx = result_tx.clone();
+
+                scope.spawn(move || loop {
+                    let job = match job_rx.lock() {
+                        Ok(rx) => rx.recv(),
+                        Err(_) => break,
+                    };
+                    let (i, id, text) = match job {
+                        Ok(job) => job,
+                        Err(_)  => break, // all entries are read
+                    };
+
+                    // Store errors cannot be sent to other threads, only their kind is kept
+                    let result = match Entry::from_str(id, &text[..]) {
+                        Ok(entry) => Ok(f(entry)),
+                        Err(e) => {
+                            debug!("Parsing failed: {:?}", e);
+                            Err(e.err_type())
+                        },
+                    };
+                    if result_tx.send((i, result)).is_err() {
+                        break;
+                    }
+                });
+            }
+            // Sending fails instead of blocking once all workers are gone
+            drop(job_rx);
+            drop(result_tx);
+
+            for (i, id) in ids.into_iter().enumerate() {
+                match store.read_raw(id.clone()) {
+                    Ok(text) => if job_tx.send((i, id, text)).is_err() {
+                        break; // all workers are gone
+                    },
+                    Err(e) => results[i] = Some(Err(e)),
+                }
+            }
+            drop(job_tx);
+
+            for (i, result) in result_rx.iter() {
+                results[i] = Some(result.map_err(|kind| SE::new(kind, None)));
+            }
+        });
+
+        results.into_iter()
+            .map(|r| r.unwrap_or_else(|| Err(SE::new(SEK::WorkerError, None))))
+            .collect()
+    }
+
+}
+
+/// Iterator over the headers of the entries of a `Selection`
+#[derive(Debug)]
+pub struct HeaderIter<'a> {
+    store: &'a Store,
+    ids: IntoIter<StoreId>,
+}
+
+impl<'a> Iterator for HeaderIter<'a> {
+    type Item = Result<(StoreId, EntryHeader)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let store = self.store;
+        self.ids.next().map(|id| store.read_header(id.clone()).map(|header| (id, header)))
+    }
+
+}
+
+/// Iterator over copies of the entries of a `Selection`
+#[derive(Debug)]
+pub struct EntryIter<'a> {
+    store: &'a Store,
+    ids: IntoIter<StoreId>,
+}
+
+impl<'a> Iterator for EntryIter<'a> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let store = self.store;
+        self.ids.next().map(|id| store.retrieve_copy(id))
+    }
+
+}
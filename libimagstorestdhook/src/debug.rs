@@ -53,6 +53,8 @@ impl HookDataAccessorProvider for DebugHook {
             HP::PostRetrieve |
             HP::PreUpdate    |
             HP::PostUpdate   => HDA::MutableAccess(&self.accessor),
+            HP::PreMove      |
+            HP::PostMove     => HDA::MoveAccess(&self.accessor),
         }
     }
 
@@ -67,6 +69,7 @@ pub mod accessor {
     use libimagstore::hook::accessor::MutableHookDataAccessor;
     use libimagstore::hook::accessor::NonMutableHookDataAccessor;
     use libimagstore::hook::accessor::StoreIdAccessor;
+    use libimagstore::hook::accessor::MoveAccessor;
     use libimagstore::hook::position::HookPosition;
 
     #[derive(Debug)]
@@ -93,6 +96,15 @@ pub mod accessor {
 
     }
 
+    impl MoveAccessor for DebugHookAccessor {
+
+        fn access_move(&self, old: &StoreId, new: &StoreId) -> HookResult<()> {
+            debug!("[DEBUG HOOK]: {:?} -> {:?}", old, new);
+            Ok(())
+        }
+
+    }
+
     impl MutableHookDataAccessor for DebugHookAccessor {
 
         fn access_mut(&self, fle: &mut FileLockEntry) -> HookResult<()> {
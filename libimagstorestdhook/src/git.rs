@@ -0,0 +1,474 @@
+//! Version control of the store with git
+//!
+//! `GitHook` commits the entries which were created, updated, deleted or moved to a git repository
+//! in the store directory. It is meant to be registered for the post-create, post-update,
+//! post-delete, post-move and store-unload positions. The repository needs no remote, it is
+//! created (with a `.gitignore` for the internal files of the store) if it does not exist.
+//!
+//! Entries are usually written when they are dropped, after the post-create and post-update hooks
+//! ran, so changes whose files did not change yet are kept and committed with the next one. When
+//! the store is unloaded, everything which is left is committed at once, so with `batch = true`
+//! there is one commit per run of an imag command.
+//!
+//! ```toml
+//! [store.hooks.stdhook_git]
+//! aspect = "version-control"
+//!
+//! # Commit once when the store is unloaded instead of after each change
+//! batch = false
+//!
+//! # Create the repository if the store is no git repository yet
+//! auto-init = true
+//!
+//! # Templates for the commit messages. {operation} is one of create, update, delete, move,
+//! # {module} the module of the entry and {id} its path inside the store. Moves also have
+//! # {new_id}. {count} and {changes} are the number and list of the files in a batch commit.
+//! message       = "{module}: {operation} {id}"
+//! move-message  = "{module}: move {id} -> {new_id}"
+//! batch-message = "imag: {count} changes\n\n{changes}"
+//! ```
+
+use std::fs::OpenOptions;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+
+use toml::Value;
+
+use libimagstore::hook::Hook;
+use libimagstore::hook::accessor::HookDataAccessor as HDA;
+use libimagstore::hook::accessor::HookDataAccessorProvider;
+use libimagstore::hook::accessor::NonMutableHookDataAccessor;
+use libimagstore::hook::accessor::StoreIdAccessor;
+use libimagstore::hook::accessor::MoveAccessor;
+use libimagstore::hook::error::{CustomData, HookError, HookErrorKind};
+use libimagstore::hook::position::HookPosition;
+use libimagstore::hook::result::HookResult;
+use libimagstore::store::FileLockEntry;
+use libimagstore::storeid::StoreId;
+
+static GITIGNORE : &'static str = "\
+# Internal files of the imag store
+/.*
+!/.gitignore
+*.imag-tx
+";
+
+/// A change to the store which is not committed yet
+#[derive(Debug, Clone)]
+pub struct Change {
+    operation: &'static str,
+    id: PathBuf,
+    new_id: Option<PathBuf>,
+}
+
+impl Change {
+
+    /// The paths (inside the store) the change touches
+    fn paths(&self) -> Vec<&PathBuf> {
+        Some(&self.id).into_iter().chain(self.new_id.as_ref()).collect()
+    }
+
+}
+
+/// The changes which are not committed yet, shared between the hooks for all positions
+pub type PendingChanges = Arc<Mutex<Vec<Change>>>;
+
+#[derive(Debug, Clone)]
+struct GitConfig {
+    batch: bool,
+    auto_init: bool,
+    message: String,
+    move_message: String,
+    batch_message: String,
+}
+
+impl Default for GitConfig {
+
+    fn default() -> GitConfig {
+        GitConfig {
+            batch: false,
+            auto_init: true,
+            message: String::from("{module}: {operation} {id}"),
+            move_message: String::from("{module}: move {id} -> {new_id}"),
+            batch_message: String::from("imag: {count} changes\n\n{changes}"),
+        }
+    }
+
+}
+
+#[derive(Debug)]
+pub struct GitHook {
+    position: HookPosition,
+    store_location: PathBuf,
+    config: GitConfig,
+    pending: PendingChanges,
+}
+
+impl GitHook {
+
+    pub fn new(position: HookPosition, store_location: PathBuf, pending: PendingChanges)
+        -> GitHook
+    {
+        GitHook {
+            position: position,
+            store_location: store_location,
+            config: GitConfig::default(),
+            pending: pending,
+        }
+    }
+
+    fn record(&self, operation: &'static str, id: &StoreId, new_id: Option<&StoreId>)
+        -> HookResult<()>
+    {
+        let change = Change {
+            operation: operation,
+            id: self.relative(id),
+            new_id: new_id.map(|id| self.relative(id)),
+        };
+        debug!("[GIT HOOK] Recording {:?}", change);
+
+        match self.pending.lock() {
+            Ok(mut pending) => pending.push(change),
+            Err(_) => return Err(hook_error(IoError::new(IoErrorKind::Other, "Lock poisoned"))),
+        }
+
+        if self.config.batch {
+            Ok(())
+        } else {
+            self.commit_pending()
+        }
+    }
+
+    /// Commit the pending changes whose entries were written already
+    ///
+    /// The other changes stay pending, the hooks may run before their files are written.
+    fn commit_pending(&self) -> HookResult<()> {
+        let mut pending = match self.pending.lock() {
+            Ok(pending) => pending,
+            Err(_) => return Err(hook_error(IoError::new(IoErrorKind::Other, "Lock poisoned"))),
+        };
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        try!(self.ensure_repository().map_err(hook_error));
+
+        let paths = pending.iter()
+            .flat_map(|c| c.paths())
+            .filter(|p| self.is_known(p))
+            .cloned()
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            debug!("[GIT HOOK] Nothing written yet, not committing");
+            return Ok(());
+        }
+
+        let mut add = vec![String::from("add"), String::from("-A"), String::from("--")];
+        add.extend(paths.iter().map(|p| format!("{}", p.display())));
+        try!(self.git(&add).map_err(hook_error));
+
+        let staged   = try!(self.staged().map_err(hook_error));
+        let is_ready = |c: &Change| c.paths().iter().any(|p| staged.contains(p));
+        let message  = {
+            let ready = pending.iter().filter(|c| is_ready(c)).collect::<Vec<_>>();
+            if ready.is_empty() {
+                debug!("[GIT HOOK] Nothing changed yet, not committing");
+                return Ok(());
+            }
+
+            if ready.len() == 1 {
+                self.message_for(ready[0])
+            } else {
+                try!(self.batch_message().map_err(hook_error))
+            }
+        };
+        try!(self.commit(&message).map_err(hook_error));
+        pending.retain(|c| !is_ready(c));
+        Ok(())
+    }
+
+    /// Get the paths (inside the store) with staged changes
+    fn staged(&self) -> Result<Vec<PathBuf>, IoError> {
+        self.git(&["diff", "--cached", "--name-only", "-z"]).map(|out| {
+            out.stdout
+                .split(|b| *b == 0)
+                .filter(|p| !p.is_empty())
+                .map(|p| PathBuf::from(String::from_utf8_lossy(p).into_owned()))
+                .collect()
+        })
+    }
+
+    /// Commit everything which changed in the store
+    fn commit_all(&self) -> HookResult<()> {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.clear();
+        }
+
+        self.ensure_repository()
+            .and_then(|_| self.git(&["add", "-A", "."]))
+            .and_then(|_| self.batch_message())
+            .and_then(|message| self.commit(&message))
+            .map_err(hook_error)
+    }
+
+    /// Commit the staged changes with `message`, if there are any
+    fn commit(&self, message: &str) -> Result<(), IoError> {
+        if self.git(&["diff", "--cached", "--quiet"]).is_ok() {
+            debug!("[GIT HOOK] Nothing to commit");
+            return Ok(());
+        }
+
+        // Commits fail without an identity, which is not configured everywhere
+        let mut args = vec![];
+        if self.git(&["config", "user.email"]).is_err() {
+            args.extend_from_slice(&["-c", "user.name=imag", "-c", "user.email=imag@localhost"]);
+        }
+        args.extend_from_slice(&["commit", "-q", "-m", message]);
+        self.git(&args).map(|_| ())
+    }
+
+    fn message_for(&self, change: &Change) -> String {
+        let template = if change.new_id.is_some() {
+            &self.config.move_message
+        } else {
+            &self.config.message
+        };
+
+        let module = change.id
+            .components()
+            .next()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .unwrap_or(String::new());
+
+        template.replace("{operation}", change.operation)
+            .replace("{module}", &module)
+            .replace("{id}", &format!("{}", change.id.display()))
+            .replace("{new_id}", &change.new_id
+                     .as_ref()
+                     .map(|p| format!("{}", p.display()))
+                     .unwrap_or(String::new()))
+    }
+
+    fn batch_message(&self) -> Result<String, IoError> {
+        self.git(&["diff", "--cached", "--name-status"]).map(|out| {
+            let changes = String::from_utf8_lossy(&out.stdout).into_owned();
+            let count   = changes.lines().count();
+            self.config.batch_message
+                .replace("{count}", &count.to_string())
+                .replace("{changes}", changes.trim_right())
+        })
+    }
+
+    /// Create the repository and its `.gitignore`, if they do not exist
+    fn ensure_repository(&self) -> Result<(), IoError> {
+        let mut git_dir = self.store_location.clone();
+        git_dir.push(".git");
+        if !git_dir.exists() {
+            if !self.config.auto_init {
+                return Err(IoError::new(IoErrorKind::NotFound, "Store is no git repository"));
+            }
+            info!("Creating git repository in {:?}", self.store_location);
+            try!(self.git(&["init", "-q"]));
+        }
+
+        let mut gitignore = self.store_location.clone();
+        gitignore.push(".gitignore");
+        if !gitignore.exists() {
+            try!(OpenOptions::new()
+                 .write(true)
+                 .create(true)
+                 .open(&gitignore)
+                 .and_then(|mut f| f.write_all(GITIGNORE.as_bytes())));
+        }
+        Ok(())
+    }
+
+    /// Whether `path` (inside the store) can be staged, ie. exists or is tracked
+    fn is_known(&self, path: &Path) -> bool {
+        let mut full = self.store_location.clone();
+        full.push(path);
+        full.exists() ||
+            self.git(&["ls-files", "--error-unmatch", "--", &format!("{}", path.display())])
+                .is_ok()
+    }
+
+    fn relative(&self, id: &StoreId) -> PathBuf {
+        id.strip_prefix(&self.store_location)
+            .map(|p| p.to_path_buf())
+            .unwrap_or_else(|_| id.to_path_buf())
+    }
+
+    /// Run git in the store directory, fails if git does not exit successfully
+    fn git<S: AsRef<str>>(&self, args: &[S]) -> Result<Output, IoError> {
+        let args = args.iter().map(|a| a.as_ref()).collect::<Vec<_>>();
+        debug!("[GIT HOOK] git {:?}", args);
+
+        Command::new("git")
+            .args(&args)
+            .current_dir(&self.store_location)
+            .output()
+            .and_then(|out| {
+                if out.status.success() {
+                    Ok(out)
+                } else {
+                    let msg = format!("git {} failed: {}",
+                                      args.join(" "),
+                                      String::from_utf8_lossy(&out.stderr).trim());
+                    Err(IoError::new(IoErrorKind::Other, msg))
+                }
+            })
+    }
+
+}
+
+/// Failing to commit does not undo the change, so errors are not aborting
+fn hook_error(e: IoError) -> HookError {
+    HookError::new(HookErrorKind::HookExecutionError, Some(Box::new(e)))
+        .with_custom_data(CustomData::default().aborting(false))
+}
+
+impl Hook for GitHook {
+
+    fn name(&self) -> &'static str {
+        "stdhook_git"
+    }
+
+    fn set_config(&mut self, config: &Value) {
+        let table = match *config {
+            Value::Table(ref t) => t,
+            _ => {
+                warn!("Config for git hook is not a table, using the defaults");
+                return;
+            },
+        };
+
+        for (key, value) in table.iter() {
+            match (&key[..], value) {
+                ("batch", &Value::Boolean(b))     => self.config.batch = b,
+                ("auto-init", &Value::Boolean(b)) => self.config.auto_init = b,
+                ("message", &Value::String(ref s))       => self.config.message = s.clone(),
+                ("move-message", &Value::String(ref s))  => self.config.move_message = s.clone(),
+                ("batch-message", &Value::String(ref s)) => self.config.batch_message = s.clone(),
+                ("aspect", _) => {},
+                _ => warn!("Ignoring invalid setting '{}' of the git hook", key),
+            }
+        }
+    }
+
+}
+
+impl HookDataAccessorProvider for GitHook {
+
+    fn accessor(&self) -> HDA {
+        use libimagstore::hook::position::HookPosition as HP;
+
+        match self.position {
+            HP::StoreUnload  |
+            HP::PreCreate    |
+            HP::PreRetrieve  |
+            HP::PreDelete    |
+            HP::PostDelete   => HDA::StoreIdAccess(self),
+            HP::PostCreate   |
+            HP::PostRetrieve |
+            HP::PreUpdate    |
+            HP::PostUpdate   => HDA::NonMutableAccess(self),
+            HP::PreMove      |
+            HP::PostMove     => HDA::MoveAccess(self),
+        }
+    }
+
+}
+
+impl StoreIdAccessor for GitHook {
+
+    fn access(&self, id: &StoreId) -> HookResult<()> {
+        match self.position {
+            HookPosition::PostDelete  => self.record("delete", id, None),
+            HookPosition::StoreUnload => self.commit_all(),
+            _ => Ok(()),
+        }
+    }
+
+}
+
+impl MoveAccessor for GitHook {
+
+    fn access_move(&self, old: &StoreId, new: &StoreId) -> HookResult<()> {
+        match self.position {
+            HookPosition::PostMove => self.record("move", old, Some(new)),
+            _ => Ok(()),
+        }
+    }
+
+}
+
+impl NonMutableHookDataAccessor for GitHook {
+
+    fn access(&self, fle: &FileLockEntry) -> HookResult<()> {
+        match self.position {
+            HookPosition::PostCreate => self.record("create", fle.get_location(), None),
+            HookPosition::PostUpdate => self.record("update", fle.get_location(), None),
+            _ => Ok(()),
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{File, create_dir_all};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use tempdir::TempDir;
+
+    use libimagstore::hook::position::HookPosition;
+    use libimagstore::storeid::StoreId;
+
+    use super::GitHook;
+
+    fn write(dir: &TempDir, rel: &str, content: &str) -> StoreId {
+        let path = dir.path().join(rel);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path).and_then(|mut f| f.write_all(content.as_bytes())).unwrap();
+        StoreId::from(path)
+    }
+
+    fn commits(hook: &GitHook) -> Vec<String> {
+        let out = hook.git(&["log", "--format=%s"]).unwrap();
+        String::from_utf8_lossy(&out.stdout).lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_changes_are_kept_until_they_are_written() {
+        let dir  = TempDir::new("imag-git-hook").unwrap();
+        let hook = GitHook::new(HookPosition::PostUpdate,
+                                dir.path().to_path_buf(),
+                                Arc::new(Mutex::new(vec![])));
+
+        let a = write(&dir, "notes/a~0.2.0", "one");
+        hook.record("create", &a, None).unwrap();
+        assert_eq!(commits(&hook), vec!["notes: create notes/a~0.2.0"]);
+
+        // The post-update hook runs before the entry is written
+        hook.record("update", &a, None).unwrap();
+        assert_eq!(commits(&hook).len(), 1);
+        assert_eq!(hook.pending.lock().unwrap().len(), 1);
+
+        write(&dir, "notes/a~0.2.0", "two");
+        let b = write(&dir, "notes/b~0.2.0", "three");
+        hook.record("create", &b, None).unwrap();
+        assert_eq!(commits(&hook).len(), 2);
+        assert!(hook.pending.lock().unwrap().is_empty());
+
+        let out = hook.git(&["show", "--name-only", "--format=", "HEAD"]).unwrap();
+        let files = String::from_utf8_lossy(&out.stdout).into_owned();
+        let files = files.lines().filter(|l| !l.is_empty()).collect::<Vec<_>>();
+        assert_eq!(files, vec!["notes/a~0.2.0", "notes/b~0.2.0"]);
+    }
+
+}
@@ -17,6 +17,7 @@
 #[macro_use] extern crate log;
 extern crate toml;
 extern crate fs2;
+#[cfg(test)] extern crate tempdir;
 
 extern crate libimagstore;
 extern crate libimagentrylink;
@@ -24,5 +25,7 @@ extern crate libimagerror;
 
 pub mod debug;
 pub mod flock;
+pub mod git;
 pub mod linkverify;
+pub mod search;
 
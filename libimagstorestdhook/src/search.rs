@@ -0,0 +1,114 @@
+use std::sync::{Arc, Mutex};
+
+use toml::Value;
+
+use libimagstore::hook::Hook;
+use libimagstore::hook::accessor::HookDataAccessor as HDA;
+use libimagstore::hook::accessor::HookDataAccessorProvider;
+use libimagstore::hook::accessor::NonMutableHookDataAccessor;
+use libimagstore::hook::accessor::StoreIdAccessor;
+use libimagstore::hook::accessor::MoveAccessor;
+use libimagstore::hook::position::HookPosition;
+use libimagstore::hook::result::HookResult;
+use libimagstore::search::SearchIndex;
+use libimagstore::store::FileLockEntry;
+use libimagstore::storeid::StoreId;
+
+/// Keeps the full-text index of the store (`Store::search_index()`) up to date
+///
+/// Meant to be registered for the post-create, post-update, post-delete and post-move positions.
+/// No hooks are executed when an entry is written by dropping its `FileLockEntry`, the store
+/// indexes those writes itself.
+#[derive(Debug)]
+pub struct SearchIndexHook {
+    position: HookPosition,
+    index: Arc<Mutex<SearchIndex>>,
+}
+
+impl SearchIndexHook {
+
+    pub fn new(position: HookPosition, index: Arc<Mutex<SearchIndex>>) -> SearchIndexHook {
+        SearchIndexHook {
+            position: position,
+            index: index,
+        }
+    }
+
+}
+
+impl Hook for SearchIndexHook {
+
+    fn name(&self) -> &'static str {
+        "stdhook_search_index"
+    }
+
+    fn set_config(&mut self, _: &Value) {
+        () // We are not configurable here.
+    }
+
+}
+
+impl HookDataAccessorProvider for SearchIndexHook {
+
+    fn accessor(&self) -> HDA {
+        use libimagstore::hook::position::HookPosition as HP;
+
+        match self.position {
+            HP::StoreUnload  |
+            HP::PreCreate    |
+            HP::PreRetrieve  |
+            HP::PreDelete    |
+            HP::PostDelete   => HDA::StoreIdAccess(self),
+            HP::PostCreate   |
+            HP::PostRetrieve |
+            HP::PreUpdate    |
+            HP::PostUpdate   => HDA::NonMutableAccess(self),
+            HP::PreMove      |
+            HP::PostMove     => HDA::MoveAccess(self),
+        }
+    }
+
+}
+
+impl StoreIdAccessor for SearchIndexHook {
+
+    fn access(&self, id: &StoreId) -> HookResult<()> {
+        if let HookPosition::PostDelete = self.position {
+            debug!("[SEARCH INDEX HOOK] Removing {:?}", id);
+            match self.index.lock() {
+                Ok(mut index) => index.remove(id),
+                Err(_) => warn!("Search index lock poisoned, cannot remove {:?}", id),
+            }
+        }
+        Ok(())
+    }
+
+}
+
+impl MoveAccessor for SearchIndexHook {
+
+    fn access_move(&self, old: &StoreId, new: &StoreId) -> HookResult<()> {
+        if let HookPosition::PostMove = self.position {
+            debug!("[SEARCH INDEX HOOK] Moving {:?} -> {:?}", old, new);
+            match self.index.lock() {
+                Ok(mut index) => index.rename(old, new),
+                Err(_) => warn!("Search index lock poisoned, cannot move {:?}", old),
+            }
+        }
+        Ok(())
+    }
+
+}
+
+impl NonMutableHookDataAccessor for SearchIndexHook {
+
+    fn access(&self, fle: &FileLockEntry) -> HookResult<()> {
+        debug!("[SEARCH INDEX HOOK] Indexing {:?}", fle.get_location());
+        match self.index.lock() {
+            Ok(mut index) => index.index(fle.get_location(), fle.get_content()),
+            Err(_) => warn!("Search index lock poisoned, cannot index {:?}", fle.get_location()),
+        }
+        Ok(())
+    }
+
+}